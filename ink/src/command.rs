@@ -0,0 +1,165 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Text commands for the command prompt.
+//!
+//! - `time 3/4`: Change the time signature.
+//! - `key G`, `key Bb`, `key F#`: Change the key signature.
+//! - `clef bass`: Change the clef (treble, bass, alto or tenor).
+//! - `goto m5`: Move the cursor to the start of measure 5.
+//! - `transpose +2`: Transpose the note at the cursor by half steps.
+
+use crate::Program;
+use scof::{Fraction, PitchName};
+use std::fmt;
+
+/// An error from running a command.
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    /// Nothing was typed.
+    Empty,
+    /// The command name isn't known.
+    Unknown(String),
+    /// The command's argument is missing or invalid.
+    InvalidArgument(String),
+    /// The measure doesn't exist.
+    NoSuchMeasure(u16),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Empty => write!(f, "No command"),
+            CommandError::Unknown(cmd) => write!(f, "Unknown command: {}", cmd),
+            CommandError::InvalidArgument(cmd) => {
+                write!(f, "Invalid argument for {}", cmd)
+            }
+            CommandError::NoSuchMeasure(measure) => {
+                write!(f, "No measure {}", measure)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Parse and run a command on a program.
+pub fn run_command(
+    program: &mut Program,
+    cmd: &str,
+) -> Result<(), CommandError> {
+    let mut words = cmd.split_whitespace();
+    let name = words.next().ok_or(CommandError::Empty)?;
+    let arg = words.next();
+    let invalid = || CommandError::InvalidArgument(name.to_string());
+    if words.next().is_some() {
+        return Err(invalid());
+    }
+    let arg = arg.ok_or_else(invalid)?;
+
+    match name {
+        "time" => {
+            let time = arg.parse::<Fraction>().map_err(|_| invalid())?;
+            if time.num == 0 || time.den == 0 {
+                return Err(invalid());
+            }
+            program.set_time(time);
+        }
+        "key" => program.set_key(parse_key(arg).ok_or_else(invalid)?),
+        "clef" => program.set_clef(arg.parse().map_err(|_| invalid())?),
+        "goto" => {
+            let measure = arg.strip_prefix('m').unwrap_or(arg);
+            let measure = measure.parse::<u16>().map_err(|_| invalid())?;
+            if measure == 0 || !program.goto(measure - 1) {
+                return Err(CommandError::NoSuchMeasure(measure));
+            }
+        }
+        "transpose" => {
+            program.transpose(arg.parse().map_err(|_| invalid())?);
+        }
+        _ => return Err(CommandError::Unknown(name.to_string())),
+    }
+    Ok(())
+}
+
+/// Parse a key name (like "Bb") into quarter steps above C.
+fn parse_key(key: &str) -> Option<u8> {
+    let name = key.get(..1)?.parse::<PitchName>().ok()?;
+    let accidental = match key.get(1..)? {
+        "" => 0,
+        "b" => -1,
+        "#" => 1,
+        _ => return None,
+    };
+    Some(((name.semitones() + accidental).rem_euclid(12) * 2) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scof::Cursor;
+
+    #[test]
+    fn time() {
+        let mut program = Program::new();
+        run_command(&mut program, "time 3/4").unwrap();
+        let sig = program.scof.sig(&program.cursor).unwrap();
+        assert_eq!(sig.time, "3/4");
+        // Four bars of 4/4 are re-barred into six bars of 3/4.
+        assert_eq!(program.scof.movement[0].bar.len(), 6);
+    }
+
+    #[test]
+    fn goto() {
+        let mut program = Program::new();
+        run_command(&mut program, "goto m2").unwrap();
+        assert_eq!(program.cursor, Cursor::new(0, 1, 0, 0));
+        assert_eq!(
+            run_command(&mut program, "goto m99"),
+            Err(CommandError::NoSuchMeasure(99))
+        );
+        assert_eq!(program.cursor, Cursor::new(0, 1, 0, 0));
+    }
+
+    #[test]
+    fn key_clef_transpose() {
+        let mut program = Program::new();
+        run_command(&mut program, "key Bb").unwrap();
+        assert_eq!(program.scof.sig(&program.cursor).unwrap().key, 20);
+        run_command(&mut program, "clef bass").unwrap();
+        assert_eq!(program.scof.clef(&program.cursor), scof::Clef::Bass);
+        // C4 => Eb4 in Bb major
+        run_command(&mut program, "transpose +3").unwrap();
+        let note = program.scof.note(&program.cursor).unwrap();
+        assert_eq!(note.pitch[0].to_string(), "Eb4");
+    }
+
+    #[test]
+    fn errors() {
+        let mut program = Program::new();
+        assert_eq!(run_command(&mut program, " "), Err(CommandError::Empty));
+        assert_eq!(
+            run_command(&mut program, "tempo 90"),
+            Err(CommandError::Unknown("tempo".to_string()))
+        );
+        assert_eq!(
+            run_command(&mut program, "clef soprano"),
+            Err(CommandError::InvalidArgument("clef".to_string()))
+        );
+    }
+}
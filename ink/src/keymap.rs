@@ -0,0 +1,257 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Key bindings for the front-ends.
+//!
+//! Front-ends translate their own key events into a [`Modifiers`] and
+//! [`Key`], look up the bound [`Action`] and run it with `Program::act()`.
+
+use crate::Program;
+use scof::{Articulation, Fraction};
+
+/// Modifier keys held down while pressing a key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Modifiers {
+    /// No modifier key.
+    None,
+    /// Shift key.
+    Shift,
+    /// Control key.
+    Ctrl,
+    /// Alt key.
+    Alt,
+}
+
+/// A key that can be bound to an action.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Key {
+    H,
+    J,
+    K,
+    L,
+    Left,
+    Down,
+    Up,
+    Right,
+    One,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    Period,
+    A,
+    M,
+    N,
+    S,
+    T,
+}
+
+/// An action that can be bound to a key.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Move cursor back.
+    Left,
+    /// Move cursor forward.
+    Right,
+    /// Move a note up 1 step within the key.
+    UpStep,
+    /// Move a note down 1 step within the key.
+    DownStep,
+    /// Move a note up a half step.
+    UpHalfStep,
+    /// Move a note down a half step.
+    DownHalfStep,
+    /// Move a note up a quarter step.
+    UpQuarterStep,
+    /// Move a note down a quarter step.
+    DownQuarterStep,
+    /// Swap the note at the cursor with the previous marking.
+    MoveSelectionLeft,
+    /// Swap the note at the cursor with the next marking.
+    MoveSelectionRight,
+    /// Halve the duration of the note at the cursor.
+    HalveDuration,
+    /// Double the duration of the note at the cursor.
+    DoubleDuration,
+    /// Set duration of a note.
+    SetDur(Fraction),
+    /// Set duration of note to dotted.
+    Dotted,
+    /// Add or remove an articulation on the note at the cursor.
+    ToggleArticulation(Articulation),
+    /// Change the heads of the note at the cursor to the next style.
+    CycleNotehead,
+}
+
+/// Get the default key bindings.
+pub fn default_keymap() -> Vec<(Modifiers, Key, Action)> {
+    use Action::*;
+    use Modifiers::{Alt, Ctrl, Shift};
+
+    let mut keymap = vec![];
+    // Vim-style keys and arrow keys do the same thing.
+    for &(vim, arrow, ctrl, alt, none) in &[
+        (Key::H, Key::Left, HalveDuration, MoveSelectionLeft, Left),
+        (Key::J, Key::Down, DownHalfStep, DownQuarterStep, DownStep),
+        (Key::K, Key::Up, UpHalfStep, UpQuarterStep, UpStep),
+        (
+            Key::L,
+            Key::Right,
+            DoubleDuration,
+            MoveSelectionRight,
+            Right,
+        ),
+    ] {
+        for &key in &[vim, arrow] {
+            keymap.push((Ctrl, key, ctrl));
+            keymap.push((Alt, key, alt));
+            keymap.push((Modifiers::None, key, none));
+        }
+    }
+    for &(key, num, den) in &[
+        (Key::One, 1, 64),
+        (Key::Two, 1, 32),
+        (Key::Three, 1, 16),
+        (Key::Four, 1, 8),
+        (Key::Five, 1, 4),
+        (Key::Six, 1, 2),
+        (Key::Seven, 1, 1),
+        (Key::Eight, 2, 1),
+        (Key::Nine, 4, 1),
+    ] {
+        keymap.push((Modifiers::None, key, SetDur(Fraction::new(num, den))));
+    }
+    keymap.push((Modifiers::None, Key::Period, Dotted));
+    keymap.push((
+        Shift,
+        Key::S,
+        ToggleArticulation(Articulation::Staccatissimo),
+    ));
+    for &(key, articulation) in &[
+        (Key::S, Articulation::Staccato),
+        (Key::T, Articulation::Tenuto),
+        (Key::A, Articulation::Accent),
+        (Key::M, Articulation::Marcato),
+    ] {
+        keymap.push((Modifiers::None, key, ToggleArticulation(articulation)));
+    }
+    keymap.push((Modifiers::None, Key::N, CycleNotehead));
+    keymap
+}
+
+/// Find the action bound to a key.
+pub fn lookup(
+    keymap: &[(Modifiers, Key, Action)],
+    mods: Modifiers,
+    key: Key,
+) -> Option<Action> {
+    keymap
+        .iter()
+        .find(|(m, k, _)| *m == mods && *k == key)
+        .map(|(_, _, action)| *action)
+}
+
+impl Program {
+    /// Run an action, usually one looked up from a key binding.  Returns
+    /// `false` if it couldn't be done (stepping past the highest or lowest
+    /// pitch), leaving the score unchanged.
+    pub fn act(&mut self, action: Action) -> bool {
+        match action {
+            Action::Left => self.left(),
+            Action::Right => self.right(),
+            Action::UpStep => return self.up_step(),
+            Action::DownStep => return self.down_step(),
+            Action::UpHalfStep => return self.up_half_step(),
+            Action::DownHalfStep => return self.down_half_step(),
+            Action::UpQuarterStep => return self.up_quarter_step(),
+            Action::DownQuarterStep => return self.down_quarter_step(),
+            Action::MoveSelectionLeft => self.move_selection_left(),
+            Action::MoveSelectionRight => self.move_selection_right(),
+            Action::HalveDuration => self.halve_duration(),
+            Action::DoubleDuration => self.double_duration(),
+            Action::SetDur(dur) => self.set_dur(dur),
+            Action::Dotted => self.dotted(),
+            Action::ToggleArticulation(articulation) => {
+                self.toggle_articulation(articulation)
+            }
+            Action::CycleNotehead => self.cycle_notehead(),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings() {
+        let keymap = default_keymap();
+        let actions: Vec<Action> = keymap.iter().map(|b| b.2).collect();
+        for &(num, den) in &[
+            (1, 64),
+            (1, 32),
+            (1, 16),
+            (1, 8),
+            (1, 4),
+            (1, 2),
+            (1, 1),
+            (2, 1),
+            (4, 1),
+        ] {
+            assert!(actions.contains(&Action::SetDur(Fraction::new(num, den))));
+        }
+        for action in &[
+            Action::UpStep,
+            Action::DownStep,
+            Action::UpHalfStep,
+            Action::DownHalfStep,
+            Action::UpQuarterStep,
+            Action::DownQuarterStep,
+        ] {
+            assert!(actions.contains(action));
+        }
+        assert_eq!(
+            lookup(&keymap, Modifiers::Ctrl, Key::Up),
+            Some(Action::UpHalfStep)
+        );
+        assert_eq!(
+            lookup(&keymap, Modifiers::Shift, Key::S),
+            Some(Action::ToggleArticulation(Articulation::Staccatissimo))
+        );
+        assert_eq!(lookup(&keymap, Modifiers::Shift, Key::H), None);
+    }
+
+    #[test]
+    fn act() {
+        let mut program = Program::new();
+        let keymap = default_keymap();
+        let eighth = lookup(&keymap, Modifiers::None, Key::Four).unwrap();
+        assert!(program.act(eighth));
+        assert_eq!(program.default_duration, Fraction::new(1, 8));
+        // Stepping past the highest pitch can't be done.
+        let curs = program.cursor.clone();
+        program.scof.set_pitch(&curs, 0, "B9".parse().unwrap());
+        assert!(!program.act(Action::UpStep));
+        assert!(program.act(Action::DownStep));
+    }
+}
@@ -16,7 +16,12 @@
 //     You should have received a copy of the GNU General Public License
 //     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use scof::{Cursor, Fraction, Marking, Note, Pitch, Scof};
+pub mod command;
+pub mod keymap;
+
+use scof::{
+    Articulation, Clef, Cursor, Fraction, Marking, Note, Pitch, Scof, ScofError,
+};
 
 /// This is the entire program context.
 pub struct Program {
@@ -24,6 +29,8 @@ pub struct Program {
     pub scof: Scof,
     /// Current cursor
     pub cursor: Cursor,
+    /// Duration of new notes (last duration used)
+    pub default_duration: Fraction,
 }
 
 impl Default for Program {
@@ -31,6 +38,7 @@ impl Default for Program {
         Self {
             scof: Scof::default(),
             cursor: Cursor::default(),
+            default_duration: Fraction::new(1, 1),
         }
     }
 }
@@ -41,6 +49,21 @@ impl Program {
         Self::default()
     }
 
+    /// Save the score and cursor as a blob, for recovering after a crash.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.scof.save_with_cursor(&self.cursor)
+    }
+
+    /// Load a program from a blob created by `save_state`.
+    pub fn load_state(bytes: &[u8]) -> Result<Program, ScofError> {
+        let (scof, cursor) = Scof::open_with_cursor(bytes)?;
+        Ok(Program {
+            scof,
+            cursor,
+            ..Default::default()
+        })
+    }
+
     /// Move cursor back.
     pub fn left(&mut self) {
         self.cursor.left(&self.scof);
@@ -55,8 +78,110 @@ impl Program {
         }
     }
 
-    /// Step up or down within the key.
-    fn move_step(&mut self, up: bool, gran: u8) {
+    /// Move the cursor to the start of a measure (0 is the first measure).
+    /// Returns false if the measure doesn't exist.
+    pub fn goto(&mut self, measure: u16) -> bool {
+        let cursor = self.cursor.bar(measure);
+        if self.scof.marking_is_empty(&cursor) {
+            return false;
+        }
+        self.cursor = cursor;
+        true
+    }
+
+    /// Delete the measure at the cursor, moving the cursor to the end of the
+    /// previous measure.
+    pub fn delete_measure(&mut self) {
+        self.scof.delete_measure(&self.cursor);
+        self.cursor = self.cursor.first_marking();
+        self.cursor.left(&self.scof);
+        self.cursor.clamp(&self.scof);
+    }
+
+    /// Change the time signature starting at the measure at the cursor, and
+    /// re-bar the following measures.
+    pub fn set_time(&mut self, time: Fraction) {
+        self.scof.set_time(&self.cursor, time);
+        let movement = self.cursor.movement().into();
+        self.scof.rebar(movement, self.cursor.measure());
+        self.cursor.clamp(&self.scof);
+    }
+
+    /// Change the key signature (quarter steps above C) starting at the
+    /// measure at the cursor.
+    pub fn set_key(&mut self, key: u8) {
+        self.scof.set_key(&self.cursor, key);
+    }
+
+    /// Change the clef of the current channel starting at the measure at the
+    /// cursor.
+    pub fn set_clef(&mut self, clef: Clef) {
+        self.scof.set_clef(&self.cursor, clef);
+    }
+
+    /// Transpose the note at the cursor by a number of half steps, respelling
+    /// it for the key.  Quarter tones are rounded down.
+    pub fn transpose(&mut self, half_steps: i32) {
+        let pitches = match self.scof.marking(&self.cursor) {
+            Some(Marking::Note(note)) => note.pitch.clone(),
+            _ => return,
+        };
+        let key = self.scof.sig(&self.cursor).map_or(0, |sig| sig.key);
+        for (i, pitch) in pitches.into_iter().enumerate() {
+            let pitch = scof::spell_pitch(pitch.midi() + half_steps, key, &[]);
+            self.scof.set_pitch(&self.cursor, i as u16, pitch);
+        }
+    }
+
+    /// Add an articulation to the note at the cursor, or remove it if the
+    /// note already has it.
+    pub fn toggle_articulation(&mut self, articulation: Articulation) {
+        let note = match self.scof.marking_mut(&self.cursor) {
+            Some(Marking::Note(note)) if !note.pitch.is_empty() => note,
+            _ => return,
+        };
+        // Articulations are kept sorted.
+        match note.articulation.binary_search(&articulation) {
+            Ok(i) => {
+                note.articulation.remove(i);
+            }
+            Err(i) => note.articulation.insert(i, articulation),
+        }
+    }
+
+    /// Change the heads of the note at the cursor to the next notehead style.
+    pub fn cycle_notehead(&mut self) {
+        match self.scof.marking_mut(&self.cursor) {
+            Some(Marking::Note(note)) if !note.pitch.is_empty() => {
+                note.head = note.head.next();
+            }
+            _ => {}
+        }
+    }
+
+    /// Move the note at the cursor earlier in time, swapping it with the
+    /// previous marking.
+    pub fn move_selection_left(&mut self) {
+        let mut prev = self.cursor.clone();
+        prev.left(&self.scof);
+        if prev != self.cursor && self.scof.swap_markings(&self.cursor, &prev) {
+            self.cursor = prev;
+        }
+    }
+
+    /// Move the note at the cursor later in time, swapping it with the next
+    /// marking.
+    pub fn move_selection_right(&mut self) {
+        let mut next = self.cursor.clone();
+        next.right(&self.scof);
+        if self.scof.swap_markings(&self.cursor, &next) {
+            self.cursor = next;
+        }
+    }
+
+    /// Step up or down within the key.  Returns `false` if the note is
+    /// already at the highest (or lowest) pitch, or isn't a note.
+    fn move_step(&mut self, up: bool, gran: u8) -> bool {
         let step_up_fn = match gran {
             0 => Note::step_up,
             1 => Note::half_step_up,
@@ -78,87 +203,69 @@ impl Program {
             scof::PitchOctave::Octave4,
         );
 
-        if let Some(mark) = self.scof.marking(&self.cursor).cloned() {
-            match mark {
-                Marking::Dynamic(_) => { /*Do nothing*/ }
-                Marking::GraceInto(note) => self.scof.set_pitch(
-                    &self.cursor,
-                    0,
-                    if up {
-                        step_up_fn(&note, 0, create)
-                    } else {
-                        step_down_fn(&note, 0, create)
-                    }
-                    .pitch[0],
-                ),
-                Marking::GraceOutOf(note) => self.scof.set_pitch(
-                    &self.cursor,
-                    0,
-                    if up {
-                        step_up_fn(&note, 0, create)
-                    } else {
-                        step_down_fn(&note, 0, create)
-                    }
-                    .pitch[0],
-                ),
-                Marking::Note(note) => self.scof.set_pitch(
-                    &self.cursor,
-                    0,
-                    if up {
-                        step_up_fn(&note, 0, create)
-                    } else {
-                        step_down_fn(&note, 0, create)
-                    }
-                    .pitch[0],
-                ),
-                Marking::Breath => { /*Do nothing*/ }
-                Marking::CaesuraShort => { /*Do nothing*/ }
-                Marking::CaesuraLong => { /*Do nothing*/ }
-                Marking::Cresc => { /*Do nothing*/ }
-                Marking::Dim => { /*Do nothing*/ }
-                Marking::Pizz => { /*Do nothing*/ }
-                Marking::Arco => { /*Do nothing*/ }
-                Marking::Mute => { /*Do nothing*/ }
-                Marking::Open => { /*Do nothing*/ }
-                Marking::Repeat => { /*Do nothing*/ }
+        let note = match self.scof.marking(&self.cursor).cloned() {
+            Some(Marking::GraceInto(note)) => note,
+            Some(Marking::GraceOutOf(note)) => note,
+            Some(Marking::Note(note)) => note,
+            Some(_) => return false,
+            None => {
+                self.scof
+                    .set_whole_pitch(&self.cursor, self.default_duration);
+                return true;
             }
+        };
+        let note = if up {
+            step_up_fn(&note, 0, create)
         } else {
-            self.scof.set_whole_pitch(&self.cursor);
+            step_down_fn(&note, 0, create)
+        };
+        if let Some(note) = note {
+            self.scof.set_pitch(&self.cursor, 0, note.pitch[0]);
+            true
+        } else {
+            false
         }
     }
 
-    /// Move a note down 1 step within the key.
-    pub fn down_step(&mut self) {
-        self.move_step(false, 0);
+    /// Move a note down 1 step within the key.  Returns `false` if it's
+    /// already at the lowest pitch.
+    pub fn down_step(&mut self) -> bool {
+        self.move_step(false, 0)
     }
 
-    /// Move a note up 1 step within the key.
-    pub fn up_step(&mut self) {
-        self.move_step(true, 0);
+    /// Move a note up 1 step within the key.  Returns `false` if it's
+    /// already at the highest pitch.
+    pub fn up_step(&mut self) -> bool {
+        self.move_step(true, 0)
     }
 
-    /// Move a note down 1 step within the key.
-    pub fn down_half_step(&mut self) {
-        self.move_step(false, 1);
+    /// Move a note down a half step.  Returns `false` if it's already at the
+    /// lowest pitch.
+    pub fn down_half_step(&mut self) -> bool {
+        self.move_step(false, 1)
     }
 
-    /// Move a note up 1 step within the key.
-    pub fn up_half_step(&mut self) {
-        self.move_step(true, 1);
+    /// Move a note up a half step.  Returns `false` if it's already at the
+    /// highest pitch.
+    pub fn up_half_step(&mut self) -> bool {
+        self.move_step(true, 1)
     }
 
-    /// Move a note down 1 step within the key.
-    pub fn down_quarter_step(&mut self) {
-        self.move_step(false, 2);
+    /// Move a note down a quarter step.  Returns `false` if it's already at
+    /// the lowest pitch.
+    pub fn down_quarter_step(&mut self) -> bool {
+        self.move_step(false, 2)
     }
 
-    /// Move a note up 1 step within the key.
-    pub fn up_quarter_step(&mut self) {
-        self.move_step(true, 2);
+    /// Move a note up a quarter step.  Returns `false` if it's already at
+    /// the highest pitch.
+    pub fn up_quarter_step(&mut self) -> bool {
+        self.move_step(true, 2)
     }
 
     /// Set duration of a note.
     pub fn set_dur(&mut self, dur: Fraction) {
+        self.default_duration = dur;
         if let Some(mark) = self.scof.marking(&self.cursor) {
             match mark {
                 Marking::Dynamic(_) => { /*Do nothing*/ }
@@ -181,19 +288,247 @@ impl Program {
                 Marking::Mute => { /*Do nothing*/ }
                 Marking::Open => { /*Do nothing*/ }
                 Marking::Repeat => { /*Do nothing*/ }
+                Marking::MeasureRepeat(_) => { /*Do nothing*/ }
+                Marking::Spacer(_) => { /*Do nothing*/ }
+                Marking::BeatRepeat(_) => { /*Do nothing*/ }
+                Marking::OctaveShift(_) => { /*Do nothing*/ }
             }
         } else {
             self.scof.set_whole_duration(&self.cursor, dur);
         }
     }
 
+    /// Halve the duration of the note at the cursor.
+    pub fn halve_duration(&mut self) {
+        self.scale_duration(Fraction::new(1, 2));
+    }
+
+    /// Double the duration of the note at the cursor.
+    pub fn double_duration(&mut self) {
+        self.scale_duration(Fraction::new(2, 1));
+    }
+
+    /// Scale the duration of the note at the cursor, staying within the range
+    /// of a 128th note to a longa.
+    fn scale_duration(&mut self, scale: Fraction) {
+        let dur = match self.scof.marking(&self.cursor) {
+            Some(Marking::Note(note)) => note.duration * scale,
+            _ => return,
+        };
+        if dur < Fraction::new(1, 128) || dur > 4 {
+            return;
+        }
+        self.set_dur(dur);
+    }
+
     /// Set duration of a note to tuplet.
     pub fn tuplet(&mut self) {
         // FIXME
     }
 
-    /// Set duration of note to dotted.
+    /// Set duration of note to dotted, or back to undotted if it already is.
     pub fn dotted(&mut self) {
-        // FIXME
+        let dur = match self.scof.marking(&self.cursor) {
+            Some(Marking::Note(note)) => note.duration.simplify(),
+            _ => return,
+        };
+        let scale = match dur.num {
+            // Tuplets aren't dotted.
+            _ if !dur.den.is_power_of_two() => return,
+            3 => Fraction::new(2, 3),
+            num if num.is_power_of_two() => Fraction::new(3, 2),
+            _ => return,
+        };
+        // A dotted 128th note isn't a whole number of 128th notes.
+        if 128 % (dur * scale).den != 0 {
+            return;
+        }
+        self.scale_duration(scale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scof::NoteheadStyle;
+
+    /// Get the markings of the measure at the cursor as a string.
+    fn measure(program: &Program) -> String {
+        let mut curs = program.cursor.first_marking();
+        let mut markings = vec![];
+        while let Some(Marking::Note(note)) = program.scof.marking(&curs) {
+            markings.push(note.to_string());
+            curs.right_unchecked();
+        }
+        markings.join(" ")
+    }
+
+    #[test]
+    fn save_load_state() {
+        let mut program = Program::new();
+        program.scof.title = "Recovered".to_string();
+        program.cursor = Cursor::new(0, 1, 1, 2);
+        program.up_step();
+        let loaded = Program::load_state(&program.save_state()).unwrap();
+        assert_eq!(loaded.cursor, program.cursor);
+        assert_eq!(loaded.scof.title, program.scof.title);
+        assert_eq!(loaded.scof.meta, program.scof.meta);
+        assert_eq!(loaded.scof.movement, program.scof.movement);
+        assert_eq!(measure(&loaded), measure(&program));
+
+        // A plain score has no cursor to restore.
+        let bytes = program.scof.save();
+        assert_eq!(
+            Program::load_state(&bytes).err(),
+            Some(ScofError::MissingFile("Cursor.muon".to_string()))
+        );
+    }
+
+    #[test]
+    fn default_duration() {
+        let mut program = Program::new();
+        program.cursor = Cursor::new(0, 4, 0, 0);
+        program.scof.new_measure();
+        assert_eq!(measure(&program), "");
+
+        program.default_duration = Fraction::new(1, 8);
+        program.up_step();
+        assert_eq!(measure(&program), "1/8C4 7/8R");
+    }
+
+    #[test]
+    fn halve_double_duration() {
+        let mut program = Program::new();
+        let before = measure(&program);
+        assert!(before.starts_with("1/4C4 1/16D4"));
+
+        program.halve_duration();
+        assert!(measure(&program).starts_with("1/8C4 1/8R 1/16D4"));
+        assert_eq!(program.default_duration, Fraction::new(1, 8));
+
+        program.double_duration();
+        assert_eq!(measure(&program), before);
+    }
+
+    #[test]
+    fn step_limits() {
+        let mut program = Program::new();
+        let curs = program.cursor.clone();
+        program.scof.set_pitch(&curs, 0, "B9".parse().unwrap());
+        assert!(!program.up_step());
+        assert!(measure(&program).starts_with("1/4B9 "));
+        assert!(program.down_step());
+        assert!(measure(&program).starts_with("1/4A9 "));
+
+        program.scof.set_pitch(&curs, 0, "C-".parse().unwrap());
+        assert!(!program.down_step());
+        assert!(!program.down_half_step());
+        assert!(measure(&program).starts_with("1/4C- "));
+    }
+
+    #[test]
+    fn dotted() {
+        let mut program = Program::new();
+        let duration = |program: &Program| {
+            program.scof.note(&program.cursor).unwrap().duration
+        };
+        assert_eq!(duration(&program), Fraction::new(1, 4));
+
+        program.dotted();
+        assert_eq!(duration(&program), Fraction::new(3, 8));
+        program.dotted();
+        assert_eq!(duration(&program), Fraction::new(1, 4));
+
+        // 128th notes can't be dotted.
+        program.set_dur(Fraction::new(1, 128));
+        program.dotted();
+        assert_eq!(duration(&program), Fraction::new(1, 128));
+        program.set_dur(Fraction::new(1, 64));
+        program.dotted();
+        assert_eq!(duration(&program), Fraction::new(3, 128));
+    }
+
+    #[test]
+    fn move_selection() {
+        let mut program = Program::new();
+        program.move_selection_right();
+        assert!(measure(&program).starts_with("1/16D4 1/4C4 1/16D4"));
+        assert_eq!(program.cursor, Cursor::new(0, 0, 0, 1));
+
+        program.move_selection_left();
+        assert!(measure(&program).starts_with("1/4C4 1/16D4"));
+        assert_eq!(program.cursor, Cursor::new(0, 0, 0, 0));
+
+        // Can't swap with notes of a different duration in the next bar.
+        program.cursor = Cursor::new(0, 0, 0, 5);
+        program.move_selection_right();
+        assert_eq!(program.cursor, Cursor::new(0, 0, 0, 5));
+
+        // Swap across bars with a note of the same duration.
+        program.cursor = Cursor::new(0, 0, 1, 4);
+        program.move_selection_right();
+        assert_eq!(program.cursor, Cursor::new(0, 1, 1, 0));
+        assert_eq!(measure(&program), "1/2A3 1/2B3");
+    }
+
+    #[test]
+    fn delete_measure() {
+        let mut program = Program::new();
+        program.cursor = Cursor::new(0, 3, 0, 2);
+        program.delete_measure();
+        assert_eq!(program.scof.movement[0].bar.len(), 3);
+        assert_eq!(program.cursor, Cursor::new(0, 2, 0, 2));
+        assert_eq!(measure(&program), "1/8B3 1/8B3 3/4R");
+
+        // The only measure can't be deleted.
+        program.scof.movement[0].bar.truncate(1);
+        program.cursor = Cursor::new(0, 0, 0, 1);
+        program.delete_measure();
+        assert_eq!(program.scof.movement[0].bar.len(), 1);
+        assert_eq!(program.cursor, Cursor::new(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn toggle_articulation() {
+        let mut program = Program::new();
+        program.toggle_articulation(Articulation::Staccato);
+        assert!(measure(&program).starts_with("1/4C4. 1/16D4"));
+        program.toggle_articulation(Articulation::Accent);
+        program.toggle_articulation(Articulation::Staccatissimo);
+        let note = program.scof.note(&program.cursor).unwrap().to_string();
+        assert_eq!(note.parse::<Note>().unwrap().to_string(), note);
+        program.toggle_articulation(Articulation::Accent);
+        program.toggle_articulation(Articulation::Staccatissimo);
+        program.toggle_articulation(Articulation::Staccato);
+        assert!(measure(&program).starts_with("1/4C4 1/16D4"));
+
+        // Rests don't have articulations.
+        program.cursor = Cursor::new(0, 1, 0, 8);
+        program.toggle_articulation(Articulation::Staccato);
+        assert!(measure(&program).contains(" 1/4R "));
+    }
+
+    #[test]
+    fn cycle_notehead() {
+        let mut program = Program::new();
+        program.cycle_notehead();
+        let note = program.scof.note(&program.cursor).unwrap();
+        assert_eq!(note.head, NoteheadStyle::X);
+        assert!(measure(&program).starts_with("1/4C4x 1/16D4"));
+        for _ in 0..4 {
+            program.cycle_notehead();
+        }
+        assert!(measure(&program).starts_with("1/4C4 1/16D4"));
+    }
+
+    #[test]
+    fn duration_range() {
+        let mut program = Program::new();
+        program.set_dur(Fraction::new(1, 128));
+        program.halve_duration();
+        assert_eq!(
+            program.scof.note(&program.cursor).unwrap().duration,
+            Fraction::new(1, 128)
+        );
     }
 }
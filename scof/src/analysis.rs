@@ -0,0 +1,179 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Voice leading analysis (a teaching aid for composition).
+
+use crate::{
+    Cursor, Fraction, Interval, IntervalQuality, Marking, Pitch, Scof,
+};
+
+/// Time of an onset: measure, and offset within the measure.
+type Time = (u16, Fraction);
+
+/// What starts sounding in a voice: the cursor of the note and its pitch
+/// (at concert pitch), or `None` for a rest.
+type Sounding = Option<(Cursor, Pitch)>;
+
+/// Find consecutive perfect fifths or octaves (or unisons, and compound
+/// fifths) between each pair of voices (channels) of a movement, where both
+/// voices move in the same direction.  Returns the cursors of the note of
+/// each voice that moves into the second interval, and the interval.
+///
+/// FIXME: Only the first pitch of a chord is a voice, and beat and measure
+/// repeats are treated as rests.
+pub fn parallel_motion(
+    scof: &Scof,
+    movement: u16,
+) -> Vec<(Cursor, Cursor, Interval)> {
+    let chans = scof.channel_count(usize::from(movement)) as u16;
+    let voices: Vec<Vec<(Time, Sounding)>> = (0..chans)
+        .map(|chan| onsets(scof, movement, chan))
+        .collect();
+    let mut found = vec![];
+    for (i, upper) in voices.iter().enumerate() {
+        for lower in &voices[i + 1..] {
+            let mut times: Vec<Time> =
+                upper.iter().chain(lower).map(|onset| onset.0).collect();
+            times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            times.dedup();
+            let mut last = None;
+            for time in times {
+                let now = match (sounding(upper, time), sounding(lower, time)) {
+                    (Some(a), Some(b)) => Some((a, b)),
+                    _ => None,
+                };
+                if let (Some(before), Some(now)) = (&last, &now) {
+                    if let Some(interval) = parallel(before, now) {
+                        let ((a, _), (b, _)) = now;
+                        found.push((a.clone(), b.clone(), interval));
+                    }
+                }
+                last = now;
+            }
+        }
+    }
+    found
+}
+
+/// Check if two voices move in parallel from one perfect fifth or octave to
+/// another.
+fn parallel(
+    before: &((Cursor, Pitch), (Cursor, Pitch)),
+    now: &((Cursor, Pitch), (Cursor, Pitch)),
+) -> Option<Interval> {
+    let moved_a = (now.0).1.midi() - (before.0).1.midi();
+    let moved_b = (now.1).1.midi() - (before.1).1.midi();
+    if moved_a == 0 || moved_b == 0 || (moved_a > 0) != (moved_b > 0) {
+        return None;
+    }
+    // Steps of the simple interval, for perfect fifths and octaves.
+    let simple = |interval: Interval| match interval.quality {
+        IntervalQuality::Perfect => Some((interval.number - 1) % 7)
+            .filter(|steps| *steps == 0 || *steps == 4),
+        _ => None,
+    };
+    let interval = Interval::between(&(now.0).1, &(now.1).1);
+    let steps = simple(interval)?;
+    if simple(Interval::between(&(before.0).1, &(before.1).1)) == Some(steps) {
+        Some(interval)
+    } else {
+        None
+    }
+}
+
+/// Get what's sounding in a voice at a time, from its onsets.
+fn sounding(onsets: &[(Time, Sounding)], time: Time) -> Sounding {
+    onsets
+        .iter()
+        .take_while(|onset| onset.0 <= time)
+        .last()
+        .and_then(|onset| onset.1.clone())
+}
+
+/// Get the onsets of the notes and rests of a channel in a movement.
+fn onsets(scof: &Scof, movement: u16, chan: u16) -> Vec<(Time, Sounding)> {
+    let bars = scof
+        .movement
+        .get(usize::from(movement))
+        .map_or(0, |m| m.bar.len() as u16);
+    let mut onsets = vec![];
+    for bar in 0..bars {
+        let mut curs = Cursor::new(movement, bar, chan, 0);
+        let mut offset = Fraction::new(0, 1);
+        // A measure without markings is a whole measure rest.
+        onsets.push(((bar, offset), None));
+        while let Some(marking) = scof.marking(&curs) {
+            if let Some(duration) = marking.duration() {
+                let sounding = match marking {
+                    Marking::Note(_) => scof
+                        .concert_pitch(&curs)
+                        .map(|pitch| (curs.clone(), pitch)),
+                    _ => None,
+                };
+                onsets.push(((bar, offset), sounding));
+                offset += duration;
+            }
+            curs.right_unchecked();
+        }
+    }
+    onsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use IntervalQuality::Perfect;
+
+    #[test]
+    fn parallel_fifths() {
+        let mut scof = Scof::new(2, Fraction::new(4, 4), 0);
+        let bar = &mut scof.movement[0].bar[0];
+        bar.chan[0] = "1/4C5 1/4D5 1/4E5 1/4C5".parse().unwrap();
+        bar.chan[1] = "1/4F4 1/4G4 1/4C4 1/4F4".parse().unwrap();
+        // C5/F4 to D5/G4 are parallel fifths, and E5/C4 to C5/F4 are fifths
+        // in contrary motion.
+        assert_eq!(
+            parallel_motion(&scof, 0),
+            [(
+                Cursor::new(0, 0, 0, 1),
+                Cursor::new(0, 0, 1, 1),
+                Interval::new(5, Perfect)
+            )]
+        );
+
+        // Octaves, after the upper voice holds a note over a lower note.
+        let bar = &mut scof.movement[0].bar[0];
+        bar.chan[0] = "1/2C5 1/4D5 1/4E5".parse().unwrap();
+        bar.chan[1] = "1/4C4 1/4C4 1/4D4 1/4E4".parse().unwrap();
+        assert_eq!(
+            parallel_motion(&scof, 0),
+            [
+                (
+                    Cursor::new(0, 0, 0, 1),
+                    Cursor::new(0, 0, 1, 2),
+                    Interval::new(8, Perfect)
+                ),
+                (
+                    Cursor::new(0, 0, 0, 2),
+                    Cursor::new(0, 0, 1, 3),
+                    Interval::new(8, Perfect)
+                ),
+            ]
+        );
+    }
+}
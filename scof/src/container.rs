@@ -0,0 +1,318 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reading and writing the `.scof` zip container.
+
+use crate::Cursor as ScofCursor;
+use crate::{Instrument, Meta, Movement, Mvmt, Scof};
+use muon_rs as muon;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Cursor, Read, Seek, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Maximum number of characters in a title.
+const TITLE_LEN: usize = 64;
+
+/// An error opening a `.scof` file.
+#[derive(Debug, PartialEq)]
+pub enum ScofError {
+    /// Not a zip file.
+    NotZip,
+    /// A required file is missing from the container.
+    MissingFile(String),
+    /// A file in the container couldn't be parsed.
+    InvalidFile(String),
+}
+
+impl fmt::Display for ScofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScofError::NotZip => write!(f, "Not a zip file"),
+            ScofError::MissingFile(name) => write!(f, "Missing file {}", name),
+            ScofError::InvalidFile(name) => write!(f, "Invalid file {}", name),
+        }
+    }
+}
+
+impl std::error::Error for ScofError {}
+
+/// Instruments of the score, as saved in the container.
+#[derive(Serialize, Deserialize)]
+struct SoundFont {
+    instrument: Vec<Instrument>,
+}
+
+/// Image format of a cover.
+fn cover_extension(cover: &[u8]) -> Option<&'static str> {
+    if cover.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if cover.starts_with(b"\xFF\xD8\xFF") {
+        Some("jpg")
+    } else if cover.starts_with(b"<svg") || cover.starts_with(b"<?xml") {
+        Some("svg")
+    } else {
+        None
+    }
+}
+
+/// Get the file name of a movement.
+fn movement_file(name: &str) -> String {
+    format!("Movement/{}.muon", name.replace('/', "\\"))
+}
+
+/// Writer for files in the container.
+struct Writer(ZipWriter<Cursor<Vec<u8>>>);
+
+impl Writer {
+    /// Write a file.
+    fn file(&mut self, name: &str, bytes: &[u8]) {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored);
+        // Writing to memory can't fail.
+        self.0.start_file(name, options).unwrap();
+        self.0.write_all(bytes).unwrap();
+    }
+
+    /// Write a muon file.
+    fn muon<T: serde::Serialize>(&mut self, name: &str, value: &T) {
+        let text = muon::to_string(value).unwrap_or_default();
+        self.file(name, text.as_bytes());
+    }
+}
+
+/// Reader for files in the container.
+struct Reader<R: Read + Seek>(ZipArchive<R>);
+
+impl<R: Read + Seek> Reader<R> {
+    /// Create a reader for a zip file.
+    fn new(reader: R) -> Result<Self, ScofError> {
+        let zip = ZipArchive::new(reader).map_err(|_| ScofError::NotZip)?;
+        Ok(Reader(zip))
+    }
+
+    /// Read a file, if it exists.
+    fn file(&mut self, name: &str) -> Option<Result<Vec<u8>, ScofError>> {
+        let mut file = self.0.by_name(name).ok()?;
+        let mut bytes = vec![];
+        Some(
+            file.read_to_end(&mut bytes)
+                .map(|_| bytes)
+                .map_err(|_| ScofError::InvalidFile(name.to_string())),
+        )
+    }
+
+    /// Read a required muon file.
+    fn muon<T: DeserializeOwned>(
+        &mut self,
+        name: &str,
+    ) -> Result<T, ScofError> {
+        let bytes = self
+            .file(name)
+            .ok_or_else(|| ScofError::MissingFile(name.to_string()))??;
+        std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|text| muon::from_str(text).ok())
+            .ok_or_else(|| ScofError::InvalidFile(name.to_string()))
+    }
+}
+
+impl Scof {
+    /// Save the score as the bytes of a `.scof` zip file.
+    pub fn save(&self) -> Vec<u8> {
+        self.writer().0.finish().unwrap().into_inner()
+    }
+
+    /// Save the score along with an editing cursor, for restoring a session.
+    pub fn save_with_cursor(&self, cursor: &ScofCursor) -> Vec<u8> {
+        let mut zip = self.writer();
+        zip.muon("Cursor.muon", cursor);
+        zip.0.finish().unwrap().into_inner()
+    }
+
+    /// Open a score and editing cursor saved with `save_with_cursor`.
+    pub fn open_with_cursor(
+        bytes: &[u8],
+    ) -> Result<(Scof, ScofCursor), ScofError> {
+        let mut zip = Reader::new(Cursor::new(bytes))?;
+        let scof = Scof::read(&mut zip)?;
+        let cursor = zip.muon("Cursor.muon")?;
+        Ok((scof, cursor))
+    }
+
+    /// Write the files of the score into a new container.
+    fn writer(&self) -> Writer {
+        let mut zip = Writer(ZipWriter::new(Cursor::new(vec![])));
+        let title: String = self.title.chars().take(TITLE_LEN).collect();
+        zip.0.set_comment(title);
+        if let Some(cover) = &self.cover {
+            if let Some(ext) = cover_extension(cover) {
+                zip.file(&format!("cover.{}", ext), cover);
+            }
+        }
+        zip.muon("Meta.muon", &self.meta);
+        zip.muon("Style.muon", &self.style);
+        zip.muon("Synth.muon", &self.synth);
+        let instrument = self.soundfont.clone();
+        zip.muon("SoundFont.muon", &SoundFont { instrument });
+        for (name, movement) in self.meta.movement.iter().zip(&self.movement) {
+            zip.muon(&movement_file(name), &Mvmt::from(movement));
+        }
+        zip
+    }
+
+    /// Open a score from the bytes of a `.scof` zip file.
+    pub fn open(bytes: &[u8]) -> Result<Scof, ScofError> {
+        Scof::from_zip(Cursor::new(bytes))
+    }
+
+    /// Open a score from a `.scof` zip file, such as a `File`.
+    pub fn from_zip<R: Read + Seek>(reader: R) -> Result<Scof, ScofError> {
+        Scof::read(&mut Reader::new(reader)?)
+    }
+
+    /// Read the files of the score from a container.
+    fn read<R: Read + Seek>(zip: &mut Reader<R>) -> Result<Scof, ScofError> {
+        let title = String::from_utf8_lossy(zip.0.comment())
+            .chars()
+            .take(TITLE_LEN)
+            .collect();
+        let mut cover = None;
+        for ext in &["png", "jpg", "svg"] {
+            if let Some(bytes) = zip.file(&format!("cover.{}", ext)) {
+                cover = Some(bytes?);
+                break;
+            }
+        }
+        let meta: Meta = zip.muon("Meta.muon")?;
+        let style = zip.muon("Style.muon")?;
+        let synth = zip.muon("Synth.muon")?;
+        let soundfont = match zip.muon::<SoundFont>("SoundFont.muon") {
+            Ok(soundfont) => soundfont.instrument,
+            // Saved before instruments were.
+            Err(ScofError::MissingFile(_)) => vec![Instrument::default()],
+            Err(e) => return Err(e),
+        };
+        let mut movement = vec![];
+        for name in &meta.movement {
+            let mvmt: Mvmt = zip.muon(&movement_file(name))?;
+            movement.push(Movement::from(mvmt));
+        }
+        let cache = vec![vec![]; movement.len()];
+
+        Ok(Scof {
+            title,
+            cover,
+            meta,
+            style,
+            synth,
+            soundfont,
+            movement,
+            cache,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1x1 PNG image.
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\
+        \x08\x06\0\0\0\x1f\x15\xc4\x89\0\0\0\rIDATx\x9cc\xf8\x0f\0\0\x01\x01\
+        \0\x05\x18\xd8N\0\0\0\0IEND\xaeB`\x82";
+
+    #[test]
+    fn title_and_cover() {
+        let scof = Scof {
+            title: "My Score / Symphony No. 1".to_string(),
+            cover: Some(PNG.to_vec()),
+            ..Default::default()
+        };
+        let opened = Scof::open(&scof.save()).unwrap();
+        assert_eq!(opened.title, scof.title);
+        assert_eq!(opened.cover.as_deref(), Some(PNG));
+        assert_eq!(opened.movement, scof.movement);
+    }
+
+    #[test]
+    fn soundfont() {
+        let mut scof = Scof::new(2, crate::Fraction::new(4, 4), 0);
+        scof.soundfont[1].transpose = -2;
+        let opened = Scof::open(&scof.save()).unwrap();
+        assert_eq!(opened.soundfont, scof.soundfont);
+    }
+
+    #[test]
+    fn long_title() {
+        let scof = Scof {
+            title: "a".repeat(100),
+            ..Default::default()
+        };
+        let opened = Scof::open(&scof.save()).unwrap();
+        assert_eq!(opened.title, "a".repeat(TITLE_LEN));
+    }
+
+    #[test]
+    fn break_before() {
+        let mut scof = Scof::default();
+        scof.movement[0].bar[2].break_before = Some(crate::BreakKind::Page);
+        let opened = Scof::open(&scof.save()).unwrap();
+        assert_eq!(opened.movement[0].bar[1].break_before, None);
+        assert_eq!(
+            opened.movement[0].bar[2].break_before,
+            Some(crate::BreakKind::Page)
+        );
+    }
+
+    #[test]
+    fn from_zip() {
+        let mut zip = Writer(ZipWriter::new(Cursor::new(vec![])));
+        zip.file("Meta.muon", include_bytes!("../scof/Meta.muon"));
+        zip.file("Style.muon", include_bytes!("../scof/Style.muon"));
+        zip.muon("Synth.muon", &crate::Synth::default());
+        let meta: Meta =
+            muon::from_str(include_str!("../scof/Meta.muon")).unwrap();
+        for name in &meta.movement {
+            zip.muon(&movement_file(name), &Mvmt::from(&Movement::default()));
+        }
+        let bytes = zip.0.finish().unwrap().into_inner();
+        let scof = Scof::from_zip(Cursor::new(bytes)).unwrap();
+        assert_eq!(scof.meta, meta);
+        assert_eq!(scof.movement.len(), meta.movement.len());
+        assert_eq!(scof.cover, None);
+    }
+
+    #[test]
+    fn missing_meta() {
+        let mut zip = Writer(ZipWriter::new(Cursor::new(vec![])));
+        zip.file("Style.muon", include_bytes!("../scof/Style.muon"));
+        let bytes = zip.0.finish().unwrap().into_inner();
+        assert_eq!(
+            Scof::from_zip(Cursor::new(bytes)).err(),
+            Some(ScofError::MissingFile("Meta.muon".to_string()))
+        );
+    }
+
+    #[test]
+    fn not_zip() {
+        assert_eq!(Scof::open(b"not a zip").err(), Some(ScofError::NotZip));
+    }
+}
@@ -31,6 +31,50 @@ impl Fraction {
         }
     }
 
+    /// Round to the nearest multiple of `grid` (halves round up).
+    pub fn quantize(self, grid: Fraction) -> Self {
+        let num = u32::from(self.num) * u32::from(grid.den);
+        let den = u32::from(self.den) * u32::from(grid.num);
+        let count = (2 * num + den) / (2 * den);
+
+        Self {
+            num: (count * u32::from(grid.num)).try_into().unwrap_or(0),
+            den: grid.den,
+        }
+        .simplify()
+    }
+
+    /// Subtract, returning `None` instead of underflowing if `other` is
+    /// larger (or if the result doesn't fit).
+    pub fn checked_sub(self, other: Fraction) -> Option<Self> {
+        let den = u32::from(self.den) * u32::from(other.den);
+        let num = (u32::from(self.num) * u32::from(other.den))
+            .checked_sub(u32::from(other.num) * u32::from(self.den))?;
+        let gcd = gcd_i(num, den);
+
+        Some(Self {
+            num: (num / gcd).try_into().ok()?,
+            den: (den / gcd).try_into().ok()?,
+        })
+    }
+
+    /// Add, returning `None` instead of overflowing if the simplified result
+    /// doesn't fit (sums of fractions with coprime denominators grow fast).
+    pub fn checked_add(self, other: Fraction) -> Option<Self> {
+        if self.num == 0 {
+            return Some(other);
+        }
+        let num = u64::from(self.num) * u64::from(other.den)
+            + u64::from(other.num) * u64::from(self.den);
+        let den = u64::from(self.den) * u64::from(other.den);
+        let gcd = gcd_i(num, den);
+
+        Some(Self {
+            num: (num / gcd).try_into().ok()?,
+            den: (den / gcd).try_into().ok()?,
+        })
+    }
+
     /// Simpify the fraction (2/2) => (1/1).
     pub fn simplify(self) -> Self {
         let a = gcd_i(self.num, self.den);
@@ -85,30 +129,9 @@ impl Add for Fraction {
     type Output = Fraction;
 
     fn add(self, other: Fraction) -> Self::Output {
-        if self.num == 0 {
-            return other;
-        }
-
-        let (self_mul, other_mul, den) = if self.den % other.den == 0 {
-            (1, self.den / other.den, self.den)
-        } else if other.den % self.den == 0 {
-            (other.den / self.den, 1, other.den)
-        } else {
-            (other.den, self.den, self.den * other.den)
-        };
-
-        let num: u32 = self.num as u32 * self_mul as u32
-            + other.num as u32 * other_mul as u32;
-        let den: u32 = den.into();
-        let gcd: u32 = gcd_i(num, den);
-        Fraction {
-            num: (num / gcd).try_into().unwrap_or_else(|_| {
-                panic!("n {} {} {}", self, other, num / gcd)
-            }),
-            den: (den / gcd).try_into().unwrap_or_else(|_| {
-                panic!("d {} {} {}", self, other, den / gcd)
-            }),
-        }
+        self.checked_add(other).unwrap_or_else(|| {
+            panic!("Fraction addition overflow {} + {}", self, other)
+        })
     }
 }
 
@@ -116,20 +139,9 @@ impl Sub for Fraction {
     type Output = Fraction;
 
     fn sub(self, other: Fraction) -> Self::Output {
-        let (self_mul, other_mul, den) = if self.den % other.den == 0 {
-            (1, self.den / other.den, self.den)
-        } else if other.den % self.den == 0 {
-            (other.den / self.den, 1, other.den)
-        } else {
-            (other.den, self.den, self.den * other.den)
-        };
-
-        let num = self.num * self_mul - other.num * other_mul;
-        let gcd = gcd_i(num, den);
-        Fraction {
-            num: num / gcd,
-            den: den / gcd,
-        }
+        self.checked_sub(other).unwrap_or_else(|| {
+            panic!("Fraction subtraction underflow {} - {}", self, other)
+        })
     }
 }
 
@@ -175,6 +187,20 @@ impl PartialOrd for Fraction {
     }
 }
 
+impl PartialEq<u16> for Fraction {
+    fn eq(&self, other: &u16) -> bool {
+        u32::from(self.num) == u32::from(*other) * u32::from(self.den)
+    }
+}
+
+impl PartialOrd<u16> for Fraction {
+    fn partial_cmp(&self, other: &u16) -> Option<Ordering> {
+        let other_num = u32::from(*other) * u32::from(self.den);
+
+        u32::from(self.num).partial_cmp(&other_num)
+    }
+}
+
 impl FromStr for Fraction {
     type Err = ();
 
@@ -285,6 +311,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn checked_sub() {
+        assert_eq!(
+            Fraction::new(1, 2).checked_sub(Fraction::new(1, 8)),
+            Some(Fraction::new(3, 8))
+        );
+        assert_eq!(Fraction::new(1, 8).checked_sub(Fraction::new(1, 2)), None);
+        assert_eq!(Fraction::new(1, 4).checked_sub(Fraction::new(1, 2)), None);
+        assert_eq!(
+            Fraction::new(3, 4).checked_sub(Fraction::new(1, 4)),
+            Some(Fraction::new(1, 2))
+        );
+    }
+
+    #[test]
+    fn checked_add() {
+        // Denominators that would overflow before simplifying.
+        assert_eq!(
+            Fraction::new(1, 32768).checked_add(Fraction::new(32767, 32768)),
+            Some(Fraction::new(1, 1))
+        );
+        assert_eq!(
+            Fraction::new(1, 60000).checked_add(Fraction::new(1, 40000)),
+            Some(Fraction::new(1, 24000))
+        );
+        // Coprime denominators: exact until the result doesn't fit.
+        let mut sum = Fraction::new(0, 1);
+        for den in &[3, 5, 7, 11, 13] {
+            sum = sum.checked_add(Fraction::new(1, *den)).unwrap();
+        }
+        assert_eq!(sum, Fraction::new(12673, 15015));
+        assert_eq!(sum.checked_add(Fraction::new(1, 17)), None);
+    }
+
     #[test]
     fn add() {
         assert_eq!(
@@ -354,4 +414,23 @@ mod tests {
         assert!(Fraction::new(1, 3) > Fraction::new(1, 4));
         assert_eq!(false, Fraction::new(0, 3) > Fraction::new(0, 4));
     }
+
+    #[test]
+    fn whole_numbers() {
+        assert!(Fraction::new(5, 4) > 1);
+        assert!(Fraction::new(3, 4) < 1);
+        assert!(Fraction::new(4, 4) == 1);
+        assert!(Fraction::new(8, 4) == 2);
+        assert!(Fraction::new(0, 4) == 0);
+        assert!(Fraction::new(7, 4) != 2);
+    }
+
+    #[test]
+    fn quantize() {
+        let grid = Fraction::new(1, 16);
+        assert_eq!(Fraction::new(5, 64).quantize(grid), Fraction::new(1, 16));
+        assert_eq!(Fraction::new(6, 64).quantize(grid), Fraction::new(1, 8));
+        assert_eq!(Fraction::new(1, 3).quantize(grid), Fraction::new(5, 16));
+        assert_eq!(Fraction::new(1, 40).quantize(grid), Fraction::new(0, 1));
+    }
 }
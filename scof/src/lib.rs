@@ -21,23 +21,32 @@
 use muon_rs as muon;
 use serde_derive::{Deserialize, Serialize};
 use std::convert::TryInto;
+use std::fmt;
 use std::str::FromStr;
 
 use cala::log::{Tag, log};
 
 const SCOF: Tag = Tag::new("SCOF");
 
+pub mod analysis;
+mod container;
 mod fraction;
+mod midi;
 pub mod note;
+mod playback;
 
+pub use container::ScofError;
 pub use fraction::{Fraction, IsZero};
+pub use midi::{export_midi, import_midi, ImportError};
 pub use note::{
-    Articulation, Note, Pitch, PitchAccidental, PitchClass, PitchName,
-    PitchOctave, Steps,
+    spell_pitch, Articulation, Feather, Interval, IntervalQuality, Note,
+    NoteheadStyle, Pitch, PitchAccidental, PitchClass, PitchName, PitchOctave,
+    Steps, A4_FREQUENCY,
 };
+pub use playback::{NoteEvent, DEFAULT_VELOCITY};
 
 /// Cursor pointing to a marking
-#[derive(Clone, Default, Debug, PartialEq)]
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Cursor {
     /// Movement number at cursor
     movement: u16,
@@ -80,6 +89,16 @@ impl Cursor {
         }
     }
 
+    /// Create a cursor from the first marking of another bar.
+    pub fn bar(&self, bar: u16) -> Self {
+        Cursor {
+            movement: self.movement,
+            bar,
+            chan: self.chan,
+            marking: 0,
+        }
+    }
+
     /// Move cursor left.
     pub fn left(&mut self, scof: &Scof) {
         if self.marking > 0 {
@@ -133,10 +152,56 @@ impl Cursor {
         self.clone()
     }
 
+    /// Get the movement number at the cursor.
+    pub fn movement(&self) -> u16 {
+        self.movement
+    }
+
+    /// Get the measure number at the cursor.
+    pub fn measure(&self) -> u16 {
+        self.bar
+    }
+
+    /// Get the channel number at the cursor.
+    pub fn channel(&self) -> u16 {
+        self.chan
+    }
+
+    /// Get the marking number within the bar at the cursor.
+    pub fn marking(&self) -> u16 {
+        self.marking
+    }
+
     /// Returns true if it's the first bar of music.
     pub fn is_first_bar(&self) -> bool {
         self.bar == 0
     }
+
+    /// Snap an out of range cursor to the nearest valid position.  A cursor
+    /// past the last bar moves to the last marking of the last bar.
+    pub fn clamp(&mut self, scof: &Scof) {
+        let last = |len: usize| len.saturating_sub(1).min(u16::MAX.into());
+        let movements = scof.movement.len();
+        if usize::from(self.movement) >= movements {
+            self.movement = last(movements) as u16;
+            self.bar = u16::MAX;
+        }
+        let bars = scof
+            .movement
+            .get(usize::from(self.movement))
+            .map_or(0, |movement| movement.bar.len());
+        if usize::from(self.bar) >= bars {
+            self.bar = last(bars) as u16;
+            self.marking = u16::MAX;
+        }
+        let chans = scof.measure_channel_count(
+            usize::from(self.movement),
+            usize::from(self.bar),
+        );
+        self.chan = self.chan.min(last(chans) as u16);
+        let len = scof.marking_len(self);
+        self.marking = self.marking.min(len.saturating_sub(1));
+    }
 }
 
 /// A Dynamic.
@@ -163,6 +228,113 @@ pub enum Dynamic {
     SFP,
 }
 
+impl fmt::Display for Dynamic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Dynamic::*;
+
+        let text = match self {
+            PPPPPP => "pppppp",
+            PPPPP => "ppppp",
+            PPPP => "pppp",
+            PPP => "ppp",
+            PP => "pp",
+            P => "p",
+            MP => "mp",
+            MF => "mf",
+            F => "f",
+            FF => "ff",
+            FFF => "fff",
+            FFFF => "ffff",
+            FFFFF => "fffff",
+            FFFFFF => "ffffff",
+            N => "n",
+            SF => "sf",
+            SFZ => "sfz",
+            FP => "fp",
+            SFP => "sfp",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl Dynamic {
+    /// Get the MIDI velocity (0-127) notes are played at after this dynamic,
+    /// or `None` for sforzandos that only accent the next note.  Niente is
+    /// near-silence rather than silence, so hairpins can grow out of it.
+    pub fn velocity(&self) -> Option<u8> {
+        use Dynamic::*;
+
+        Some(match self {
+            N => 1,
+            PPPPPP => 4,
+            PPPPP => 8,
+            PPPP => 12,
+            PPP => 16,
+            PP => 32,
+            P | FP | SFP => 48,
+            MP => 64,
+            MF => 80,
+            F => 96,
+            FF => 112,
+            FFF | FFFF | FFFFF | FFFFFF => 127,
+            SF | SFZ => return None,
+        })
+    }
+
+    /// Get the dynamic (from niente to fff) closest to a MIDI velocity.
+    pub fn from_velocity(velocity: u8) -> Self {
+        use Dynamic::*;
+
+        [N, PPPPPP, PPPPP, PPPP, PPP, PP, P, MP, MF, F, FF, FFF]
+            .iter()
+            .min_by_key(|dynamic| {
+                let level = dynamic.velocity().unwrap();
+                (i16::from(level) - i16::from(velocity)).abs()
+            })
+            .unwrap()
+            .clone()
+    }
+
+    /// Whether the note following this dynamic is attacked forte (sf, sfz, fp
+    /// and sfp).
+    pub fn is_sforzando(&self) -> bool {
+        use Dynamic::*;
+
+        matches!(self, SF | SFZ | FP | SFP)
+    }
+}
+
+impl FromStr for Dynamic {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Dynamic::*;
+
+        Ok(match s {
+            "pppppp" => PPPPPP,
+            "ppppp" => PPPPP,
+            "pppp" => PPPP,
+            "ppp" => PPP,
+            "pp" => PP,
+            "p" => P,
+            "mp" => MP,
+            "mf" => MF,
+            "f" => F,
+            "ff" => FF,
+            "fff" => FFF,
+            "ffff" => FFFF,
+            "fffff" => FFFFF,
+            "ffffff" => FFFFFF,
+            "n" => N,
+            "sf" => SF,
+            "sfz" => SFZ,
+            "fp" => FP,
+            "sfp" => SFP,
+            _ => return Err(()),
+        })
+    }
+}
+
 /// A marking.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Marking {
@@ -174,15 +346,15 @@ pub enum Marking {
     GraceOutOf(Note),
     /// Note
     Note(Note),
-    /// Breath
+    /// Breath (",")
     Breath,
-    /// Short grand pause for all instruments
+    /// Short grand pause for all instruments ("/")
     CaesuraShort,
-    /// Long grand pause for all instruments
+    /// Long grand pause for all instruments ("//")
     CaesuraLong,
-    /// Increase intensity
+    /// Increase intensity until the next dynamic, drawn as a hairpin ("<").
     Cresc,
-    /// Decrease intensity
+    /// Decrease intensity until the next dynamic, drawn as a hairpin (">").
     Dim,
     /// Pizzicato (pluck)
     Pizz,
@@ -194,13 +366,107 @@ pub enum Marking {
     Open,
     /// Repeat
     Repeat,
+    /// Replay the measure this many bars back instead of notating it ("%"
+    /// for a single measure repeat, "%%" for each bar of a double).
+    MeasureRepeat(u8),
+    /// Invisible spacer that takes time, but isn't drawn ("1/2s").
+    Spacer(Fraction),
+    /// Replay the beat of this length right before it, drawn as a slash
+    /// ("1/4%").
+    BeatRepeat(Fraction),
+    /// Start drawing the following notes this many octaves lower ("8va" = 1,
+    /// "15ma" = 2), or higher ("8vb" = -1, "15mb" = -2), under a bracket.  0
+    /// ends the shift ("loco").  Notes are stored at sounding pitch.
+    OctaveShift(i8),
+}
+
+impl Marking {
+    /// Get the duration of time the marking takes up in the measure.
+    pub fn duration(&self) -> Option<Fraction> {
+        match self {
+            Marking::Note(note) => Some(note.duration),
+            Marking::Spacer(duration) => Some(*duration),
+            Marking::BeatRepeat(duration) => Some(*duration),
+            _ => None,
+        }
+    }
+
+    /// Check if the marking takes up no time and is attached to the note
+    /// after it (drawn with it, rather than taking up space of its own).
+    pub fn is_attached(&self) -> bool {
+        matches!(
+            self,
+            Marking::Dynamic(_)
+                | Marking::GraceInto(_)
+                | Marking::Breath
+                | Marking::CaesuraShort
+                | Marking::CaesuraLong
+                | Marking::Cresc
+                | Marking::Dim
+                | Marking::Pizz
+                | Marking::Arco
+                | Marking::Mute
+                | Marking::Open
+        )
+    }
+}
+
+impl fmt::Display for Marking {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Marking::Dynamic(dynamic) => write!(f, "{}", dynamic),
+            Marking::Note(note) => write!(f, "{}", note),
+            Marking::MeasureRepeat(count) => {
+                write!(f, "{}", "%".repeat(usize::from(*count)))
+            }
+            Marking::Cresc => write!(f, "<"),
+            Marking::Dim => write!(f, ">"),
+            Marking::Breath => write!(f, ","),
+            Marking::CaesuraShort => write!(f, "/"),
+            Marking::CaesuraLong => write!(f, "//"),
+            Marking::Spacer(duration) => write!(f, "{}s", duration),
+            Marking::BeatRepeat(duration) => write!(f, "{}%", duration),
+            Marking::OctaveShift(octaves) => match octaves {
+                0 => write!(f, "loco"),
+                1 => write!(f, "8va"),
+                -1 => write!(f, "8vb"),
+                o if *o > 0 => write!(f, "15ma"),
+                _ => write!(f, "15mb"),
+            },
+            // FIXME: Text for the rest of the markings.
+            _ => Ok(()),
+        }
+    }
 }
 
 impl FromStr for Marking {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Marking::Note(s.parse::<Note>().and_then(Ok)?))
+        if let Ok(dynamic) = s.parse() {
+            return Ok(Marking::Dynamic(dynamic));
+        }
+        match s {
+            "<" => Ok(Marking::Cresc),
+            ">" => Ok(Marking::Dim),
+            "," => Ok(Marking::Breath),
+            "/" => Ok(Marking::CaesuraShort),
+            "//" => Ok(Marking::CaesuraLong),
+            "%" => Ok(Marking::MeasureRepeat(1)),
+            "%%" => Ok(Marking::MeasureRepeat(2)),
+            "8va" => Ok(Marking::OctaveShift(1)),
+            "15ma" => Ok(Marking::OctaveShift(2)),
+            "8vb" => Ok(Marking::OctaveShift(-1)),
+            "15mb" => Ok(Marking::OctaveShift(-2)),
+            "loco" => Ok(Marking::OctaveShift(0)),
+            _ if s.ends_with('s') => {
+                Ok(Marking::Spacer(s[..s.len() - 1].parse()?))
+            }
+            _ if s.ends_with('%') => {
+                Ok(Marking::BeatRepeat(s[..s.len() - 1].parse()?))
+            }
+            _ => Ok(Marking::Note(s.parse::<Note>().and_then(Ok)?)),
+        }
     }
 }
 
@@ -226,6 +492,165 @@ pub enum Repeat {
     Ending(u8),
 }
 
+/// Style of the barline at the end of a measure.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Barline {
+    /// Single thin line
+    Single,
+    /// Two thin lines
+    Double,
+    /// Thin line followed by thick line (end of movement)
+    Final,
+    /// Dashed thin line
+    Dashed,
+    /// No line drawn
+    Invisible,
+}
+
+impl fmt::Display for Barline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Barline::Single => write!(f, "single"),
+            Barline::Double => write!(f, "double"),
+            Barline::Final => write!(f, "final"),
+            Barline::Dashed => write!(f, "dashed"),
+            Barline::Invisible => write!(f, "invisible"),
+        }
+    }
+}
+
+impl FromStr for Barline {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "single" => Barline::Single,
+            "double" => Barline::Double,
+            "final" => Barline::Final,
+            "dashed" => Barline::Dashed,
+            "invisible" => Barline::Invisible,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// A break forced before a measure when laying out the score.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BreakKind {
+    /// Start a new system (line)
+    System,
+    /// Start a new page
+    Page,
+}
+
+impl fmt::Display for BreakKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakKind::System => write!(f, "system"),
+            BreakKind::Page => write!(f, "page"),
+        }
+    }
+}
+
+impl FromStr for BreakKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "system" => BreakKind::System,
+            "page" => BreakKind::Page,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// A clef.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Clef {
+    /// G clef on the second line
+    #[default]
+    Treble,
+    /// F clef on the fourth line
+    Bass,
+    /// C clef on the middle line
+    Alto,
+    /// C clef on the fourth line
+    Tenor,
+}
+
+impl Clef {
+    /// Get the number of steps the middle line of a stave with this clef is
+    /// above middle C.
+    pub fn middle_steps(self) -> Steps {
+        match self {
+            Clef::Treble => Steps(6), // B4
+            Clef::Bass => Steps(-6),  // D3
+            Clef::Alto => Steps(0),   // C4
+            Clef::Tenor => Steps(-2), // A3
+        }
+    }
+}
+
+impl fmt::Display for Clef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Clef::Treble => write!(f, "treble"),
+            Clef::Bass => write!(f, "bass"),
+            Clef::Alto => write!(f, "alto"),
+            Clef::Tenor => write!(f, "tenor"),
+        }
+    }
+}
+
+impl FromStr for Clef {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "treble" => Clef::Treble,
+            "bass" => Clef::Bass,
+            "alto" => Clef::Alto,
+            "tenor" => Clef::Tenor,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// The lyric sung on a note.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lyric {
+    /// A syllable, followed by a hyphen if the word continues onto the next
+    /// syllable ("Hal-").
+    Syllable(String, bool),
+    /// The previous syllable is held over this note ("_").
+    Extend,
+}
+
+impl fmt::Display for Lyric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lyric::Syllable(text, false) => write!(f, "{}", text),
+            Lyric::Syllable(text, true) => write!(f, "{}-", text),
+            Lyric::Extend => write!(f, "_"),
+        }
+    }
+}
+
+impl FromStr for Lyric {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "" => return Err(()),
+            "_" => Lyric::Extend,
+            _ if s.len() > 1 && s.ends_with('-') => {
+                Lyric::Syllable(s[..s.len() - 1].to_string(), true)
+            }
+            _ => Lyric::Syllable(s.to_string(), false),
+        })
+    }
+}
+
 /////////////////////
 ////             ////
 /////////////////////
@@ -260,18 +685,52 @@ pub struct SynthChan {
     volume: f32,
 }
 
+fn default_a4() -> f64 {
+    A4_FREQUENCY
+}
+
 /// Synthesis file.
-#[derive(Debug, PartialEq, Default, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Synth {
     /// Instrument presets,
     /// Reverb presets, IDs automatically assigned.
     effect: Vec<Effect>,
     /// Channels
     chan: Vec<SynthChan>,
+    /// Tuning reference: frequency of A4 in Hz (default=440).
+    #[serde(default = "default_a4")]
+    pub a4: f64,
+    /// Tuning table: cents above equal temperament for each sounding pitch
+    /// class, starting from C (empty for equal temperament).
+    #[serde(default)]
+    pub tuning: Vec<f64>,
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Synth {
+            effect: vec![],
+            chan: vec![],
+            a4: A4_FREQUENCY,
+            tuning: vec![],
+        }
+    }
+}
+
+impl Synth {
+    /// Get the frequency in Hz that a pitch sounds at, after transposing it
+    /// `transpose` half steps.
+    pub fn frequency(&self, pitch: Pitch, transpose: i32) -> f64 {
+        let pitch_class = (pitch.midi() + transpose).rem_euclid(12) as usize;
+        let cents = f64::from(transpose) * 100.0
+            + self.tuning.get(pitch_class).copied().unwrap_or(0.0);
+
+        pitch.frequency(self.a4) * (cents / 1200.0).exp2()
+    }
 }
 
 /// A signature.
-#[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct Sig {
     /// The key signature (0-23 quarter steps above C, 24+ reserved for middle
     /// eastern and Indian key signatures).
@@ -284,6 +743,39 @@ pub struct Sig {
     pub swing: Option<u8>,
 }
 
+impl Sig {
+    /// Parse the time signature, `None` if it isn't "num_beats/note_len".
+    pub fn time_fraction(&self) -> Option<Fraction> {
+        let time: Fraction = self.time.parse().ok()?;
+        if time.den == 0 {
+            return None;
+        }
+        Some(time)
+    }
+
+    /// Get the number of beats in a bar (the top of the time signature).
+    pub fn beats(&self) -> Option<u16> {
+        self.time_fraction().map(|time| time.num)
+    }
+
+    /// Get the note that gets one beat (the bottom of the time signature).
+    pub fn beat_value(&self) -> Option<u16> {
+        self.time_fraction().map(|time| time.den)
+    }
+
+    /// Get the length of a beat: one of the note that gets it, or three in
+    /// compound time (6/8, 9/8, 12/8 ...).
+    pub fn beat(&self) -> Option<Fraction> {
+        let time = self.time_fraction()?;
+        let notes = if time.num > 3 && time.num % 3 == 0 {
+            3
+        } else {
+            1
+        };
+        Some(Fraction::new(notes, time.den))
+    }
+}
+
 /// Channel information for a specific bar of music.
 #[derive(PartialEq, Debug, Serialize, Deserialize)]
 struct Chan {
@@ -291,6 +783,8 @@ struct Chan {
     notes: String,
     /// Channel lyrics for 1 bar.
     lyric: Option<String>,
+    /// Clef change at the start of this bar.
+    clef: Option<String>,
 }
 
 /// A parsed and transformed channel information for a specific bar of music.
@@ -300,13 +794,26 @@ pub struct Channel {
     notes: Vec<Marking>,
     /// Channel lyrics for 1 bar.
     lyric: Option<String>,
+    /// Clef change at the start of this bar.
+    clef: Option<Clef>,
 }
 
 impl Default for Chan {
     fn default() -> Self {
         let notes = String::new(); // no notes = whole measure rest
         let lyric = None;
-        Chan { notes, lyric }
+        let clef = None;
+        Chan { notes, lyric, clef }
+    }
+}
+
+impl Channel {
+    /// Get the total duration of the markings.
+    fn duration(&self) -> Fraction {
+        self.notes
+            .iter()
+            .filter_map(Marking::duration)
+            .fold(Fraction::new(0, 1), |a, b| a + b)
     }
 }
 
@@ -327,12 +834,47 @@ impl From<Chan> for Channel {
         }
 
         let lyric = chan.lyric;
+        let clef = chan.clef.and_then(|clef| clef.parse().ok());
 
-        Channel { notes, lyric }
+        Channel { notes, lyric, clef }
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+impl From<&Channel> for Chan {
+    fn from(channel: &Channel) -> Self {
+        let notes: Vec<String> = channel
+            .notes
+            .iter()
+            .map(|marking| marking.to_string())
+            .filter(|marking| !marking.is_empty())
+            .collect();
+        let notes = notes.join(" ");
+        let lyric = channel.lyric.clone();
+        let clef = channel.clef.map(|clef| clef.to_string());
+
+        Chan { notes, lyric, clef }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut notes = vec![];
+
+        for marking in s.split(' ').filter(|m| !m.is_empty()) {
+            notes.push(marking.parse()?);
+        }
+
+        Ok(Channel {
+            notes,
+            lyric: None,
+            clef: None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SigRef {
     /// Index into sig list.
     index: u32,
@@ -349,6 +891,10 @@ pub struct Bar {
     chan: Vec<Chan>,
     /// Repeat symbols for this measure.
     repeat: Vec<String>,
+    /// Barline at the end of this measure.
+    barline: Option<String>,
+    /// Break forced before this measure.
+    break_before: Option<String>,
 }
 
 /// A bar (or measure) of music.
@@ -360,6 +906,10 @@ pub struct Measure {
     pub chan: Vec<Channel>,
     /// Repeat symbols for this measure.
     pub repeat: Vec<String>,
+    /// Barline at the end of this measure (default depends on position).
+    pub barline: Option<Barline>,
+    /// Break forced before this measure.
+    pub break_before: Option<BreakKind>,
 }
 
 impl From<Bar> for Measure {
@@ -372,8 +922,28 @@ impl From<Bar> for Measure {
 
         let sig = bar.sig;
         let repeat = bar.repeat;
+        let barline = bar.barline.and_then(|barline| barline.parse().ok());
+        let break_before = bar.break_before.and_then(|kind| kind.parse().ok());
 
-        Measure { sig, chan, repeat }
+        Measure {
+            sig,
+            chan,
+            repeat,
+            barline,
+            break_before,
+        }
+    }
+}
+
+impl From<&Measure> for Bar {
+    fn from(measure: &Measure) -> Self {
+        Bar {
+            sig: measure.sig.clone(),
+            chan: measure.chan.iter().map(|chan| chan.into()).collect(),
+            repeat: measure.repeat.clone(),
+            barline: measure.barline.map(|barline| barline.to_string()),
+            break_before: measure.break_before.map(|kind| kind.to_string()),
+        }
     }
 }
 
@@ -418,10 +988,21 @@ impl From<Mvmt> for Movement {
     }
 }
 
+impl From<&Movement> for Mvmt {
+    fn from(movement: &Movement) -> Mvmt {
+        Mvmt {
+            sig: movement.sig.clone(),
+            bar: movement.bar.iter().map(|bar| bar.into()).collect(),
+        }
+    }
+}
+
 /// An instrument in the soundfont for this score.
-#[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct Instrument {
-    // Default waveform for instrument.
+    // Default waveform for instrument.  Not written when empty, since muon
+    // can't parse an empty string.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     waveform: String,
     // Straight or Palm mute depending on instrument.
     mute: Option<String>,
@@ -450,6 +1031,11 @@ pub struct Instrument {
     ff: Option<String>,
     // Use different waveform for this dynamic
     fff: Option<String>,
+
+    /// Half steps the instrument sounds above how it's written (-2 for a Bb
+    /// clarinet).
+    #[serde(default)]
+    pub transpose: i32,
 }
 
 /*/// A soundfont used in the score (either in the .scof or a .sfsf and linked to).
@@ -566,7 +1152,7 @@ pub struct Scof {
 
 impl Default for Scof {
     fn default() -> Scof {
-        Scof {
+        let mut scof = Scof {
             title: "Untitled Score".to_string(),
             cover: None,
             meta: Meta::default(),
@@ -576,11 +1162,58 @@ impl Default for Scof {
             soundfont: vec![Instrument::default()],
 
             cache: vec![vec![]],
-        }
+        };
+        scof.sync_movement_names();
+        scof
     }
 }
 
 impl Scof {
+    /// Create an empty score: one movement of one measure (rest) with the
+    /// time and key signature (quarter steps above C), for `parts`
+    /// instruments.
+    pub fn new(parts: usize, time: Fraction, key: u8) -> Scof {
+        let sig = Sig {
+            key,
+            time: time.to_string(),
+            tempo: 120,
+            swing: None,
+        };
+        let bar = Measure {
+            sig: Some(SigRef {
+                index: 0,
+                beat: None,
+            }),
+            chan: (0..parts).map(|_| Chan::default().into()).collect(),
+            repeat: vec![],
+            barline: None,
+            break_before: None,
+        };
+        let mut scof = Scof {
+            movement: vec![Movement {
+                sig: vec![sig],
+                bar: vec![bar],
+            }],
+            soundfont: (0..parts).map(|_| Instrument::default()).collect(),
+            ..Default::default()
+        };
+        scof.build_cache();
+        scof
+    }
+
+    /// Rebuild the cache of time signatures of each measure.
+    pub fn build_cache(&mut self) {
+        self.cache = (0..self.movement.len() as u16)
+            .map(|movement| {
+                (0..self.movement[usize::from(movement)].bar.len() as u16)
+                    .map(|bar| {
+                        self.bar_length(&Cursor::new(movement, bar, 0, 0))
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
     /// Lookup a marking at a cursor position
     pub fn marking(&self, cursor: &Cursor) -> Option<&Marking> {
         self.movement
@@ -593,6 +1226,126 @@ impl Scof {
             .get(cursor.marking as usize)
     }
 
+    /// Get the signature in effect for the measure at a cursor position
+    pub fn sig(&self, cursor: &Cursor) -> Option<&Sig> {
+        let index = self.sig_index(cursor)?;
+        self.movement
+            .get(cursor.movement as usize)?
+            .sig
+            .get(index as usize)
+    }
+
+    /// Get the style of the signature in effect for the measure at a cursor
+    /// position.
+    pub fn sig_style(&self, cursor: &Cursor) -> Option<&SigStyle> {
+        let index = self.sig_index(cursor)?;
+        self.style.sig.get(index as usize)
+    }
+
+    /// Change the signature starting at the measure at a cursor position.
+    fn set_sig(&mut self, cursor: &Cursor, change: impl FnOnce(&mut Sig)) {
+        let mut sig = self.sig(cursor).cloned().unwrap_or_default();
+        change(&mut sig);
+        let movement = match self.movement.get_mut(cursor.movement as usize) {
+            Some(movement) => movement,
+            None => return,
+        };
+        if cursor.bar as usize >= movement.bar.len() {
+            return;
+        }
+        // Reuse an identical signature if there is one.
+        let index = match movement.sig.iter().position(|s| *s == sig) {
+            Some(index) => index,
+            None => {
+                movement.sig.push(sig);
+                movement.sig.len() - 1
+            }
+        };
+        movement.bar[cursor.bar as usize].sig = Some(SigRef {
+            index: index as u32,
+            beat: None,
+        });
+    }
+
+    /// Set the time signature starting at the measure at a cursor position.
+    pub fn set_time(&mut self, cursor: &Cursor, time: Fraction) {
+        self.set_sig(cursor, |sig| sig.time = time.to_string());
+    }
+
+    /// Set the key signature (quarter steps above C) starting at the measure
+    /// at a cursor position.
+    pub fn set_key(&mut self, cursor: &Cursor, key: u8) {
+        self.set_sig(cursor, |sig| sig.key = key);
+    }
+
+    /// Get the clef in effect for the channel at a cursor position.
+    pub fn clef(&self, cursor: &Cursor) -> Clef {
+        let mut clef = Clef::default();
+        if let Some(movement) = self.movement.get(cursor.movement as usize) {
+            for bar in movement.bar.iter().take(cursor.bar as usize + 1) {
+                let chan = bar.chan.get(cursor.chan as usize);
+                if let Some(change) = chan.and_then(|chan| chan.clef) {
+                    clef = change;
+                }
+            }
+        }
+        clef
+    }
+
+    /// Get the lyrics of the channel in the measure at a cursor position, one
+    /// for each note (not rest) in order.
+    pub fn lyrics(&self, cursor: &Cursor) -> Vec<Lyric> {
+        self.movement
+            .get(cursor.movement as usize)
+            .and_then(|movement| movement.bar.get(cursor.bar as usize))
+            .and_then(|bar| bar.chan.get(cursor.chan as usize))
+            .and_then(|chan| chan.lyric.as_ref())
+            .map_or(vec![], |lyric| {
+                lyric
+                    .split_whitespace()
+                    .filter_map(|s| s.parse().ok())
+                    .collect()
+            })
+    }
+
+    /// Set the lyrics of the channel in the measure at a cursor position
+    /// (whitespace-separated, one for each note).
+    pub fn set_lyrics(&mut self, cursor: &Cursor, lyrics: &str) {
+        if let Some(chan) = self
+            .movement
+            .get_mut(cursor.movement as usize)
+            .and_then(|movement| movement.bar.get_mut(cursor.bar as usize))
+            .and_then(|bar| bar.chan.get_mut(cursor.chan as usize))
+        {
+            chan.lyric = Some(lyrics.to_string());
+        }
+    }
+
+    /// Change the clef of the channel starting at the measure at a cursor
+    /// position.
+    pub fn set_clef(&mut self, cursor: &Cursor, clef: Clef) {
+        if let Some(chan) = self
+            .movement
+            .get_mut(cursor.movement as usize)
+            .and_then(|movement| movement.bar.get_mut(cursor.bar as usize))
+            .and_then(|bar| bar.chan.get_mut(cursor.chan as usize))
+        {
+            chan.clef = Some(clef);
+        }
+    }
+
+    /// Get the index of the signature in effect at a cursor position
+    fn sig_index(&self, cursor: &Cursor) -> Option<u32> {
+        let movement = self.movement.get(cursor.movement as usize)?;
+        let mut index = 0;
+        for bar in movement.bar.iter().take(cursor.bar as usize + 1) {
+            if let Some(sig) = &bar.sig {
+                index = sig.index;
+            }
+        }
+        Some(index)
+    }
+
     /// Get mutable marking at a cursor position
     pub fn marking_mut(&mut self, cursor: &Cursor) -> Option<&mut Marking> {
         self.chan_notes_mut(cursor)?
@@ -639,11 +1392,121 @@ impl Scof {
                     sig: None,      // No signature changes
                     repeat: vec![], // No repeat symbols
                     chan,
+                    barline: None,
+                    break_before: None,
                 },
             );
         }
     }
 
+    /// Delete the measure at the cursor, unless it's the only measure in the
+    /// movement.
+    pub fn delete_measure(&mut self, cursor: &Cursor) {
+        let (movement, bar) = (cursor.movement as usize, cursor.bar as usize);
+        let bars = match self.movement.get_mut(movement) {
+            Some(movement) if bar < movement.bar.len() => &mut movement.bar,
+            _ => return,
+        };
+        if bars.len() == 1 {
+            return;
+        }
+        let measure = bars.remove(bar);
+        // Keep a signature change for the following measures.
+        if let Some(next) = bars.get_mut(bar) {
+            if next.sig.is_none() {
+                next.sig = measure.sig;
+            }
+        }
+        if let Some(cache) = self.cache.get_mut(movement) {
+            if bar < cache.len() {
+                cache.remove(bar);
+            }
+        }
+    }
+
+    /// Get the name of a movement
+    pub fn movement_name(&self, i: usize) -> Option<&str> {
+        self.meta.movement.get(i).map(|name| name.as_str())
+    }
+
+    /// Give unnamed movements a default name, and drop names of movements
+    /// that don't exist.
+    fn sync_movement_names(&mut self) {
+        let len = self.movement.len();
+        self.meta.movement.truncate(len);
+        for i in self.meta.movement.len()..len {
+            self.meta.movement.push(format!("Movement {}", i + 1));
+        }
+    }
+
+    /// Add a new movement at the end of the score.
+    pub fn new_movement(&mut self) {
+        self.sync_movement_names();
+        self.movement.push(Movement::default());
+        self.cache.push(vec![]);
+        self.sync_movement_names();
+    }
+
+    /// Merge a movement with the one following it.
+    pub fn merge_movements(&mut self, movement: usize) {
+        if movement + 1 >= self.movement.len() {
+            return;
+        }
+        let next = self.movement.remove(movement + 1);
+        let prev = &mut self.movement[movement];
+        // Signature indices of the following movement's bars are shifted.
+        let offset = prev.sig.len() as u32;
+        prev.sig.extend(next.sig);
+        for (i, mut bar) in next.bar.into_iter().enumerate() {
+            match bar.sig {
+                Some(ref mut sig) => sig.index += offset,
+                None if i == 0 => {
+                    bar.sig = Some(SigRef {
+                        index: offset,
+                        beat: None,
+                    })
+                }
+                None => {}
+            }
+            prev.bar.push(bar);
+        }
+        if movement + 1 < self.cache.len() {
+            let cache = self.cache.remove(movement + 1);
+            self.cache[movement].extend(cache);
+        }
+        if movement + 1 < self.meta.movement.len() {
+            self.meta.movement.remove(movement + 1);
+        }
+        self.sync_movement_names();
+    }
+
+    /// Split a movement into two, starting the new movement at the cursor's
+    /// bar.
+    pub fn split_movement(&mut self, cursor: &Cursor) {
+        let (movement, bar) = (cursor.movement as usize, cursor.bar as usize);
+        let len = self.movement.get(movement).map_or(0, |m| m.bar.len());
+        if bar == 0 || bar >= len {
+            return;
+        }
+        self.sync_movement_names();
+        // Keep the signature that was in effect at the split.
+        let index = self.sig_index(cursor).unwrap_or(0);
+        let prev = &mut self.movement[movement];
+        let mut bars = prev.bar.split_off(bar);
+        if bars[0].sig.is_none() {
+            bars[0].sig = Some(SigRef { index, beat: None });
+        }
+        let sig = prev.sig.clone();
+        self.movement
+            .insert(movement + 1, Movement { sig, bar: bars });
+        if let Some(cache) = self.cache.get_mut(movement) {
+            let rest = cache.split_off(bar.min(cache.len()));
+            self.cache.insert(movement + 1, rest);
+        }
+        let name = format!("Movement {}", movement + 2);
+        self.meta.movement.insert(movement + 1, name);
+    }
+
     /// Get the count of markings in a measure
     pub fn marking_len(&self, cursor: &Cursor) -> u16 {
         let mut curs = (*cursor).clone();
@@ -660,6 +1523,140 @@ impl Scof {
         self.marking_len(cursor) == 0
     }
 
+    /// Get the number of channels in a movement, or 0 if it doesn't exist.
+    pub fn channel_count(&self, movement: usize) -> usize {
+        self.measure_channel_count(movement, 0)
+    }
+
+    /// Get the number of channels in a measure of a movement, or 0 if it
+    /// doesn't exist.
+    pub fn measure_channel_count(
+        &self,
+        movement: usize,
+        measure: usize,
+    ) -> usize {
+        self.movement
+            .get(movement)
+            .and_then(|movement| movement.bar.get(measure))
+            .map_or(0, |bar| bar.chan.len())
+    }
+
+    /// Get the barline at the end of the measure at the cursor.  The last
+    /// measure of a movement defaults to a final barline.
+    pub fn barline(&self, cursor: &Cursor) -> Barline {
+        let movement = match self.movement.get(cursor.movement as usize) {
+            Some(movement) => movement,
+            None => return Barline::Single,
+        };
+        match movement.bar.get(cursor.bar as usize) {
+            Some(bar) => bar.barline.unwrap_or_else(|| {
+                if cursor.bar as usize + 1 == movement.bar.len() {
+                    Barline::Final
+                } else {
+                    Barline::Single
+                }
+            }),
+            None => Barline::Single,
+        }
+    }
+
+    /// Get the length of the measure at a cursor (a fraction of a whole
+    /// note) from its time signature, or a whole note if it has none.
+    pub fn bar_length(&self, cursor: &Cursor) -> Fraction {
+        self.sig(cursor)
+            .and_then(Sig::time_fraction)
+            .unwrap_or_else(|| Fraction::new(1, 1))
+    }
+
+    /// Get the capacity of the measure at a cursor in 128th notes, from its
+    /// time signature (4/4 = 128, 3/4 = 96, 6/8 = 96).
+    pub fn measure_128ths(&self, cursor: &Cursor) -> u16 {
+        let length = self.bar_length(cursor);
+        (u32::from(length.num) * 128 / u32::from(length.den))
+            .try_into()
+            .unwrap_or(u16::MAX)
+    }
+
+    /// Get the longest duration a marking at a cursor can take up without
+    /// overflowing its measure: the measure's capacity (from its time
+    /// signature) less the markings before the cursor.
+    pub fn max_duration_at(&self, cursor: &Cursor) -> Fraction {
+        let before = self
+            .movement
+            .get(cursor.movement as usize)
+            .and_then(|movement| movement.bar.get(cursor.bar as usize))
+            .and_then(|bar| bar.chan.get(cursor.chan as usize))
+            .map_or(Fraction::new(0, 1), |chan| {
+                chan.notes
+                    .iter()
+                    .take(cursor.marking as usize)
+                    .filter_map(Marking::duration)
+                    .fold(Fraction::new(0, 1), |a, b| a + b)
+            });
+        self.bar_length(cursor)
+            .checked_sub(before)
+            .unwrap_or_else(|| Fraction::new(0, 1))
+    }
+
+    /// Get the number of bars back a measure repeat at the cursor's bar
+    /// replays.
+    pub fn measure_repeat(&self, cursor: &Cursor) -> Option<u8> {
+        match self.marking(&cursor.first_marking())? {
+            Marking::MeasureRepeat(count) => Some(*count),
+            _ => None,
+        }
+    }
+
+    /// Get the octave shift in effect for the marking at a cursor position
+    /// (set by the last `Marking::OctaveShift` before it in the channel).
+    pub fn octave_shift(&self, cursor: &Cursor) -> i8 {
+        let mut shift = 0;
+        let movement = match self.movement.get(cursor.movement as usize) {
+            Some(movement) => movement,
+            None => return shift,
+        };
+        for (i, bar) in movement.bar.iter().enumerate() {
+            let notes = match bar.chan.get(cursor.chan as usize) {
+                Some(chan) => &chan.notes,
+                None => continue,
+            };
+            let len = match i.cmp(&(cursor.bar as usize)) {
+                std::cmp::Ordering::Less => notes.len(),
+                std::cmp::Ordering::Equal => cursor.marking as usize,
+                std::cmp::Ordering::Greater => break,
+            };
+            for marking in notes.iter().take(len) {
+                if let Marking::OctaveShift(octaves) = marking {
+                    shift = *octaves;
+                }
+            }
+        }
+        shift
+    }
+
+    /// Get the bars of a channel in a movement in the order they're played,
+    /// replacing measure repeats with the bars they repeat.
+    pub fn playback_order(&self, movement: u16, chan: u16) -> Vec<u16> {
+        let bars = self
+            .movement
+            .get(movement as usize)
+            .map_or(0, |m| m.bar.len());
+        let mut order = vec![];
+        for bar in 0..bars as u16 {
+            let mut played = bar;
+            while let Some(count) =
+                self.measure_repeat(&Cursor::new(movement, played, chan, 0))
+            {
+                match played.checked_sub(count.into()) {
+                    Some(prev) => played = prev,
+                    None => break,
+                }
+            }
+            order.push(played);
+        }
+        order
+    }
+
     /// Get the note at cursor
     pub fn note(&self, cursor: &Cursor) -> Option<&Note> {
         if let Marking::Note(note) = self.marking(cursor)? {
@@ -669,6 +1666,225 @@ impl Scof {
         }
     }
 
+    /// Get the notes (including rests) that begin together in a measure of a
+    /// movement across all channels.  Returns each distinct onset (offset
+    /// within the measure) in order, with the channel of each note starting
+    /// there.
+    pub fn simultaneities(
+        &self,
+        movement: usize,
+        measure: u16,
+    ) -> Vec<(Fraction, Vec<(u16, &Note)>)> {
+        let mut onsets: Vec<(Fraction, Vec<(u16, &Note)>)> = vec![];
+        let chans = self.measure_channel_count(movement, measure.into());
+        for chan in 0..chans as u16 {
+            let mut curs = Cursor::new(movement as u16, measure, chan, 0);
+            let mut offset = Fraction::new(0, 1);
+            while let Some(marking) = self.marking(&curs) {
+                if let Marking::Note(note) = marking {
+                    match onsets.iter_mut().find(|onset| onset.0 == offset) {
+                        Some(onset) => onset.1.push((chan, note)),
+                        None => onsets.push((offset, vec![(chan, note)])),
+                    }
+                }
+                if let Some(duration) = marking.duration() {
+                    offset += duration;
+                }
+                curs.right_unchecked();
+            }
+        }
+        onsets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        onsets
+    }
+
+    /// Swap the markings at two cursor positions.  Markings in different
+    /// bars are only swapped if their durations match, so that the length of
+    /// each bar doesn't change.  Returns true if the markings were swapped.
+    pub fn swap_markings(&mut self, a: &Cursor, b: &Cursor) -> bool {
+        let (mark_a, mark_b) = match (self.marking(a), self.marking(b)) {
+            (Some(mark_a), Some(mark_b)) => (mark_a.clone(), mark_b.clone()),
+            _ => return false,
+        };
+        let same_bar = a.movement == b.movement && a.bar == b.bar;
+        if !same_bar && mark_a.duration() != mark_b.duration() {
+            return false;
+        }
+        *self.marking_mut(a).unwrap() = mark_b;
+        *self.marking_mut(b).unwrap() = mark_a;
+        true
+    }
+
+    /// Get the half steps the instrument of a cursor's channel sounds above
+    /// its written pitch.
+    pub fn transposition(&self, cursor: &Cursor) -> i32 {
+        self.soundfont
+            .get(cursor.chan as usize)
+            .map_or(0, |instrument| instrument.transpose)
+    }
+
+    /// Get the sounding (concert) pitch of the note at a cursor, which is
+    /// written transposed for transposing instruments.
+    pub fn concert_pitch(&self, cursor: &Cursor) -> Option<Pitch> {
+        let written = *self.note(cursor)?.pitch.first()?;
+        let transpose = self.transposition(cursor);
+        if transpose == 0 {
+            return Some(written);
+        }
+        // Key signature of the concert pitch (quarter steps above C).
+        let key = self.sig(cursor).map_or(0, |sig| i32::from(sig.key));
+        let key = (key + 2 * transpose).rem_euclid(24) as u8;
+        Some(spell_pitch(written.midi() + transpose, key, &[]))
+    }
+
+    /// Merge tied notes of the same pitch within each measure into single
+    /// notes, where the combined duration can be written as one note (plain,
+    /// dotted or double dotted).  Other ties are kept.
+    pub fn coalesce_ties(&mut self) {
+        for movement in self.movement.iter_mut() {
+            for bar in movement.bar.iter_mut() {
+                for chan in bar.chan.iter_mut() {
+                    coalesce_ties(&mut chan.notes);
+                }
+            }
+        }
+    }
+
+    /// Merge adjacent rests in the channel of the measure at a cursor into as
+    /// few rests as possible.  Rests within a beat never cross into the next
+    /// one, and whole beats merge into rests of 2, 4 ... beats starting at a
+    /// multiple of their length.  Rests after a tuplet are left alone.
+    pub fn consolidate_rests(&mut self, cursor: &Cursor) {
+        let beat = self
+            .sig(cursor)
+            .and_then(Sig::beat)
+            .and_then(in_128ths)
+            .filter(|beat| *beat > 0)
+            .unwrap_or(32);
+        if let Some(notes) = self.chan_notes_mut(cursor) {
+            consolidate_rests(notes, beat);
+        }
+    }
+
+    /// Re-bar the measures of a movement for the time signature in effect at
+    /// `from_measure`, up to the next signature change.  Notes crossing the
+    /// new barlines are split into tied notes.
+    pub fn rebar(&mut self, movement: usize, from_measure: u16) {
+        let cursor = Cursor::new(movement as u16, from_measure, 0, 0);
+        // In lowest terms, like the durations `set_duration` writes.
+        let length = self.bar_length(&cursor).simplify();
+        let from = usize::from(from_measure);
+        let bars = match self.movement.get_mut(movement) {
+            Some(movement) if from < movement.bar.len() => &mut movement.bar,
+            _ => return,
+        };
+        let end = (from + 1..bars.len())
+            .find(|i| bars[*i].sig.is_some())
+            .unwrap_or(bars.len());
+        // Measure repeats can't be re-barred.
+        if bars[from..end]
+            .iter()
+            .flat_map(|bar| &bar.chan)
+            .flat_map(|chan| &chan.notes)
+            .any(|marking| matches!(marking, Marking::MeasureRepeat(_)))
+        {
+            return;
+        }
+        let old: Vec<Measure> = bars.drain(from..end).collect();
+        // Length of an old measure, from its notes.
+        let measure_len = |bar: &Measure| {
+            bar.chan
+                .iter()
+                .map(Channel::duration)
+                .find(|len| !len.is_zero())
+                .unwrap_or(length)
+        };
+
+        // Re-bar each channel's stream of markings.
+        let mut chans = vec![];
+        let chan_count = old.iter().map(|bar| bar.chan.len()).max();
+        for chan in 0..chan_count.unwrap_or(0) {
+            let mut markings = vec![];
+            for bar in &old {
+                let channel = bar.chan.get(chan);
+                let notes = channel.map_or(&[][..], |c| &c.notes[..]);
+                if notes.is_empty() {
+                    // Whole measure rest, as long as the other channels.
+                    markings.push((rest(measure_len(bar)), None));
+                }
+                // Each note (not rest) is sung to the next lyric.
+                let mut words = channel
+                    .and_then(|channel| channel.lyric.as_ref())
+                    .into_iter()
+                    .flat_map(|lyric| lyric.split_whitespace());
+                for marking in notes {
+                    let word = match marking {
+                        Marking::Note(note) if !note.pitch.is_empty() => {
+                            words.next().map(String::from)
+                        }
+                        _ => None,
+                    };
+                    markings.push((marking.clone(), word));
+                }
+            }
+            chans.push(rebar_channel(markings, length));
+        }
+
+        // Replace the measures.
+        let count = chans.iter().map(Vec::len).max().unwrap_or(0).max(1);
+        let mut new = vec![];
+        for i in 0..count {
+            let chan = chans
+                .iter_mut()
+                .map(|bars| {
+                    bars.get_mut(i).map(std::mem::take).unwrap_or_default()
+                })
+                .collect();
+            new.push(Measure {
+                sig: None,
+                chan,
+                repeat: vec![],
+                barline: None,
+                break_before: None,
+            });
+        }
+        // Index of the new measure a time is in, or (if `ending`) the one
+        // that ends at it.
+        let bar_at = |time: Fraction, ending: bool| {
+            let bars = time / length;
+            let (num, den) = (usize::from(bars.num), usize::from(bars.den));
+            let i = if ending {
+                (num + den - 1) / den
+            } else {
+                num / den + 1
+            };
+            i.clamp(1, count) - 1
+        };
+        // Move the attributes of each old measure to the new measure it
+        // starts in (or, for its barline, ends in).  Clef changes within a
+        // new measure move to its start.
+        let mut start = Fraction::new(0, 1);
+        for bar in old {
+            let end = start + measure_len(&bar);
+            let i = bar_at(start, false);
+            if bar.sig.is_some() {
+                new[i].sig = bar.sig;
+            }
+            new[i].repeat.extend(bar.repeat);
+            new[i].break_before = new[i].break_before.or(bar.break_before);
+            for (chan, old) in new[i].chan.iter_mut().zip(bar.chan) {
+                chan.clef = old.clef.or(chan.clef);
+            }
+            if let Some(barline) = bar.barline {
+                new[bar_at(end, true)].barline = Some(barline);
+            }
+            start = end;
+        }
+        bars.splice(from..from, new);
+        if let Some(cache) = self.cache.get_mut(movement) {
+            cache.truncate(from);
+        }
+    }
+
     /// Set pitch class and octave of a note at a cursor
     pub fn set_pitch(&mut self, cursor: &Cursor, i: u16, pitch: Pitch) {
         let mut note = self.note(cursor).unwrap().clone();
@@ -724,7 +1940,15 @@ impl Scof {
         i = loop {
             if i == notes.len() {
                 log!(SCOF, "END {} {}", note.duration, quota);
-                note.duration -= quota;
+                note.duration = match note.duration.checked_sub(quota) {
+                    Some(duration) => duration,
+                    None => {
+                        // Leave the measure as it was.
+                        new_notes.append(notes);
+                        *notes = new_notes;
+                        return None;
+                    }
+                };
                 new_notes.push(Marking::Note(note));
                 *notes = new_notes;
                 return Some(quota);
@@ -737,13 +1961,28 @@ impl Scof {
             // Only count notes.
             if let Marking::Note(parsed_note) = marking {
                 if quota > parsed_note.duration {
-                    quota -= parsed_note.duration;
+                    quota = match quota.checked_sub(parsed_note.duration) {
+                        Some(quota) => quota,
+                        None => {
+                            new_notes.append(notes);
+                            *notes = new_notes;
+                            return None;
+                        }
+                    };
                 } else if quota == parsed_note.duration {
                     new_notes.push(Marking::Note(note));
                     break i;
                 } else {
                     let mut modified = parsed_note.clone();
-                    modified.duration -= quota;
+                    modified.duration =
+                        match parsed_note.duration.checked_sub(quota) {
+                            Some(duration) => duration,
+                            None => {
+                                new_notes.append(notes);
+                                *notes = new_notes;
+                                return None;
+                            }
+                        };
                     new_notes.push(Marking::Note(note));
                     new_notes.push(Marking::Note(modified));
                     break i;
@@ -757,13 +1996,21 @@ impl Scof {
         None
     }
 
-    /// Set whole rest at cursor to C4.
-    pub fn set_whole_pitch(&mut self, cursor: &Cursor) {
-        // If it's a whole measure rest, insert a whole note (4/4)
-        // FIXME: Add time signatures.
-        self.chan_notes_mut(cursor)
-            .unwrap()
-            .push("1/1C4".parse().unwrap());
+    /// Set whole measure rest at cursor to a C4 of a duration, with rests
+    /// filling the remainder of the measure.
+    pub fn set_whole_pitch(&mut self, cursor: &Cursor, dur: Fraction) {
+        let note = Note {
+            pitch: vec!["C4".parse().unwrap()],
+            duration: dur,
+            articulation: vec![],
+            tremolo: 0,
+            tie: false,
+            beam_break: false,
+            feather: None,
+            head: NoteheadStyle::Normal,
+        };
+
+        self.set_empty_measure(cursor, &note);
     }
 
     /// Set duration of a note.
@@ -771,15 +2018,20 @@ impl Scof {
         let mut note = self.note(cursor).unwrap().clone();
         let old = note.duration;
         note.set_duration(dur);
-        if old > dur {
-            let rests = old - dur;
-
+        // Fill the time left over when shortening with rests.
+        let rests = old.checked_sub(dur).filter(|rests| !rests.is_zero());
+        if let Some(rests) = rests {
             self.insert_after(
                 cursor,
                 Marking::Note(Note {
                     pitch: vec![],
                     duration: rests,
                     articulation: vec![],
+                    tremolo: 0,
+                    tie: false,
+                    beam_break: false,
+                    feather: None,
+                    head: NoteheadStyle::Normal,
                 }),
             );
 
@@ -808,6 +2060,11 @@ impl Scof {
             pitch: vec![],
             duration: dur,
             articulation: vec![],
+            tremolo: 0,
+            tie: false,
+            beam_break: false,
+            feather: None,
+            head: NoteheadStyle::Normal,
         };
 
         self.set_empty_measure(cursor, &note);
@@ -825,3 +2082,232 @@ impl Scof {
         Some(())
     }
 }
+
+/// Merge tied notes of the same pitch in a measure where the combined
+/// duration can be written as a single note.
+fn coalesce_ties(notes: &mut Vec<Marking>) {
+    let mut i = 0;
+    while i + 1 < notes.len() {
+        if let (Marking::Note(a), Marking::Note(b)) = (&notes[i], &notes[i + 1])
+        {
+            let duration = (a.duration + b.duration).simplify();
+            if a.tie
+                && !a.pitch.is_empty()
+                && a.pitch == b.pitch
+                && is_single_duration(duration)
+            {
+                let tie = b.tie;
+                notes.remove(i + 1);
+                if let Marking::Note(note) = &mut notes[i] {
+                    note.duration = duration;
+                    note.tie = tie;
+                }
+                continue;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Merge adjacent rests (without articulations) in a measure, with beats
+/// `beat` 128th notes long.
+fn consolidate_rests(notes: &mut Vec<Marking>, beat: u32) {
+    let mut merged = vec![];
+    // Offset in 128th notes (`None` after a tuplet), and the start and
+    // length of the rests being merged.
+    let mut offset = Some(0);
+    let mut rests: Option<(u32, u32)> = None;
+    for marking in notes.drain(..) {
+        let length = marking.duration().and_then(in_128ths);
+        if let (Marking::Note(note), Some(start), Some(length)) =
+            (&marking, offset, length)
+        {
+            if note.pitch.is_empty() && note.articulation.is_empty() {
+                rests.get_or_insert((start, 0)).1 += length;
+                offset = Some(start + length);
+                continue;
+            }
+        }
+        if let Some((start, length)) = rests.take() {
+            push_rests(&mut merged, start, length, beat);
+        }
+        if marking.duration().is_some() {
+            offset = offset.zip(length).map(|(offset, length)| offset + length);
+        }
+        merged.push(marking);
+    }
+    if let Some((start, length)) = rests {
+        push_rests(&mut merged, start, length, beat);
+    }
+    *notes = merged;
+}
+
+/// Add rests from `start` for `length` (in 128th notes), with beats `beat`
+/// 128th notes long.  Whole beats are merged into rests of a power of two
+/// beats, and the rest of a beat into rests of a power of two 128th notes,
+/// each as long as possible while starting at a multiple of its length.
+fn push_rests(
+    notes: &mut Vec<Marking>,
+    mut start: u32,
+    length: u32,
+    beat: u32,
+) {
+    let end = start + length;
+    while start < end {
+        let size = if start % beat == 0 && end - start >= beat {
+            let mut size = beat;
+            while size * 2 <= end - start && start % (size * 2) == 0 {
+                size *= 2;
+            }
+            size
+        } else {
+            // Offset into the beat, which the rest doesn't cross.
+            let offset = start % beat;
+            let mut size = 128;
+            while size > (end - start).min(beat - offset) || offset % size != 0
+            {
+                size /= 2;
+            }
+            size
+        };
+        notes.push(rest(Fraction::new(size as u16, 128).simplify()));
+        start += size;
+    }
+}
+
+/// Get a duration in 128th notes, if it's a whole number of them.
+fn in_128ths(duration: Fraction) -> Option<u32> {
+    let num = u32::from(duration.num) * 128;
+    let den = u32::from(duration.den);
+    if num % den == 0 {
+        Some(num / den)
+    } else {
+        None
+    }
+}
+
+/// Create a rest marking.
+fn rest(duration: Fraction) -> Marking {
+    Marking::Note(Note {
+        pitch: vec![],
+        duration,
+        articulation: vec![],
+        tremolo: 0,
+        tie: false,
+        beam_break: false,
+        feather: None,
+        head: NoteheadStyle::Normal,
+    })
+}
+
+/// Split a stream of markings (with lyrics) into channels for bars of
+/// `length`, tying notes across barlines.  The tied parts of a split note
+/// extend its lyric.  Attached markings stay with the note after them.
+/// FIXME: Notes without lyrics before notes with lyrics in the same bar
+/// shift the lyrics.
+fn rebar_channel(
+    markings: Vec<(Marking, Option<String>)>,
+    length: Fraction,
+) -> Vec<Channel> {
+    let mut bars = vec![(vec![], vec![])];
+    let mut left = length;
+    let mut attached = vec![];
+    for (mut marking, mut word) in markings {
+        let extend = word.is_some();
+        loop {
+            let duration = match marking.duration() {
+                Some(duration) => duration,
+                None if marking.is_attached() => {
+                    attached.push(marking);
+                    break;
+                }
+                None => {
+                    bars.last_mut().unwrap().0.push(marking);
+                    break;
+                }
+            };
+            if left.is_zero() {
+                bars.push((vec![], vec![]));
+                left = length;
+            }
+            bars.last_mut().unwrap().0.append(&mut attached);
+            // Split the marking at the barline.
+            let over = duration.checked_sub(left).filter(|d| !d.is_zero());
+            let tail = over.map(|over| {
+                let mut tail = marking.clone();
+                split_marking(&mut marking, &mut tail, left, over);
+                tail
+            });
+            let (notes, lyrics) = bars.last_mut().unwrap();
+            lyrics.extend(word.take());
+            left = left.checked_sub(marking.duration().unwrap()).unwrap();
+            notes.push(marking);
+            match tail {
+                Some(tail) => marking = tail,
+                None => break,
+            }
+            if extend {
+                word = Some("_".to_string());
+            }
+        }
+    }
+    bars.last_mut().unwrap().0.append(&mut attached);
+    // Fill the last bar.
+    if !left.is_zero() {
+        bars.last_mut().unwrap().0.push(rest(left));
+    }
+    bars.into_iter()
+        .map(|(notes, lyrics)| Channel {
+            notes,
+            lyric: if lyrics.is_empty() {
+                None
+            } else {
+                Some(lyrics.join(" "))
+            },
+            clef: None,
+        })
+        .collect()
+}
+
+/// Split a marking into a `head` of duration `left` and a `tail` of duration
+/// `over`.
+fn split_marking(
+    head: &mut Marking,
+    tail: &mut Marking,
+    left: Fraction,
+    over: Fraction,
+) {
+    match (head, tail) {
+        (Marking::Note(head), Marking::Note(tail)) => {
+            head.duration = left;
+            head.tie = !head.pitch.is_empty();
+            tail.duration = over;
+            // Articulations and beam breaks belong to the attack.
+            tail.articulation.clear();
+            tail.beam_break = false;
+            tail.feather = None;
+        }
+        (Marking::Spacer(head), Marking::Spacer(tail))
+        | (Marking::BeatRepeat(head), Marking::BeatRepeat(tail)) => {
+            *head = left;
+            *tail = over;
+        }
+        _ => {}
+    }
+}
+
+/// Check if a duration can be written as one note, from a 128th note to a
+/// longa, optionally dotted or double dotted.
+fn is_single_duration(duration: Fraction) -> bool {
+    let dur = match in_128ths(duration) {
+        Some(dur) => dur,
+        None => return false,
+    };
+    // (Multiplier, Undotted duration divisor) for plain, dotted and double
+    // dotted notes.
+    [(1, 1), (3, 2), (7, 4)].iter().any(|(mul, div)| {
+        dur % mul == 0
+            && (dur / mul).is_power_of_two()
+            && dur / mul * div <= 512
+    })
+}
@@ -0,0 +1,570 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Standard MIDI File import and export.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+
+use crate::{
+    spell_pitch, Channel, Fraction, Marking, Measure, Movement, Note,
+    NoteheadStyle, Pitch, Scof, Sig, SigRef,
+};
+
+/// Number of recent spellings to consider when spelling a pitch.
+const SPELLING_CONTEXT: usize = 8;
+
+/// Ticks per quarter note in exported files.
+const EXPORT_DIVISION: u16 = 480;
+
+/// An error importing a MIDI file.
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    /// Not a Standard MIDI File (missing `MThd` header).
+    NotMidi,
+    /// The file ended in the middle of a chunk or event.
+    UnexpectedEnd,
+    /// SMPTE time division isn't supported, only ticks per quarter note.
+    SmpteTiming,
+    /// The quantization grid doesn't evenly divide a measure.
+    InvalidGrid,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::NotMidi => write!(f, "Not a Standard MIDI File"),
+            ImportError::UnexpectedEnd => write!(f, "Unexpected end of file"),
+            ImportError::SmpteTiming => write!(f, "SMPTE timing unsupported"),
+            ImportError::InvalidGrid => write!(f, "Invalid quantization grid"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// A note read from a MIDI track, in ticks.
+struct MidiNote {
+    /// MIDI channel (0-15).
+    chan: u8,
+    /// MIDI note number.
+    key: u8,
+    /// Start time in ticks.
+    start: u32,
+    /// End time in ticks.
+    end: u32,
+}
+
+/// Reader over the bytes of a MIDI file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Read a slice of bytes.
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ImportError> {
+        let end = self.index.checked_add(len);
+        let bytes = end
+            .and_then(|end| self.bytes.get(self.index..end))
+            .ok_or(ImportError::UnexpectedEnd)?;
+        self.index += len;
+        Ok(bytes)
+    }
+
+    /// Read a byte.
+    fn u8(&mut self) -> Result<u8, ImportError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Read a big-endian 32-bit integer.
+    fn u32(&mut self) -> Result<u32, ImportError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Read a variable-length quantity.
+    fn vlq(&mut self) -> Result<u32, ImportError> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | u32::from(byte & 0x7F);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Read the notes of a track, appending them to `notes`.  Returns the tempo
+/// (microseconds per quarter note) if the track sets one.
+fn read_track(
+    track: &[u8],
+    notes: &mut Vec<MidiNote>,
+) -> Result<Option<u32>, ImportError> {
+    let mut reader = Reader {
+        bytes: track,
+        index: 0,
+    };
+    let mut tempo = None;
+    let mut time = 0u32;
+    let mut status = 0u8;
+    // Start times of sounding notes for each channel and key.
+    let mut sounding: HashMap<(u8, u8), Vec<u32>> = HashMap::new();
+
+    while reader.index < track.len() {
+        time = time.saturating_add(reader.vlq()?);
+        let mut byte = reader.u8()?;
+        if byte >= 0xF0 {
+            // Meta and sysex events cancel running status.
+            status = 0;
+            match byte {
+                0xFF => {
+                    let kind = reader.u8()?;
+                    let len = reader.vlq()? as usize;
+                    let data = reader.bytes(len)?;
+                    match kind {
+                        0x2F => break, // End of track
+                        0x51 if len == 3 && tempo.is_none() => {
+                            tempo = Some(
+                                u32::from(data[0]) << 16
+                                    | u32::from(data[1]) << 8
+                                    | u32::from(data[2]),
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+                0xF0 | 0xF7 => {
+                    let len = reader.vlq()? as usize;
+                    reader.bytes(len)?;
+                }
+                _ => return Err(ImportError::NotMidi),
+            }
+            continue;
+        }
+        if byte >= 0x80 {
+            status = byte;
+            byte = reader.u8()?;
+        }
+        // `byte` is now the first data byte (running status).
+        match status {
+            0x80..=0x9F => {
+                let chan = status & 0x0F;
+                let key = byte;
+                let velocity = reader.u8()?;
+                let starts = sounding.entry((chan, key)).or_default();
+                if status >= 0x90 && velocity != 0 {
+                    starts.push(time);
+                } else if !starts.is_empty() {
+                    let start = starts.remove(0);
+                    notes.push(MidiNote {
+                        chan,
+                        key,
+                        start,
+                        end: time,
+                    });
+                }
+            }
+            0xC0..=0xDF => {}
+            0xA0..=0xEF => {
+                reader.u8()?;
+            }
+            _ => return Err(ImportError::NotMidi),
+        }
+    }
+
+    Ok(tempo)
+}
+
+/// Import a Standard MIDI File as a single movement score, quantizing note
+/// starts and ends to `quantize`.  Each MIDI channel becomes a part, and
+/// overlapping notes on a channel become chords.
+///
+/// FIXME: Time signatures (assumes 4/4).
+pub fn import_midi(
+    bytes: &[u8],
+    quantize: Fraction,
+) -> Result<Scof, ImportError> {
+    let mut reader = Reader { bytes, index: 0 };
+
+    // Header chunk
+    if reader.bytes(4).map_err(|_| ImportError::NotMidi)? != b"MThd" {
+        return Err(ImportError::NotMidi);
+    }
+    let len = reader.u32()? as usize;
+    let header = reader.bytes(len)?;
+    if header.len() < 6 {
+        return Err(ImportError::UnexpectedEnd);
+    }
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if division & 0x8000 != 0 {
+        return Err(ImportError::SmpteTiming);
+    }
+
+    // Grid units per measure
+    let per_bar = Fraction::new(1, 1) / quantize;
+    if quantize.num == 0 || per_bar.den != 1 || per_bar.num == 0 {
+        return Err(ImportError::InvalidGrid);
+    }
+    let per_bar = u32::from(per_bar.num);
+    let bar_ticks = u32::from(division) * 4;
+
+    // Track chunks
+    let mut notes = vec![];
+    let mut tempo = None;
+    while reader.index < bytes.len() {
+        let kind = reader.bytes(4)?;
+        let len = reader.u32()? as usize;
+        let chunk = reader.bytes(len)?;
+        if kind == b"MTrk" {
+            let track_tempo = read_track(chunk, &mut notes)?;
+            tempo = tempo.or(track_tempo);
+        }
+    }
+
+    // Quantize a time in ticks to grid units.
+    let grid_units = |ticks: u32| -> u32 {
+        if bar_ticks == 0 {
+            return 0;
+        }
+        let bar = ticks / bar_ticks;
+        let offset = ticks % bar_ticks;
+        // Large divisions are scaled down to fit a fraction.
+        let scale = bar_ticks / u32::from(u16::MAX) + 1;
+        let offset = Fraction::new(
+            (offset / scale).try_into().unwrap_or(0),
+            (bar_ticks / scale).try_into().unwrap_or(1),
+        )
+        .quantize(quantize);
+        let units = offset / quantize;
+        bar * per_bar + u32::from(units.num) / u32::from(units.den)
+    };
+
+    // Group notes into parts by channel.
+    let mut chans: Vec<u8> = notes.iter().map(|note| note.chan).collect();
+    chans.sort_unstable();
+    chans.dedup();
+    let mut parts = vec![];
+    let mut end = 0;
+    for chan in &chans {
+        let mut part = vec![];
+        for note in notes.iter().filter(|note| note.chan == *chan) {
+            let start = grid_units(note.start);
+            let stop = grid_units(note.end).max(start + 1);
+            end = end.max(stop);
+            part.push((start, stop, note.key));
+        }
+        part.sort_unstable();
+        parts.push(part);
+    }
+    let bars = end.div_ceil(per_bar).max(1);
+    let end = bars * per_bar;
+
+    // Notate each part.
+    let mut measures: Vec<Measure> = (0..bars)
+        .map(|_| Measure {
+            sig: None,
+            chan: vec![],
+            repeat: vec![],
+            barline: None,
+            break_before: None,
+        })
+        .collect();
+    measures[0].sig = Some(SigRef {
+        index: 0,
+        beat: None,
+    });
+    if parts.is_empty() {
+        for measure in measures.iter_mut() {
+            measure.chan.push(Channel::default());
+        }
+    }
+    for part in parts {
+        // Every time a note starts or stops, or a bar starts.
+        let mut times: Vec<u32> = (0..=bars).map(|bar| bar * per_bar).collect();
+        for (start, stop, _) in &part {
+            times.push(*start);
+            times.push(*stop);
+        }
+        times.sort_unstable();
+        times.dedup();
+
+        let mut channels: Vec<Channel> =
+            (0..bars).map(|_| Channel::default()).collect();
+        let mut spellings: Vec<Pitch> = vec![];
+        for window in times.windows(2) {
+            let (start, stop) = (window[0], window[1]);
+            if start >= end {
+                break;
+            }
+            let sounding: Vec<&(u32, u32, u8)> = part
+                .iter()
+                .filter(|note| note.0 <= start && note.1 > start)
+                .collect();
+            // Tie if every note keeps sounding after this segment.
+            let tie = !sounding.is_empty()
+                && sounding.iter().all(|note| note.1 > stop);
+            let mut keys: Vec<u8> =
+                sounding.iter().map(|note| note.2).collect();
+            keys.sort_unstable();
+            keys.dedup();
+            let mut pitch = vec![];
+            for key in keys {
+                let context = spellings.len().saturating_sub(SPELLING_CONTEXT);
+                let spelled =
+                    spell_pitch(i32::from(key), 0, &spellings[context..]);
+                spellings.push(spelled);
+                pitch.push(spelled);
+            }
+            let duration = Fraction::new(
+                (stop - start).try_into().unwrap_or(0),
+                per_bar.try_into().unwrap_or(1),
+            )
+            .simplify();
+            channels[(start / per_bar) as usize]
+                .notes
+                .push(Marking::Note(Note {
+                    pitch,
+                    duration,
+                    articulation: vec![],
+                    tremolo: 0,
+                    tie,
+                    beam_break: false,
+                    feather: None,
+                    head: NoteheadStyle::Normal,
+                }));
+        }
+        for (measure, channel) in measures.iter_mut().zip(channels) {
+            measure.chan.push(channel);
+        }
+    }
+
+    let sig = Sig {
+        key: 0,
+        time: "4/4".to_string(),
+        tempo: tempo
+            .and_then(|tempo| (60_000_000 / tempo.max(1)).try_into().ok())
+            .unwrap_or(120),
+        swing: None,
+    };
+    let mut scof = Scof {
+        movement: vec![Movement {
+            sig: vec![sig],
+            bar: measures,
+        }],
+        cache: vec![vec![]],
+        ..Default::default()
+    };
+    scof.coalesce_ties();
+
+    Ok(scof)
+}
+
+/// Write a variable-length quantity.
+fn write_vlq(bytes: &mut Vec<u8>, value: u32) {
+    let mut shift = 21;
+    while shift > 0 && value >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        bytes.push(0x80 | (value >> shift) as u8 & 0x7F);
+        shift -= 7;
+    }
+    bytes.push(value as u8 & 0x7F);
+}
+
+/// Export a movement as a single track Standard MIDI File, as it's played
+/// back.  Each part plays on its own MIDI channel, and the notes of a chord
+/// start at the same time (unless strummed).
+///
+/// FIXME: Tempo changes (uses the first tempo of the movement).
+pub fn export_midi(scof: &Scof, movement: u16) -> Vec<u8> {
+    let whole = f64::from(EXPORT_DIVISION) * 4.0;
+    let ticks = |time: f64| (time * whole).round().max(0.0) as u32;
+
+    // Note on and off events: time in ticks, whether it's a note on, MIDI
+    // channel, key and velocity.
+    let mut events = vec![];
+    for event in scof.playback_events(movement) {
+        let key: u8 = match event.midi.try_into() {
+            Ok(key) if key < 128 => key,
+            _ => continue,
+        };
+        let chan = (event.chan % 16) as u8;
+        let start = ticks(event.start);
+        let end = ticks(event.start + event.duration).max(start + 1);
+        events.push((start, true, chan, key, event.velocity.clamp(1, 127)));
+        events.push((end, false, chan, key, 0));
+    }
+    // Stable sort keeps chord tones together; note offs come first so
+    // repeated notes aren't cut short.
+    events.sort_by_key(|event| (event.0, event.1));
+
+    let tempo = scof
+        .movement
+        .get(movement as usize)
+        .and_then(|movement| movement.sig.first())
+        .map_or(120, |sig| u32::from(sig.tempo).max(1));
+    let tempo = 60_000_000 / tempo;
+    let mut track = vec![0x00, 0xFF, 0x51, 0x03];
+    track.extend(&tempo.to_be_bytes()[1..]);
+    let mut time = 0;
+    for (tick, on, chan, key, velocity) in events {
+        write_vlq(&mut track, tick - time);
+        time = tick;
+        let status = if on { 0x90 } else { 0x80 };
+        track.extend(&[status | chan, key, velocity]);
+    }
+    track.extend(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    let mut bytes = b"MThd\0\0\0\x06\0\0\0\x01".to_vec();
+    bytes.extend(&EXPORT_DIVISION.to_be_bytes());
+    bytes.extend(b"MTrk");
+    bytes.extend(&(track.len() as u32).to_be_bytes());
+    bytes.extend(track);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a Standard MIDI File from a single track's events.
+    fn smf(division: u16, events: &[u8]) -> Vec<u8> {
+        let mut bytes = b"MThd\0\0\0\x06\0\0\0\x01".to_vec();
+        bytes.extend(&division.to_be_bytes());
+        bytes.extend(b"MTrk");
+        bytes.extend(&(events.len() as u32 + 4).to_be_bytes());
+        bytes.extend(events);
+        bytes.extend(&[0x00, 0xFF, 0x2F, 0x00]);
+        bytes
+    }
+
+    /// Get the notes of a channel in a measure.
+    fn notes(scof: &Scof, bar: usize, chan: usize) -> String {
+        let notes = &scof.movement[0].bar[bar].chan[chan].notes;
+        let notes: Vec<String> = notes
+            .iter()
+            .map(|marking| match marking {
+                Marking::Note(note) => note.to_string(),
+                _ => unreachable!(),
+            })
+            .collect();
+        notes.join(" ")
+    }
+
+    #[test]
+    fn four_quarters() {
+        let bytes = smf(
+            96,
+            &[
+                0x00, 0x90, 60, 64, 0x60, 0x80, 60, 0, // C4
+                0x00, 0x90, 62, 64, 0x60, 0x80, 62, 0, // D4
+                0x00, 0x90, 64, 64, 0x5E, 0x80, 64, 0, // E4 (early)
+                0x02, 0x90, 65, 64, 0x60, 65, 0, // F4 (running status)
+            ],
+        );
+        let scof = import_midi(&bytes, Fraction::new(1, 16)).unwrap();
+        assert_eq!(scof.movement.len(), 1);
+        assert_eq!(scof.movement[0].bar.len(), 1);
+        assert_eq!(scof.movement[0].bar[0].chan.len(), 1);
+        assert_eq!(scof.movement[0].sig[0].time, "4/4");
+        assert_eq!(notes(&scof, 0, 0), "1/4C4 1/4D4 1/4E4 1/4F4");
+    }
+
+    #[test]
+    fn running_status_after_meta() {
+        // A meta event between notes cancels running status.
+        let bytes = smf(
+            96,
+            &[
+                0x00, 0x90, 60, 64, 0x60, 0x80, 60, 0, // C4
+                0x00, 0xFF, 0x01, 0x01, b'x', // Text
+                0x00, 62, 64, // D4 (no status)
+            ],
+        );
+        assert!(import_midi(&bytes, Fraction::new(1, 16)).is_err());
+    }
+
+    #[test]
+    fn chords_and_parts() {
+        let bytes = smf(
+            96,
+            &[
+                0x00, 0x90, 60, 64, 0x00, 0x90, 64, 64, // C4 + E4
+                0x00, 0x91, 48, 64, // C3 on channel 2
+                0x81, 0x40, 0x80, 60, 0, 0x00, 0x80, 64, 0, // half notes
+                0x00, 0x81, 48, 0,
+            ],
+        );
+        let scof = import_midi(&bytes, Fraction::new(1, 16)).unwrap();
+        assert_eq!(notes(&scof, 0, 0), "1/2C4E4 1/2R");
+        assert_eq!(notes(&scof, 0, 1), "1/2C3 1/2R");
+    }
+
+    #[test]
+    fn export_chord() {
+        let mut scof = Scof::default();
+        scof.movement[0].bar.truncate(1);
+        scof.movement[0].bar[0].chan = vec!["1/4C4E4G4 3/4R".parse().unwrap()];
+        let bytes = export_midi(&scof, 0);
+        assert!(bytes.starts_with(b"MThd"));
+        // Skip the header, track chunk header and tempo.
+        let events = &bytes[14 + 8 + 7..];
+        assert_eq!(
+            events,
+            [
+                0x00, 0x90, 60, 80, 0x00, 0x90, 64, 80, 0x00, 0x90, 67, 80,
+                0x83, 0x60, 0x80, 60, 0, 0x00, 0x80, 64, 0, 0x00, 0x80, 67, 0,
+                0x00, 0xFF, 0x2F, 0x00,
+            ]
+        );
+
+        // Exported chords import back.
+        let scof = import_midi(&bytes, Fraction::new(1, 16)).unwrap();
+        assert_eq!(notes(&scof, 0, 0), "1/4C4E4G4 3/4R");
+    }
+
+    #[test]
+    fn strum() {
+        let mut scof = Scof::default();
+        scof.movement[0].bar.truncate(1);
+        scof.movement[0].bar[0].chan = vec!["1/4C4E4G4 3/4R".parse().unwrap()];
+        if let Marking::Note(note) =
+            &mut scof.movement[0].bar[0].chan[0].notes[0]
+        {
+            note.articulation.push(crate::Articulation::StrumUp);
+        }
+        let events = scof.playback_events(0);
+        let starts: Vec<(i32, f64)> =
+            events.iter().map(|e| (e.midi, e.start)).collect();
+        assert_eq!(starts, [(67, 0.0), (64, 1.0 / 64.0), (60, 1.0 / 32.0)]);
+        assert!(events.iter().all(|e| e.start + e.duration == 0.25));
+    }
+
+    #[test]
+    fn not_midi() {
+        assert_eq!(
+            import_midi(b"RIFF", Fraction::new(1, 16)).err(),
+            Some(ImportError::NotMidi)
+        );
+    }
+}
@@ -3,7 +3,7 @@
 use std::{fmt, str::FromStr};
 
 /// An articulation (affects how the note is played).
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Articulation {
     /// Really separated.
     Staccatissimo,
@@ -0,0 +1,112 @@
+use crate::note::Pitch;
+
+/// Semitones above the lower pitch of each simple interval number (major or
+/// perfect).
+const SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Quality of an interval.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IntervalQuality {
+    /// Diminished (1), doubly diminished (2), etc.
+    Diminished(u8),
+    /// Minor (second, third, sixth or seventh)
+    Minor,
+    /// Perfect (unison, fourth, fifth or octave)
+    Perfect,
+    /// Major (second, third, sixth or seventh)
+    Major,
+    /// Augmented (1), doubly augmented (2), etc.
+    Augmented(u8),
+}
+
+/// A diatonic interval between two pitches.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Interval {
+    /// Number of steps, counting both pitches (1 is a unison, 8 an octave,
+    /// 10 a compound third).
+    pub number: u8,
+    /// Quality of the interval.
+    pub quality: IntervalQuality,
+}
+
+impl Interval {
+    /// Create a new interval.
+    pub fn new(number: u8, quality: IntervalQuality) -> Self {
+        assert_ne!(number, 0);
+        Interval { number, quality }
+    }
+
+    /// Get the interval between two pitches (in either order), from their
+    /// names and accidentals.  Quarter tones are rounded down.
+    pub fn between(a: &Pitch, b: &Pitch) -> Self {
+        let (low, high) = if (a.visual_distance().0, a.midi())
+            <= (b.visual_distance().0, b.midi())
+        {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        let steps = high.visual_distance().0 - low.visual_distance().0;
+        let offset = high.midi() - low.midi() - base_semitones(steps);
+        let perfect = matches!(steps.rem_euclid(7), 0 | 3 | 4);
+        let quality = match offset {
+            0 if perfect => IntervalQuality::Perfect,
+            0 => IntervalQuality::Major,
+            -1 if !perfect => IntervalQuality::Minor,
+            o if o > 0 => IntervalQuality::Augmented(o as u8),
+            o if perfect => IntervalQuality::Diminished(-o as u8),
+            o => IntervalQuality::Diminished((-o - 1) as u8),
+        };
+        Interval::new(steps as u8 + 1, quality)
+    }
+
+    /// Get the number of semitones the interval spans.
+    pub fn semitones(&self) -> i32 {
+        let steps = i32::from(self.number) - 1;
+        let perfect = matches!(steps.rem_euclid(7), 0 | 3 | 4);
+        let offset = match self.quality {
+            IntervalQuality::Perfect | IntervalQuality::Major => 0,
+            IntervalQuality::Minor => -1,
+            IntervalQuality::Augmented(n) => i32::from(n),
+            IntervalQuality::Diminished(n) if perfect => -i32::from(n),
+            IntervalQuality::Diminished(n) => -i32::from(n) - 1,
+        };
+        base_semitones(steps) + offset
+    }
+}
+
+/// Get the semitones of the major or perfect interval `steps` steps wide.
+fn base_semitones(steps: i32) -> i32 {
+    SEMITONES[steps.rem_euclid(7) as usize] + 12 * steps.div_euclid(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn between() {
+        use IntervalQuality::*;
+
+        let interval = |a: &str, b: &str| {
+            Interval::between(&a.parse().unwrap(), &b.parse().unwrap())
+        };
+        let major_third = interval("C4", "E4");
+        assert_eq!(major_third, Interval::new(3, Major));
+        assert_eq!(major_third.semitones(), 4);
+        let minor_third = interval("C4", "Eb4");
+        assert_eq!(minor_third, Interval::new(3, Minor));
+        assert_eq!(minor_third.semitones(), 3);
+        let tritone = interval("C4", "F#4");
+        assert_eq!(tritone, Interval::new(4, Augmented(1)));
+        assert_eq!(tritone.semitones(), 6);
+        // Spelled differently: a diminished fifth.
+        assert_eq!(interval("C4", "Gb4"), Interval::new(5, Diminished(1)));
+        // Either order, and compound intervals.
+        assert_eq!(interval("G4", "C4"), Interval::new(5, Perfect));
+        assert_eq!(interval("C4", "C5"), Interval::new(8, Perfect));
+        assert_eq!(interval("C4", "E5").semitones(), 16);
+        assert_eq!(interval("C#4", "Eb4"), Interval::new(3, Diminished(1)));
+        assert_eq!(interval("C#4", "Eb4").semitones(), 2);
+    }
+}
@@ -65,15 +65,24 @@ use std::convert::TryInto;
 use std::{fmt, str::FromStr};
 
 mod articulation;
+mod interval;
 mod pitch;
 
 pub use self::articulation::*;
+pub use self::interval::*;
 pub use self::pitch::*;
 
 /// Number of steps above middle C
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Debug)]
 pub struct Steps(pub i32);
 
+impl Steps {
+    /// Get the distance in steps, regardless of direction.
+    pub fn abs(self) -> Steps {
+        Steps(self.0.abs())
+    }
+}
+
 impl std::ops::Add for Steps {
     type Output = Self;
 
@@ -90,6 +99,14 @@ impl std::ops::Sub for Steps {
     }
 }
 
+impl std::ops::Neg for Steps {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Steps(-self.0)
+    }
+}
+
 impl std::ops::Mul<i32> for Steps {
     type Output = Self;
 
@@ -106,6 +123,69 @@ impl std::ops::Div<i32> for Steps {
     }
 }
 
+/// Direction of a feathered beam, which fans out or in to show a gradual
+/// change of tempo.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Feather {
+    /// Getting faster: the beams fan out ("{").
+    Accelerando,
+    /// Getting slower: the beams fan in ("}").
+    Ritardando,
+}
+
+/// Shape of a note's heads.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoteheadStyle {
+    /// Oval (no symbol).
+    Normal,
+    /// X, for unpitched sounds ("x").
+    X,
+    /// Diamond, for harmonics ("d").
+    Diamond,
+    /// Triangle ("t").
+    Triangle,
+    /// Slash, for rhythms without pitch ("s").
+    Slash,
+}
+
+impl NoteheadStyle {
+    /// Get the next style, wrapping around to `Normal` after the last.
+    pub fn next(self) -> Self {
+        use NoteheadStyle::*;
+        match self {
+            Normal => X,
+            X => Diamond,
+            Diamond => Triangle,
+            Triangle => Slash,
+            Slash => Normal,
+        }
+    }
+
+    /// Get the symbol written after a note's pitches (`None` for `Normal`).
+    fn symbol(self) -> Option<char> {
+        use NoteheadStyle::*;
+        match self {
+            Normal => None,
+            X => Some('x'),
+            Diamond => Some('d'),
+            Triangle => Some('t'),
+            Slash => Some('s'),
+        }
+    }
+
+    /// Get the style written with a symbol.
+    fn from_symbol(symbol: char) -> Option<Self> {
+        use NoteheadStyle::*;
+        Some(match symbol {
+            'x' => X,
+            'd' => Diamond,
+            't' => Triangle,
+            's' => Slash,
+            _ => return None,
+        })
+    }
+}
+
 /// A note.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Note {
@@ -113,8 +193,18 @@ pub struct Note {
     pub pitch: Vec<Pitch>,
     /// Duration of the note as a fraction.
     pub duration: Fraction,
-    /// Articulation.
+    /// Articulation, in canonical (declaration) order without duplicates.
     pub articulation: Vec<Articulation>,
+    /// Number of tremolo slashes on the stem (0 for none).
+    pub tremolo: u8,
+    /// Tied to the following note ("~").
+    pub tie: bool,
+    /// Force a new beam group to start at this note ("[").
+    pub beam_break: bool,
+    /// Feather the beams of the group starting at this note.
+    pub feather: Option<Feather>,
+    /// Style of the note's heads.
+    pub head: NoteheadStyle,
 }
 
 impl fmt::Display for Note {
@@ -132,6 +222,10 @@ impl fmt::Display for Note {
                 // Write note name & octave.
                 write!(f, "{}", pitch)?;
             }
+            // Write notehead style.
+            if let Some(symbol) = self.head.symbol() {
+                write!(f, "{}", symbol)?;
+            }
         }
 
         // Write articulation symbols.
@@ -139,6 +233,28 @@ impl fmt::Display for Note {
             write!(f, "{}", articulation)?;
         }
 
+        // Write tremolo slashes.
+        for _ in 0..self.tremolo {
+            write!(f, "/")?;
+        }
+
+        // Write beam break.
+        if self.beam_break {
+            write!(f, "[")?;
+        }
+
+        // Write feathered beam.
+        match self.feather {
+            Some(Feather::Accelerando) => write!(f, "{{")?,
+            Some(Feather::Ritardando) => write!(f, "}}")?,
+            None => {}
+        }
+
+        // Write tie.
+        if self.tie {
+            write!(f, "~")?;
+        }
+
         Ok(())
     }
 }
@@ -173,49 +289,84 @@ impl Note {
         self.duration
     }
 
+    /// Get how long the note sounds for and how hard it's played, after
+    /// applying articulations to `base_velocity` (0-127).  Staccato sounds
+    /// for half of the written duration and staccatissimo for a quarter, while
+    /// accents and marcato are played louder.
+    pub fn playback_params(&self, base_velocity: u8) -> (Fraction, u8) {
+        use Articulation::*;
+
+        let mut sounding = Fraction::new(1, 1);
+        let mut boost = 0;
+        for articulation in &self.articulation {
+            match articulation {
+                Staccatissimo => sounding = Fraction::new(1, 4),
+                Staccato if sounding > Fraction::new(1, 2) => {
+                    sounding = Fraction::new(1, 2)
+                }
+                // Tenuto holds the note for its full duration.
+                Tenuto => {}
+                Accent => boost = boost.max(20),
+                Marcato => boost = boost.max(30),
+                _ => {}
+            }
+        }
+
+        (
+            self.duration * sounding,
+            base_velocity.saturating_add(boost).min(127),
+        )
+    }
+
     fn move_step(
         &self,
         i: usize,
         create: Pitch,
-        run: &dyn Fn(&Pitch) -> Pitch,
-    ) -> Note {
+        run: &dyn Fn(&Pitch) -> Option<Pitch>,
+    ) -> Option<Note> {
         let mut pitch = self.pitch.clone();
         if let Some(old_pitch) = self.pitch.get(i) {
-            pitch[i] = (run)(old_pitch);
+            pitch[i] = (run)(old_pitch)?;
         } else {
             pitch.resize(i + 1, create);
         };
 
-        Note {
+        Some(Note {
             pitch,
             duration: self.duration,
             articulation: self.articulation.clone(),
-        }
+            tremolo: self.tremolo,
+            tie: self.tie,
+            beam_break: self.beam_break,
+            feather: self.feather,
+            head: self.head,
+        })
     }
 
     /// Calculate note one quarter step up.
-    pub fn quarter_step_up(&self, i: usize, create: Pitch) -> Note {
+    pub fn quarter_step_up(&self, i: usize, create: Pitch) -> Option<Note> {
         self.step_up(i, create) // FIXME
     }
 
     /// Calculate note one quarter step down.
-    pub fn quarter_step_down(&self, i: usize, create: Pitch) -> Note {
+    pub fn quarter_step_down(&self, i: usize, create: Pitch) -> Option<Note> {
         self.step_down(i, create) // FIXME
     }
 
     /// Calculate note one half step up.
-    pub fn half_step_up(&self, i: usize, create: Pitch) -> Note {
+    pub fn half_step_up(&self, i: usize, create: Pitch) -> Option<Note> {
         self.step_up(i, create) // FIXME
     }
 
     /// Calculate note one half step down.
-    pub fn half_step_down(&self, i: usize, create: Pitch) -> Note {
+    pub fn half_step_down(&self, i: usize, create: Pitch) -> Option<Note> {
         self.step_down(i, create) // FIXME
     }
 
-    /// Calculate note one step up within the key.
+    /// Calculate note one step up within the key, or `None` if the pitch is
+    /// B9 (the highest B), which has no step above it.
     /// - `create`: Note that is generated from a rest.
-    pub fn step_up(&self, i: usize, create: Pitch) -> Note {
+    pub fn step_up(&self, i: usize, create: Pitch) -> Option<Note> {
         self.move_step(i, create, &|pitch| {
             let (pitch_class, offset) = match pitch.0.name {
                 PitchName::A => (PitchName::B, false),
@@ -226,29 +377,22 @@ impl Note {
                 PitchName::F => (PitchName::G, false),
                 PitchName::G => (PitchName::A, false),
             };
-            let pitch_octave = if offset {
-                pitch.1.raise()
-            } else {
-                Some(pitch.1)
-            };
-
-            if let Some(pitch_octave) = pitch_octave {
-                Pitch(
-                    PitchClass {
-                        name: pitch_class,
-                        accidental: pitch.0.accidental,
-                    },
-                    pitch_octave,
-                )
-            } else {
-                *pitch
-            }
+            let pitch_octave = if offset { pitch.1.raise()? } else { pitch.1 };
+
+            Some(Pitch(
+                PitchClass {
+                    name: pitch_class,
+                    accidental: pitch.0.accidental,
+                },
+                pitch_octave,
+            ))
         })
     }
 
-    /// Calculate note one step down within the key.
+    /// Calculate note one step down within the key, or `None` if the pitch
+    /// is C- (the lowest C), which has no step below it.
     /// - `create`: Note that is generated from a rest.
-    pub fn step_down(&self, i: usize, create: Pitch) -> Note {
+    pub fn step_down(&self, i: usize, create: Pitch) -> Option<Note> {
         self.move_step(i, create, &|pitch| {
             let (pitch_class, offset) = match pitch.0.name {
                 PitchName::A => (PitchName::G, false),
@@ -259,23 +403,15 @@ impl Note {
                 PitchName::F => (PitchName::E, false),
                 PitchName::G => (PitchName::F, false),
             };
-            let pitch_octave = if offset {
-                pitch.1.lower()
-            } else {
-                Some(pitch.1)
-            };
-
-            if let Some(pitch_octave) = pitch_octave {
-                Pitch(
-                    PitchClass {
-                        name: pitch_class,
-                        accidental: pitch.0.accidental,
-                    },
-                    pitch_octave,
-                )
-            } else {
-                Pitch(pitch.0, pitch.1)
-            }
+            let pitch_octave = if offset { pitch.1.lower()? } else { pitch.1 };
+
+            Some(Pitch(
+                PitchClass {
+                    name: pitch_class,
+                    accidental: pitch.0.accidental,
+                },
+                pitch_octave,
+            ))
         })
     }
 }
@@ -334,20 +470,58 @@ impl FromStr for Note {
             });
         }
 
-        // Read articulation symbols.
+        // Read articulation symbols, tremolo slashes, beam break, feathered
+        // beam and tie.
         let mut articulation = vec![];
+        let mut tremolo = 0;
+        let mut tie = false;
+        let mut beam_break = false;
+        let mut feather = None;
+        let mut head = NoteheadStyle::Normal;
         let mut articulation_str = "".to_string();
         for articulation_char in s[end_index..].chars() {
+            if articulation_char == '/' {
+                tremolo += 1;
+                continue;
+            }
+            if articulation_char == '~' {
+                tie = true;
+                continue;
+            }
+            if articulation_char == '[' {
+                beam_break = true;
+                continue;
+            }
+            if let Some(style) = NoteheadStyle::from_symbol(articulation_char) {
+                head = style;
+                continue;
+            }
+            if articulation_char == '{' {
+                feather = Some(Feather::Accelerando);
+                continue;
+            }
+            if articulation_char == '}' {
+                feather = Some(Feather::Ritardando);
+                continue;
+            }
             articulation_str.clear();
             articulation_str.push(articulation_char);
             articulation
                 .push(articulation_str.parse::<Articulation>().or(Err(()))?);
         }
+        // Equivalent notes compare (and write) the same.
+        articulation.sort_unstable();
+        articulation.dedup();
 
         Ok(Note {
             pitch,
             duration,
             articulation,
+            tremolo,
+            tie,
+            beam_break,
+            feather,
+            head,
         })
     }
 }
@@ -364,7 +538,112 @@ mod tests {
                 pitch: vec![],
                 duration: Fraction::new(1, 1),
                 articulation: vec![],
+                tremolo: 0,
+                tie: false,
+                beam_break: false,
+                feather: None,
+                head: NoteheadStyle::Normal,
             }
         );
     }
+
+    #[test]
+    fn steps() {
+        assert_eq!(-Steps(3), Steps(-3));
+        assert_eq!(Steps(-5).abs(), Steps(5));
+        assert_eq!(Steps(5).abs(), Steps(5));
+    }
+
+    #[test]
+    fn step_limits() {
+        let c4: Pitch = "C4".parse().unwrap();
+        let note = |note: &str| note.parse::<Note>().unwrap();
+        let up = |n: &str| note(n).step_up(0, c4).map(|n| n.to_string());
+        let down = |n: &str| note(n).step_down(0, c4).map(|n| n.to_string());
+        assert_eq!(up("1/4A9"), Some("1/4B9".to_string()));
+        assert_eq!(up("1/4B9"), None);
+        assert_eq!(down("1/4D-"), Some("1/4C-".to_string()));
+        assert_eq!(down("1/4C-"), None);
+        assert_eq!(up("1/4B8"), Some("1/4C9".to_string()));
+        // Rests become the created pitch.
+        assert_eq!(up("1/4R"), Some("1/4C4".to_string()));
+    }
+
+    #[test]
+    fn notehead_style() {
+        let note = "1/4C4E4x.".parse::<Note>().unwrap();
+        assert_eq!(note.head, NoteheadStyle::X);
+        assert_eq!(note.articulation, [Articulation::Staccato]);
+        assert_eq!(note.to_string(), "1/4C4E4x.");
+        assert_eq!("1/2G4d".parse::<Note>().unwrap().to_string(), "1/2G4d");
+        let mut style = NoteheadStyle::Normal;
+        for _ in 0..5 {
+            style = style.next();
+        }
+        assert_eq!(style, NoteheadStyle::Normal);
+    }
+
+    #[test]
+    fn tremolo() {
+        let note = "1/4C4.///".parse::<Note>().unwrap();
+        assert_eq!(note.tremolo, 3);
+        assert_eq!(note.articulation, [Articulation::Staccato]);
+        assert_eq!(note.to_string(), "1/4C4.///");
+    }
+
+    #[test]
+    fn playback_params() {
+        let params =
+            |note: &str| note.parse::<Note>().unwrap().playback_params(80);
+        assert_eq!(params("1/4C4"), (Fraction::new(1, 4), 80));
+        assert_eq!(params("1/4C4."), (Fraction::new(1, 8), 80));
+        assert_eq!(params("1/4C4'"), (Fraction::new(1, 16), 80));
+        assert_eq!(params("1/4C4_"), (Fraction::new(1, 4), 80));
+        assert!(params("1/4C4>").1 > 80);
+        assert!(params("1/4C4^").1 > params("1/4C4>").1);
+        assert_eq!(params("1/4C4.>"), (Fraction::new(1, 8), 100));
+        assert_eq!(
+            "1/4C4^".parse::<Note>().unwrap().playback_params(120).1,
+            127
+        );
+    }
+
+    #[test]
+    fn articulation_order() {
+        let a = "1/4C4>.".parse::<Note>().unwrap();
+        let b = "1/4C4.>".parse::<Note>().unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.to_string(), b.to_string());
+        assert_eq!(
+            a.articulation,
+            [Articulation::Staccato, Articulation::Accent]
+        );
+        assert_eq!("1/4C4...".parse::<Note>().unwrap().to_string(), "1/4C4.");
+    }
+
+    #[test]
+    fn tie() {
+        let note = "1/4C4~".parse::<Note>().unwrap();
+        assert!(note.tie);
+        assert_eq!(note.to_string(), "1/4C4~");
+    }
+
+    #[test]
+    fn beam_break() {
+        let note = "1/8C4.[~".parse::<Note>().unwrap();
+        assert!(note.beam_break);
+        assert!(note.tie);
+        assert_eq!(note.to_string(), "1/8C4.[~");
+    }
+
+    #[test]
+    fn feather() {
+        let note = "1/16C4[{".parse::<Note>().unwrap();
+        assert!(note.beam_break);
+        assert_eq!(note.feather, Some(Feather::Accelerando));
+        assert_eq!(note.to_string(), "1/16C4[{");
+        let note = "1/16C4}~".parse::<Note>().unwrap();
+        assert_eq!(note.feather, Some(Feather::Ritardando));
+        assert_eq!(note.to_string(), "1/16C4}~");
+    }
 }
@@ -1,7 +1,9 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
 
 use crate::note::Steps;
+use crate::Clef;
 
 /// A Pitch Name.
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -15,6 +17,23 @@ pub enum PitchName {
     B = 6,
 }
 
+impl PitchName {
+    /// Get the number of semitones above C.
+    pub fn semitones(self) -> i32 {
+        use PitchName::*;
+
+        match self {
+            C => 0,
+            D => 2,
+            E => 4,
+            F => 5,
+            G => 7,
+            A => 9,
+            B => 11,
+        }
+    }
+}
+
 impl fmt::Display for PitchName {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         use PitchName::*;
@@ -71,6 +90,25 @@ pub enum PitchAccidental {
     DoubleSharp,
 }
 
+impl PitchAccidental {
+    /// Get the number of quarter steps the accidental raises the pitch.
+    pub fn quarter_steps(self) -> i32 {
+        use PitchAccidental::*;
+
+        match self {
+            DoubleFlat => -4,
+            FlatQuarterFlat => -3,
+            Flat => -2,
+            QuarterFlat => -1,
+            Natural => 0,
+            QuarterSharp => 1,
+            Sharp => 2,
+            SharpQuarterSharp => 3,
+            DoubleSharp => 4,
+        }
+    }
+}
+
 impl fmt::Display for PitchAccidental {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         use PitchAccidental::*;
@@ -89,6 +127,21 @@ impl fmt::Display for PitchAccidental {
     }
 }
 
+impl PitchAccidental {
+    /// Parse the longest accidental at the start of a string, so that `db`
+    /// and `t#` aren't read as `d` and `t`.  Returns the accidental and its
+    /// length in bytes.
+    fn longest_prefix(s: &str) -> Result<(Self, usize), ()> {
+        for len in (1..=2).rev() {
+            if let Some(accidental) = s.get(..len).and_then(|a| a.parse().ok())
+            {
+                return Ok((accidental, len));
+            }
+        }
+        Err(())
+    }
+}
+
 impl FromStr for PitchAccidental {
     type Err = ();
 
@@ -129,19 +182,20 @@ impl FromStr for PitchClass {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        /*        if s.is_empty() {
-            Err(())
-        } else if s.len() == 1 {*/
-        Ok(PitchClass {
-            name: s.parse()?,
-            accidental: None,
-        })
-        /*        } else {
-            Ok(PitchClass {
-                name: PitchName::from_str(s.get(..1).ok_or(())?)?,
-                accidental: Some(PitchAccidental::from_str(s.get(1..).ok_or(())?)?),
-            })
-        }*/
+        let name = s.get(..1).ok_or(())?.parse()?;
+        let mut rest = &s[1..];
+        let accidental = if rest.is_empty() {
+            None
+        } else {
+            let (accidental, len) = PitchAccidental::longest_prefix(rest)?;
+            rest = &rest[len..];
+            Some(accidental)
+        };
+        // Only one accidental is allowed.
+        if !rest.is_empty() {
+            return Err(());
+        }
+        Ok(PitchClass { name, accidental })
     }
 }
 
@@ -213,6 +267,29 @@ impl PitchOctave {
     }
 }
 
+impl TryFrom<i32> for PitchOctave {
+    type Error = ();
+
+    fn try_from(octave: i32) -> Result<Self, Self::Error> {
+        use PitchOctave::*;
+
+        Ok(match octave {
+            -1 => Octave_,
+            0 => Octave0,
+            1 => Octave1,
+            2 => Octave2,
+            3 => Octave3,
+            4 => Octave4,
+            5 => Octave5,
+            6 => Octave6,
+            7 => Octave7,
+            8 => Octave8,
+            9 => Octave9,
+            _ => return Err(()),
+        })
+    }
+}
+
 impl fmt::Display for PitchOctave {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         use PitchOctave::*;
@@ -254,6 +331,9 @@ impl FromStr for PitchOctave {
     }
 }
 
+/// Standard concert pitch of A4 in Hz.
+pub const A4_FREQUENCY: f64 = 440.0;
+
 /// Pitch Class & Octave
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Pitch(pub PitchClass, pub PitchOctave);
@@ -270,6 +350,110 @@ impl Pitch {
             0: steps + octaves * 7,
         }
     }
+
+    /// Get the number of steps the pitch is written above the middle line of
+    /// a stave with a clef (negative is below).
+    pub fn staff_position(self, clef: Clef) -> Steps {
+        self.visual_distance() - clef.middle_steps()
+    }
+
+    /// Get the MIDI note number (C4 = 60).  Quarter tones are rounded down.
+    pub fn midi(self) -> i32 {
+        let quarter_steps = self.0.accidental.map_or(0, |a| a.quarter_steps());
+        let semitones = self.0.name.semitones() + quarter_steps.div_euclid(2);
+
+        (self.1 as i32 + 1) * 12 + semitones
+    }
+
+    /// Get the number of semitones above middle C (C4), including the
+    /// accidental.  Quarter tones are rounded down, like [`Pitch::midi`].
+    pub fn semitones_from_c4(self) -> i32 {
+        self.midi() - 60
+    }
+
+    /// Get the number of cents above middle C (C4), including quarter tones.
+    pub fn cents_from_c4(self) -> i32 {
+        let quarter_steps = self.0.accidental.map_or(0, |a| a.quarter_steps());
+        let octaves = self.1 as i32 - 4;
+
+        octaves * 1200 + self.0.name.semitones() * 100 + quarter_steps * 50
+    }
+
+    /// Get the equal-tempered frequency in Hz, tuned so that A4 sounds at
+    /// `a4` Hz (usually [`A4_FREQUENCY`]).  Quarter tones sound half way
+    /// between semitones.
+    pub fn frequency(self, a4: f64) -> f64 {
+        let quarter_steps = self.0.accidental.map_or(0, |a| a.quarter_steps());
+        let semitones = f64::from(self.midi() - 69)
+            + f64::from(quarter_steps.rem_euclid(2)) / 2.0;
+
+        a4 * (semitones / 12.0).exp2()
+    }
+}
+
+/// Choose a spelling for a MIDI note number.
+///
+/// - `key`: The key signature (quarter steps above C).
+/// - `prev_spellings`: Recently spelled pitches, most recent last.  A pitch
+///   class that was spelled recently is spelled the same way again, to avoid
+///   flip-flopping between enharmonics (C# then Db).
+pub fn spell_pitch(midi: i32, key: u8, prev_spellings: &[Pitch]) -> Pitch {
+    use PitchName::*;
+
+    let octave = midi.div_euclid(12) - 1;
+    let octave = PitchOctave::try_from(octave).unwrap_or(if octave < 0 {
+        PitchOctave::Octave_
+    } else {
+        PitchOctave::Octave9
+    });
+    let semitone = midi.rem_euclid(12);
+
+    // Reuse the most recent spelling of the same pitch class.
+    for prev in prev_spellings.iter().rev() {
+        if prev.midi().rem_euclid(12) == semitone {
+            let octave = prev.1 as i32 + (midi - prev.midi()).div_euclid(12);
+            if let Ok(octave) = PitchOctave::try_from(octave) {
+                return Pitch(prev.0, octave);
+            }
+        }
+    }
+
+    let natural = [C, D, E, F, G, A, B]
+        .iter()
+        .find(|name| name.semitones() == semitone);
+    let pitch_class = if let Some(&name) = natural {
+        PitchClass {
+            name,
+            accidental: None,
+        }
+    } else if [1, 3, 5, 6, 8, 10].contains(&(i32::from(key) / 2)) {
+        // Flat keys: Db, Eb, F, Gb, Ab, Bb
+        let name = match semitone {
+            1 => D,
+            3 => E,
+            6 => G,
+            8 => A,
+            _ => B,
+        };
+        PitchClass {
+            name,
+            accidental: Some(PitchAccidental::Flat),
+        }
+    } else {
+        let name = match semitone {
+            1 => C,
+            3 => D,
+            6 => F,
+            8 => G,
+            _ => A,
+        };
+        PitchClass {
+            name,
+            accidental: Some(PitchAccidental::Sharp),
+        }
+    };
+
+    Pitch(pitch_class, octave)
 }
 
 impl fmt::Display for Pitch {
@@ -290,3 +474,112 @@ impl FromStr for Pitch {
         Ok(Pitch(pitch_class, pitch_octave))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accidentals() {
+        use PitchAccidental::*;
+
+        let accidental =
+            |pitch: &str| pitch.parse::<Pitch>().unwrap().0.accidental;
+        assert_eq!(accidental("D4"), None);
+        assert_eq!(accidental("Dbb4"), Some(DoubleFlat));
+        assert_eq!(accidental("Ddb4"), Some(FlatQuarterFlat));
+        assert_eq!(accidental("Db4"), Some(Flat));
+        assert_eq!(accidental("Dd4"), Some(QuarterFlat));
+        assert_eq!(accidental("Dn4"), Some(Natural));
+        assert_eq!(accidental("Dt4"), Some(QuarterSharp));
+        assert_eq!(accidental("D#4"), Some(Sharp));
+        assert_eq!(accidental("Dt#4"), Some(SharpQuarterSharp));
+        assert_eq!(accidental("Dx4"), Some(DoubleSharp));
+        // Every accidental is written the way it's parsed.
+        for pitch in &[
+            "Cbb4", "Cdb4", "Cb4", "Cd4", "Cn4", "Ct4", "C#4", "Ct#4", "Cx4",
+        ] {
+            assert_eq!(pitch.parse::<Pitch>().unwrap().to_string(), *pitch);
+        }
+        // Only one accidental.
+        assert!("Db#4".parse::<Pitch>().is_err());
+        assert!("Dbbb4".parse::<Pitch>().is_err());
+        assert!("Dq4".parse::<Pitch>().is_err());
+    }
+
+    #[test]
+    fn staff_position() {
+        let c4: Pitch = "C4".parse().unwrap();
+        // One ledger line below the treble stave, and one above the bass.
+        assert_eq!(c4.staff_position(Clef::Treble), Steps(-6));
+        assert_eq!(c4.staff_position(Clef::Bass), Steps(6));
+        assert_eq!(c4.staff_position(Clef::Alto), Steps(0));
+        assert_eq!(c4.staff_position(Clef::Tenor), Steps(2));
+        // The G clef curls around the second line from the bottom.
+        let g4: Pitch = "G4".parse().unwrap();
+        assert_eq!(g4.staff_position(Clef::Treble), Steps(-2));
+    }
+
+    #[test]
+    fn midi() {
+        assert_eq!("C4".parse::<Pitch>().unwrap().midi(), 60);
+        assert_eq!("A4".parse::<Pitch>().unwrap().midi(), 69);
+        assert_eq!(spell_pitch(60, 0, &[]).to_string(), "C4");
+        assert_eq!(spell_pitch(71, 0, &[]).to_string(), "B4");
+    }
+
+    #[test]
+    fn from_c4() {
+        let pitch = |pitch: &str| pitch.parse::<Pitch>().unwrap();
+        assert_eq!(pitch("C4").semitones_from_c4(), 0);
+        assert_eq!(pitch("A4").semitones_from_c4(), 9);
+        assert_eq!(pitch("Cb4").semitones_from_c4(), -1);
+        assert_eq!(pitch("Bx3").semitones_from_c4(), 1);
+        assert_eq!(pitch("C5").semitones_from_c4(), 12);
+        // Accidentals don't move the note on the stave.
+        assert_eq!(
+            pitch("C#4").visual_distance(),
+            pitch("C4").visual_distance()
+        );
+        // Quarter tones
+        assert_eq!(pitch("Ct4").semitones_from_c4(), 0);
+        assert_eq!(pitch("Ct4").cents_from_c4(), 50);
+        assert_eq!(pitch("Cd4").cents_from_c4(), -50);
+        assert_eq!(pitch("A4").cents_from_c4(), 900);
+    }
+
+    #[test]
+    fn frequency() {
+        let hz = |pitch: &str| {
+            pitch.parse::<Pitch>().unwrap().frequency(A4_FREQUENCY)
+        };
+        assert_eq!(hz("A4"), 440.0);
+        assert_eq!(hz("A5"), 880.0);
+        assert!((hz("C4") - 261.63).abs() < 0.01);
+        // A quarter tone is half of a semitone.
+        let a4 = |accidental| {
+            let name = PitchName::A;
+            Pitch(PitchClass { name, accidental }, PitchOctave::Octave4)
+                .frequency(A4_FREQUENCY)
+        };
+        let quarter = a4(Some(PitchAccidental::QuarterFlat)) / 440.0;
+        let half = a4(Some(PitchAccidental::Flat)) / 440.0;
+        assert!((quarter * quarter - half).abs() < 1e-9);
+        assert_eq!("A4".parse::<Pitch>().unwrap().frequency(432.0), 432.0);
+    }
+
+    #[test]
+    fn spelling() {
+        // C major
+        assert_eq!(spell_pitch(61, 0, &[]).to_string(), "C#4");
+        // Db major
+        assert_eq!(spell_pitch(61, 2, &[]).to_string(), "Db4");
+        // Gb major
+        assert_eq!(spell_pitch(66, 12, &[]).to_string(), "Gb4");
+        assert_eq!(spell_pitch(70, 12, &[]).to_string(), "Bb4");
+        // Keep a recent spelling in any octave.
+        let prev = [spell_pitch(73, 2, &[])];
+        assert_eq!(spell_pitch(61, 0, &prev).to_string(), "Db4");
+        assert_eq!(spell_pitch(61, 0, &prev).midi(), 61);
+    }
+}
@@ -0,0 +1,494 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Note events for playback.
+
+use crate::{Articulation, Cursor, Dynamic, Fraction, Marking, Scof};
+
+/// A hairpin being played: start time and velocity, end time and velocity.
+type Hairpin = (f64, u8, f64, u8);
+
+/// A note to be played.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteEvent {
+    /// Channel playing the note.
+    pub chan: u16,
+    /// MIDI note number.
+    pub midi: i32,
+    /// Frequency in Hz, tuned by the score's synthesis settings.
+    pub frequency: f64,
+    /// Start time in whole notes from the beginning of the movement.
+    pub start: f64,
+    /// Length in whole notes.
+    pub duration: f64,
+    /// MIDI velocity (0-127).
+    pub velocity: u8,
+}
+
+/// Velocity of notes without accents.
+pub const DEFAULT_VELOCITY: u8 = 80;
+
+/// Minimum velocity of notes attacked sforzando.
+const SFORZANDO_VELOCITY: u8 = 112;
+
+/// Velocity a hairpin changes by if there's no dynamic after it in the bar.
+const HAIRPIN_VELOCITY: u8 = 16;
+
+/// Time between the notes of a strummed chord, in whole notes.
+const STRUM_DELAY: f64 = 1.0 / 64.0;
+
+/// Get the silence before the next note for a breath mark or caesura, in
+/// whole notes (cut from the end of the note before it).
+fn pause_length(marking: &Marking) -> Option<f64> {
+    match marking {
+        Marking::Breath => Some(1.0 / 16.0),
+        Marking::CaesuraShort => Some(1.0 / 8.0),
+        Marking::CaesuraLong => Some(1.0 / 4.0),
+        _ => None,
+    }
+}
+
+/// Convert a fraction to a float.
+fn float(fraction: Fraction) -> f64 {
+    f64::from(fraction.num) / f64::from(fraction.den)
+}
+
+/// Get the velocity a hairpin has reached at a time.
+fn hairpin_velocity(hairpin: Hairpin, time: f64) -> u8 {
+    let (from, from_level, to, to_level) = hairpin;
+    if to <= from {
+        return from_level;
+    }
+    let amount = (time - from) / (to - from);
+    let delta = f64::from(to_level) - f64::from(from_level);
+    (f64::from(from_level) + delta * amount).round() as u8
+}
+
+impl Scof {
+    /// Get the time and velocity of the first dynamic (that isn't a
+    /// sforzando) at or after a cursor in its bar, given the time at the
+    /// cursor.
+    fn next_dynamic(&self, cursor: &Cursor, start: f64) -> Option<(f64, u8)> {
+        let mut curs = cursor.clone();
+        let mut time = start;
+        while let Some(marking) = self.marking(&curs) {
+            curs.right_unchecked();
+            if let Marking::Dynamic(dynamic) = marking {
+                if let Some(velocity) = dynamic.velocity() {
+                    return Some((time, velocity));
+                }
+            }
+            time += marking.duration().map_or(0.0, float);
+        }
+        None
+    }
+
+    /// Get the hairpin starting at a cursor, given the time and velocity at
+    /// the cursor and the time at the end of the bar.  It goes to the next
+    /// dynamic in the bar, or changes by one level by the end of the bar.
+    fn hairpin(
+        &self,
+        cursor: &Cursor,
+        cresc: bool,
+        (start, level): (f64, u8),
+        bar_end: f64,
+    ) -> Hairpin {
+        let (end, to) = self.next_dynamic(cursor, start).unwrap_or_else(|| {
+            let to = if cresc {
+                level.saturating_add(HAIRPIN_VELOCITY)
+            } else {
+                level.saturating_sub(HAIRPIN_VELOCITY)
+            };
+            (bar_end, to.clamp(1, 127))
+        });
+        (start, level, end, to)
+    }
+
+    /// Get the dynamic in effect at a cursor: the last dynamic before it in
+    /// its channel (mf if there's none), or the level a crescendo or
+    /// diminuendo has reached at the cursor, rounded to the closest dynamic.
+    /// Sforzandos only accent the note after them.
+    pub fn dynamic_at(&self, cursor: &Cursor) -> Dynamic {
+        let mut level = DEFAULT_VELOCITY;
+        let mut hairpin: Option<Hairpin> = None;
+        for bar in 0..=cursor.bar {
+            let mut curs = Cursor::new(cursor.movement, bar, cursor.chan, 0);
+            let bar_end = float(self.bar_length(&curs));
+            let mut start = 0.0;
+            while let Some(marking) = self.marking(&curs) {
+                if curs == *cursor {
+                    break;
+                }
+                curs.right_unchecked();
+                match marking {
+                    Marking::Dynamic(dynamic) => {
+                        level = dynamic.velocity().unwrap_or(level);
+                        hairpin = None;
+                    }
+                    Marking::Cresc | Marking::Dim => {
+                        let cresc = *marking == Marking::Cresc;
+                        hairpin = Some(self.hairpin(
+                            &curs,
+                            cresc,
+                            (start, level),
+                            bar_end,
+                        ));
+                    }
+                    _ => {}
+                }
+                start += marking.duration().map_or(0.0, float);
+            }
+            if bar == cursor.bar {
+                if let Some(hairpin) = hairpin {
+                    level = hairpin_velocity(hairpin, start);
+                }
+            } else if let Some((_, _, _, to)) = hairpin.take() {
+                // Hairpins without a dynamic after them end with the bar.
+                level = to;
+            }
+        }
+        Dynamic::from_velocity(level)
+    }
+
+    /// Get the notes of a movement in the order they're played, sorted by
+    /// start time.  Measure repeats replay earlier bars, beat repeats replay
+    /// the beat before them, and tremolos are played as repeated attacks.
+    /// Dynamics set the velocity of the notes after them, and hairpins change
+    /// it gradually up to the next dynamic.  Chords sound together unless
+    /// they're strummed.  Breath marks and caesuras cut a short silence out
+    /// of the end of the note before them (at most half of it).
+    pub fn playback_events(&self, movement: u16) -> Vec<NoteEvent> {
+        use Articulation::{StrumDown, StrumUp};
+
+        let chans = self.channel_count(movement as usize);
+        let mut events: Vec<NoteEvent> = vec![];
+        for chan in 0..chans as u16 {
+            // Play transposing instruments at concert pitch.
+            let transpose =
+                self.transposition(&Cursor::new(movement, 0, chan, 0));
+            let mut bar_start = 0.0;
+            let mut level = DEFAULT_VELOCITY;
+            let mut sforzando = false;
+            let mut hairpin: Option<Hairpin> = None;
+            // Events and start time of the last note played.
+            let mut last_note = (0..0, 0.0);
+            for (bar, played) in
+                self.playback_order(movement, chan).into_iter().enumerate()
+            {
+                let cursor = Cursor::new(movement, bar as u16, chan, 0);
+                let bar_end = bar_start + float(self.bar_length(&cursor));
+                let mut curs = Cursor::new(movement, played, chan, 0);
+                let mut start = bar_start;
+                while let Some(marking) = self.marking(&curs) {
+                    curs.right_unchecked();
+                    let note = match marking {
+                        Marking::Note(note) => note,
+                        Marking::Dynamic(dynamic) => {
+                            level = dynamic.velocity().unwrap_or(level);
+                            sforzando = dynamic.is_sforzando();
+                            hairpin = None;
+                            continue;
+                        }
+                        Marking::Cresc | Marking::Dim => {
+                            let cresc = *marking == Marking::Cresc;
+                            hairpin = Some(self.hairpin(
+                                &curs,
+                                cresc,
+                                (start, level),
+                                bar_end,
+                            ));
+                            continue;
+                        }
+                        Marking::Spacer(duration) => {
+                            start += float(*duration);
+                            continue;
+                        }
+                        Marking::Breath
+                        | Marking::CaesuraShort
+                        | Marking::CaesuraLong => {
+                            let (played, from) = last_note.clone();
+                            let pause = pause_length(marking).unwrap();
+                            let end = (start - pause).max((from + start) / 2.0);
+                            for event in &mut events[played] {
+                                event.duration =
+                                    event.duration.min(end - event.start);
+                            }
+                            continue;
+                        }
+                        Marking::BeatRepeat(duration) => {
+                            let duration = float(*duration);
+                            let beat: Vec<NoteEvent> = events
+                                .iter()
+                                .filter(|e| {
+                                    e.chan == chan
+                                        && e.start >= start - duration
+                                        && e.start < start
+                                })
+                                .map(|e| NoteEvent {
+                                    start: e.start + duration,
+                                    ..e.clone()
+                                })
+                                .collect();
+                            events.extend(beat);
+                            start += duration;
+                            continue;
+                        }
+                        _ => continue,
+                    };
+                    let duration = float(note.duration);
+                    let mut base = hairpin.map_or(level, |hairpin| {
+                        hairpin_velocity(hairpin, start)
+                    });
+                    if std::mem::take(&mut sforzando) {
+                        base = base.max(SFORZANDO_VELOCITY);
+                    }
+                    let (sounding, velocity) = note.playback_params(base);
+                    // Tremolo slashes each halve the length of an attack,
+                    // starting from an eighth note.
+                    let attack = if note.tremolo > 0 {
+                        (0.125 / f64::from(1u32 << (note.tremolo - 1).min(7)))
+                            .min(duration)
+                    } else {
+                        duration
+                    };
+                    let attacks = (duration / attack).round().max(1.0) as u32;
+                    // Articulations shorten single attacks.
+                    let length = if attacks == 1 {
+                        float(sounding)
+                    } else {
+                        attack
+                    };
+                    // Strummed chords stagger their pitches, which all end
+                    // together.
+                    let mut pitches = note.pitch.clone();
+                    let strum = if note.articulation.contains(&StrumDown) {
+                        pitches.sort_by_key(|pitch| pitch.midi());
+                        STRUM_DELAY
+                    } else if note.articulation.contains(&StrumUp) {
+                        pitches.sort_by_key(|pitch| -pitch.midi());
+                        STRUM_DELAY
+                    } else {
+                        0.0
+                    }
+                    .min(length / pitches.len().max(1) as f64);
+                    last_note = (events.len()..events.len(), start);
+                    for (p, pitch) in pitches.iter().enumerate() {
+                        let delay = strum * p as f64;
+                        for i in 0..attacks {
+                            events.push(NoteEvent {
+                                chan,
+                                midi: pitch.midi() + transpose,
+                                frequency: self
+                                    .synth
+                                    .frequency(*pitch, transpose),
+                                start: start + attack * f64::from(i) + delay,
+                                duration: length - delay,
+                                velocity,
+                            });
+                        }
+                    }
+                    last_note.0.end = events.len();
+                    start += duration;
+                }
+                // Hairpins without a dynamic after them end with the bar.
+                if let Some((_, _, _, to)) = hairpin.take() {
+                    level = to;
+                }
+                bar_start = bar_end;
+            }
+        }
+        // Tremolo attacks cut off by a pause aren't played.
+        events.retain(|event| event.duration > 0.0);
+        events.sort_by(|a, b| {
+            a.start
+                .partial_cmp(&b.start)
+                .unwrap()
+                .then(a.chan.cmp(&b.chan))
+        });
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Score with a single bar on one channel.
+    fn score(notes: &str) -> Scof {
+        let mut scof = Scof::default();
+        scof.movement[0].bar.truncate(1);
+        scof.movement[0].bar[0].chan = vec![notes.parse().unwrap()];
+        scof
+    }
+
+    #[test]
+    fn events() {
+        let events = score("1/4C4 1/4R 1/2C4E4").playback_events(0);
+        let starts: Vec<f64> = events.iter().map(|e| e.start).collect();
+        let midi: Vec<i32> = events.iter().map(|e| e.midi).collect();
+        assert_eq!(starts, [0.0, 0.5, 0.5]);
+        assert_eq!(midi, [60, 60, 64]);
+        assert_eq!(events[1].duration, 0.5);
+    }
+
+    #[test]
+    fn articulations() {
+        let events = score("1/4C4. 1/4C4> 1/2C4").playback_events(0);
+        let durations: Vec<f64> = events.iter().map(|e| e.duration).collect();
+        let velocities: Vec<u8> = events.iter().map(|e| e.velocity).collect();
+        let starts: Vec<f64> = events.iter().map(|e| e.start).collect();
+        assert_eq!(durations, [0.125, 0.25, 0.5]);
+        assert!(velocities[1] > velocities[0]);
+        assert_eq!(velocities[2], DEFAULT_VELOCITY);
+        assert_eq!(starts, [0.0, 0.25, 0.5]);
+    }
+
+    #[test]
+    fn dynamics() {
+        let velocities = |notes: &str| -> Vec<u8> {
+            let events = score(notes).playback_events(0);
+            events.iter().map(|e| e.velocity).collect()
+        };
+        assert_eq!(velocities("1/2C4 p 1/4C4 ff 1/4C4"), [80, 48, 112]);
+        // Niente is near-silence.
+        assert_eq!(velocities("n 1/1C4"), [1]);
+        // Dal niente crescendo up to the forte at the end of the bar.
+        assert_eq!(
+            velocities("n < 1/4C4 1/4C4 1/4C4 1/4C4 f"),
+            [1, 25, 49, 72]
+        );
+        // Al niente diminuendo.
+        assert_eq!(velocities("f > 1/2C4 1/2C4 n"), [96, 49]);
+        // Without a dynamic after it, a hairpin changes by one level.
+        assert_eq!(velocities("p < 1/2C4 1/2C4"), [48, 56]);
+        // Sforzandos accent only the next note.
+        assert_eq!(velocities("p 1/4C4 sfz 1/4C4 1/2C4"), [48, 112, 48]);
+        assert_eq!(velocities("fp 1/2C4 1/2C4"), [112, 48]);
+    }
+
+    #[test]
+    fn dynamic_at() {
+        let dynamics = |notes: &str| -> Vec<Dynamic> {
+            let scof = score(notes);
+            let mut curs = Cursor::new(0, 0, 0, 0);
+            let mut dynamics = vec![];
+            while let Some(marking) = scof.marking(&curs) {
+                if let Marking::Note(_) = marking {
+                    dynamics.push(scof.dynamic_at(&curs));
+                }
+                curs.right_unchecked();
+            }
+            dynamics
+        };
+        // Mezzo forte until the first dynamic.
+        assert_eq!(
+            dynamics("1/2C4 pp 1/4C4 1/4C4"),
+            [Dynamic::MF, Dynamic::PP, Dynamic::PP]
+        );
+        // Crescendo from pianissimo to forte, through piano and mezzo forte.
+        assert_eq!(
+            dynamics("pp < 1/4C4 1/4C4 1/4C4 1/4C4 f"),
+            [Dynamic::PP, Dynamic::P, Dynamic::MP, Dynamic::MF]
+        );
+        // Sforzandos don't change the level.
+        assert_eq!(dynamics("p sfz 1/1C4"), [Dynamic::P]);
+    }
+
+    #[test]
+    fn pauses() {
+        let durations = |notes: &str| -> Vec<f64> {
+            let events = score(notes).playback_events(0);
+            events.iter().map(|e| e.duration).collect()
+        };
+        assert_eq!(durations("1/2C4 , 1/2C4"), [0.4375, 0.5]);
+        assert_eq!(durations("1/2C4 // 1/2C4"), [0.25, 0.5]);
+        // At most half of a short note.
+        assert_eq!(durations("1/8C4 / 7/8C4"), [0.0625, 0.875]);
+        // The next note starts on time.
+        let events = score("1/2C4 / 1/2C4").playback_events(0);
+        assert_eq!(events[1].start, 0.5);
+    }
+
+    #[test]
+    fn beat_repeat() {
+        let events = score("1/8C4 1/8E4 1/4% 1/4% 1/4G4").playback_events(0);
+        let midi: Vec<i32> = events.iter().map(|e| e.midi).collect();
+        let starts: Vec<f64> = events.iter().map(|e| e.start).collect();
+        assert_eq!(midi, [60, 64, 60, 64, 60, 64, 67]);
+        assert_eq!(starts, [0.0, 0.125, 0.25, 0.375, 0.5, 0.625, 0.75]);
+    }
+
+    #[test]
+    fn tremolo() {
+        // Three slashes on a quarter note is eight 32nd notes.
+        let events = score("1/4C4/// 3/4R").playback_events(0);
+        assert_eq!(events.len(), 8);
+        assert!(events.iter().all(|e| e.duration == 1.0 / 32.0));
+        assert_eq!(events[7].start, 7.0 / 32.0);
+    }
+
+    #[test]
+    fn transposing_instrument() {
+        // Written C sounds as Bb on a Bb clarinet.
+        let mut scof = score("1/1C4");
+        scof.soundfont[0].transpose = -2;
+        let events = scof.playback_events(0);
+        assert_eq!(events[0].midi, 58);
+        let concert = scof.concert_pitch(&Cursor::new(0, 0, 0, 0)).unwrap();
+        assert_eq!(concert.to_string(), "Bb3");
+    }
+
+    #[test]
+    fn tuning() {
+        let frequencies = |scof: &Scof| -> Vec<f64> {
+            let events = scof.playback_events(0);
+            events.iter().map(|e| e.frequency).collect()
+        };
+        let mut scof = score("1/4A4 1/4C4 1/2A5E4");
+        let concert = frequencies(&scof);
+        assert_eq!(concert[0], 440.0);
+        assert_eq!(concert[2], 880.0);
+
+        // Baroque pitch.
+        scof.synth.a4 = 415.0;
+        for (baroque, concert) in frequencies(&scof).iter().zip(&concert) {
+            assert!((baroque / concert - 415.0 / 440.0).abs() < 1e-9);
+        }
+
+        // Just intonation in C: E is 14 cents flat.
+        scof.synth.a4 = 440.0;
+        scof.synth.tuning = vec![0.0; 12];
+        scof.synth.tuning[4] = -14.0;
+        let just = frequencies(&scof);
+        assert_eq!(just[0], concert[0]);
+        assert!(
+            (just[3] / concert[3] - (-14.0f64 / 1200.0).exp2()).abs() < 1e-9
+        );
+    }
+
+    #[test]
+    fn octave_shift() {
+        // Notes under 8va are stored (and played) an octave above where
+        // they're written.
+        let events = score("8va 1/4C6 1/4C6 loco 1/2C4").playback_events(0);
+        let midi: Vec<i32> = events.iter().map(|e| e.midi).collect();
+        let starts: Vec<f64> = events.iter().map(|e| e.start).collect();
+        assert_eq!(midi, [84, 84, 60]);
+        assert_eq!(starts, [0.0, 0.25, 0.5]);
+    }
+}
@@ -54,3 +54,398 @@ fn style() {
     assert_eq!(style, style_clone);
     assert_eq!(input, output);
 }
+
+#[test]
+fn movement_names() {
+    let mut scof = scof::Scof::default();
+    assert_eq!(scof.movement_name(0), Some("Movement 1"));
+    assert_eq!(scof.movement_name(1), None);
+
+    let bars = scof.movement[0].bar.len();
+    scof.new_movement();
+    assert_eq!(scof.movement.len(), 2);
+    assert_eq!(scof.meta.movement, ["Movement 1", "Movement 2"]);
+
+    scof.meta.movement[1] = "Finale".to_string();
+    scof.merge_movements(0);
+    assert_eq!(scof.movement.len(), 1);
+    assert_eq!(scof.meta.movement, ["Movement 1"]);
+    assert_eq!(scof.movement[0].bar.len(), bars * 2);
+
+    scof.split_movement(&scof::Cursor::new(0, bars as u16, 0, 0));
+    assert_eq!(scof.movement.len(), 2);
+    assert_eq!(scof.movement[1].bar.len(), bars);
+    assert_eq!(scof.movement_name(1), Some("Movement 2"));
+}
+
+#[test]
+fn measure_repeat_playback() {
+    let mut scof = scof::Scof::default();
+    let bar = &mut scof.movement[0].bar[1];
+    bar.chan[0] = "%".parse().unwrap();
+    bar.chan[1] = "%".parse().unwrap();
+    assert_eq!(scof.measure_repeat(&scof::Cursor::new(0, 1, 0, 0)), Some(1));
+    assert_eq!(scof.playback_order(0, 0), [0, 0, 2, 3]);
+
+    // Double measure repeat
+    scof.movement[0].bar[2].chan[0] = "%%".parse().unwrap();
+    scof.movement[0].bar[3].chan[0] = "%%".parse().unwrap();
+    assert_eq!(scof.playback_order(0, 0), [0, 0, 0, 0]);
+}
+
+#[test]
+fn spacer() {
+    let spacer: scof::Marking = "1/2s".parse().unwrap();
+    assert_eq!(spacer, scof::Marking::Spacer(scof::Fraction::new(1, 2)));
+    assert_eq!(spacer.duration(), Some(scof::Fraction::new(1, 2)));
+}
+
+#[test]
+fn pauses() {
+    for (text, pause) in [
+        (",", scof::Marking::Breath),
+        ("/", scof::Marking::CaesuraShort),
+        ("//", scof::Marking::CaesuraLong),
+    ] {
+        let marking: scof::Marking = text.parse().unwrap();
+        assert_eq!(marking, pause);
+        assert_eq!(marking.to_string(), text);
+        assert_eq!(marking.duration(), None);
+    }
+}
+
+#[test]
+fn consolidate_rests() {
+    let consolidated_in = |time: &str, notes: &str| -> Vec<String> {
+        let mut scof = scof::Scof::default();
+        scof.movement[0].bar[0].chan[0] = notes.parse().unwrap();
+        let mut curs = scof::Cursor::new(0, 0, 0, 0);
+        scof.set_time(&curs, time.parse().unwrap());
+        scof.consolidate_rests(&curs);
+        let mut markings = vec![];
+        while let Some(marking) = scof.marking(&curs) {
+            markings.push(marking.to_string());
+            curs.right_unchecked();
+        }
+        markings
+    };
+    let consolidated = |notes| consolidated_in("4/4", notes);
+    // On a beat.
+    assert_eq!(
+        consolidated("1/8R 1/8R 1/4C4 1/2C4"),
+        ["1/4R", "1/4C4", "1/2C4"]
+    );
+    // Straddling a beat.
+    assert_eq!(
+        consolidated("1/8C4 1/8R 1/8R 1/8C4 1/2C4"),
+        ["1/8C4", "1/8R", "1/8R", "1/8C4", "1/2C4"]
+    );
+    // Whole beats, and the rest of a beat.
+    assert_eq!(
+        consolidated("1/4C4 1/8C4 1/8R 1/4R 1/4R"),
+        ["1/4C4", "1/8C4", "1/8R", "1/2R"]
+    );
+    assert_eq!(consolidated("1/4R 1/4R 1/4R 1/4R"), ["1/1R"]);
+    // Compound time has dotted quarter beats, so rests either side of the
+    // beat in the middle of the bar aren't merged.
+    assert_eq!(
+        consolidated_in("6/8", "1/4C4 1/8R 1/8R 1/4C4"),
+        ["1/4C4", "1/8R", "1/8R", "1/4C4"]
+    );
+    assert_eq!(
+        consolidated_in("6/8", "1/8R 1/8R 1/8R 1/8C4 1/8R 1/8R"),
+        ["3/8R", "1/8C4", "1/8R", "1/8R"]
+    );
+    assert_eq!(consolidated_in("6/8", "1/4R 1/4R 1/4R"), ["3/4R"]);
+}
+
+#[test]
+fn coalesce_ties() {
+    let mut scof = scof::Scof::default();
+    let chan: scof::Channel =
+        "1/4C4~ 1/4C4 1/4D4~ 1/8D4 1/8E4~".parse().unwrap();
+    scof.movement[0].bar[0].chan[0] = chan;
+    scof.coalesce_ties();
+
+    let mut curs = scof::Cursor::new(0, 0, 0, 0);
+    let mut notes = vec![];
+    while let Some(note) = scof.note(&curs) {
+        notes.push(note.to_string());
+        curs.right_unchecked();
+    }
+    // Quarter + quarter = half, quarter + eighth = dotted quarter, and the
+    // last tie continues past the bar.
+    assert_eq!(notes, ["1/2C4", "3/8D4", "1/8E4~"]);
+}
+
+#[test]
+fn set_duration_odd_fractions() {
+    // Subtracting these overflowed with 16-bit intermediate math.
+    let mut scof = scof::Scof::default();
+    scof.movement[0].bar[0].chan[0] = "1/300C4 299/300R".parse().unwrap();
+    let curs = scof::Cursor::new(0, 0, 0, 0);
+    scof.set_duration(&curs, scof::Fraction::new(1, 256));
+
+    let mut curs = curs;
+    let mut notes = vec![];
+    while let Some(note) = scof.note(&curs) {
+        notes.push(note.to_string());
+        curs.right_unchecked();
+    }
+    assert_eq!(notes, ["1/256C4", "255/256R"]);
+}
+
+#[test]
+fn lyrics() {
+    let mut scof = scof::Scof::default();
+    let cursor = scof::Cursor::default();
+    assert!(scof.lyrics(&cursor).is_empty());
+    scof.set_lyrics(&cursor, "Hal- le- lu- jah _");
+    let lyrics = scof.lyrics(&cursor);
+    assert_eq!(lyrics.len(), 5);
+    assert_eq!(lyrics[0], scof::Lyric::Syllable("Hal".to_string(), true));
+    assert_eq!(lyrics[3], scof::Lyric::Syllable("jah".to_string(), false));
+    assert_eq!(lyrics[4], scof::Lyric::Extend);
+    let text: Vec<String> = lyrics.iter().map(|l| l.to_string()).collect();
+    assert_eq!(text.join(" "), "Hal- le- lu- jah _");
+}
+
+#[test]
+fn measure_128ths() {
+    let mut scof = scof::Scof::default();
+    let cursor = scof::Cursor::default();
+    assert_eq!(scof.measure_128ths(&cursor), 128);
+    scof.set_time(&cursor, scof::Fraction::new(3, 4));
+    assert_eq!(scof.measure_128ths(&cursor), 96);
+    scof.set_time(&cursor, scof::Fraction::new(6, 8));
+    assert_eq!(scof.measure_128ths(&cursor), 96);
+    // The time signature, not simplified.
+    let length = scof.bar_length(&cursor);
+    assert_eq!((length.num, length.den), (6, 8));
+    scof.set_time(&cursor, scof::Fraction::new(2, 2));
+    assert_eq!(scof.measure_128ths(&cursor), 128);
+}
+
+#[test]
+fn clamp_cursor() {
+    let mut scof = scof::Scof::default();
+    let mut cursor = scof::Cursor::new(0, 3, 1, 1);
+    scof.delete_measure(&cursor);
+    cursor.clamp(&scof);
+    // Last marking of the previous measure.
+    assert_eq!(cursor, scof::Cursor::new(0, 2, 1, 2));
+
+    let mut cursor = scof::Cursor::new(5, 0, 9, 99);
+    cursor.clamp(&scof);
+    assert_eq!(cursor, scof::Cursor::new(0, 2, 1, 2));
+
+    let mut cursor = scof::Cursor::new(0, 0, 0, 99);
+    cursor.clamp(&scof);
+    assert_eq!(cursor, scof::Cursor::new(0, 0, 0, 5));
+}
+
+#[test]
+fn rebar() {
+    let mut scof = scof::Scof::default();
+    let bars = &mut scof.movement[0].bar;
+    bars.truncate(2);
+    bars[0].chan.truncate(1);
+    bars[1].chan.truncate(1);
+    bars[0].chan[0] = "1/8C4 1/8D4 1/8E4 1/8F4 1/8G4 1/8A4 1/8B4 1/8C5"
+        .parse()
+        .unwrap();
+    bars[1].chan[0] = "1/8D5 1/8E5 1/2F5 1/4G5".parse().unwrap();
+    let cursor = scof::Cursor::default();
+    scof.set_lyrics(&cursor, "do re mi fa sol la ti do");
+    scof.set_lyrics(&cursor.bar(1), "a b c d");
+    scof.set_time(&cursor, scof::Fraction::new(3, 4));
+    scof.rebar(0, 0);
+
+    let measure = |bar| {
+        let mut curs = scof::Cursor::new(0, bar, 0, 0);
+        let mut notes = vec![];
+        while let Some(note) = scof.note(&curs) {
+            notes.push(note.to_string());
+            curs.right_unchecked();
+        }
+        notes.join(" ")
+    };
+    assert_eq!(scof.movement[0].bar.len(), 3);
+    assert_eq!(measure(0), "1/8C4 1/8D4 1/8E4 1/8F4 1/8G4 1/8A4");
+    assert_eq!(measure(1), "1/8B4 1/8C5 1/8D5 1/8E5 1/4F5~");
+    assert_eq!(measure(2), "1/4F5 1/4G5 1/4R");
+    // The tied part of the split note extends its syllable.
+    let lyrics = |bar| {
+        let lyrics = scof.lyrics(&scof::Cursor::new(0, bar, 0, 0));
+        let text: Vec<String> = lyrics.iter().map(|l| l.to_string()).collect();
+        text.join(" ")
+    };
+    assert_eq!(lyrics(0), "do re mi fa sol la");
+    assert_eq!(lyrics(1), "ti do a b c");
+    assert_eq!(lyrics(2), "_ d");
+    assert_eq!(scof.measure_128ths(&cursor.bar(2)), 96);
+}
+
+#[test]
+fn rebar_attached() {
+    let mut scof = scof::Scof::default();
+    let bars = &mut scof.movement[0].bar;
+    bars.truncate(1);
+    bars[0].chan.truncate(1);
+    bars[0].chan[0] = "1/2C4 1/4D4 1/4E4 p 1/2F4 f".parse().unwrap();
+    scof.set_time(&scof::Cursor::default(), scof::Fraction::new(2, 4));
+    scof.rebar(0, 0);
+
+    let measure = |bar| {
+        let mut curs = scof::Cursor::new(0, bar, 0, 0);
+        let mut markings = vec![];
+        while let Some(marking) = scof.marking(&curs) {
+            markings.push(marking.to_string());
+            curs.right_unchecked();
+        }
+        markings.join(" ")
+    };
+    // The dynamic stays with the note after it, in the next measure.
+    assert_eq!(scof.movement[0].bar.len(), 3);
+    assert_eq!(measure(0), "1/2C4");
+    assert_eq!(measure(1), "1/4D4 1/4E4");
+    assert_eq!(measure(2), "p 1/2F4 f");
+}
+
+#[test]
+fn rebar_measure_attributes() {
+    let mut scof = scof::Scof::default();
+    let bars = &mut scof.movement[0].bar;
+    bars.truncate(2);
+    bars[0].chan.truncate(1);
+    bars[1].chan.truncate(1);
+    bars[0].chan[0] = "1/1C4".parse().unwrap();
+    bars[1].chan[0] = "1/1D4".parse().unwrap();
+    // Only the second measure has a second channel.
+    bars[1].chan.push("1/1C3".parse().unwrap());
+    bars[0].barline = Some(scof::Barline::Double);
+    bars[1].repeat = vec!["segno".to_string()];
+    bars[1].break_before = Some(scof::BreakKind::Page);
+    let cursor = scof::Cursor::default();
+    scof.set_clef(&cursor.bar(1), scof::Clef::Bass);
+    scof.set_time(&cursor, scof::Fraction::new(2, 4));
+    scof.rebar(0, 0);
+
+    let bars = &scof.movement[0].bar;
+    assert_eq!(bars.len(), 4);
+    assert!(bars.iter().all(|bar| bar.chan.len() == 2));
+    assert_eq!(bars[1].barline, Some(scof::Barline::Double));
+    assert_eq!(bars[2].repeat, ["segno"]);
+    assert_eq!(bars[2].break_before, Some(scof::BreakKind::Page));
+    assert_eq!(scof.clef(&cursor.bar(1)), scof::Clef::Treble);
+    assert_eq!(scof.clef(&cursor.bar(2)), scof::Clef::Bass);
+    let note = |bar, chan| {
+        let curs = scof::Cursor::new(0, bar, chan, 0);
+        scof.note(&curs).unwrap().to_string()
+    };
+    assert_eq!(note(0, 1), "1/2R");
+    assert_eq!(note(2, 1), "1/2C3~");
+}
+
+#[test]
+fn new() {
+    let time = scof::Fraction::new(3, 4);
+    let scof = scof::Scof::new(3, time, 0);
+    assert_eq!(scof.movement.len(), 1);
+    assert_eq!(scof.soundfont.len(), 3);
+    assert!(scof.movement[0].bar.iter().all(|bar| bar.chan.len() == 3));
+    assert_eq!(scof.cache, [[time]]);
+    let sig = scof.sig(&scof::Cursor::default()).unwrap();
+    assert_eq!((sig.time.as_str(), sig.key), ("3/4", 0));
+    assert_eq!(scof.measure_128ths(&scof::Cursor::default()), 96);
+}
+
+#[test]
+fn channel_count() {
+    let scof = scof::Scof::default();
+    assert_eq!(scof.channel_count(0), 2);
+    assert_eq!(scof.measure_channel_count(0, 1), 2);
+    assert_eq!(scof.channel_count(1), 0);
+    assert_eq!(scof.measure_channel_count(0, 100), 0);
+    let scof = scof::Scof::new(3, scof::Fraction::new(3, 4), 0);
+    assert_eq!(scof.channel_count(0), 3);
+}
+
+#[test]
+fn simultaneities() {
+    let mut scof = scof::Scof::default();
+    let bar = &mut scof.movement[0].bar[0];
+    bar.chan[0] = "1/4C4 1/4D4 1/2E4".parse().unwrap();
+    bar.chan[1] = "3/8C3 1/8G3 1/2C3".parse().unwrap();
+    let onsets: Vec<(scof::Fraction, Vec<(u16, String)>)> = scof
+        .simultaneities(0, 0)
+        .into_iter()
+        .map(|(beat, notes)| {
+            let notes = notes
+                .into_iter()
+                .map(|(chan, note)| (chan, note.to_string()))
+                .collect();
+            (beat, notes)
+        })
+        .collect();
+    let frac = scof::Fraction::new;
+    let note = |chan, note: &str| (chan, note.to_string());
+    // Beats 1 and 3 line up, but the second notes don't.
+    assert_eq!(
+        onsets,
+        vec![
+            (frac(0, 1), vec![note(0, "1/4C4"), note(1, "3/8C3")]),
+            (frac(1, 4), vec![note(0, "1/4D4")]),
+            (frac(3, 8), vec![note(1, "1/8G3")]),
+            (frac(1, 2), vec![note(0, "1/2E4"), note(1, "1/2C3")]),
+        ]
+    );
+}
+
+#[test]
+fn max_duration_at() {
+    let mut scof = scof::Scof::default();
+    let bars = &mut scof.movement[0].bar;
+    bars.truncate(1);
+    bars[0].chan.truncate(1);
+    bars[0].chan[0] = "1/2C4".parse().unwrap();
+    let cursor = scof::Cursor::default();
+    let half = scof::Fraction::new(1, 2);
+    assert_eq!(scof.max_duration_at(&cursor), scof::Fraction::new(1, 1));
+    assert_eq!(
+        scof.max_duration_at(&cursor.clone().right_unchecked()),
+        half
+    );
+
+    // Overfull measures have no room left.
+    scof.set_time(&cursor, scof::Fraction::new(1, 4));
+    let next = cursor.clone().right_unchecked();
+    assert_eq!(scof.max_duration_at(&next), scof::Fraction::new(0, 1));
+}
+
+#[test]
+fn time_signature() {
+    let sig = |time: &str| scof::Sig {
+        time: time.to_string(),
+        ..Default::default()
+    };
+    let common = sig("4/4");
+    assert_eq!(common.time_fraction(), Some(scof::Fraction::new(4, 4)));
+    assert_eq!(common.beats(), Some(4));
+    assert_eq!(common.beat_value(), Some(4));
+
+    // Not simplified to 3/4
+    let compound = sig("6/8");
+    assert_eq!(compound.beats(), Some(6));
+    assert_eq!(compound.beat_value(), Some(8));
+    assert_eq!(compound.beat(), Some(scof::Fraction::new(3, 8)));
+    assert_eq!(common.beat(), Some(scof::Fraction::new(1, 4)));
+    assert_eq!(sig("3/8").beat(), Some(scof::Fraction::new(1, 8)));
+
+    for garbage in &["", "4", "4/", "C", "4/4/4", "x/4", "4/0"] {
+        assert_eq!(sig(garbage).time_fraction(), None);
+        assert_eq!(sig(garbage).beats(), None);
+        assert_eq!(sig(garbage).beat_value(), None);
+        assert_eq!(sig(garbage).beat(), None);
+    }
+}
@@ -1,15 +1,28 @@
 //! ScoreFall Font Format
 
-use std::convert::TryInto;
-use std::io::{Read, Write};
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
 
 /// The number of units per stave space in this format.
 pub const STAVE_SPACE: i32 = 100;
 
+/// Advance width for glyphs in fonts without advance widths (version 0),
+/// roughly the width of a time signature digit.
+const DEFAULT_ADVANCE: i32 = 450;
+
+/// Number of notehead bounding boxes (stem positions) in the metadata.
+const NOTEHEADS: usize = 20;
+
+/// Number of `i32` fields between the font name and the advance widths: 14
+/// non-glyph components followed by the notehead bounding boxes.
+const METADATA_FIELDS: usize = 14 + NOTEHEADS * 4;
+
 /// Indices of each glyph (grouping most common ones at the beginning to help
 /// with caching.
 #[repr(u16)]
-#[derive(PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Glyph {
     /* Noteheads */
     NoteheadFill = 0x1,
@@ -161,12 +174,292 @@ pub enum Glyph {
     Len = 0x6C,
 }
 
+impl Glyph {
+    /// Every glyph, in index order.
+    pub const ALL: &[Glyph] = {
+        use Glyph::*;
+        &[
+            NoteheadFillX,
+            NoteheadFill,
+            NoteheadHalf,
+            NoteheadWhole,
+            Flat,
+            Sharp,
+            Natural,
+            FlagUp8,
+            FlagDown8,
+            FlagUp16,
+            FlagDown16,
+            Rest1,
+            Rest2,
+            Rest4,
+            Rest8,
+            Rest16,
+            NoteheadDouble,
+            NoteheadHalfX,
+            NoteheadWholeX,
+            NoteheadDoubleX,
+            NoteheadFillTriangle,
+            NoteheadHalfTriangle,
+            NoteheadWholeTriangle,
+            NoteheadDoubleTriangle,
+            NoteheadFillDiamond,
+            NoteheadHalfDiamond,
+            NoteheadWholeDiamond,
+            NoteheadDoubleDiamond,
+            NoteheadFillSlash,
+            NoteheadHalfSlash,
+            NoteheadWholeSlash,
+            NoteheadDoubleSlash,
+            NoteheadFillSlashed,
+            NoteheadHalfSlashed,
+            NoteheadWholeSlashed,
+            NoteheadDoubleSlashed,
+            DoubleFlat,
+            DoubleSharp,
+            QuarterFlat,
+            QuarterSharp,
+            ThreeQuarterFlat,
+            ThreeQuarterSharp,
+            ThirdFlat,
+            ThirdSharp,
+            TwoThirdFlat,
+            TwoThirdSharp,
+            Rest32,
+            Rest64,
+            FlagUp32,
+            FlagDown32,
+            FlagUp64,
+            FlagDown64,
+            ClefC,
+            ClefG,
+            ClefF,
+            ClefN,
+            Clef8,
+            Clef15,
+            Tab4,
+            Tab6,
+            P,
+            MP,
+            MF,
+            F,
+            TimeSig0,
+            TimeSig1,
+            TimeSig2,
+            TimeSig3,
+            TimeSig4,
+            TimeSig5,
+            TimeSig6,
+            TimeSig7,
+            TimeSig8,
+            TimeSig9,
+            TimeSigCommon,
+            TimeSigCut,
+            TimeSigPlus,
+            S,
+            Z,
+            N,
+            Tuplet0,
+            Tuplet1,
+            Tuplet2,
+            Tuplet3,
+            Tuplet4,
+            Tuplet5,
+            Tuplet6,
+            Tuplet7,
+            Tuplet8,
+            Tuplet9,
+            Coda,
+            Segno,
+            RestMulti,
+            RepeatSlash,
+            RepeatUpDot,
+            RepeatDownDot,
+            TupletColon,
+            Tremelo1,
+            Tremelo2,
+            Tremelo3,
+            Tremelo4,
+            Tremelo5,
+            BuzzRoll,
+            Damp,
+            HarpStringNoise,
+            RimShot,
+            BowBridge,
+            BowTailpiece,
+        ]
+    };
+
+    /// Get the stable name of a glyph (the SMuFL name where there is one).
+    pub fn name(self) -> &'static str {
+        use Glyph::*;
+        match self {
+            NoteheadFillX => "noteheadXBlack",
+            NoteheadFill => "noteheadBlack",
+            NoteheadHalf => "noteheadHalf",
+            NoteheadWhole => "noteheadWhole",
+            Flat => "accidentalFlat",
+            Sharp => "accidentalSharp",
+            Natural => "accidentalNatural",
+            FlagUp8 => "flag8thUp",
+            FlagDown8 => "flag8thDown",
+            FlagUp16 => "flag16thUp",
+            FlagDown16 => "flag16thDown",
+            Rest1 => "restWhole",
+            Rest2 => "restHalf",
+            Rest4 => "restQuarter",
+            Rest8 => "rest8th",
+            Rest16 => "rest16th",
+            NoteheadDouble => "noteheadDoubleWhole",
+            NoteheadHalfX => "noteheadXHalf",
+            NoteheadWholeX => "noteheadXWhole",
+            NoteheadDoubleX => "noteheadXDoubleWhole",
+            NoteheadFillTriangle => "noteheadTriangleUpBlack",
+            NoteheadHalfTriangle => "noteheadTriangleUpHalf",
+            NoteheadWholeTriangle => "noteheadTriangleUpWhole",
+            NoteheadDoubleTriangle => "noteheadTriangleUpDoubleWhole",
+            NoteheadFillDiamond => "noteheadDiamondBlack",
+            NoteheadHalfDiamond => "noteheadDiamondHalf",
+            NoteheadWholeDiamond => "noteheadDiamondWhole",
+            NoteheadDoubleDiamond => "noteheadDiamondDoubleWhole",
+            NoteheadFillSlash => "noteheadSlashHorizontalEnds",
+            NoteheadHalfSlash => "noteheadSlashWhiteHalf",
+            NoteheadWholeSlash => "noteheadSlashWhiteWhole",
+            NoteheadDoubleSlash => "noteheadSlashWhiteDoubleWhole",
+            NoteheadFillSlashed => "noteheadSlashedBlack1",
+            NoteheadHalfSlashed => "noteheadSlashedHalf1",
+            NoteheadWholeSlashed => "noteheadSlashedWhole1",
+            NoteheadDoubleSlashed => "noteheadSlashedDoubleWhole1",
+            DoubleFlat => "accidentalDoubleFlat",
+            DoubleSharp => "accidentalDoubleSharp",
+            QuarterFlat => "accidentalQuarterToneFlatStein",
+            QuarterSharp => "accidentalQuarterToneSharpStein",
+            ThreeQuarterFlat => "accidentalThreeQuarterTonesFlatZimmermann",
+            ThreeQuarterSharp => "accidentalThreeQuarterTonesSharpStein",
+            ThirdFlat => "accidentalOneThirdToneFlatFerneyhough",
+            ThirdSharp => "accidentalOneThirdToneSharpFerneyhough",
+            TwoThirdFlat => "accidentalTwoThirdTonesFlatFerneyhough",
+            TwoThirdSharp => "accidentalTwoThirdTonesSharpFerneyhough",
+            Rest32 => "rest32nd",
+            Rest64 => "rest64th",
+            FlagUp32 => "flag32ndUp",
+            FlagDown32 => "flag32ndDown",
+            FlagUp64 => "flag64thUp",
+            FlagDown64 => "flag64thDown",
+            ClefC => "cClef",
+            ClefG => "gClef",
+            ClefF => "fClef",
+            ClefN => "unpitchedPercussionClef1",
+            Clef8 => "clef8",
+            Clef15 => "clef15",
+            Tab4 => "4stringTabClef",
+            Tab6 => "6stringTabClef",
+            P => "dynamicPiano",
+            MP => "dynamicMP",
+            MF => "dynamicMF",
+            F => "dynamicForte",
+            TimeSig0 => "timeSig0",
+            TimeSig1 => "timeSig1",
+            TimeSig2 => "timeSig2",
+            TimeSig3 => "timeSig3",
+            TimeSig4 => "timeSig4",
+            TimeSig5 => "timeSig5",
+            TimeSig6 => "timeSig6",
+            TimeSig7 => "timeSig7",
+            TimeSig8 => "timeSig8",
+            TimeSig9 => "timeSig9",
+            TimeSigCommon => "timeSigCommon",
+            TimeSigCut => "timeSigCutCommon",
+            TimeSigPlus => "timeSigPlus",
+            S => "dynamicSforzando",
+            Z => "dynamicZ",
+            N => "dynamicNiente",
+            Tuplet0 => "tuplet0",
+            Tuplet1 => "tuplet1",
+            Tuplet2 => "tuplet2",
+            Tuplet3 => "tuplet3",
+            Tuplet4 => "tuplet4",
+            Tuplet5 => "tuplet5",
+            Tuplet6 => "tuplet6",
+            Tuplet7 => "tuplet7",
+            Tuplet8 => "tuplet8",
+            Tuplet9 => "tuplet9",
+            Coda => "coda",
+            Segno => "segno",
+            RestMulti => "restHBar",
+            RepeatSlash => "repeatBarSlash",
+            RepeatUpDot => "repeatBarUpperDot",
+            RepeatDownDot => "repeatBarLowerDot",
+            TupletColon => "tupletColon",
+            Tremelo1 => "tremolo1",
+            Tremelo2 => "tremolo2",
+            Tremelo3 => "tremolo3",
+            Tremelo4 => "tremolo4",
+            Tremelo5 => "tremolo5",
+            BuzzRoll => "buzzRoll",
+            Damp => "stemDamp",
+            HarpStringNoise => "stemHarpStringNoise",
+            RimShot => "stemRimShot",
+            BowBridge => "stemBowOnBridge",
+            BowTailpiece => "stemBowOnTailpiece",
+            Len => "",
+        }
+    }
+}
+
 impl From<Glyph> for u16 {
     fn from(g: Glyph) -> Self {
         g as u16
     }
 }
 
+/// Different styles of noteheads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notehead {
+    Normal,
+    X,
+    Diamond,
+    Triangle,
+    Slash,
+}
+
+impl Notehead {
+    /// Get the style of a notehead glyph (`Normal` if it isn't a notehead).
+    pub fn of(glyph: Glyph) -> Self {
+        use Glyph::*;
+        match glyph {
+            NoteheadDoubleX | NoteheadWholeX | NoteheadHalfX
+            | NoteheadFillX => Notehead::X,
+            NoteheadDoubleDiamond
+            | NoteheadWholeDiamond
+            | NoteheadHalfDiamond
+            | NoteheadFillDiamond => Notehead::Diamond,
+            NoteheadDoubleTriangle
+            | NoteheadWholeTriangle
+            | NoteheadHalfTriangle
+            | NoteheadFillTriangle => Notehead::Triangle,
+            NoteheadDoubleSlash | NoteheadWholeSlash | NoteheadHalfSlash
+            | NoteheadFillSlash => Notehead::Slash,
+            _ => Notehead::Normal,
+        }
+    }
+}
+
+/// Error for a glyph index that isn't a glyph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UnknownGlyph(pub u16);
+
+impl TryFrom<u16> for Glyph {
+    type Error = UnknownGlyph;
+
+    fn try_from(index: u16) -> Result<Self, Self::Error> {
+        Glyph::ALL
+            .get(usize::from(index))
+            .copied()
+            .ok_or(UnknownGlyph(index))
+    }
+}
+
 /// Create defs section of SVG for string of glyphs.
 pub fn generate_defs(glyphs: &str) -> String {
     const HEADER: &str = "<defs>";
@@ -195,9 +488,20 @@ pub fn generate_defs(glyphs: &str) -> String {
     String::from_utf8(writer.into_inner().unwrap().into_inner()).unwrap()
 }
 
+/// Iterate over each glyph and its SVG path in a string of glyphs.
+///
+/// Panics if the string doesn't have a path for every glyph.
+pub fn glyph_paths(glyphs: &str) -> impl Iterator<Item = (Glyph, &str)> {
+    assert_eq!(glyphs.split('\0').count(), Glyph::Len as usize);
+
+    Glyph::ALL.iter().copied().zip(glyphs.split('\0'))
+}
+
 /// Builder for all of the glyphs.
 pub struct GlyphsBuilder {
     glyphs: Vec<Option<String>>,
+    // First glyph pushed more than once
+    duplicate: Option<Glyph>,
 }
 
 impl Default for GlyphsBuilder {
@@ -210,15 +514,33 @@ impl GlyphsBuilder {
     pub fn new() -> Self {
         Self {
             glyphs: vec![None; Glyph::Len as usize],
+            duplicate: None,
         }
     }
 
-    /// Add an SVG path.  Must be added in order.
+    /// Add an SVG path.  Each glyph should only be added once.
     pub fn push(&mut self, glyph: Glyph, path: String) {
-        self.glyphs[glyph as usize] = Some(path);
+        if self.glyphs[glyph as usize].replace(path).is_some() {
+            self.duplicate = self.duplicate.or(Some(glyph));
+        }
+    }
+
+    /// Join the SVG paths, separated by null bytes.  Fails if any glyph was
+    /// added more than once, or not at all.
+    pub fn into_string(self) -> Result<String, BuildError> {
+        if let Some(glyph) = self.duplicate {
+            return Err(BuildError::DuplicatePush(glyph));
+        }
+        if let Some(i) = self.glyphs.iter().position(Option::is_none) {
+            return Err(BuildError::MissingGlyph(Glyph::ALL[i]));
+        }
+        Ok(self.into_string_unchecked())
     }
 
-    pub fn into_string(self) -> String {
+    /// Join the SVG paths, separated by null bytes.
+    ///
+    /// Panics if any glyph wasn't added.
+    pub fn into_string_unchecked(self) -> String {
         let mut output = String::new();
 
         for glyph in self.glyphs.iter() {
@@ -232,6 +554,15 @@ impl GlyphsBuilder {
     }
 }
 
+/// Error for building the glyphs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    /// A glyph wasn't added.
+    MissingGlyph(Glyph),
+    /// A glyph was added more than once.
+    DuplicatePush(Glyph),
+}
+
 /// Error for writing the format.
 #[derive(Debug)]
 pub enum WriteError {
@@ -252,11 +583,14 @@ pub enum ReadError {
     UnexpectedEOF,
     /// Wrong number of glyphs are in the file.
     WrongGlyphCount,
+    /// Compressed data is corrupt.
+    InvalidCompression,
 }
 
 /// A ScoreFall Font Metadata
 pub struct SfFontMetadata {
-    /// Must be 0
+    /// 0, 1 for fonts with advance widths, or 2 for compressed fonts (also
+    /// with advance widths)
     pub sffonts_version: u16,
     /// Name of this font
     pub font_name: String,
@@ -316,9 +650,122 @@ pub struct SfFontMetadata {
     pub notehead_double_diamond: [[i32; 2]; 2],
     pub notehead_double_triangle: [[i32; 2]; 2],
     pub notehead_double_slash: [[i32; 2]; 2],
+
+    /// Advance width of each glyph, indexed by `Glyph` (empty for version 0)
+    pub advance: Vec<i32>,
 }
 
 impl SfFontMetadata {
+    /// Get the advance width of a glyph.
+    pub fn advance(&self, glyph: Glyph) -> i32 {
+        self.advance
+            .get(glyph as usize)
+            .copied()
+            .unwrap_or(DEFAULT_ADVANCE)
+    }
+
+    /// Get the left and right stem attachment points of a notehead style.
+    /// The duration (in 128th notes) chooses the filled, half, whole or
+    /// double whole notehead.
+    pub fn stem_anchors(
+        &self,
+        notehead: Notehead,
+        duration: u16,
+    ) -> [[i32; 2]; 2] {
+        use Notehead::*;
+
+        let [double, whole, half, fill] = match notehead {
+            Normal => [
+                self.notehead_double,
+                self.notehead_whole,
+                self.notehead_half,
+                self.notehead,
+            ],
+            X => [
+                self.notehead_double_x,
+                self.notehead_whole_x,
+                self.notehead_half_x,
+                self.notehead_x,
+            ],
+            Diamond => [
+                self.notehead_double_diamond,
+                self.notehead_whole_diamond,
+                self.notehead_half_diamond,
+                self.notehead_diamond,
+            ],
+            Triangle => [
+                self.notehead_double_triangle,
+                self.notehead_whole_triangle,
+                self.notehead_half_triangle,
+                self.notehead_triangle,
+            ],
+            Slash => [
+                self.notehead_double_slash,
+                self.notehead_whole_slash,
+                self.notehead_half_slash,
+                self.notehead_slash,
+            ],
+        };
+        match duration {
+            0..=63 => fill,
+            64..=127 => half,
+            128..=255 => whole,
+            _ => double,
+        }
+    }
+
+    /// Get the notehead bounding boxes, in the order they're stored.
+    fn noteheads(&self) -> [&[[i32; 2]; 2]; NOTEHEADS] {
+        [
+            &self.notehead,
+            &self.notehead_x,
+            &self.notehead_diamond,
+            &self.notehead_triangle,
+            &self.notehead_slash,
+            &self.notehead_half,
+            &self.notehead_half_x,
+            &self.notehead_half_diamond,
+            &self.notehead_half_triangle,
+            &self.notehead_half_slash,
+            &self.notehead_whole,
+            &self.notehead_whole_x,
+            &self.notehead_whole_diamond,
+            &self.notehead_whole_triangle,
+            &self.notehead_whole_slash,
+            &self.notehead_double,
+            &self.notehead_double_x,
+            &self.notehead_double_diamond,
+            &self.notehead_double_triangle,
+            &self.notehead_double_slash,
+        ]
+    }
+
+    /// Get the notehead bounding boxes mutably, in the order they're stored.
+    fn noteheads_mut(&mut self) -> [&mut [[i32; 2]; 2]; NOTEHEADS] {
+        [
+            &mut self.notehead,
+            &mut self.notehead_x,
+            &mut self.notehead_diamond,
+            &mut self.notehead_triangle,
+            &mut self.notehead_slash,
+            &mut self.notehead_half,
+            &mut self.notehead_half_x,
+            &mut self.notehead_half_diamond,
+            &mut self.notehead_half_triangle,
+            &mut self.notehead_half_slash,
+            &mut self.notehead_whole,
+            &mut self.notehead_whole_x,
+            &mut self.notehead_whole_diamond,
+            &mut self.notehead_whole_triangle,
+            &mut self.notehead_whole_slash,
+            &mut self.notehead_double,
+            &mut self.notehead_double_x,
+            &mut self.notehead_double_diamond,
+            &mut self.notehead_double_triangle,
+            &mut self.notehead_double_slash,
+        ]
+    }
+
     /// Write font data.
     pub fn write<T: Write>(
         &self,
@@ -329,323 +776,122 @@ impl SfFontMetadata {
         writer
             .write(&self.sffonts_version.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        // FIXME: Start Compression
-        writer
+        // Everything after the version is compressed (since version 2).
+        let mut buffer = vec![];
+        buffer
             .write(&[self
                 .font_name
                 .len()
                 .try_into()
                 .map_err(|_| WriteError::FontNameTooLong)?])
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(self.font_name.as_bytes())
             .map_err(|_| WriteError::Prevented)?;
 
         // Non-glyph components (in thousandths of stave space)
-        writer
+        buffer
             .write(&self.stave_line_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.stem_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.ledger_line_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.ledger_line_extension.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.slur_endpoint_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.slur_midpoint_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.barline_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.thick_barline_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.barlines_space.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.barline_repeatdot_space.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.bracket_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.subbracket_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.hairpin_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
-        writer
+        buffer
             .write(&self.rehearsal_box_thickness.to_le_bytes())
             .map_err(|_| WriteError::Prevented)?;
 
-        // Glyph
-        writer
-            .write(&self.notehead[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_x[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_x[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_x[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_x[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_diamond[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_diamond[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_diamond[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_diamond[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_triangle[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_triangle[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_triangle[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_triangle[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_slash[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_slash[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_slash[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_slash[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-
-        writer
-            .write(&self.notehead_half[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_x[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_x[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_x[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_x[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_diamond[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_diamond[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_diamond[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_diamond[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_triangle[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_triangle[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_triangle[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_triangle[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_slash[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_slash[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_slash[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_half_slash[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
+        // Glyph metadata (notehead & stem positions)
+        for bounds in self.noteheads() {
+            for value in bounds.iter().flatten() {
+                buffer
+                    .write(&value.to_le_bytes())
+                    .map_err(|_| WriteError::Prevented)?;
+            }
+        }
 
-        writer
-            .write(&self.notehead_whole[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_x[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_x[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_x[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_x[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_diamond[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_diamond[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_diamond[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_diamond[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_triangle[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_triangle[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_triangle[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_triangle[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_slash[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_slash[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_slash[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_whole_slash[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
+        // Glyph advance widths
+        if self.sffonts_version >= 1 {
+            for i in 0..Glyph::Len as usize {
+                let advance = self.advance.get(i).copied().unwrap_or(0);
+                buffer
+                    .write(&advance.to_le_bytes())
+                    .map_err(|_| WriteError::Prevented)?;
+            }
+        }
 
-        writer
-            .write(&self.notehead_double[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_x[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_x[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_x[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_x[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_diamond[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_diamond[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_diamond[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_diamond[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_triangle[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_triangle[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_triangle[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_triangle[1][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_slash[0][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_slash[0][1].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_slash[1][0].to_le_bytes())
-            .map_err(|_| WriteError::Prevented)?;
-        writer
-            .write(&self.notehead_double_slash[1][1].to_le_bytes())
+        // Glyph SVG paths
+        buffer
+            .write(glyph_paths.as_bytes())
             .map_err(|_| WriteError::Prevented)?;
 
-        // Glyph SVG paths
+        if self.sffonts_version >= 2 {
+            buffer = miniz_oxide::deflate::compress_to_vec(&buffer, 9);
+        }
         writer
-            .write(glyph_paths.as_bytes())
+            .write_all(&buffer)
             .map_err(|_| WriteError::Prevented)?;
 
         // Make sure everything was written.
         writer.flush().map_err(|_| WriteError::Prevented)
     }
 
+    /// Write font data to a file.
+    pub fn write_to_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+        glyph_paths: &str,
+    ) -> Result<(), WriteError> {
+        let file = File::create(path).map_err(|_| WriteError::Prevented)?;
+        self.write(&mut BufWriter::new(file), glyph_paths)
+    }
+
+    /// Read a font file into a metadata struct and a defs section of an SVG.
+    pub fn read_from_path<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(Self, String), ReadError> {
+        let file = File::open(path).map_err(|_| ReadError::Prevented)?;
+        Self::from_buf_reader(BufReader::new(file))
+    }
+
     /// Read a font into a metadata struct and a defs section of an SVG.
     pub fn from_buf_reader<T: Read>(
         mut reader: T,
     ) -> Result<(Self, String), ReadError> {
         let mut byte = [0u8; 1];
         let mut word = [0u8; 2];
-        let mut long = [0u8; 4];
 
         // Header
         reader
@@ -653,7 +899,16 @@ impl SfFontMetadata {
             .map_err(|_| ReadError::UnexpectedEOF)?;
         let sffonts_version = u16::from_le_bytes(word);
 
-        // FIXME: Start De-Compression
+        // Everything after the version is compressed (since version 2).
+        let mut buffer = vec![];
+        reader
+            .read_to_end(&mut buffer)
+            .map_err(|_| ReadError::Prevented)?;
+        if sffonts_version >= 2 {
+            buffer = miniz_oxide::inflate::decompress_to_vec(&buffer)
+                .map_err(|_| ReadError::InvalidCompression)?;
+        }
+        let mut reader = buffer.as_slice();
         reader
             .read_exact(&mut byte)
             .map_err(|_| ReadError::UnexpectedEOF)?;
@@ -664,442 +919,38 @@ impl SfFontMetadata {
         let font_name =
             String::from_utf8(font_name).map_err(|_| ReadError::InvalidText)?;
 
-        // Non-glyph components (in thousandths of stave space)
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let stave_line_thickness = u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let stem_thickness = u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let ledger_line_thickness =
-            u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let ledger_line_extension =
-            u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let slur_endpoint_thickness =
-            u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let slur_midpoint_thickness =
-            u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let barline_thickness = u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let thick_barline_thickness =
-            u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let barlines_space = u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let barline_repeatdot_space =
-            u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let bracket_thickness = u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let subbracket_thickness = u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let hairpin_thickness = u32::from_le_bytes(long).try_into().unwrap();
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let rehearsal_box_thickness =
-            u32::from_le_bytes(long).try_into().unwrap();
-
-        // Glyph Metadata (Quarter)
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_x = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_diamond = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_triangle = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_slash = [[x1, y1], [x2, y2]];
-
-        // Glyph Metadata (Half)
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_half = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_half_x = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_half_diamond = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_half_triangle = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_half_slash = [[x1, y1], [x2, y2]];
-
-        // Glyph Metadata (Whole)
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_whole = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_whole_x = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_whole_diamond = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_whole_triangle = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_whole_slash = [[x1, y1], [x2, y2]];
-
-        // Glyph Metadata (Double Whole Notes)
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_double = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_double_x = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_double_diamond = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_double_triangle = [[x1, y1], [x2, y2]];
-
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y1 = i32::from_le_bytes(long);
-        reader
-            .read_exact(&mut long)
-            .map_err(|_| ReadError::UnexpectedEOF)?;
-        let x2 = i32::from_le_bytes(long);
+        // Everything up to the glyph paths is fixed-size, so read it at once.
+        let mut len = METADATA_FIELDS * 4;
+        if sffonts_version >= 1 {
+            len += Glyph::Len as usize * 4;
+        }
+        let mut block = vec![0; len];
         reader
-            .read_exact(&mut long)
+            .read_exact(&mut block)
             .map_err(|_| ReadError::UnexpectedEOF)?;
-        let y2 = i32::from_le_bytes(long);
-        let notehead_double_slash = [[x1, y1], [x2, y2]];
-
-        // Glyph SVG paths
-        let mut glyph_paths = String::new();
-        reader
-            .read_to_string(&mut glyph_paths)
-            .map_err(|_| ReadError::Prevented)?;
+        let mut fields = block
+            .chunks_exact(4)
+            .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]));
+        let mut field = || fields.next().unwrap();
 
-        let new = Self {
+        // Non-glyph components (in thousandths of stave space)
+        let stave_line_thickness = field();
+        let stem_thickness = field();
+        let ledger_line_thickness = field();
+        let ledger_line_extension = field();
+        let slur_endpoint_thickness = field();
+        let slur_midpoint_thickness = field();
+        let barline_thickness = field();
+        let thick_barline_thickness = field();
+        let barlines_space = field();
+        let barline_repeatdot_space = field();
+        let bracket_thickness = field();
+        let subbracket_thickness = field();
+        let hairpin_thickness = field();
+        let rehearsal_box_thickness = field();
+
+        let zero = [[0; 2]; 2];
+        let mut new = Self {
             sffonts_version,
             font_name,
             stave_line_thickness,
@@ -1116,28 +967,265 @@ impl SfFontMetadata {
             subbracket_thickness,
             hairpin_thickness,
             rehearsal_box_thickness,
-            notehead, // also includes slashed notehead
-            notehead_x,
-            notehead_diamond,
-            notehead_triangle,
-            notehead_slash,
-            notehead_half, // also includes slashed notehead
-            notehead_half_x,
-            notehead_half_diamond,
-            notehead_half_triangle,
-            notehead_half_slash,
-            notehead_whole, // also includes slashed notehead
-            notehead_whole_x,
-            notehead_whole_diamond,
-            notehead_whole_triangle,
-            notehead_whole_slash,
-            notehead_double, // also includes slashed notehead
-            notehead_double_x,
-            notehead_double_diamond,
-            notehead_double_triangle,
-            notehead_double_slash,
+            notehead: zero,
+            notehead_x: zero,
+            notehead_diamond: zero,
+            notehead_triangle: zero,
+            notehead_slash: zero,
+            notehead_half: zero,
+            notehead_half_x: zero,
+            notehead_half_diamond: zero,
+            notehead_half_triangle: zero,
+            notehead_half_slash: zero,
+            notehead_whole: zero,
+            notehead_whole_x: zero,
+            notehead_whole_diamond: zero,
+            notehead_whole_triangle: zero,
+            notehead_whole_slash: zero,
+            notehead_double: zero,
+            notehead_double_x: zero,
+            notehead_double_diamond: zero,
+            notehead_double_triangle: zero,
+            notehead_double_slash: zero,
+            advance: vec![],
         };
 
+        // Glyph metadata (notehead & stem positions)
+        for bounds in new.noteheads_mut() {
+            *bounds = [[field(), field()], [field(), field()]];
+        }
+
+        // Glyph advance widths
+        if sffonts_version >= 1 {
+            for _ in 0..Glyph::Len as usize {
+                new.advance.push(field());
+            }
+        }
+
+        // Glyph SVG paths
+        let mut glyph_paths = String::new();
+        reader
+            .read_to_string(&mut glyph_paths)
+            .map_err(|_| ReadError::Prevented)?;
+
         Ok((new, glyph_paths))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_glyphs() {
+        assert_eq!(Glyph::ALL.len(), Glyph::Len as usize);
+        let mut names = vec![];
+        for (i, glyph) in Glyph::ALL.iter().enumerate() {
+            assert_eq!(*glyph as usize, i);
+            assert!(!names.contains(&glyph.name()));
+            names.push(glyph.name());
+        }
+        assert_eq!(Glyph::NoteheadFill.name(), "noteheadBlack");
+    }
+
+    /// An uncompressed (version 1) font named "Test", with a different value
+    /// in each metadata field and the same placeholder path for each glyph.
+    fn blank_font() -> Vec<u8> {
+        let paths = vec!["M0 0h1v1z"; Glyph::Len as usize].join("\0");
+        let mut data = 1u16.to_le_bytes().to_vec();
+        data.push(4);
+        data.extend(b"Test");
+        for i in 0..(METADATA_FIELDS + Glyph::Len as usize) as i32 {
+            data.extend(&i.to_le_bytes());
+        }
+        data.extend(paths.as_bytes());
+        data
+    }
+
+    #[test]
+    fn path_round_trip() {
+        let (mut meta, glyphs) =
+            SfFontMetadata::from_buf_reader(blank_font().as_slice()).unwrap();
+        meta.sffonts_version = 2;
+
+        let file = std::env::temp_dir()
+            .join(format!("sfff-path-round-trip-{}.sfff", std::process::id()));
+        meta.write_to_path(&file, &glyphs).unwrap();
+        let read = SfFontMetadata::read_from_path(&file);
+        std::fs::remove_file(&file).unwrap();
+        let (read, read_glyphs) = read.unwrap();
+        assert_eq!(read.font_name, "Test");
+        assert_eq!(metadata_fields(&read), metadata_fields(&meta));
+        assert_eq!(read_glyphs, glyphs);
+        assert!(SfFontMetadata::read_from_path(&file).is_err());
+    }
+
+    #[test]
+    fn notehead_round_trip() {
+        let (mut meta, glyphs) =
+            SfFontMetadata::from_buf_reader(blank_font().as_slice()).unwrap();
+        // Different sentinel values in every notehead field.
+        for (i, bounds) in meta.noteheads_mut().iter_mut().enumerate() {
+            let i = i as i32 * 4;
+            **bounds = [[i + 1000, i + 1001], [i + 1002, i + 1003]];
+        }
+        meta.notehead_double_slash = [[-1, -2], [-3, -4]];
+        let mut written = vec![];
+        meta.write(&mut written, &glyphs).unwrap();
+        let (read, _glyphs) =
+            SfFontMetadata::from_buf_reader(written.as_slice()).unwrap();
+        assert_eq!(read.notehead, [[1000, 1001], [1002, 1003]]);
+        assert_eq!(read.notehead_x, [[1004, 1005], [1006, 1007]]);
+        assert_eq!(read.notehead_whole_slash, [[1056, 1057], [1058, 1059]]);
+        assert_eq!(read.notehead_double_slash, [[-1, -2], [-3, -4]]);
+        assert_eq!(metadata_fields(&read), metadata_fields(&meta));
+    }
+
+    #[test]
+    fn stem_anchors() {
+        let (mut meta, _glyphs) =
+            SfFontMetadata::from_buf_reader(blank_font().as_slice()).unwrap();
+        meta.notehead_x = [[0, 10], [118, -10]];
+        meta.notehead_half_x = [[0, 12], [120, -12]];
+        assert_eq!(meta.stem_anchors(Notehead::X, 32), meta.notehead_x);
+        assert_eq!(meta.stem_anchors(Notehead::X, 64), meta.notehead_half_x);
+        assert_eq!(meta.stem_anchors(Notehead::Normal, 32), meta.notehead);
+    }
+
+    #[test]
+    fn build_glyphs() {
+        let builder = || {
+            let mut builder = GlyphsBuilder::new();
+            for glyph in Glyph::ALL {
+                builder.push(*glyph, "M0 0h1v1z".to_string());
+            }
+            builder
+        };
+        let glyphs = builder().into_string().unwrap();
+        assert_eq!(glyphs.split('\0').count(), Glyph::Len as usize);
+
+        let mut missing = GlyphsBuilder::new();
+        for glyph in Glyph::ALL.iter().filter(|g| **g != Glyph::Segno) {
+            missing.push(*glyph, "M0 0h1v1z".to_string());
+        }
+        assert_eq!(
+            missing.into_string(),
+            Err(BuildError::MissingGlyph(Glyph::Segno))
+        );
+
+        let mut duplicate = builder();
+        duplicate.push(Glyph::Flat, "M0 0h2v2z".to_string());
+        assert_eq!(
+            duplicate.into_string(),
+            Err(BuildError::DuplicatePush(Glyph::Flat))
+        );
+    }
+
+    #[test]
+    fn paths_by_glyph() {
+        let mut builder = GlyphsBuilder::new();
+        for glyph in Glyph::ALL {
+            builder.push(*glyph, format!("M{} 0z", u16::from(*glyph)));
+        }
+        let glyphs = builder.into_string().unwrap();
+        let paths: Vec<(Glyph, &str)> = glyph_paths(&glyphs).collect();
+        assert_eq!(paths.len(), Glyph::Len as usize);
+        assert_eq!(paths[0], (Glyph::ALL[0], "M0 0z"));
+        assert_eq!(paths[0x6B], (Glyph::ALL[0x6B], "M107 0z"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn paths_missing_glyph() {
+        glyph_paths("M0 0z\0M1 0z").count();
+    }
+
+    #[test]
+    fn glyph_from_index() {
+        for glyph in Glyph::ALL {
+            assert_eq!(Glyph::try_from(u16::from(*glyph)), Ok(*glyph));
+        }
+        assert_eq!(Glyph::try_from(Glyph::Len as u16), Err(UnknownGlyph(0x6C)));
+        assert_eq!(Glyph::try_from(0xFFFF), Err(UnknownGlyph(0xFFFF)));
+    }
+
+    /// Read the metadata the old way, one `read_exact` per field.
+    fn read_fields_one_by_one(mut reader: &[u8]) -> (u16, String, Vec<i32>) {
+        let mut word = [0u8; 2];
+        reader.read_exact(&mut word).unwrap();
+        let version = u16::from_le_bytes(word);
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte).unwrap();
+        let mut name = vec![0; byte[0] as usize];
+        reader.read_exact(&mut name).unwrap();
+        let mut count = METADATA_FIELDS;
+        if version >= 1 {
+            count += Glyph::Len as usize;
+        }
+        let mut fields = vec![];
+        for _ in 0..count {
+            let mut long = [0u8; 4];
+            reader.read_exact(&mut long).unwrap();
+            fields.push(i32::from_le_bytes(long));
+        }
+        (version, String::from_utf8(name).unwrap(), fields)
+    }
+
+    /// Get every `i32` field of font metadata, in the order they're stored.
+    fn metadata_fields(meta: &SfFontMetadata) -> Vec<i32> {
+        let mut fields = vec![
+            meta.stave_line_thickness,
+            meta.stem_thickness,
+            meta.ledger_line_thickness,
+            meta.ledger_line_extension,
+            meta.slur_endpoint_thickness,
+            meta.slur_midpoint_thickness,
+            meta.barline_thickness,
+            meta.thick_barline_thickness,
+            meta.barlines_space,
+            meta.barline_repeatdot_space,
+            meta.bracket_thickness,
+            meta.subbracket_thickness,
+            meta.hairpin_thickness,
+            meta.rehearsal_box_thickness,
+        ];
+        for bounds in meta.noteheads() {
+            fields.extend(bounds.iter().flatten());
+        }
+        fields.extend(&meta.advance);
+        fields
+    }
+
+    #[test]
+    fn one_pass_read_matches_field_by_field() {
+        // Every field has a different value, so fields read out of order
+        // don't match.
+        let data = blank_font();
+        let (meta, _paths) =
+            SfFontMetadata::from_buf_reader(data.as_slice()).unwrap();
+        let (version, name, expected) = read_fields_one_by_one(&data);
+
+        assert_eq!(meta.sffonts_version, version);
+        assert_eq!(meta.font_name, name);
+        assert_eq!(metadata_fields(&meta), expected);
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let data = blank_font();
+        let (mut meta, glyphs) =
+            SfFontMetadata::from_buf_reader(data.as_slice()).unwrap();
+        assert_eq!(meta.sffonts_version, 1);
+
+        meta.sffonts_version = 2;
+        let mut compressed = vec![];
+        meta.write(&mut compressed, &glyphs).unwrap();
+        assert!(compressed.len() < data.len());
+        let (read, read_glyphs) =
+            SfFontMetadata::from_buf_reader(compressed.as_slice()).unwrap();
+        assert_eq!(read.sffonts_version, 2);
+        assert_eq!(read.font_name, "Test");
+        assert_eq!(metadata_fields(&read), metadata_fields(&meta));
+        assert_eq!(read_glyphs, glyphs);
+    }
+}
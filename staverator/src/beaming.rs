@@ -22,11 +22,13 @@
 
 use std::collections::VecDeque;
 
-use scof::{Pitch, Steps};
+use crate::glyph::duration_128ths;
+use crate::stem::{stem_up, StemContext};
+use scof::{Clef, Feather, Fraction, Note, Steps};
 
-use cala::log::{Tag, log};
+use cala::log::Tag;
 
-const INFO: Tag = Tag::new("Beaming");
+const INFO: Tag = Tag::new("Beaming").show(cfg!(feature = "trace"));
 
 // Beaming rules for a time signature
 struct BeamRules {
@@ -45,6 +47,20 @@ const BEAMRULE_4_4: BeamRules = BeamRules {
     inner: 16,
 };
 
+// 3/4 Time signature beaming rules (each beat beamed separately).
+const BEAMRULE_3_4: BeamRules = BeamRules {
+    eighth: 32,
+    sixteenth: 32,
+    inner: 16,
+};
+
+// 6/8 Time signature beaming rules (beamed by dotted quarters).
+const BEAMRULE_6_8: BeamRules = BeamRules {
+    eighth: 48,
+    sixteenth: 48,
+    inner: 16,
+};
+
 /// Should there be a beam connecting to previous note?
 #[derive(PartialEq, Debug)]
 pub enum BeamProp {
@@ -55,68 +71,123 @@ pub enum BeamProp {
     Flag,
 }
 
+/// Direction of partial beams (beamlets) on a note with more beams than it
+/// shares with its neighbors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Beamlet {
+    /// No beamlets
+    None,
+    /// Beamlets point to the previous note
+    Left,
+    /// Beamlets point to the next note
+    Right,
+}
+
+/// Note (as written) of a chord, Y offset of its stave, and the stave's clef.
+pub(crate) type Chord = (Note, Steps, Clef);
+
+/// A note in a beamed group: duration, position, steps of the closest pitch to
+/// the beam (see `Stave::pitch_steps`) and Y offset of its stave, whether only the primary beam joins the previous note, and beamlet
+/// direction.
+pub(crate) type BeamNote = (u16, f32, (Steps, Steps), bool, Beamlet);
+
 /// All of the beams in a measure.
 pub(crate) struct Beams {
     // Duration not notated yet in the measure.
     dur: u16,
     // Notes that may be flagged or beamed.
-    short: VecDeque<(BeamProp, u16, f32, (Vec<Pitch>, Steps))>,
+    // The `bool` is set when a rest comes between this and the last note.
+    short: VecDeque<(BeamProp, u16, f32, Chord, bool)>,
     // Last was short?
     last_short: bool,
+    // Is there a rest since the last short note?
+    rest: bool,
     // Minimum duration within current beam.
     min_dur: u16,
-    // Notes in the beamed group.
-    notes: Vec<(u16, f32, (Vec<Pitch>, Steps), bool)>,
+    // Notes in the beamed group (secondary beam break, rest before).
+    notes: Vec<(u16, f32, Chord, bool, bool)>,
     // For iterator.
     queued: Option<Short>,
+    // Steps the middle line of the stave is above middle C.
+    middle: Steps,
+    // Beaming rules for the time signature.
+    rules: &'static BeamRules,
 }
 
 impl Beams {
     /// Create an empty instance of beams for the measure.
-    pub fn new() -> Self {
+    ///
+    /// - `middle`: Steps the middle line of the stave is above middle C.
+    /// - `time`: Time signature of the measure.
+    /// - `measure`: Length of the measure in 128th notes.
+    pub fn new(middle: Steps, time: Fraction, measure: u16) -> Self {
+        // Compound meters (6/8, 9/8, 12/8) are beamed by dotted quarters.
+        let compound = time.den == 8 && time.num > 3 && time.num % 3 == 0;
         Beams {
-            // Start with 4 beats left (4/4)
-            dur: 128,
+            // Start with the whole measure left
+            dur: measure,
             // Start with no discovered flag/beam notes yet.
             short: VecDeque::new(),
             //
             last_short: false,
             //
+            rest: false,
+            //
             min_dur: 0,
             //
             notes: vec![],
             //
             queued: None,
+            //
+            middle,
+            //
+            rules: if compound {
+                &BEAMRULE_6_8
+            } else if measure % BEAMRULE_4_4.eighth == 0 {
+                &BEAMRULE_4_4
+            } else {
+                &BEAMRULE_3_4
+            },
         }
     }
 
     /// Advance duration.
+    ///
+    /// - `y`: The note, or `None` for a rest.  Rests shorter than a quarter
+    ///   note within a beamed group are beamed over, while longer rests end
+    ///   the group (the next note starts a new one).
+    /// - `beam_break`: Start a new beam group at this note, regardless of the
+    ///   beaming rules.
     pub fn advance(
         &mut self,
         dur: u16,
         width: f32,
-        y: Option<(Vec<Pitch>, Steps)>,
+        y: Option<Chord>,
+        beam_break: bool,
     ) {
         let new_dur = self.dur - dur;
+        let continues = self.last_short
+            && !beam_break
+            && self.dur / self.rules.eighth == new_dur / self.rules.eighth;
         // Not a rest
         self.last_short = if let Some(y) = y {
+            // Flags and beams are for the written duration, which is longer
+            // than the time it takes up for tuplets.
+            let dur = duration_128ths(&y.0);
             // Less than a quarter note
             if dur < 32 {
-                let prop = if self.last_short
-                    && self.dur / BEAMRULE_4_4.eighth
-                        == new_dur / BEAMRULE_4_4.eighth
-                {
+                let prop = if continues {
                     // If last note could be beamed to this note
                     let mut prev = self.short.pop_back().unwrap();
                     if prev.0 == BeamProp::Flag {
                         prev.0 = BeamProp::None;
                     }
                     self.short.push_back(prev);
-                    if self.dur / BEAMRULE_4_4.sixteenth
-                        == new_dur / BEAMRULE_4_4.sixteenth
+                    if self.dur / self.rules.sixteenth
+                        == new_dur / self.rules.sixteenth
                     {
-                        if self.dur / BEAMRULE_4_4.inner
-                            == new_dur / BEAMRULE_4_4.inner
+                        if self.dur / self.rules.inner
+                            == new_dur / self.rules.inner
                         {
                             BeamProp::ContinueInner
                         } else {
@@ -129,14 +200,17 @@ impl Beams {
                     BeamProp::Flag
                 };
 
-                self.short.push_back((prop, dur, width, y));
-                log!(INFO, "{:?}", self.short);
+                let rest = std::mem::take(&mut self.rest) && continues;
+                self.short.push_back((prop, dur, width, y, rest));
+                trace!(INFO, "{:?}", self.short);
                 true
             } else {
                 false
             }
         } else {
-            false
+            // Keep beaming over a short rest within the group.
+            self.rest = continues && dur < 32;
+            self.rest
         };
         // Reduce remaining duration.
         self.dur = new_dur;
@@ -150,7 +224,7 @@ impl Iterator for Beams {
         if let Some(ret) = self.queued.take() {
             return Some(ret);
         }
-        while let Some((prop, dur, width, y)) = self.short.pop_front() {
+        while let Some((prop, dur, width, y, rest)) = self.short.pop_front() {
             match prop {
                 BeamProp::None => {
                     // Start of a beam
@@ -159,7 +233,7 @@ impl Iterator for Beams {
                     } else {
                         None
                     };
-                    self.notes.push((dur, width, y, false));
+                    self.notes.push((dur, width, y, false, rest));
                     self.min_dur = dur;
                     if let Some(beam) = beam {
                         return Some(Short::Beam(beam));
@@ -169,20 +243,20 @@ impl Iterator for Beams {
                     // If there's more than one beam, break into 2 beam groups.
                     if self.min_dur < 16 {
                         let beam = Beam::new(self);
-                        self.notes.push((dur, width, y, false));
+                        self.notes.push((dur, width, y, false, rest));
                         self.min_dur = dur;
                         return Some(Short::Beam(beam));
                     }
-                    self.notes.push((dur, width, y, false));
+                    self.notes.push((dur, width, y, false, rest));
                     self.min_dur = dur.min(self.min_dur);
                 }
                 BeamProp::ContinueSixteenth => {
                     // Set single beam point for 3+ beams to true
-                    self.notes.push((dur, width, y, true));
+                    self.notes.push((dur, width, y, true, rest));
                     self.min_dur = dur.min(self.min_dur);
                 }
                 BeamProp::ContinueInner => {
-                    self.notes.push((dur, width, y, false));
+                    self.notes.push((dur, width, y, false, rest));
                     self.min_dur = dur.min(self.min_dur);
                 }
                 BeamProp::Flag => {
@@ -212,7 +286,7 @@ impl Iterator for Beams {
 /// Short note: A flag or a beam
 pub(crate) enum Short {
     /// Flag
-    Flag(u16, f32, (Vec<Pitch>, Steps)),
+    Flag(u16, f32, Chord),
     /// Beam
     Beam(Beam),
 }
@@ -220,35 +294,168 @@ pub(crate) enum Short {
 /// A beamed group.
 pub(crate) struct Beam {
     // Notes in the beamed group.
-    pub(crate) notes: Vec<(u16, f32, (Pitch, Steps), bool)>,
+    pub(crate) notes: Vec<BeamNote>,
     // Stem direction (false is down).
     pub(crate) stems_up: bool,
+    // Feathered beams, from the first note of the group.
+    pub(crate) feather: Option<Feather>,
 }
 
 impl Beam {
     /// Create a new beam object.
     pub fn new(beams: &mut Beams) -> Self {
         // Choose stem direction of beamed group.
-        let mut sum = 0i16;
-        for note_i in 0..beams.notes.len() {
-            let vd = beams.notes[note_i].2 .0[0].visual_distance();
-            match vd.0 {
-                _a if _a > 0 => sum += 1,
-                _a if _a < 0 => sum -= 1,
-                _ => {}
-            }
-        }
-        let stems_up = sum < 0;
+        let group: Vec<Steps> = beams
+            .notes
+            .iter()
+            .map(|note| note.2 .0.pitch[0].staff_position(note.2 .2))
+            .collect();
+        let stems_up = stem_up(group[0], StemContext::Group(&group));
         let uses_three_beams = beams.min_dur < 8; // Less than 16th note
+        let feather = beams.notes[0].2 .0.feather;
 
         // Select closest notes to the beam.
         let mut notes = vec![];
         for note in beams.notes.drain(..) {
-            let one_beam = note.3 && uses_three_beams;
+            let one_beam = (note.3 && uses_three_beams) || note.4;
             // FIXME: Choose closest note to beam.
-            notes.push((note.0, note.1, (note.2 .0[0], note.2 .1), one_beam));
+            let steps = note.2 .0.pitch[0].staff_position(note.2 .2);
+            let pitch = (steps + beams.middle, note.2 .1);
+            notes.push((note.0, note.1, pitch, one_beam, Beamlet::None));
+        }
+
+        // Add beamlets where a note has more beams than it shares.
+        let mut beam = Beam {
+            notes,
+            stems_up,
+            feather,
+        };
+        for i in 0..beam.notes.len() {
+            let (left, right) = (beam.joins(i), beam.joins(i + 1));
+            if beam_count(beam.notes[i].0) > left.max(right) {
+                beam.notes[i].4 = if right >= left {
+                    Beamlet::Right
+                } else {
+                    Beamlet::Left
+                };
+            }
+        }
+        beam
+    }
+
+    /// Get the number of beams shared by a note and the previous note (0 if
+    /// there's no note).
+    pub(crate) fn joins(&self, i: usize) -> u8 {
+        if i == 0 || i >= self.notes.len() {
+            0
+        } else if self.notes[i].3 {
+            1
+        } else {
+            beam_count(self.notes[i - 1].0).min(beam_count(self.notes[i].0))
         }
+    }
+}
+
+/// Get the number of beams for a note duration (in 128th notes).
+pub(crate) fn beam_count(dur: u16) -> u8 {
+    match dur {
+        1 => 5,       // 128th note beams
+        2..=3 => 4,   // 64th note beams
+        4..=7 => 3,   // 32nd note beams
+        8..=15 => 2,  // 16th note beams
+        16..=31 => 1, // 8th note beams
+        a => panic!("Invalid {}", a),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beam_break() {
+        let groups = |breaks: [bool; 4]| {
+            let mut beams = Beams::new(Steps(0), Fraction::new(4, 4), 128);
+            for (i, beam_break) in breaks.iter().enumerate() {
+                let c5: Note = "1/8C5".parse().unwrap();
+                let width = i as f32 / 8.0;
+                beams.advance(
+                    16,
+                    width,
+                    Some((c5, Steps(0), Clef::Alto)),
+                    *beam_break,
+                );
+            }
+            beams
+                .map(|short| match short {
+                    Short::Beam(beam) => beam.notes.len(),
+                    Short::Flag(..) => 1,
+                })
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(groups([false; 4]), [4]);
+        assert_eq!(groups([false, false, true, false]), [2, 2]);
+    }
+
+    #[test]
+    fn time_signatures() {
+        let groups = |time: Fraction| {
+            let measure = time.num * 128 / time.den;
+            let mut beams = Beams::new(Steps(0), time, measure);
+            for i in 0..6 {
+                let c5: Note = "1/8C5".parse().unwrap();
+                let width = i as f32 / 6.0;
+                beams.advance(
+                    16,
+                    width,
+                    Some((c5, Steps(0), Clef::Alto)),
+                    false,
+                );
+            }
+            beams
+                .map(|short| match short {
+                    Short::Beam(beam) => beam.notes.len(),
+                    Short::Flag(..) => 1,
+                })
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(groups(Fraction::new(3, 4)), [2, 2, 2]);
+        assert_eq!(groups(Fraction::new(6, 8)), [3, 3]);
+    }
 
-        Beam { notes, stems_up }
+    #[test]
+    fn rests() {
+        // Durations, negative for rests.
+        let groups = |durs: &[i16]| {
+            let mut beams = Beams::new(Steps(0), Fraction::new(4, 4), 128);
+            let mut width = 0.0;
+            for dur in durs {
+                let note = if *dur > 0 {
+                    let duration = Fraction::new(*dur as u16, 128).simplify();
+                    let note = format!("{}C5", duration).parse().unwrap();
+                    Some((note, Steps(0), Clef::Alto))
+                } else {
+                    None
+                };
+                beams.advance(dur.unsigned_abs(), width, note, false);
+                width += 0.125;
+            }
+            beams
+                .map(|short| match short {
+                    Short::Beam(beam) => {
+                        beam.notes.iter().map(|note| note.3).collect()
+                    }
+                    Short::Flag(..) => vec![],
+                })
+                .collect::<Vec<Vec<bool>>>()
+        };
+        // A quarter rest ends the group: two flagged eighths.
+        assert_eq!(groups(&[16, -32, 16]), [vec![], vec![]]);
+        // An eighth rest is beamed over, with only the primary beam
+        // joining the notes either side of it.
+        assert_eq!(groups(&[16, -16, 16]), [vec![false, true]]);
+        assert_eq!(groups(&[8, -8, 8, 8]), [vec![false, true, false]]);
+        // A new group starts after the quarter rest.
+        assert_eq!(groups(&[16, -32, 8, 8]), [vec![], vec![false, false]]);
     }
 }
@@ -18,60 +18,408 @@
 
 #![allow(unused)] // FIXME: For now, until all of the glyphs are implemented.
 
-use scof::Fraction;
+use crate::{notehead, Notehead};
+use scof::{Clef, Dynamic, Fraction, Note, Pitch, PitchAccidental, Steps};
 use sfff::Glyph;
+use std::collections::HashSet;
+use std::fmt;
 
-/// Get the glyph for a rest with a specific duration
-pub(super) fn rest_duration(duration: u16) -> Glyph {
-    use Glyph::*;
-    match duration {
-        2 | 3 => Rest64,
-        4 | 6 | 9 => Rest32,
-        8 | 12 | 18 | 27 => Rest16,
-        16 | 24 | 36 | 54 | 81 => Rest8,
-        32 | 48 | 72 | 108 | 162 => Rest4,
-        64 | 96 | 144 | 216 => Rest2,
-        128 | 192 | 288 => Rest1,
-        256 | 384 => Rest1, // FIXME: Double Whole Rest
-        512 => Rest1,       // FIXME: Quadruple Whole Rest
-        _ => panic!("Bug in Notator, no glyph for ({})", duration),
+/// The notated duration a note or rest is drawn as, ignoring dots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DurationClass {
+    /// 128th note
+    HundredTwentyEighth,
+    /// 64th note
+    SixtyFourth,
+    /// 32nd note
+    ThirtySecond,
+    /// 16th note
+    Sixteenth,
+    /// Eighth note
+    Eighth,
+    /// Quarter note
+    Quarter,
+    /// Half note
+    Half,
+    /// Whole note
+    Whole,
+    /// Double whole note (breve), or longer
+    DoubleWhole,
+}
+
+impl DurationClass {
+    /// All duration classes, shortest first.
+    pub const ALL: [DurationClass; 9] = [
+        DurationClass::HundredTwentyEighth,
+        DurationClass::SixtyFourth,
+        DurationClass::ThirtySecond,
+        DurationClass::Sixteenth,
+        DurationClass::Eighth,
+        DurationClass::Quarter,
+        DurationClass::Half,
+        DurationClass::Whole,
+        DurationClass::DoubleWhole,
+    ];
+
+    /// Classify a duration in 128th notes.  Dotted durations belong to the
+    /// class of their undotted note (a dotted quarter is a quarter).
+    pub fn new(duration: u16) -> Self {
+        // Position of the highest set bit (0 for 128th notes).
+        let log2 = 15 - duration.max(1).leading_zeros() as usize;
+        Self::ALL[log2.min(Self::ALL.len() - 1)]
+    }
+
+    /// Duration of the undotted note in 128th notes.
+    pub fn duration(self) -> u16 {
+        1 << self as u16
     }
 }
 
-/// Get the flag glyph for a note with a specific duration
-pub(super) fn flag_duration(duration: u16, up: bool) -> Option<Glyph> {
-    use Glyph::*;
+/// Glyph resolution for notes and rests.
+pub struct Symbol;
 
-    Some(match duration {
-        2 | 3 => {
-            if up {
-                FlagUp64
-            } else {
-                FlagDown64
+impl Symbol {
+    /// Get the notehead glyph for a note with a specific style and duration
+    /// (in 128th notes).
+    pub fn for_note(style: Notehead, duration: u16) -> Glyph {
+        use DurationClass::*;
+        use Glyph::*;
+        use Notehead::*;
+
+        let [double, whole, half, fill] = match style {
+            Normal => {
+                [NoteheadDouble, NoteheadWhole, NoteheadHalf, NoteheadFill]
             }
+            X => [
+                NoteheadDoubleX,
+                NoteheadWholeX,
+                NoteheadHalfX,
+                NoteheadFillX,
+            ],
+            Diamond => [
+                NoteheadDoubleDiamond,
+                NoteheadWholeDiamond,
+                NoteheadHalfDiamond,
+                NoteheadFillDiamond,
+            ],
+            Triangle => [
+                NoteheadDoubleTriangle,
+                NoteheadWholeTriangle,
+                NoteheadHalfTriangle,
+                NoteheadFillTriangle,
+            ],
+            Slash => [
+                NoteheadDoubleSlash,
+                NoteheadWholeSlash,
+                NoteheadHalfSlash,
+                NoteheadFillSlash,
+            ],
+        };
+        match DurationClass::new(duration) {
+            DoubleWhole => double,
+            Whole => whole,
+            Half => half,
+            _ => fill,
         }
-        4 | 6 | 9 => {
-            if up {
-                FlagUp32
-            } else {
-                FlagDown32
-            }
+    }
+
+    /// Get the glyph for a rest with a specific duration (in 128th notes).
+    pub fn for_rest(duration: u16) -> Glyph {
+        use DurationClass::*;
+        use Glyph::*;
+
+        match DurationClass::new(duration) {
+            HundredTwentyEighth => Rest64, // FIXME: 128th Rest
+            SixtyFourth => Rest64,
+            ThirtySecond => Rest32,
+            Sixteenth => Rest16,
+            Eighth => Rest8,
+            Quarter => Rest4,
+            Half => Rest2,
+            Whole => Rest1,
+            // Engraved as a block, not a glyph.
+            DoubleWhole => Rest1,
         }
-        8 | 12 | 18 | 27 => {
-            if up {
-                FlagUp16
-            } else {
-                FlagDown16
-            }
+    }
+
+    /// Get the glyph for an accidental.
+    pub fn for_accidental(accidental: PitchAccidental) -> Glyph {
+        use Glyph::*;
+
+        match accidental {
+            PitchAccidental::DoubleFlat => DoubleFlat,
+            PitchAccidental::FlatQuarterFlat => ThreeQuarterFlat,
+            PitchAccidental::Flat => Flat,
+            PitchAccidental::QuarterFlat => QuarterFlat,
+            PitchAccidental::Natural => Natural,
+            PitchAccidental::QuarterSharp => QuarterSharp,
+            PitchAccidental::Sharp => Sharp,
+            PitchAccidental::SharpQuarterSharp => ThreeQuarterSharp,
+            PitchAccidental::DoubleSharp => DoubleSharp,
         }
-        16 | 24 | 36 | 54 | 81 => {
-            if up {
-                FlagUp8
-            } else {
-                FlagDown8
+    }
+
+    /// Get the glyph for a clef, and the steps its origin sits above the
+    /// middle line of the stave.
+    pub fn for_clef(clef: Clef) -> (Glyph, Steps) {
+        use Glyph::*;
+
+        match clef {
+            Clef::Treble => (ClefG, Steps(-2)),
+            Clef::Bass => (ClefF, Steps(2)),
+            Clef::Alto => (ClefC, Steps(0)),
+            Clef::Tenor => (ClefC, Steps(2)),
+        }
+    }
+
+    /// Get the flag glyph for a note with a specific duration (in 128th
+    /// notes), or `None` if it's too long to have a flag.
+    pub fn for_flag(duration: u16, up: bool) -> Option<Glyph> {
+        use DurationClass::*;
+        use Glyph::*;
+
+        let [flag_up, flag_down] = match DurationClass::new(duration) {
+            // FIXME: 128th Flags
+            HundredTwentyEighth | SixtyFourth => [FlagUp64, FlagDown64],
+            ThirtySecond => [FlagUp32, FlagDown32],
+            Sixteenth => [FlagUp16, FlagDown16],
+            Eighth => [FlagUp8, FlagDown8],
+            // All other longer durations don't have flags.
+            _ => return None,
+        };
+        Some(if up { flag_up } else { flag_down })
+    }
+}
+
+/// What a glyph chosen by a `GlyphResolver` is drawn as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlyphRole {
+    /// Notehead for one pitch of the note's chord.
+    Notehead(Pitch),
+    /// Rest (the note has no pitches).
+    Rest,
+    /// Flag on a stem pointing up (`true`) or down (`false`).
+    Flag(bool),
+}
+
+/// Chooses the glyphs the engraver draws for notes and rests, so they can be
+/// substituted (for example, with shape note heads).
+///
+/// The note passed in is the part of the note being drawn, as written: notes
+/// split across beats have the duration of the part (tied to the next), and
+/// pitches are moved by any octave shift.  Its duration is always a multiple
+/// of a 128th note.
+pub trait GlyphResolver: fmt::Debug {
+    /// Get the glyph to draw for a note in a specific role.
+    fn resolve(&self, note: &Note, role: GlyphRole) -> Glyph;
+}
+
+/// The engraver's standard choice of glyphs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultGlyphResolver;
+
+impl GlyphResolver for DefaultGlyphResolver {
+    fn resolve(&self, note: &Note, role: GlyphRole) -> Glyph {
+        let duration = duration_128ths(note);
+        match role {
+            GlyphRole::Notehead(_) => {
+                Symbol::for_note(notehead::from_style(note.head), duration)
             }
+            GlyphRole::Rest => Symbol::for_rest(duration),
+            // Longer notes don't have flags, so use the eighth note's.
+            GlyphRole::Flag(up) => Symbol::for_flag(duration, up)
+                .unwrap_or_else(|| Symbol::for_flag(16, up).unwrap()),
         }
-        // All other longer durations don't have flags.
+    }
+}
+
+/// Get the duration of a note in 128th notes.
+pub(crate) fn duration_128ths(note: &Note) -> u16 {
+    let Fraction { num, den } = note.duration;
+    (u32::from(num) * 128 / u32::from(den.max(1))).min(u16::MAX.into()) as u16
+}
+
+/// Get every glyph the engraver can emit, so a font can be checked for
+/// missing glyphs.
+pub fn required_glyphs() -> HashSet<Glyph> {
+    use Glyph::*;
+
+    let mut glyphs = HashSet::new();
+    for class in DurationClass::ALL.iter() {
+        let duration = class.duration();
+        for style in [
+            Notehead::Normal,
+            Notehead::X,
+            Notehead::Diamond,
+            Notehead::Triangle,
+            Notehead::Slash,
+        ] {
+            glyphs.insert(Symbol::for_note(style, duration));
+        }
+        glyphs.insert(Symbol::for_rest(duration));
+        glyphs.extend(Symbol::for_flag(duration, true));
+        glyphs.extend(Symbol::for_flag(duration, false));
+    }
+    glyphs.extend("0123456789".chars().filter_map(time_sig_digit));
+    glyphs.extend(&[TimeSigCommon, TimeSigCut]);
+    glyphs.extend((1..=5).map(tremolo));
+    glyphs.extend(&[ClefG, ClefF, ClefC]);
+    glyphs.extend(&[RepeatSlash, RepeatUpDot, RepeatDownDot]);
+    glyphs.extend(&[P, MP, MF, F, S, Z, N]);
+    glyphs.extend("0123456789".chars().filter_map(tuplet_digit));
+    glyphs.extend(&[DoubleFlat, ThreeQuarterFlat, Flat, QuarterFlat]);
+    glyphs.extend(&[Natural, QuarterSharp, Sharp, ThreeQuarterSharp]);
+    glyphs.insert(DoubleSharp);
+    glyphs
+}
+
+/// Get the time signature glyph for a digit
+pub(super) fn time_sig_digit(digit: char) -> Option<Glyph> {
+    use Glyph::*;
+    Some(match digit {
+        '0' => TimeSig0,
+        '1' => TimeSig1,
+        '2' => TimeSig2,
+        '3' => TimeSig3,
+        '4' => TimeSig4,
+        '5' => TimeSig5,
+        '6' => TimeSig6,
+        '7' => TimeSig7,
+        '8' => TimeSig8,
+        '9' => TimeSig9,
         _ => return None,
     })
 }
+
+/// Get the tuplet number glyph for a digit
+pub(super) fn tuplet_digit(digit: char) -> Option<Glyph> {
+    use Glyph::*;
+    Some(match digit {
+        '0' => Tuplet0,
+        '1' => Tuplet1,
+        '2' => Tuplet2,
+        '3' => Tuplet3,
+        '4' => Tuplet4,
+        '5' => Tuplet5,
+        '6' => Tuplet6,
+        '7' => Tuplet7,
+        '8' => Tuplet8,
+        '9' => Tuplet9,
+        _ => return None,
+    })
+}
+
+/// Get the glyphs for the letters of a dynamic
+pub(super) fn dynamic(dynamic: &Dynamic) -> Vec<Glyph> {
+    use Glyph::*;
+    match dynamic {
+        Dynamic::MP => vec![MP],
+        Dynamic::MF => vec![MF],
+        dynamic => dynamic
+            .to_string()
+            .chars()
+            .filter_map(|letter| match letter {
+                'p' => Some(P),
+                'f' => Some(F),
+                's' => Some(S),
+                'z' => Some(Z),
+                'n' => Some(N),
+                _ => None,
+            })
+            .collect(),
+    }
+}
+
+/// Get the glyph for a number of tremolo slashes
+pub(super) fn tremolo(slashes: u8) -> Glyph {
+    use Glyph::*;
+    match slashes {
+        0 | 1 => Tremelo1,
+        2 => Tremelo2,
+        3 => Tremelo3,
+        4 => Tremelo4,
+        _ => Tremelo5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hundred_twenty_eighth() {
+        assert_eq!(Symbol::for_rest(1), Glyph::Rest64);
+        assert_eq!(Symbol::for_flag(1, true), Some(Glyph::FlagUp64));
+        assert_eq!(Symbol::for_flag(1, false), Some(Glyph::FlagDown64));
+    }
+
+    #[test]
+    #[ignore = "modern.sfff must be fetched from git-lfs"]
+    fn required_glyphs_in_modern() {
+        let (_meta, paths) = sfff::SfFontMetadata::from_buf_reader(
+            &include_bytes!("../modern.sfff")[..],
+        )
+        .unwrap();
+        let paths: Vec<&str> = paths.split('\0').collect();
+        let present: HashSet<Glyph> = Glyph::ALL
+            .iter()
+            .copied()
+            .filter(|glyph| !paths[*glyph as usize].is_empty())
+            .collect();
+        assert!(required_glyphs().is_subset(&present));
+    }
+
+    #[test]
+    fn required() {
+        let required = required_glyphs();
+        assert!(required.contains(&Glyph::NoteheadFill));
+        assert!(required.contains(&Glyph::Rest1));
+        assert!(required.contains(&Glyph::FlagDown64));
+        assert!(required.contains(&Glyph::TimeSig9));
+        assert!(required.contains(&Glyph::TimeSigCut));
+        assert!(required.contains(&Glyph::Tremelo5));
+        assert!(required.contains(&Glyph::ThreeQuarterSharp));
+        assert!(required.contains(&Glyph::ClefG));
+        assert!(required.contains(&Glyph::ClefF));
+        // Noteheads of every style can be engraved.
+        assert!(required.contains(&Glyph::NoteheadHalfX));
+        assert!(required.contains(&Glyph::NoteheadWholeSlash));
+    }
+
+    #[test]
+    fn duration_classes() {
+        use Notehead::*;
+
+        for class in DurationClass::ALL.iter().copied() {
+            let duration = class.duration();
+            // Dotted and double dotted notes resolve like undotted notes.
+            for dur in [duration, duration * 3 / 2, duration * 7 / 4] {
+                assert_eq!(DurationClass::new(dur), class);
+                assert_eq!(Symbol::for_rest(dur), Symbol::for_rest(duration));
+                assert_eq!(
+                    Symbol::for_flag(dur, true),
+                    Symbol::for_flag(duration, true)
+                );
+                for style in [Normal, X, Diamond, Triangle, Slash] {
+                    assert_eq!(
+                        Symbol::for_note(style, dur),
+                        Symbol::for_note(style, duration)
+                    );
+                }
+            }
+            // Short notes have flags, long notes don't.
+            assert_eq!(
+                Symbol::for_flag(duration, false).is_some(),
+                class < DurationClass::Quarter
+            );
+        }
+        assert_eq!(Symbol::for_rest(32), Glyph::Rest4);
+        assert_eq!(Symbol::for_note(Normal, 48), Glyph::NoteheadFill);
+        assert_eq!(Symbol::for_note(X, 96), Glyph::NoteheadHalfX);
+        assert_eq!(Symbol::for_note(Diamond, 128), Glyph::NoteheadWholeDiamond);
+        assert_eq!(Symbol::for_note(Slash, 512), Glyph::NoteheadDoubleSlash);
+        // Durations outside of the table don't panic.
+        assert_eq!(Symbol::for_rest(0), Glyph::Rest64);
+        assert_eq!(Symbol::for_rest(u16::MAX), Glyph::Rest1);
+    }
+}
@@ -0,0 +1,114 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/* ************************************************************************** */
+
+//! Key signature accidentals and their staff positions.
+
+use scof::{Clef, Steps};
+use sfff::Glyph;
+
+/// Sharps in key signature order (F# C# G# D# A# E# B#), as steps above
+/// middle C in the treble clef.
+const SHARPS: [i32; 7] = [10, 7, 11, 8, 5, 9, 6];
+
+/// Flats in key signature order (Bb Eb Ab Db Gb Cb Fb), as steps above
+/// middle C in the treble clef.
+const FLATS: [i32; 7] = [6, 9, 5, 8, 4, 7, 3];
+
+/// Sharps in the tenor clef, which start low to stay within the stave.
+const TENOR_SHARPS: [i32; 7] = [-4, 0, -3, 1, -2, 2, -1];
+
+/// Get the number of sharps (positive) or flats (negative) in the major key
+/// signature a number of quarter steps above C.  Keys between semitones
+/// have no accidentals.
+pub(crate) fn key_accidentals(key: u8) -> i32 {
+    if key % 2 != 0 {
+        return 0;
+    }
+    // Walk the circle of fifths, preferring sharps up to F# major.
+    let fifths = (i32::from(key / 2) * 7) % 12;
+    if fifths > 6 {
+        fifths - 12
+    } else {
+        fifths
+    }
+}
+
+/// Get the accidentals of a key signature in the order they're drawn, and
+/// the steps each is above the middle line of a stave with a clef.
+pub(crate) fn key_signature(key: u8, clef: Clef) -> Vec<(Glyph, Steps)> {
+    let count = key_accidentals(key);
+    let (glyph, table) = match (count > 0, clef) {
+        (true, Clef::Tenor) => (Glyph::Sharp, TENOR_SHARPS),
+        (true, _) => (Glyph::Sharp, SHARPS),
+        (false, _) => (Glyph::Flat, FLATS),
+    };
+    // Same shape as the treble clef, an octave or two lower.
+    let octave = match clef {
+        Clef::Treble => 0,
+        Clef::Alto => -7,
+        Clef::Tenor if count > 0 => 0,
+        Clef::Tenor => -7,
+        Clef::Bass => -14,
+    };
+    table
+        .iter()
+        .take(count.unsigned_abs() as usize)
+        .map(|steps| (glyph, Steps(steps + octave) - clef.middle_steps()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accidental_counts() {
+        // C, G, D, F# and F, Bb, Db major.
+        assert_eq!(key_accidentals(0), 0);
+        assert_eq!(key_accidentals(14), 1);
+        assert_eq!(key_accidentals(4), 2);
+        assert_eq!(key_accidentals(12), 6);
+        assert_eq!(key_accidentals(10), -1);
+        assert_eq!(key_accidentals(20), -2);
+        assert_eq!(key_accidentals(2), -5);
+        // Quarter tone keys
+        assert_eq!(key_accidentals(1), 0);
+    }
+
+    #[test]
+    fn g_major() {
+        // F# on the top line of the treble stave, and the fourth line of the
+        // bass stave.
+        assert_eq!(key_signature(14, Clef::Treble), [(Glyph::Sharp, Steps(4))]);
+        assert_eq!(key_signature(14, Clef::Bass), [(Glyph::Sharp, Steps(2))]);
+    }
+
+    #[test]
+    fn flats_by_clef() {
+        let positions = |clef| -> Vec<i32> {
+            key_signature(20, clef).iter().map(|(_, s)| s.0).collect()
+        };
+        // Bb and Eb
+        assert_eq!(positions(Clef::Treble), [0, 3]);
+        assert_eq!(positions(Clef::Bass), [-2, 1]);
+        assert_eq!(positions(Clef::Alto), [-1, 2]);
+        assert_eq!(positions(Clef::Tenor), [1, 4]);
+    }
+}
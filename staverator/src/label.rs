@@ -0,0 +1,89 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/* ************************************************************************** */
+
+//! Note name and solfège labels for noteheads.
+
+use scof::{Pitch, PitchAccidental};
+
+/// Movable-do syllables for each semitone above the tonic, raised spellings.
+const SOLFEGE_SHARP: [&str; 12] = [
+    "do", "di", "re", "ri", "mi", "fa", "fi", "sol", "si", "la", "li", "ti",
+];
+/// Movable-do syllables for each semitone above the tonic, lowered spellings.
+const SOLFEGE_FLAT: [&str; 12] = [
+    "do", "ra", "re", "me", "mi", "fa", "se", "sol", "le", "la", "te", "ti",
+];
+
+/// Which label to draw on each notehead.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NoteLabel {
+    /// Letter name of the pitch class (C, D, E...).
+    Name,
+    /// Movable-do solfège syllable relative to the key.
+    Solfege,
+}
+
+impl NoteLabel {
+    /// Get the label text for a pitch.
+    ///
+    /// - `key`: The key signature (quarter steps above C).
+    pub(crate) fn text(self, pitch: Pitch, key: u8) -> String {
+        match self {
+            NoteLabel::Name => pitch.0.to_string(),
+            NoteLabel::Solfege => solfege(pitch, key).to_string(),
+        }
+    }
+}
+
+/// Get the movable-do syllable for a pitch in a key.
+fn solfege(pitch: Pitch, key: u8) -> &'static str {
+    use PitchAccidental::*;
+
+    let name = pitch.0.name.semitones();
+    // Quarter tones round toward natural.
+    let (accidental, flat) = match pitch.0.accidental {
+        Some(DoubleFlat) => (-2, true),
+        Some(FlatQuarterFlat) | Some(Flat) => (-1, true),
+        Some(SharpQuarterSharp) | Some(Sharp) => (1, false),
+        Some(DoubleSharp) => (2, false),
+        _ => (0, false),
+    };
+    let tonic = i32::from(key) / 2;
+    let degree = (name + accidental - tonic).rem_euclid(12) as usize;
+    if flat {
+        SOLFEGE_FLAT[degree]
+    } else {
+        SOLFEGE_SHARP[degree]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movable_do() {
+        let g4: Pitch = "G4".parse().unwrap();
+        assert_eq!(NoteLabel::Solfege.text(g4, 0), "sol");
+        // G major: tonic is 7 semitones (14 quarter steps) above C.
+        assert_eq!(NoteLabel::Solfege.text(g4, 14), "do");
+        assert_eq!(NoteLabel::Name.text(g4, 14), "G");
+    }
+}
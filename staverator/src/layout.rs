@@ -0,0 +1,178 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/* ************************************************************************** */
+
+//! Breaking the measures of a movement into systems.
+
+use crate::{BarElem, BarEngraver, RenderOptions, Stave};
+use scof::{BreakKind, Cursor, Pitch, Scof, Steps};
+use sfff::SfFontMetadata;
+use std::ops::Range;
+
+/// A system (line) of measures.
+#[derive(Clone, Debug, PartialEq)]
+pub struct System {
+    /// Measures in the system.
+    pub measures: Range<u16>,
+    /// Whether the system starts a new page.
+    pub new_page: bool,
+}
+
+/// Break the measures of the first movement into systems that fit in `width`
+/// font units (a measure wider than that gets a system of its own).  A
+/// measure with a forced break always starts a new system.  Measures are
+/// spaced as they'd be rendered with `options`.
+///
+/// FIXME: Page height (pages only break where forced).
+pub fn layout_systems(
+    scof: &Scof,
+    meta: &SfFontMetadata,
+    options: &RenderOptions,
+    width: i32,
+) -> Vec<System> {
+    let bars = match scof.movement.first() {
+        Some(movement) => &movement.bar,
+        None => return vec![],
+    };
+    let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+    let mut elem = BarElem::new(Stave::new(5, Steps(4), Steps(0)), c4, c4);
+    elem.options = options.clone();
+    let mut systems = vec![];
+    let mut start = 0;
+    let mut new_page = false;
+    let mut used = 0;
+    for (measure, bar) in (0..).zip(bars) {
+        let curs = Cursor::new(0, measure, 0, 0);
+        let mut bar_width =
+            BarEngraver::measure_width(&elem, scof, &curs, meta);
+        if measure > start
+            && (bar.break_before.is_some() || used + bar_width > width)
+        {
+            systems.push(System {
+                measures: start..measure,
+                new_page,
+            });
+            start = measure;
+            new_page = bar.break_before == Some(BreakKind::Page);
+            used = 0;
+            // Notes tied into the system get courtesy accidentals.
+            elem.system_start = true;
+            bar_width = BarEngraver::measure_width(&elem, scof, &curs, meta);
+            elem.system_start = false;
+        }
+        used += bar_width;
+    }
+    if !bars.is_empty() {
+        systems.push(System {
+            measures: start..bars.len() as u16,
+            new_page,
+        });
+    }
+    systems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{font, score};
+    use crate::Element;
+    use sfff::Glyph;
+
+    #[test]
+    fn forced_breaks() {
+        let (meta, _glyphs) = font();
+        let options = RenderOptions::default();
+        let mut scof = Scof::default();
+        let bars = scof.movement[0].bar.len() as u16;
+        let measures = |systems: Vec<System>| -> Vec<Range<u16>> {
+            systems.into_iter().map(|system| system.measures).collect()
+        };
+        let systems = layout_systems(&scof, &meta, &options, i32::MAX);
+        assert_eq!(systems.len(), 1);
+        assert_eq!(systems[0].measures, 0..bars);
+
+        // A system break before the second measure, though there's room.
+        scof.movement[0].bar[1].break_before = Some(BreakKind::System);
+        let systems = layout_systems(&scof, &meta, &options, i32::MAX);
+        assert!(!systems[1].new_page);
+        assert_eq!(measures(systems), [0..1, 1..bars]);
+
+        scof.movement[0].bar[1].break_before = Some(BreakKind::Page);
+        let systems = layout_systems(&scof, &meta, &options, i32::MAX);
+        assert!(systems[1].new_page);
+
+        // Measures that don't fit start a new system.
+        scof.movement[0].bar[1].break_before = None;
+        assert_eq!(
+            measures(layout_systems(&scof, &meta, &options, 1)).len(),
+            bars.into()
+        );
+    }
+
+    #[test]
+    fn courtesy_accidentals() {
+        let (meta, _glyphs) = font();
+        let options = RenderOptions::default();
+        // A C# tied over a system break.
+        let mut scof = score(&["1/2R 1/2C#5~"]);
+        scof.movement[0].bar.push(scof::Measure {
+            sig: None,
+            chan: vec!["1/2C#5 1/2R".parse().unwrap()],
+            repeat: vec![],
+            barline: None,
+            break_before: Some(BreakKind::System),
+        });
+        let systems = layout_systems(&scof, &meta, &options, i32::MAX);
+        let start = systems[1].measures.start;
+        assert_eq!(start, 1);
+        // X of the sharp, and of the text drawn in the measure.
+        let engrave = |system_start| -> (Vec<i32>, Vec<(String, i32)>) {
+            let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+            let mut bar =
+                BarElem::new(Stave::new(5, Steps(4), Steps(0)), c4, c4);
+            bar.options = options.clone();
+            bar.system_start = system_start;
+            let mut curs = Cursor::new(0, start, 0, 0);
+            bar.add_markings(&meta, &scof, &Cursor::default(), &mut curs);
+            let mut sharps = vec![];
+            let mut texts = vec![];
+            for elem in bar.elements {
+                match elem {
+                    Element::Use(u) if u.id == u16::from(Glyph::Sharp) => {
+                        sharps.push(u.x)
+                    }
+                    Element::Text(t) => texts.push((t.text, t.x)),
+                    _ => {}
+                }
+            }
+            (sharps, texts)
+        };
+        // The sharp is in parentheses at the start of the system.
+        let (sharps, texts) = engrave(true);
+        assert_eq!(sharps.len(), 1);
+        assert_eq!(texts.len(), 2);
+        assert_eq!(texts[0].0, ")");
+        assert_eq!(texts[1].0, "(");
+        assert!(texts[1].1 < sharps[0] && sharps[0] < texts[0].1);
+        // Mid-system, it's drawn as written.
+        let (sharps, texts) = engrave(false);
+        assert_eq!(sharps.len(), 1);
+        assert!(texts.is_empty());
+    }
+}
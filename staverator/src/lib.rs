@@ -18,32 +18,93 @@
 
 #![allow(clippy::blacklisted_name)] // bar is a useful musical term
 
+/// Log a message under a tag (shown with the `trace` feature).  Tests record
+/// the messages that are shown, to check what's logged.
+macro_rules! trace {
+    ($tag:ident, $($arg:tt)*) => {{
+        #[cfg(test)]
+        {
+            if $tag.is_shown() {
+                crate::tests::LOG.with(|log| {
+                    log.borrow_mut().push(format!($($arg)*))
+                });
+            }
+        }
+        cala::log::log!($tag, $($arg)*);
+    }};
+}
+
 mod beaming;
 mod glyph;
+mod key;
+mod label;
+mod layout;
 mod notator;
 mod notehead;
+mod render;
 mod rhythmic_spacing;
+#[cfg(test)]
+mod snapshot;
+mod stem;
 mod svg;
+mod warning;
 
-pub use sfff::{SfFontMetadata, STAVE_SPACE};
-pub use svg::{Element, Group, Path, Rect, Use};
+pub use glyph::{
+    required_glyphs, DefaultGlyphResolver, DurationClass, GlyphResolver,
+    GlyphRole, Symbol,
+};
+pub use label::NoteLabel;
+pub use layout::{layout_systems, System};
+#[cfg(all(feature = "png", not(target_arch = "wasm32")))]
+pub use render::render_png;
+pub use render::{render_score_svg, RenderOptions};
+pub use rhythmic_spacing::BarEngraver;
+pub use sfff::{Notehead, SfFontMetadata, STAVE_SPACE};
+pub use svg::{Element, Group, Path, Rect, Text, Use};
+pub use warning::{RenderWarning, RenderWarnings};
 
-use beaming::{Beam, Beams, Short};
+use beaming::{beam_count, Beam, Beamlet, Beams, Chord, Short};
 use notator::Notator;
-use notehead::Notehead;
-use rhythmic_spacing::BarEngraver;
+use stem::{stem_up, StemContext};
 
-use scof::{Cursor, Scof, Steps};
+use cala::log::Tag;
+use scof::{
+    Barline, Clef, Cursor, Dynamic, Feather, Marking, Note, Pitch, Scof, Steps,
+};
 use sfff::Glyph;
 use std::fmt;
-use cala::log::{Tag, log};
+use std::ops::Range;
 
-const INFO: Tag = Tag::new("Staverator");
+const INFO: Tag = Tag::new("Staverator").show(cfg!(feature = "trace"));
 
 /// Width of one bar (measure)
 const BAR_WIDTH: i32 = 8 * STAVE_SPACE;
-/// Width of a whole rest (in font units).
-const WHOLE_REST_WIDTH: i32 = 230;
+/// Width of a measure repeat slash (in font units).
+const REPEAT_SLASH_WIDTH: i32 = 200;
+/// Width of a tremolo glyph (in font units).
+const TREMOLO_WIDTH: i32 = 130;
+/// Maximum length of a partial beam (in font units).
+const BEAMLET: i32 = STAVE_SPACE;
+/// Number of beam lines at the wide end of a feathered beam.
+const FEATHERED_BEAMS: i32 = 3;
+/// Width of a breve or longa rest (in font units).
+const LONG_REST_WIDTH: i32 = STAVE_SPACE / 2;
+/// Thickness of a caesura slash, and radius of a breath mark (in font units).
+const PAUSE_THICKNESS: i32 = STAVE_SPACE / 4;
+/// Length of a dash in an octave shift bracket (in font units).
+const OCTAVE_DASH: i32 = 50;
+/// Font size of lyrics (in font units).
+const LYRIC_SIZE: i32 = STAVE_SPACE * 3 / 2;
+/// Space between a hairpin and a dynamic (in font units).
+const HAIRPIN_GAP: i32 = STAVE_SPACE / 2;
+/// Radius of the circle at the narrow end of a niente hairpin (in font units).
+const NIENTE_RADIUS: i32 = STAVE_SPACE / 4;
+/// Space between an accidental and its notehead (in font units).
+const ACCIDENTAL_GAP: i32 = STAVE_SPACE / 4;
+/// Width of a parenthesis around a courtesy accidental (in font units).
+const PAREN_WIDTH: i32 = STAVE_SPACE / 3;
+/// Font size of the parentheses around a courtesy accidental.
+const PAREN_SIZE: i32 = STAVE_SPACE * 2;
 
 /// FIXME: REMOVE - Get Bravura font paths
 pub fn bravura() -> Vec<Path> {
@@ -53,14 +114,79 @@ pub fn bravura() -> Vec<Path> {
 /// Get Modern font data as SVG defs.
 pub fn modern() -> (sfff::SfFontMetadata, String) {
     let data: &[u8] = include_bytes!("../modern.sfff");
-    let data = std::io::Cursor::new(data);
     let (meta, glyphs) = sfff::SfFontMetadata::from_buf_reader(data).unwrap();
     let glyphs = sfff::generate_defs(&glyphs);
 
     (meta, glyphs)
 }
 
+/// Get the number of ledger lines a note needs on a five line stave, and
+/// whether they're above it.
+///
+/// - `steps`: Steps the note is above middle C.
+/// - `clef`: Clef of the stave.
+pub fn ledger_lines(steps: Steps, clef: Clef) -> (i32, bool) {
+    let position = steps - clef.middle_steps();
+    // The outer stave lines are 4 steps from the middle line.
+    (
+        (position.abs() - Steps(4)).0.max(0) / 2,
+        position > Steps(0),
+    )
+}
+
+/// Get path data for the beam lines of a feathered beam, between a note of a
+/// beamed group and the previous note.
+///
+/// - `ends`: X and Y of the primary beam at each stem in the group.
+/// - `beam_distance`: Distance between beam lines at the wide end.
+fn feathered_beams(
+    feather: Feather,
+    ends: &[(i32, i32)],
+    note_i: usize,
+    beam_distance: i32,
+) -> String {
+    let (first, last) = (ends[0].0, ends[ends.len() - 1].0);
+    // Distance between beam lines, converging to nothing at the narrow end.
+    let spread = |x: i32| {
+        let frac = (x - first) as f32 / (last - first).max(1) as f32;
+        let frac = match feather {
+            Feather::Accelerando => frac,
+            Feather::Ritardando => 1.0 - frac,
+        };
+        (frac * beam_distance as f32) as i32
+    };
+    let ((x0, y0), (x1, y1)) = (ends[note_i - 1], ends[note_i]);
+    let mut d = String::new();
+    for i in 0..FEATHERED_BEAMS {
+        let (y0, y1) = (y0 - i * spread(x0), y1 - i * spread(x1));
+        d.push_str(&format!(
+            "M{} {}L{} {}v{}L{} {}z",
+            x0,
+            y0,
+            x1,
+            y1,
+            Stave::STEP,
+            x0,
+            y0 + Stave::STEP
+        ));
+    }
+    d
+}
+
+/// Get the width of the accidental drawn before a notehead (in font units),
+/// including parentheses around a courtesy accidental.
+fn accidental_width(
+    meta: &SfFontMetadata,
+    pitch: Pitch,
+    courtesy: bool,
+) -> Option<i32> {
+    let glyph = Symbol::for_accidental(pitch.0.accidental?);
+    let parens = if courtesy { 2 * PAREN_WIDTH } else { 0 };
+    Some(meta.advance(glyph) + parens)
+}
+
 /// Stave lines
+#[derive(Clone)]
 pub struct Stave {
     /// Number of lines on stave
     pub lines: i32,
@@ -87,27 +213,69 @@ impl Stave {
         }
     }
 
+    /// Round steps down to a stave line (or where a ledger line would be).
+    /// Lines are every other step from the top line, which may be odd, so
+    /// this uses floor division rather than truncating toward zero.
+    fn line_below(&self, steps: Steps) -> Steps {
+        let top = self.steps_middle_c.0;
+        Steps(top + (steps.0 - top).div_euclid(2) * 2)
+    }
+
+    /// Round steps up to a stave line (or where a ledger line would be).
+    fn line_above(&self, steps: Steps) -> Steps {
+        let top = self.steps_middle_c.0;
+        Steps(top - (top - steps.0).div_euclid(2) * 2)
+    }
+
     /// Get number of steps top margin is above middle C
     fn steps_top(&self, steps: Steps) -> Steps {
-        let top = ((steps / 2) * 2).0 + 2; // round to nearest line
+        let top = (self.line_below(steps) + Steps(2)).0;
         let dflt = self.steps_middle_c + Self::MARGIN_STEPS + self.ypos;
         Steps(dflt.0.max(top))
     }
 
     /// Get number of steps bottom margin is above middle C
     fn steps_bottom(&self, steps: Steps) -> Steps {
-        let bottom = ((steps / 2) * 2).0 - 2; // round to nearest line
+        let bottom = (self.line_above(steps) - Steps(2)).0;
         let dflt =
             self.steps_middle_c - self.height_steps() - Self::MARGIN_STEPS
                 + self.ypos;
         Steps(dflt.0.min(bottom))
     }
 
+    /// Get the steps a pitch is drawn at, relative to the stave's frame (where
+    /// the top line is `steps_middle_c`), given the clef of the stave.
+    fn pitch_steps(&self, pitch: Pitch, clef: Clef) -> Steps {
+        pitch.staff_position(clef) + self.middle_steps()
+    }
+
     /// Get number of steps bottom of stave is above middle C
     fn steps_stave_bottom(&self) -> Steps {
         self.steps_middle_c - self.height_steps()
     }
 
+    /// Get number of steps a line (0 is the top line) is above middle C.
+    fn line_steps(&self, line: i32) -> Steps {
+        self.steps_middle_c - Steps(2 * line)
+    }
+
+    /// Get the line a whole rest hangs from (second from the top).
+    fn whole_rest_line(&self) -> i32 {
+        (self.lines - 1).clamp(0, 1)
+    }
+
+    /// Get the line a half rest sits on (the middle line, or the line below
+    /// the middle for an even number of lines).
+    fn half_rest_line(&self) -> i32 {
+        self.lines / 2
+    }
+
+    /// Get number of steps the middle line of the stave is above middle C.
+    /// The stave height is always an even number of steps.
+    pub fn middle_steps(&self) -> Steps {
+        self.steps_middle_c - self.height_steps() / 2
+    }
+
     /// Get the height of the stave
     pub fn height_steps(&self) -> Steps {
         if self.lines > 0 {
@@ -121,15 +289,14 @@ impl Stave {
     pub fn path(
         &self,
         meta: &SfFontMetadata,
+        x: i32,
         top: i32,
         width: i32,
         ofs: Steps,
     ) -> Path {
-        let width = width;
         let ofs = (ofs * Stave::STEP).0;
         let mut d = String::new();
         for i in 0..self.lines {
-            let x = 0;
             let y =
                 top + Stave::SPACE * i - meta.stave_line_thickness / 2 + ofs;
             let line = &format!(
@@ -147,6 +314,20 @@ impl Stave {
     }
 }
 
+/// A notehead for one pitch of a note.
+struct Head {
+    /// Pitch of the notehead
+    pitch: Pitch,
+    /// Clef the stave is in
+    clef: Clef,
+    /// Y position of the notehead
+    y: i32,
+    /// ID of the notehead element
+    id: Option<String>,
+    /// Repeat the accidental in parentheses, as a courtesy
+    courtesy: bool,
+}
+
 pub struct BarElem {
     /// Stave containing the measure
     pub stave: Stave,
@@ -156,8 +337,20 @@ pub struct BarElem {
     pub steps_bottom: Steps,
     /// Width of measure
     pub width: i32,
+    /// Minimum width of the engraved bar, for stretching it to justify a
+    /// system (0 for its natural width).
+    pub min_width: i32,
     /// SVG Elements
     pub elements: Vec<Element>,
+    /// Rendering options
+    pub options: RenderOptions,
+    /// Barline at the end of the measure
+    pub barline: Barline,
+    /// Problems found engraving the measure
+    pub warnings: RenderWarnings,
+    /// Whether the measure starts a system (notes tied into it repeat their
+    /// accidentals in parentheses)
+    pub system_start: bool,
 }
 
 impl fmt::Display for BarElem {
@@ -185,12 +378,19 @@ impl BarElem {
         let steps_bottom = stave.steps_bottom(low);
         let width = 0;
         let elements = vec![];
+        let options = RenderOptions::default();
+        let barline = Barline::Single;
         Self {
             stave,
             steps_top,
             steps_bottom,
             width,
+            min_width: 0,
             elements,
+            options,
+            barline,
+            warnings: RenderWarnings::default(),
+            system_start: false,
         }
     }
 
@@ -209,20 +409,52 @@ impl BarElem {
 
         // Make notators for each stave.
         let mut notators = vec![];
-        for chan in 0..scof.movement[0].bar[0].chan.len() as u16 {
+        for chan in self.staves(scof, &reset_cursor) {
             *curs = reset_cursor.chan(chan);
             notators.push(Notator::new(scof, cursor.clone(), curs.clone()));
         }
 
         // Engrave the music.
-        let (width, rect) = BarEngraver::new(self, &mut notators).engrave(meta);
+        self.barline = scof.barline(curs);
+        let (width, rect) =
+            BarEngraver::new(self, &mut notators, scof, curs).engrave(meta);
         self.width += width;
         rect
     }
 
+    /// Create an empty bar element with the same stave and options, for
+    /// measuring the bar without drawing it.
+    fn blank(&self) -> Self {
+        Self {
+            stave: self.stave.clone(),
+            steps_top: self.steps_top,
+            steps_bottom: self.steps_bottom,
+            width: 0,
+            min_width: self.min_width,
+            elements: vec![],
+            options: self.options.clone(),
+            barline: Barline::Single,
+            warnings: RenderWarnings::default(),
+            system_start: self.system_start,
+        }
+    }
+
+    /// Get the channels of the measure at a cursor drawn as staves, from the
+    /// top.
+    fn staves(&self, scof: &Scof, curs: &Cursor) -> Range<u16> {
+        let chans = scof.measure_channel_count(
+            curs.movement().into(),
+            curs.measure().into(),
+        ) as u16;
+        match &self.options.staves {
+            Some(staves) => staves.start.min(chans)..staves.end.min(chans),
+            None => 0..chans,
+        }
+    }
+
     /// Get the Y offset of a step value
     fn offset_y(&self, steps: Steps) -> i32 {
-        debug_assert!(steps.0 <= self.steps_top.0);
+        debug_assert!(steps <= self.steps_top);
         ((self.steps_top - steps) * Stave::STEP).0
     }
 
@@ -231,19 +463,53 @@ impl BarElem {
         ((self.steps_top - self.steps_bottom) * Stave::STEP).0
     }
 
+    /// Choose stem direction for a single note or chord.
+    fn stem_up(&self, steps: Steps) -> bool {
+        stem_up(steps - self.stave.middle_steps(), StemContext::Single)
+    }
+
     /// Get the middle of the stave y position
     fn middle(&self) -> i32 {
-        let steps = self.stave.steps_middle_c - self.stave.height_steps() / 2;
-        self.offset_y(steps)
+        // Average the top and bottom lines in font units rather than halving
+        // `Steps`, which would truncate.
+        let top = self.offset_y(self.stave.steps_middle_c);
+        let bottom = self.offset_y(self.stave.steps_stave_bottom());
+        (top + bottom) / 2
     }
 
     /// Add a barline to stave
     fn add_barline(&mut self, meta: &SfFontMetadata, x: i32, ofs: Steps) {
-        let width = meta.barline_thickness;
+        let thin = meta.barline_thickness;
         let ofs = (ofs * Stave::STEP).0;
         let y = self.offset_y(self.stave.steps_middle_c) + ofs;
         let y_bottom = self.offset_y(self.stave.steps_stave_bottom()) + ofs;
         let height = y_bottom - y;
+        // Lines left of `x` are spaced by `barlines_space`.
+        let before = x - meta.barlines_space - thin;
+        match self.barline {
+            Barline::Single => self.add_barline_rect(x, y, thin, height),
+            Barline::Double => {
+                self.add_barline_rect(before, y, thin, height);
+                self.add_barline_rect(x, y, thin, height);
+            }
+            Barline::Final => {
+                let thick = meta.thick_barline_thickness;
+                self.add_barline_rect(before, y, thin, height);
+                self.add_barline_rect(x, y, thick, height);
+            }
+            Barline::Dashed => {
+                let dash = Stave::SPACE / 2;
+                for dash_y in (y..y_bottom).step_by(Stave::SPACE as usize) {
+                    let dash = dash.min(y_bottom - dash_y);
+                    self.add_barline_rect(x, dash_y, thin, dash);
+                }
+            }
+            Barline::Invisible => {}
+        }
+    }
+
+    /// Add a rectangle for one line of a barline.
+    fn add_barline_rect(&mut self, x: i32, y: i32, width: i32, height: i32) {
         let rect = Rect::new(x, y, width, height, None, None, None);
         self.elements.push(Element::Rect(rect));
     }
@@ -256,21 +522,31 @@ impl BarElem {
         y + ofs
     }
 
+    /// Get the glyph to draw for a note, from the resolver in the options.
+    fn glyph(&self, note: &Note, role: GlyphRole) -> Glyph {
+        match &self.options.glyph_resolver {
+            Some(resolver) => resolver.resolve(note, role),
+            None => DefaultGlyphResolver.resolve(note, role),
+        }
+    }
+
     /// Add elements for flag and stem.
     fn add_flag(
         &mut self,
         meta: &SfFontMetadata,
         dur: u16,
         offset: f32,
-        y: Steps,
-        y_offset: Steps,
+        (note, y_offset, clef): Chord,
     ) {
+        // FIXME: Use closest to flag.
+        let y = self.stave.pitch_steps(note.pitch[0], clef);
+        let up = self.stem_up(y);
         let y = self.y_from_steps(y, y_offset);
-        let flag_glyph = glyph::flag_duration(dur, y > self.middle()).unwrap();
+        let flag_glyph = self.glyph(&note, GlyphRole::Flag(up));
         let x = self.width + ((offset * BAR_WIDTH as f32) as i32);
         let [left, right] = notehead::stems(Notehead::Normal, meta, dur);
 
-        if y > self.middle() {
+        if up {
             // Right Stem
             let ofsx = right[0] - meta.stem_thickness;
             let ofsy = -Self::STEM_LENGTH;
@@ -298,93 +574,156 @@ impl BarElem {
         };
 
         let mut d = String::new();
-        log!(INFO, "ADD_BEAM {} notes", beam.notes.len());
-        let mut old_x = None;
-        for note_i in 0..beam.notes.len() {
-            let (y, y_offset) = beam.notes[note_i].2;
-            let y = self.y_from_steps(y.visual_distance(), y_offset);
-            let x =
-                self.width + ((beam.notes[note_i].1 * BAR_WIDTH as f32) as i32);
+        trace!(INFO, "ADD_BEAM {} notes", beam.notes.len());
+        let beam_distance =
+            if beam.stems_up { -1 } else { 1 } * (3 * Stave::STEP) / 2;
+        let xs: Vec<i32> = beam
+            .notes
+            .iter()
+            .map(|note| self.width + ((note.1 * BAR_WIDTH as f32) as i32))
+            .collect();
+        // Where each stem meets the primary beam.
+        let ends: Vec<(i32, i32)> = beam
+            .notes
+            .iter()
+            .zip(&xs)
+            .map(|(note, x)| {
+                let (y, y_offset) = note.2;
+                let y = self.y_from_steps(y, y_offset);
+                (x + ofsx + (meta.stem_thickness / 2), y + ofsy + beamy)
+            })
+            .collect();
+        for (note_i, note) in beam.notes.iter().enumerate() {
+            let (y, y_offset) = note.2;
+            let y = self.y_from_steps(y, y_offset);
+            let x = xs[note_i];
 
             self.add_stem2(meta, x + ofsx, y + ofsy, Self::STEM_LENGTH);
 
-            if let Some(old_x) = old_x {
-                let diff: i32 = x - old_x;
-
-                let mut count = match beam.notes[note_i].0 {
-                    1 => 5,       // 128th note beams
-                    2..=3 => 4,   // 64th note beams
-                    4..=7 => 3,   // 32nd note beams
-                    8..=15 => 2,  // 16th note beams
-                    16..=31 => 1, // 8th note beams
-                    a => panic!("Invalid {}", a),
-                };
-
-                if beam.notes[note_i].3 {
-                    count = count.min(1);
-                }
-
-                let beam_distance = if beam.stems_up { -1 } else { 1 }
-                    * (3 * Stave::STEP) / 2;
-                for i in 0..count {
-                    d.push_str(&format!(
-                        "M{} {}l{} {}l{} {}l{} {}z",
-                        x + ofsx + (meta.stem_thickness / 2),
-                        y + ofsy + beamy - i * beam_distance,
-                        -diff,
-                        0,
-                        0,
-                        thickness,
-                        diff,
-                        0
+            let (beam_x, beam_y) = ends[note_i];
+            let mut beam_rect = |width: i32, i: i32| {
+                d.push_str(&format!(
+                    "M{} {}l{} {}l{} {}l{} {}z",
+                    beam_x,
+                    beam_y - i * beam_distance,
+                    width,
+                    0,
+                    0,
+                    thickness,
+                    -width,
+                    0
+                ));
+            };
+            // Feathered beams fan out (or in) across the whole group, with
+            // no beamlets.
+            if let Some(feather) = beam.feather {
+                if note_i != 0 {
+                    d.push_str(&feathered_beams(
+                        feather,
+                        &ends,
+                        note_i,
+                        beam_distance,
                     ));
                 }
+                continue;
+            }
+            let joins = beam.joins(note_i);
+            for i in 0..i32::from(joins) {
+                beam_rect(xs[note_i - 1] - x, i);
+            }
+            // Partial beams, at most half way to the neighboring note.
+            let width = match note.4 {
+                Beamlet::None => continue,
+                Beamlet::Left => -(x - xs[note_i - 1]).min(2 * BEAMLET) / 2,
+                Beamlet::Right => (xs[note_i + 1] - x).min(2 * BEAMLET) / 2,
+            };
+            let right = beam.joins(note_i + 1);
+            for i in joins.max(right)..beam_count(note.0) {
+                beam_rect(width, i32::from(i));
             }
-            old_x = Some(x);
         }
         self.elements.push(Element::Path(Path::new(None, d)));
     }
 
     /// Add stems and either flags or beam elements for short notes.
     fn add_flags_and_beams(&mut self, meta: &SfFontMetadata, beams: Beams) {
+        if self.options.stemless {
+            return;
+        }
         for short in beams {
             match short {
-                Short::Flag(dur, offset, (pitches, y_offset)) => {
-                    let pitch = pitches[0]; // FIXME: Use closest to beam/flag.
-                    self.add_flag(
-                        meta,
-                        dur,
-                        offset,
-                        pitch.visual_distance(),
-                        y_offset,
-                    );
+                Short::Flag(dur, offset, chord) => {
+                    self.add_flag(meta, dur, offset, chord)
                 }
                 Short::Beam(beam) => self.add_beam(meta, beam),
             }
         }
     }
 
-    /// Add elements for a note
+    /// Add the accidental of a pitch left of its notehead at `x`, in
+    /// parentheses for a courtesy accidental.
+    fn add_accidental(
+        &mut self,
+        meta: &SfFontMetadata,
+        pitch: Pitch,
+        x: i32,
+        y: i32,
+        courtesy: bool,
+    ) {
+        let glyph = match pitch.0.accidental {
+            Some(accidental) => Symbol::for_accidental(accidental),
+            None => return,
+        };
+        let mut x = x - ACCIDENTAL_GAP;
+        if courtesy {
+            x -= PAREN_WIDTH;
+            self.add_paren(x + PAREN_WIDTH / 2, y, ")");
+        }
+        x -= meta.advance(glyph);
+        self.add_use(glyph, x, y);
+        if courtesy {
+            self.add_paren(x - PAREN_WIDTH / 2, y, "(");
+        }
+    }
+
+    /// Add a parenthesis (of a courtesy accidental) centered at `x`.
+    fn add_paren(&mut self, x: i32, y: i32, paren: &str) {
+        let text = Text::new(x, y, PAREN_SIZE, None, paren);
+        self.elements.push(Element::Text(text));
+    }
+
+    /// Add elements for one pitch of a note
     fn add_pitch(
         &mut self,
         meta: &SfFontMetadata,
-        dur: u16,
+        note: &Note,
         offset: f32,
-        steps: Steps,
-        y: i32,
+        head: Head,
     ) {
+        let Head {
+            pitch,
+            clef,
+            y,
+            id,
+            courtesy,
+        } = head;
         let x = self.width + ((offset * BAR_WIDTH as f32) as i32);
+        let steps = self.stave.pitch_steps(pitch, clef);
+        let dur = glyph::duration_128ths(note);
 
-        let cp = notehead::duration(dur);
-        self.add_use(cp, x, y);
+        let cp = self.glyph(note, GlyphRole::Notehead(pitch));
+        // Stems and ledger lines fit the notehead that's drawn.
+        let style = Notehead::of(cp);
+        self.add_use_id(cp, x, y, id);
+        self.add_accidental(meta, pitch, x, y, courtesy);
         // Only draw stem if not a whole note or double whole note (breve) or
         // Shorter than quarter note.
         match dur {
+            _ if self.options.stemless => {}
             1..=31 | 128..=511 => {}
             _ => {
-                let [left, right] =
-                    notehead::stems(Notehead::Normal, meta, dur);
-                let (ofsx, ofsy) = if y > self.middle() {
+                let [left, right] = notehead::stems(style, meta, dur);
+                let (ofsx, ofsy) = if self.stem_up(steps) {
                     (right[0] - meta.stem_thickness, -Self::STEM_LENGTH)
                 } else {
                     (left[0], 0)
@@ -393,15 +732,17 @@ impl BarElem {
             }
         }
 
-        // Draw Ledger Lines if below or above stave.
-        let head_width = notehead::width(Notehead::Normal, meta, dur);
-        let dir_step = if steps.0 > 0 { 1 } else { -1 } * Stave::STEP;
-        let yyy = steps.0.abs();
-        let mut count = if yyy % 2 == 0 { 0 } else { 1 };
-        for _ in (6..yyy + 1).step_by(2) {
+        // Draw Ledger Lines if below or above stave, from the notehead (or
+        // the step next to it, for a space) toward the stave.
+        let head_width = notehead::width(style, meta, dur);
+        let (count, above) = ledger_lines(pitch.visual_distance(), clef);
+        let toward = |steps: Steps| if above { steps } else { -steps };
+        let space = Steps(pitch.staff_position(clef).0.rem_euclid(2));
+        for i in 0..count {
             let rect = Rect::new(
                 x - (meta.ledger_line_extension - (meta.stem_thickness / 4)),
-                y - (meta.stave_line_thickness / 2) + count * dir_step,
+                y - (meta.stave_line_thickness / 2)
+                    + (toward(space + Steps(2 * i)) * Stave::STEP).0,
                 head_width + meta.ledger_line_extension * 2,
                 meta.stave_line_thickness,
                 None,
@@ -409,10 +750,394 @@ impl BarElem {
                 None,
             );
             self.elements.push(Element::Rect(rect));
-            count += 2;
         }
     }
 
+    /// Add tremolo slashes across the stem of a note.
+    fn add_tremolo(
+        &mut self,
+        meta: &SfFontMetadata,
+        dur: u16,
+        offset: f32,
+        steps: Steps,
+        y: i32,
+        tremolo: u8,
+    ) {
+        let glyph = glyph::tremolo(tremolo);
+        let up = self.stem_up(steps);
+        let x = self.width + ((offset * BAR_WIDTH as f32) as i32);
+        let [left, right] = notehead::stems(Notehead::Normal, meta, dur);
+        // Same side as the stem, or centered on stemless notes.
+        let (x, y) = match dur {
+            128..=511 => {
+                let head_width = notehead::width(Notehead::Normal, meta, dur);
+                let y = if up {
+                    y - Self::STEM_LENGTH / 2
+                } else {
+                    y + Self::STEM_LENGTH / 2
+                };
+                (x + head_width / 2, y)
+            }
+            _ if up => (
+                x + right[0] - meta.stem_thickness / 2,
+                y - Self::STEM_LENGTH / 2,
+            ),
+            _ => (
+                x + left[0] + meta.stem_thickness / 2,
+                y + Self::STEM_LENGTH / 2,
+            ),
+        };
+        self.add_use(glyph, x - TREMOLO_WIDTH / 2, y);
+    }
+
+    /// Add a label centered on a notehead, if enabled.
+    ///
+    /// - `key`: The key signature (quarter steps above C).
+    fn add_label(
+        &mut self,
+        meta: &SfFontMetadata,
+        pitch: Pitch,
+        key: u8,
+        dur: u16,
+        offset: f32,
+        y: i32,
+    ) {
+        let label = match self.options.label {
+            Some(label) => label,
+            None => return,
+        };
+        let x = self.width + ((offset * BAR_WIDTH as f32) as i32);
+        let head_width = notehead::width(Notehead::Normal, meta, dur);
+        // Knock out text on filled noteheads.
+        let fill = if dur < 64 { Some(0xFFFFFF) } else { None };
+        let text = Text::new(
+            x + head_width / 2,
+            y,
+            Stave::SPACE * 3 / 4,
+            fill,
+            label.text(pitch, key),
+        );
+        self.elements.push(Element::Text(text));
+    }
+
+    /// Add an octave shift bracket: "8" or "15" followed by a dashed line
+    /// from the note at `start` through the note at `end`, above the stave
+    /// for 8va/15ma and below it for 8vb/15mb.
+    fn add_octave_bracket(
+        &mut self,
+        meta: &SfFontMetadata,
+        octaves: i8,
+        start: f32,
+        end: f32,
+        ofs: Steps,
+    ) {
+        let ofs = (ofs * Stave::STEP).0;
+        let x = self.width + ((start * BAR_WIDTH as f32) as i32);
+        let x_end = self.width
+            + ((end * BAR_WIDTH as f32) as i32)
+            + notehead::width(Notehead::Normal, meta, 32);
+        let (y, hook) = if octaves > 0 {
+            let top = self.offset_y(self.stave.steps_middle_c) + ofs;
+            (top - 3 * Stave::SPACE, Stave::SPACE)
+        } else {
+            let bottom = self.offset_y(self.stave.steps_stave_bottom()) + ofs;
+            (bottom + 3 * Stave::SPACE, -Stave::SPACE)
+        };
+        let size = Stave::SPACE * 3 / 2;
+        let text = if octaves.abs() == 1 { "8" } else { "15" };
+        let text = Text::new(x + size / 2, y, size, None, text.to_string());
+        self.elements.push(Element::Text(text));
+
+        // Dashed line, ending with a hook towards the stave.
+        let thickness = meta.hairpin_thickness;
+        let mut d = String::new();
+        let mut dash_x = x + size;
+        while dash_x < x_end {
+            let dash = OCTAVE_DASH.min(x_end - dash_x);
+            d.push_str(&format!(
+                "M{} {}h{}v{}h{}z",
+                dash_x, y, dash, thickness, -dash
+            ));
+            dash_x += 2 * OCTAVE_DASH;
+        }
+        d.push_str(&format!(
+            "M{} {}v{}h{}v{}z",
+            x_end, y, hook, -thickness, -hook
+        ));
+        self.elements.push(Element::Path(Path::new(None, d)));
+    }
+
+    /// Add a tuplet number above the stave, centered over the noteheads
+    /// from the `start` to `end` offsets, with an optional bracket (for
+    /// groups that aren't beamed together).
+    fn add_tuplet(
+        &mut self,
+        meta: &SfFontMetadata,
+        tuplet: u8,
+        start: f32,
+        end: f32,
+        bracket: bool,
+        ofs: Steps,
+    ) {
+        let digits = tuplet.to_string();
+        let digits: Vec<Glyph> =
+            digits.chars().filter_map(glyph::tuplet_digit).collect();
+        let width: i32 = digits.iter().map(|digit| meta.advance(*digit)).sum();
+        let head = notehead::width(Notehead::Normal, meta, 32);
+        let middle = ((start + end) / 2.0 * BAR_WIDTH as f32) as i32 + head / 2;
+        let mut x = self.width + middle - width / 2;
+        let y = self.y_from_steps(self.stave.steps_middle_c, ofs)
+            - 2 * Stave::SPACE;
+        if bracket {
+            // Line through the middle of the number, hooked down towards the
+            // stave at both ends, leaving a gap for the number.
+            let thickness = meta.hairpin_thickness;
+            let line_y = y - Stave::SPACE / 2;
+            let hook = Stave::SPACE / 2 + thickness;
+            let x_start = self.width + (start * BAR_WIDTH as f32) as i32;
+            let x_end = self.width + (end * BAR_WIDTH as f32) as i32 + head;
+            let gap_start = x - Stave::STEP;
+            let gap_end = x + width + Stave::STEP;
+            let d = format!(
+                "M{} {}v{}h{}v{}H{}v{}zM{} {}H{}v{}h{}v{}H{}z",
+                x_start,
+                line_y,
+                hook,
+                thickness,
+                thickness - hook,
+                gap_start,
+                -thickness,
+                gap_end,
+                line_y,
+                x_end,
+                hook,
+                -thickness,
+                thickness - hook,
+                gap_end,
+            );
+            self.elements.push(Element::Path(Path::new(None, d)));
+        }
+        for digit in digits {
+            self.add_use(digit, x, y);
+            x += meta.advance(digit);
+        }
+    }
+
+    /// Get the Y position of the dynamics line below the stave.
+    fn dynamic_y(&self, ofs: Steps) -> i32 {
+        // FIXME: Move lyrics down to make room for dynamics.
+        self.offset_y(self.stave.steps_stave_bottom())
+            + (ofs * Stave::STEP).0
+            + 2 * Stave::SPACE
+    }
+
+    /// Get the width of a dynamic.
+    fn dynamic_width(meta: &SfFontMetadata, dynamic: &Dynamic) -> i32 {
+        glyph::dynamic(dynamic)
+            .into_iter()
+            .map(|glyph| meta.advance(glyph))
+            .sum()
+    }
+
+    /// Add a dynamic below the note at `offset`, moved right by `shift` (in
+    /// font units).
+    fn add_dynamic(
+        &mut self,
+        meta: &SfFontMetadata,
+        dynamic: &Dynamic,
+        offset: f32,
+        shift: i32,
+        ofs: Steps,
+    ) {
+        let mut x = self.width + ((offset * BAR_WIDTH as f32) as i32) + shift;
+        let y = self.dynamic_y(ofs);
+        for glyph in glyph::dynamic(dynamic) {
+            self.add_use(glyph, x, y);
+            x += meta.advance(glyph);
+        }
+    }
+
+    /// Add a hairpin below the stave from `start` to `end` (each an offset
+    /// and a shift in font units), opening to the right for a crescendo.  A
+    /// niente hairpin has a circle at its narrow end.
+    fn add_hairpin(
+        &mut self,
+        meta: &SfFontMetadata,
+        cresc: bool,
+        start: (f32, i32),
+        end: (f32, i32),
+        niente: bool,
+        ofs: Steps,
+    ) {
+        let x = |(offset, shift): (f32, i32)| {
+            self.width + ((offset * BAR_WIDTH as f32) as i32) + shift
+        };
+        let (mut narrow, wide) = if cresc {
+            (x(start), x(end))
+        } else {
+            (x(end), x(start))
+        };
+        let dir = if cresc { 1 } else { -1 };
+        let y = self.dynamic_y(ofs) - Stave::SPACE / 2;
+        let thickness = meta.hairpin_thickness;
+
+        let mut d = String::new();
+        if niente {
+            // Ring, with the inside drawn the other way around to cut it out.
+            let center = narrow + dir * NIENTE_RADIUS;
+            for (r, sweep) in
+                [(NIENTE_RADIUS, 0), (NIENTE_RADIUS - thickness, 1)]
+            {
+                d.push_str(&format!(
+                    "M{} {}a{} {} 0 1 {} {} 0a{} {} 0 1 {} {} 0z",
+                    center - r,
+                    y,
+                    r,
+                    r,
+                    sweep,
+                    2 * r,
+                    r,
+                    r,
+                    sweep,
+                    -2 * r
+                ));
+            }
+            narrow += dir * 2 * NIENTE_RADIUS;
+        }
+        // Two lines, meeting at the narrow end.
+        let opening = Stave::SPACE / 2;
+        for side in [-1, 1] {
+            d.push_str(&format!(
+                "M{} {}L{} {}v{}L{} {}z",
+                narrow,
+                y - thickness / 2,
+                wide,
+                y + side * opening - thickness / 2,
+                thickness,
+                narrow,
+                y + thickness / 2
+            ));
+        }
+        self.elements.push(Element::Path(Path::new(None, d)));
+    }
+
+    /// Add a tie from the notehead at `start` and `y` to the one at `end` (or
+    /// the barline), curving over the notes or under them.
+    fn add_tie(
+        &mut self,
+        meta: &SfFontMetadata,
+        start: f32,
+        end: f32,
+        y: i32,
+        over: bool,
+    ) {
+        let gap = Stave::SPACE / 8;
+        let head_width = notehead::width(Notehead::Normal, meta, 32);
+        let x0 = self.width + ((start * BAR_WIDTH as f32) as i32) + head_width;
+        let x1 = self.width + ((end * BAR_WIDTH as f32) as i32);
+        let (x0, x1) = (x0 + gap, x1 - gap);
+        let dir = if over { -1 } else { 1 };
+        // Ends beside the noteheads, bulging half a space further out.
+        let y = y + dir * Stave::SPACE / 2;
+        let height = dir * Stave::SPACE;
+        let middle = (x0 + x1) / 2;
+        // A quadratic curve reaches half way to its control point, so the
+        // inside curve's control point is twice the thickness closer.
+        let inside = height - dir * 2 * meta.slur_midpoint_thickness;
+        let d = format!(
+            "M{} {}Q{} {} {} {}Q{} {} {} {}z",
+            x0,
+            y,
+            middle,
+            y + height,
+            x1,
+            y,
+            middle,
+            y + inside,
+            x0,
+            y
+        );
+        self.elements.push(Element::Path(Path::new(None, d)));
+    }
+
+    /// Get the Y position of the lyrics line below the stave.
+    fn lyric_y(&self, ofs: Steps) -> i32 {
+        self.offset_y(self.stave.steps_stave_bottom())
+            + (ofs * Stave::STEP).0
+            + 3 * Stave::SPACE
+    }
+
+    /// Get the X position of the center of a lyric syllable sung on the
+    /// note at `offset`, and the syllable's approximate width.
+    fn lyric_x(
+        &self,
+        meta: &SfFontMetadata,
+        text: &str,
+        offset: f32,
+    ) -> (i32, i32) {
+        let x = self.width
+            + ((offset * BAR_WIDTH as f32) as i32)
+            + notehead::width(Notehead::Normal, meta, 32) / 2;
+        (x, text.chars().count() as i32 * LYRIC_SIZE / 2)
+    }
+
+    /// Add a lyric syllable centered under the note at `offset`.
+    fn add_lyric(
+        &mut self,
+        meta: &SfFontMetadata,
+        text: &str,
+        offset: f32,
+        ofs: Steps,
+    ) {
+        let (x, _width) = self.lyric_x(meta, text, offset);
+        let y = self.lyric_y(ofs);
+        let text = Text::new(x, y, LYRIC_SIZE, None, text.to_string());
+        self.elements.push(Element::Text(text));
+    }
+
+    /// Add a hyphen centered in the gap between two syllables of a word,
+    /// each given as the syllable and the offset of the note it's sung on.
+    fn add_lyric_hyphen(
+        &mut self,
+        meta: &SfFontMetadata,
+        first: (&str, f32),
+        second: (&str, f32),
+        ofs: Steps,
+    ) {
+        let (first_x, first_width) = self.lyric_x(meta, first.0, first.1);
+        let (second_x, second_width) = self.lyric_x(meta, second.0, second.1);
+        let x = (first_x + first_width / 2 + second_x - second_width / 2) / 2;
+        let y = self.lyric_y(ofs);
+        let text = Text::new(x, y, LYRIC_SIZE, None, "-".to_string());
+        self.elements.push(Element::Text(text));
+    }
+
+    /// Add a melisma extender line from after a syllable (and the offset of
+    /// the note it's sung on) through the last note at `end` it's held over.
+    fn add_melisma(
+        &mut self,
+        meta: &SfFontMetadata,
+        syllable: (&str, f32),
+        end: f32,
+        ofs: Steps,
+    ) {
+        let (x, width) = self.lyric_x(meta, syllable.0, syllable.1);
+        let x = x + width / 2;
+        let x_end = self.width
+            + ((end * BAR_WIDTH as f32) as i32)
+            + notehead::width(Notehead::Normal, meta, 32);
+        let y = self.lyric_y(ofs) + LYRIC_SIZE / 2;
+        let d = format!(
+            "M{} {}h{}v{}h{}z",
+            x,
+            y,
+            x_end - x,
+            meta.stave_line_thickness,
+            x - x_end
+        );
+        self.elements.push(Element::Path(Path::new(None, d)));
+    }
+
     /// Add a stem FIXME: Replace add_stem with this.
     fn add_stem2(
         &mut self,
@@ -428,69 +1153,1336 @@ impl BarElem {
         self.elements.push(Element::Rect(rect));
     }
 
-    /// Add `use` element for a whole measure rest
-    fn add_measure_rest(&mut self, width: f32, y: Steps) {
-        let x = ((width * BAR_WIDTH as f32) as i32 - WHOLE_REST_WIDTH) / 2;
-        let y = self.middle() + ((y - Steps(2)) * Stave::STEP).0;
+    /// Add `use` element for a whole measure rest, centered in a bar
+    /// `width` wide and hanging from the stave's whole rest line.
+    fn add_measure_rest(
+        &mut self,
+        meta: &SfFontMetadata,
+        width: i32,
+        y: Steps,
+    ) {
+        let x = self.width + (width - meta.advance(Glyph::Rest1)) / 2;
+        let line = self.stave.line_steps(self.stave.whole_rest_line());
+        let y = self.offset_y(line) + (y * Stave::STEP).0;
         self.add_use(Glyph::Rest1, x, y);
     }
 
+    /// Add `use` elements for a measure repeat, with a slash for each bar
+    /// repeated.
+    fn add_measure_repeat(&mut self, width: i32, y: Steps, count: u8) {
+        let count = i32::from(count);
+        let glyphs_width = REPEAT_SLASH_WIDTH * count;
+        let x = self.width + (width - glyphs_width) / 2;
+        let y = self.middle() + (y * Stave::STEP).0;
+        self.add_use(Glyph::RepeatUpDot, x, y);
+        for i in 0..count {
+            self.add_use(Glyph::RepeatSlash, x + REPEAT_SLASH_WIDTH * i, y);
+        }
+        self.add_use(Glyph::RepeatDownDot, x + glyphs_width, y);
+    }
+
+    /// Add `use` element for a beat repeat slash.
+    fn add_beat_repeat(&mut self, offset: f32, y: Steps) {
+        let x = self.width + ((offset * BAR_WIDTH as f32) as i32);
+        let y = self.middle() + (y * Stave::STEP).0;
+        self.add_use(Glyph::RepeatSlash, x, y);
+    }
+
+    /// Add a breath mark (a comma above the stave) or caesura (slashes
+    /// through the top line) just before an offset.
+    fn add_pause(&mut self, pause: &Marking, offset: f32, ofs: Steps) {
+        let x =
+            self.width + ((offset * BAR_WIDTH as f32) as i32) - Stave::SPACE;
+        let top = self.y_from_steps(self.stave.steps_middle_c, ofs);
+        let mut d = String::new();
+        let slashes = match pause {
+            Marking::Breath => {
+                // Round head with a tail curving down and to the left.
+                let r = PAUSE_THICKNESS;
+                let y = top - 2 * Stave::SPACE;
+                d.push_str(&format!(
+                    "M{} {}a{} {} 0 0 1 {} 0q0 {} {} {}q{} {} {} {}z",
+                    x - r,
+                    y,
+                    r,
+                    r,
+                    2 * r,
+                    2 * r,
+                    -2 * r,
+                    3 * r,
+                    r,
+                    -r,
+                    r,
+                    -2 * r,
+                ));
+                0
+            }
+            Marking::CaesuraShort => 1,
+            Marking::CaesuraLong => 2,
+            _ => unreachable!(),
+        };
+        for i in 0..slashes {
+            d.push_str(&format!(
+                "M{} {}l{} 0l{} {}l{} 0z",
+                x - Stave::SPACE / 2 + i * Stave::SPACE / 2,
+                top + Stave::SPACE,
+                PAUSE_THICKNESS,
+                Stave::SPACE,
+                -2 * Stave::SPACE,
+                -PAUSE_THICKNESS,
+            ));
+        }
+        self.elements.push(Element::Path(Path::new(None, d)));
+    }
+
     /// Add `use` element for a rest.
     fn add_rest(&mut self, glyph: Glyph, offset: f32, ofs: Steps) {
         let x = self.width + ((offset * BAR_WIDTH as f32) as i32);
         let ofs = (ofs * Stave::STEP).0;
-        let mut y = self.middle() + ofs;
-        // Position whole rest glyph up 1 stave space.
-        if glyph == Glyph::Rest1 {
-            y -= Stave::SPACE;
-        }
-        self.add_use(glyph, x, y);
+        let y = match glyph {
+            Glyph::Rest1 => {
+                let line = self.stave.whole_rest_line();
+                self.offset_y(self.stave.line_steps(line))
+            }
+            Glyph::Rest2 => {
+                let line = self.stave.half_rest_line();
+                self.offset_y(self.stave.line_steps(line))
+            }
+            _ => self.middle(),
+        };
+        self.add_use(glyph, x, y + ofs);
+    }
+
+    /// Add a filled block for a breve rest (a space tall, hanging from the
+    /// whole rest line), or a longa rest (two spaces tall) if the duration
+    /// (in 128th notes) is a longa or longer.  Fonts don't have a glyph for
+    /// these.
+    fn add_long_rest(&mut self, dur: u16, offset: f32, ofs: Steps) {
+        let x = self.width + ((offset * BAR_WIDTH as f32) as i32);
+        let line = self.stave.line_steps(self.stave.whole_rest_line());
+        let y = self.offset_y(line) + (ofs * Stave::STEP).0;
+        let spaces = if dur >= 512 { 2 } else { 1 };
+        let rect = Rect::new(
+            x,
+            y,
+            LONG_REST_WIDTH,
+            Stave::SPACE * spaces,
+            None,
+            None,
+            None,
+        );
+        self.elements.push(Element::Rect(rect));
     }
 
     /// Add use element
     fn add_use(&mut self, glyph: Glyph, x: i32, y: i32) {
-        self.elements
-            .push(Element::Use(Use::new(x, y, glyph.into())));
+        self.add_use_id(glyph, x, y, None);
     }
 
-    /// Add clef
-    pub fn add_clefs(&mut self, scof: &Scof) {
-        for i in 0..scof.movement[0].bar[0].chan.len() as i32 {
+    /// Add use element with an ID
+    fn add_use_id(&mut self, glyph: Glyph, x: i32, y: i32, id: Option<String>) {
+        let mut elem = Use::new(x, y, glyph.into());
+        elem.element_id = id;
+        self.elements.push(Element::Use(elem));
+    }
+
+    /// Add clefs of the measure at a cursor.
+    pub fn add_clefs(&mut self, scof: &Scof, curs: &Cursor) {
+        for (i, chan) in self.staves(scof, curs).enumerate() {
+            let clef = scof.clef(&curs.chan(chan));
+            let (glyph, steps) = Symbol::for_clef(clef);
             let ymargin =
                 (self.stave.height_steps() + Steps(12)).0 * Stave::STEP;
-            self.add_use(Glyph::ClefC, 150, self.middle() + ymargin * i);
+            let y = self.middle() - (steps * Stave::STEP).0;
+            self.add_use(glyph, 150, y + ymargin * i as i32);
         }
         self.width += 1000;
     }
 
-    /// Add time signature
-    pub fn add_times(&mut self, scof: &Scof) {
-        for i in 0..scof.movement[0].bar[0].chan.len() as i32 {
+    /// Add key signature of the measure at a cursor, with the accidentals
+    /// of each stave placed for its clef.
+    pub fn add_key(
+        &mut self,
+        meta: &SfFontMetadata,
+        scof: &Scof,
+        curs: &Cursor,
+    ) {
+        let key = scof.sig(curs).map_or(0, |sig| sig.key);
+        let mut width = 0;
+        for (i, chan) in self.staves(scof, curs).enumerate() {
+            let clef = scof.clef(&curs.chan(chan));
+            let ymargin = self.stave.height_steps() + Steps(12);
+            let ofs = ymargin * i as i32;
+            let mut x = self.width + 50;
+            for (glyph, steps) in key::key_signature(key, clef) {
+                let steps = steps + self.stave.middle_steps();
+                self.add_use(glyph, x, self.y_from_steps(steps, ofs));
+                x += meta.advance(glyph);
+            }
+            width = width.max(x - self.width);
+        }
+        if width > 0 {
+            self.width += width + 120;
+        }
+    }
+
+    /// Add time signature of the measure at a cursor, centering the
+    /// numerator and denominator over each other.  If the signature's style
+    /// asks for a symbol, 4/4 is drawn as common time and 2/2 as cut time
+    /// (other signatures have no symbol).
+    pub fn add_times(
+        &mut self,
+        meta: &SfFontMetadata,
+        scof: &Scof,
+        curs: &Cursor,
+    ) {
+        let time = scof.sig(curs).map_or("4/4", |sig| &sig.time);
+        let mut rows = time.split('/');
+        let num = rows.next().unwrap_or("");
+        let den = rows.next().unwrap_or("");
+        let symbol = match (num, den) {
+            ("4", "4") => Some(Glyph::TimeSigCommon),
+            ("2", "2") => Some(Glyph::TimeSigCut),
+            _ => None,
+        }
+        .filter(|_| scof.sig_style(curs).is_some_and(|s| s.time_symbol));
+        // The wider row determines the signature's width.
+        let width = match symbol {
+            Some(symbol) => meta.advance(symbol),
+            None => {
+                Self::time_width(meta, num).max(Self::time_width(meta, den))
+            }
+        };
+        let center = self.width + 50 + width / 2;
+        for i in 0..self.staves(scof, curs).len() as i32 {
             let ymargin =
                 (self.stave.height_steps() + Steps(12)).0 * Stave::STEP;
-            // width=421
-            self.add_use(
-                Glyph::TimeSig3,
-                self.width + 50,
-                self.middle() - Stave::SPACE + ymargin * i,
-            );
-            // width=470
-            self.add_use(
-                Glyph::TimeSig4,
-                self.width + 50 - ((470 - 421) / 2),
-                self.middle() + Stave::SPACE + ymargin * i,
-            );
+            let y = self.middle() + ymargin * i;
+            if let Some(symbol) = symbol {
+                self.add_use(symbol, center - width / 2, y);
+                continue;
+            }
+            self.add_time_row(meta, num, center, y - Stave::SPACE);
+            self.add_time_row(meta, den, center, y + Stave::SPACE);
         }
 
-        self.width += 640;
+        self.width += width + 170;
+    }
+
+    /// Get the width of a row of time signature digits.
+    fn time_width(meta: &SfFontMetadata, digits: &str) -> i32 {
+        digits
+            .chars()
+            .filter_map(glyph::time_sig_digit)
+            .map(|digit| meta.advance(digit))
+            .sum()
+    }
+
+    /// Add a row of time signature digits centered on `x`.
+    fn add_time_row(
+        &mut self,
+        meta: &SfFontMetadata,
+        digits: &str,
+        x: i32,
+        y: i32,
+    ) {
+        let mut x = x - Self::time_width(meta, digits) / 2;
+        for digit in digits.chars().filter_map(glyph::time_sig_digit) {
+            self.add_use(digit, x, y);
+            x += meta.advance(digit);
+        }
     }
 
     /// Add clef & time signature.
     pub fn add_signatures(&mut self, _scof: &Scof) {
-        //self.add_clefs(_scof);
-        //self.add_times(_scof);
+        //self.add_clefs(_scof, _curs);
+        //self.add_times(meta, _scof, curs);
     }
 }
 
 #[cfg(test)]
-mod tests {}
+pub(crate) mod tests {
+    use super::*;
+    use scof::Fraction;
+    use std::cell::RefCell;
+
+    thread_local! {
+        /// Messages logged by the test running on this thread.
+        pub(crate) static LOG: RefCell<Vec<String>> =
+            const { RefCell::new(Vec::new()) };
+    }
+
+    /// Font for tests, with placeholder glyph paths.
+    pub(crate) fn font() -> (SfFontMetadata, String) {
+        let meta = SfFontMetadata {
+            sffonts_version: 0,
+            font_name: "Test".to_string(),
+            stave_line_thickness: 13,
+            stem_thickness: 12,
+            ledger_line_thickness: 16,
+            ledger_line_extension: 40,
+            slur_endpoint_thickness: 10,
+            slur_midpoint_thickness: 22,
+            barline_thickness: 16,
+            thick_barline_thickness: 50,
+            barlines_space: 40,
+            barline_repeatdot_space: 16,
+            bracket_thickness: 50,
+            subbracket_thickness: 16,
+            hairpin_thickness: 16,
+            rehearsal_box_thickness: 16,
+            notehead: [[0, -17], [118, 17]],
+            notehead_x: [[0, -17], [116, 17]],
+            notehead_diamond: [[0, 0], [105, 0]],
+            notehead_triangle: [[0, -50], [118, 50]],
+            notehead_slash: [[0, -100], [150, 100]],
+            notehead_half: [[0, -17], [118, 17]],
+            notehead_half_x: [[0, -17], [116, 17]],
+            notehead_half_diamond: [[0, 0], [105, 0]],
+            notehead_half_triangle: [[0, -50], [118, 50]],
+            notehead_half_slash: [[0, -100], [150, 100]],
+            notehead_whole: [[0, 0], [169, 0]],
+            notehead_whole_x: [[0, 0], [169, 0]],
+            notehead_whole_diamond: [[0, 0], [140, 0]],
+            notehead_whole_triangle: [[0, 0], [169, 0]],
+            notehead_whole_slash: [[0, 0], [200, 0]],
+            notehead_double: [[0, 0], [250, 0]],
+            notehead_double_x: [[0, 0], [250, 0]],
+            notehead_double_diamond: [[0, 0], [250, 0]],
+            notehead_double_triangle: [[0, 0], [250, 0]],
+            notehead_double_slash: [[0, 0], [250, 0]],
+            advance: vec![],
+        };
+        let glyphs: Vec<String> = (0..Glyph::Len as usize)
+            .map(|i| format!("M0 {}h100v100h-100z", i))
+            .collect();
+
+        (meta, glyphs.join("\0"))
+    }
+
+    /// Score with a single bar, one channel per string of notes.
+    pub(crate) fn score(chans: &[&str]) -> Scof {
+        let mut scof = Scof::default();
+        let chan = chans.iter().map(|notes| notes.parse().unwrap()).collect();
+        scof.movement[0].bar = vec![scof::Measure {
+            sig: None,
+            chan,
+            repeat: vec![],
+            barline: None,
+            break_before: None,
+        }];
+        scof
+    }
+
+    /// Engrave the first bar of a score.
+    pub(crate) fn engrave(scof: &Scof, options: RenderOptions) -> BarElem {
+        let (meta, _glyphs) = font();
+        let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+        let mut bar = BarElem::new(Stave::new(5, Steps(4), Steps(0)), c4, c4);
+        bar.options = options;
+        let mut curs = Cursor::default();
+        bar.add_markings(&meta, scof, &Cursor::default(), &mut curs);
+        bar
+    }
+
+    #[test]
+    fn quiet_by_default() {
+        LOG.with(|log| log.borrow_mut().clear());
+        // Beamed notes used to log while rendering.
+        let bar = engrave(
+            &score(&["1/8C4 1/8D4 1/8E4 1/8F4 1/2R"]),
+            Default::default(),
+        );
+        assert!(!bar.elements.is_empty());
+        // Only the trace feature logs.
+        let logged = LOG.with(|log| log.borrow().len());
+        assert_eq!(logged > 0, cfg!(feature = "trace"));
+    }
+
+    #[test]
+    fn clef_positions() {
+        let (meta, _glyphs) = font();
+        // Y of the whole note and number of ledger lines.
+        let middle_c = |clef| {
+            let mut scof = score(&["1/1C4"]);
+            scof.set_clef(&Cursor::default(), clef);
+            let bar = engrave(&scof, Default::default());
+            let mut head = None;
+            let mut ledgers = 0;
+            for elem in &bar.elements {
+                match elem {
+                    Element::Use(u) if u.id == Glyph::NoteheadWhole.into() => {
+                        head = Some(u.y)
+                    }
+                    Element::Rect(r)
+                        if r.rx.is_none()
+                            && r.height == meta.stave_line_thickness =>
+                    {
+                        ledgers += 1
+                    }
+                    _ => {}
+                }
+            }
+            let top = bar.offset_y(bar.stave.steps_middle_c);
+            (head.unwrap() - top, ledgers)
+        };
+        // One ledger line below the treble stave, on the middle line of the
+        // alto stave, and one ledger line above the bass stave.
+        assert_eq!(middle_c(Clef::Treble), (Stave::SPACE * 5, 1));
+        assert_eq!(middle_c(Clef::Alto), (Stave::SPACE * 2, 0));
+        assert_eq!(middle_c(Clef::Tenor), (Stave::SPACE, 0));
+        assert_eq!(middle_c(Clef::Bass), (-Stave::SPACE, 1));
+    }
+
+    #[test]
+    fn ledger_line_count() {
+        let steps =
+            |pitch: &str| pitch.parse::<Pitch>().unwrap().visual_distance();
+        assert_eq!(ledger_lines(steps("A5"), Clef::Treble), (1, true));
+        assert_eq!(ledger_lines(steps("G5"), Clef::Treble), (0, true));
+        assert_eq!(ledger_lines(steps("C6"), Clef::Treble), (2, true));
+        assert_eq!(ledger_lines(steps("C4"), Clef::Treble), (1, false));
+        assert_eq!(ledger_lines(steps("B3"), Clef::Treble), (1, false));
+        assert_eq!(ledger_lines(steps("C4"), Clef::Alto).0, 0);
+        assert_eq!(ledger_lines(steps("C4"), Clef::Bass), (1, true));
+    }
+
+    #[test]
+    fn stave_middle() {
+        let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+        // Number of lines and index of the center line from the top.
+        for &(lines, center) in &[(1, 0), (3, 1), (5, 2)] {
+            let stave = Stave::new(lines, Steps(4), Steps(0));
+            let bar = BarElem::new(stave, c4, c4);
+            let top = bar.offset_y(bar.stave.steps_middle_c);
+            assert_eq!(bar.middle(), top + Stave::SPACE * center);
+        }
+        // Top line on an odd step.
+        let bar = BarElem::new(Stave::new(3, Steps(3), Steps(0)), c4, c4);
+        let top = bar.offset_y(bar.stave.steps_middle_c);
+        assert_eq!(bar.middle(), top + Stave::SPACE);
+        assert_eq!(bar.middle(), bar.offset_y(Steps(1)));
+    }
+
+    #[test]
+    fn stave_margins_on_lines() {
+        let stave = Stave::new(3, Steps(3), Steps(0));
+        assert_eq!(stave.steps_top(Steps(12)), Steps(13));
+        assert_eq!(stave.steps_top(Steps(13)), Steps(15));
+        assert_eq!(stave.steps_bottom(Steps(-12)), Steps(-13));
+        assert_eq!(stave.steps_bottom(Steps(-13)), Steps(-15));
+
+        let stave = Stave::new(5, Steps(4), Steps(0));
+        assert_eq!(stave.steps_top(Steps(11)), Steps(12));
+        assert_eq!(stave.steps_bottom(Steps(-11)), Steps(-12));
+    }
+
+    #[test]
+    fn stemless() {
+        let scof = score(&["1/8C4 1/8D4 1/16E4 3/16F4 1/2G4"]);
+        // Stems are rounded `rect`s, beams are `path`s besides the stave and
+        // ties (curves).
+        let count = |bar: &BarElem| {
+            let mut counts = (0, 0, 0);
+            for elem in &bar.elements {
+                match elem {
+                    Element::Use(_) => counts.0 += 1,
+                    Element::Rect(rect) if rect.rx.is_some() => counts.1 += 1,
+                    Element::Path(path) if path.d.contains('Q') => {}
+                    Element::Path(_) => counts.2 += 1,
+                    _ => {}
+                }
+            }
+            counts.2 -= 1;
+            counts
+        };
+        let (heads, stems, beams) = count(&engrave(&scof, Default::default()));
+        assert!(stems > 0 && beams > 0);
+
+        let options = RenderOptions {
+            stemless: true,
+            ..Default::default()
+        };
+        let bar = engrave(&scof, options);
+        assert_eq!(count(&bar), (heads, 0, 0));
+    }
+
+    #[test]
+    fn glyph_resolver() {
+        #[derive(Debug)]
+        struct Diamonds;
+
+        impl GlyphResolver for Diamonds {
+            fn resolve(&self, note: &Note, role: GlyphRole) -> Glyph {
+                let dur = note.duration.num * 128 / note.duration.den;
+                match role {
+                    GlyphRole::Notehead(_) => {
+                        Symbol::for_note(Notehead::Diamond, dur)
+                    }
+                    role => DefaultGlyphResolver.resolve(note, role),
+                }
+            }
+        }
+
+        let scof = score(&["1/8C4 1/8D4 1/4R 1/2G4"]);
+        let options = RenderOptions {
+            glyph_resolver: Some(std::rc::Rc::new(Diamonds)),
+            ..Default::default()
+        };
+        let bar = engrave(&scof, options);
+        let glyphs: Vec<u16> = bar
+            .elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Use(u) => Some(u.id),
+                _ => None,
+            })
+            .collect();
+        let count = |glyph: Glyph| {
+            glyphs.iter().filter(|id| **id == glyph.into()).count()
+        };
+        assert_eq!(count(Glyph::NoteheadFillDiamond), 2);
+        assert_eq!(count(Glyph::NoteheadHalfDiamond), 1);
+        assert_eq!(count(Glyph::NoteheadFill), 0);
+        assert_eq!(count(Glyph::NoteheadHalf), 0);
+        // Rests are still resolved by the default resolver.
+        assert_eq!(count(Glyph::Rest4), 1);
+    }
+
+    #[test]
+    fn hundred_twenty_eighth_rest() {
+        let scof = score(&["1/128R 1/128C4 63/64R"]);
+        let bar = engrave(&scof, Default::default());
+        assert!(bar.elements.iter().any(|elem| match elem {
+            Element::Use(u) => u.id == Glyph::Rest64.into(),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn measure_repeat() {
+        let slashes = |notes| {
+            let bar = engrave(&score(&[notes]), Default::default());
+            bar.elements
+                .iter()
+                .filter(|elem| match elem {
+                    Element::Use(u) => u.id == Glyph::RepeatSlash.into(),
+                    _ => false,
+                })
+                .count()
+        };
+        assert_eq!(slashes("%"), 1);
+        assert_eq!(slashes("%%"), 2);
+        assert_eq!(slashes("1/1C4"), 0);
+    }
+
+    #[test]
+    fn lyrics() {
+        let texts = |notes, lyrics| -> Vec<(i32, String)> {
+            let mut scof = score(&[notes]);
+            scof.set_lyrics(&Cursor::default(), lyrics);
+            let bar = engrave(&scof, Default::default());
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Text(t) => Some((t.x, t.text.clone())),
+                    _ => None,
+                })
+                .collect()
+        };
+        let heads = |notes| -> Vec<i32> {
+            let bar = engrave(&score(&[notes]), Default::default());
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some(u.x),
+                    _ => None,
+                })
+                .collect()
+        };
+        let (meta, _) = font();
+        let head_width = notehead::width(Notehead::Normal, &meta, 32);
+
+        // Syllables centered under their notes, hyphen centered between.
+        let word = texts("1/4C5 1/4C5 1/2R", "ba- by");
+        let x = heads("1/4C5 1/4C5 1/2R");
+        assert_eq!(word.len(), 3);
+        assert_eq!(word[0], (x[0] + head_width / 2, "ba".to_string()));
+        assert_eq!(word[2], (x[1] + head_width / 2, "by".to_string()));
+        assert_eq!(word[1], ((word[0].0 + word[2].0) / 2, "-".to_string()));
+        // The hyphen is in the gap between the syllables.
+        assert!(word[1].0 > word[0].0 + LYRIC_SIZE / 2);
+        assert!(word[1].0 < word[2].0 - LYRIC_SIZE / 2);
+
+        // A melisma adds an extender line, but no text.
+        let paths = |notes, lyrics| {
+            let mut scof = score(&[notes]);
+            scof.set_lyrics(&Cursor::default(), lyrics);
+            let bar = engrave(&scof, Default::default());
+            bar.elements
+                .iter()
+                .filter(|elem| matches!(elem, Element::Path(_)))
+                .count()
+        };
+        let melisma = texts("1/4C5 1/4D5 1/2E5", "Ah _ _");
+        assert_eq!(melisma.len(), 1);
+        assert_eq!(
+            paths("1/4C5 1/4D5 1/2E5", "Ah _ _"),
+            paths("1/4C5 1/4D5 1/2E5", "Ah") + 1
+        );
+    }
+
+    #[test]
+    fn three_four() {
+        let elements = |notes| {
+            let mut scof = score(&[notes]);
+            scof.set_time(&Cursor::default(), scof::Fraction::new(3, 4));
+            engrave(&scof, Default::default()).elements
+        };
+        let count = |elements: &[Element]| {
+            let mut counts = (0, 0);
+            for elem in elements {
+                match elem {
+                    Element::Use(_) => counts.0 += 1,
+                    Element::Path(_) => counts.1 += 1,
+                    _ => {}
+                }
+            }
+            counts
+        };
+        // Six eighth notes are beamed in three beats (besides the stave).
+        let eighths = elements("1/8C5 1/8D5 1/8E5 1/8F5 1/8C5 1/8D5");
+        assert_eq!(count(&eighths), (6, 4));
+        // A full 3/4 bar doesn't have rests filling it to 4/4.
+        let quarters = elements("1/4C5 1/4D5 1/4E5");
+        assert_eq!(count(&quarters), (3, 1));
+    }
+
+    #[test]
+    fn six_eight() {
+        let mut scof = score(&["1/8C5 1/8D5 1/8E5 1/8F5 1/8C5 1/8D5"]);
+        scof.set_time(&Cursor::default(), scof::Fraction::new(6, 8));
+        let elements = engrave(&scof, Default::default()).elements;
+        let paths = elements
+            .iter()
+            .filter(|elem| matches!(elem, Element::Path(_)))
+            .count();
+        // Six eighth notes are beamed in two dotted quarters (besides the
+        // stave).
+        assert_eq!(paths, 3);
+    }
+
+    #[test]
+    fn beamlets() {
+        let scof = score(&["1/16C5 1/16R 1/16C5 1/16R 3/4R"]);
+        let bar = engrave(&scof, Default::default());
+        // The stave is drawn with horizontal lines, beams aren't.
+        let beam = bar.elements.iter().find_map(|elem| match elem {
+            Element::Path(path) if !path.d.contains('h') => Some(&path.d),
+            _ => None,
+        });
+        // Start, height and width of each beam rectangle.
+        let rects: Vec<[i32; 3]> = beam
+            .unwrap()
+            .split('M')
+            .skip(1)
+            .map(|rect| {
+                let mut nums =
+                    rect.split([' ', 'l']).map(|num| num.parse().unwrap());
+                [
+                    nums.next().unwrap(),
+                    nums.next().unwrap(),
+                    nums.next().unwrap(),
+                ]
+            })
+            .collect();
+        assert_eq!(rects.len(), 3);
+        // Full primary beam between the notes.
+        let [right, primary_y, width] = rects[1];
+        let left = right + width;
+        assert!(width < 0);
+        // Secondary beamlets pointing inward.
+        let ([first_x, first_y, first], [last_x, last_y, last]) =
+            (rects[0], rects[2]);
+        assert_eq!((first_x, last_x), (left, right));
+        assert!(first > 0 && first <= -width / 2);
+        assert!(last < 0 && last >= width / 2);
+        assert_eq!(first_y, last_y);
+        assert_ne!(first_y, primary_y);
+    }
+
+    #[test]
+    fn feathered_beams() {
+        // Spread of the beam lines at the start and end of each segment.
+        let spreads = |notes| -> Vec<(i32, i32)> {
+            let bar = engrave(&score(&[notes]), Default::default());
+            let beam = bar.elements.iter().find_map(|elem| match elem {
+                Element::Path(path) if path.d.contains('L') => Some(&path.d),
+                _ => None,
+            });
+            // Y at the start and end of each beam line.
+            let lines: Vec<(i32, i32)> = beam
+                .unwrap()
+                .split('M')
+                .skip(1)
+                .map(|line| {
+                    let nums: Vec<i32> = line
+                        .split([' ', 'L', 'v'])
+                        .take(4)
+                        .map(|num| num.parse().unwrap())
+                        .collect();
+                    (nums[1], nums[3])
+                })
+                .collect();
+            lines
+                .chunks(3)
+                .map(|segment| {
+                    let (first, last) = (segment[0], segment[2]);
+                    ((first.0 - last.0).abs(), (first.1 - last.1).abs())
+                })
+                .collect()
+        };
+        // Three beam lines between each pair of notes, fanning out.
+        let accel = spreads("1/8C5{ 1/8C5 1/8C5 1/8C5 1/2R");
+        assert_eq!(accel.len(), 3);
+        assert_eq!(accel[0].0, 0);
+        for (i, (start, end)) in accel.iter().enumerate() {
+            assert!(start < end);
+            if i > 0 {
+                assert_eq!(*start, accel[i - 1].1);
+            }
+        }
+        // And fanning in.
+        let rit = spreads("1/8C5} 1/8C5 1/8C5 1/8C5 1/2R");
+        assert_eq!(rit.len(), 3);
+        assert_eq!(rit[2].1, 0);
+        assert!(rit.iter().all(|(start, end)| start > end));
+        assert_eq!(rit[0].0, accel[2].1);
+    }
+
+    #[test]
+    fn feathered_beam_stems() {
+        let (meta, _) = font();
+        let bar = engrave(
+            &score(&["1/8C4{ 1/8D4 1/8E4 1/8F4 1/2R"]),
+            Default::default(),
+        );
+        // Top of each stem, at its center.
+        let stems: Vec<(i32, i32)> = bar
+            .elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Rect(r) if r.rx.is_some() => {
+                    Some((r.x + meta.stem_thickness / 2, r.y))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stems.len(), 4);
+        let beam = bar.elements.iter().find_map(|elem| match elem {
+            Element::Path(path) if path.d.contains('L') => Some(&path.d),
+            _ => None,
+        });
+        // The primary beam line joins the tops of the stems, sloping with
+        // the notes.
+        let primary: Vec<(i32, i32, i32, i32)> = beam
+            .unwrap()
+            .split('M')
+            .skip(1)
+            .step_by(FEATHERED_BEAMS as usize)
+            .map(|line| {
+                let nums: Vec<i32> = line
+                    .split([' ', 'L', 'v'])
+                    .take(4)
+                    .map(|num| num.parse().unwrap())
+                    .collect();
+                (nums[0], nums[1], nums[2], nums[3])
+            })
+            .collect();
+        assert_eq!(primary.len(), 3);
+        for (i, (x0, y0, x1, y1)) in primary.into_iter().enumerate() {
+            assert_eq!((x0, y0), stems[i]);
+            assert_eq!((x1, y1), stems[i + 1]);
+        }
+    }
+
+    #[test]
+    fn pauses() {
+        // Subpaths of the path drawn for a pause, and the top line's Y.
+        let pause = |notes| -> (Vec<String>, i32) {
+            let bar = engrave(&score(&[notes]), Default::default());
+            let top = bar.y_from_steps(bar.stave.steps_middle_c, Steps(0));
+            let path = bar.elements.iter().find_map(|elem| match elem {
+                Element::Path(path) if path.d.ends_with('z') => Some(&path.d),
+                _ => None,
+            });
+            let subpaths = path
+                .unwrap()
+                .split('M')
+                .skip(1)
+                .map(str::to_string)
+                .collect();
+            (subpaths, top)
+        };
+        // A comma above the stave.
+        let (breath, top) = pause("1/2C4 , 1/2C4");
+        assert_eq!(breath.len(), 1);
+        assert!(breath[0].contains('a') && breath[0].contains('q'));
+        let y: i32 =
+            breath[0].split([' ', 'a']).nth(1).unwrap().parse().unwrap();
+        assert!(y < top);
+        // Slashes through the top line, at the end of the bar too.
+        let (caesura, top) = pause("1/1C4 //");
+        assert_eq!(caesura.len(), 2);
+        for slash in caesura {
+            let y: i32 =
+                slash.split([' ', 'l']).nth(1).unwrap().parse().unwrap();
+            assert!(y > top && y - 2 * Stave::SPACE < top);
+        }
+        assert_eq!(pause("1/2C4 / 1/2C4").0.len(), 1);
+    }
+
+    #[test]
+    fn beat_repeat() {
+        let uses = |notes| -> Vec<(i32, u16)> {
+            let bar = engrave(&score(&[notes]), Default::default());
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some((u.x, u.id)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let simile = uses("1/4C5 1/4% 1/2R");
+        let notes = uses("1/4C5 1/4C5 1/2R");
+        // The slash is drawn where the second beat's note would be.
+        assert_eq!(simile.len(), notes.len());
+        assert_eq!(simile[1], (notes[1].0, Glyph::RepeatSlash.into()));
+        assert_eq!(simile[2], notes[2]);
+    }
+
+    #[test]
+    fn tremolo() {
+        let bar = engrave(&score(&["1/4C4/// 3/4R"]), Default::default());
+        let tremolos: Vec<u16> = bar
+            .elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Use(u) if u.id >= Glyph::Tremelo1.into() => Some(u.id),
+                _ => None,
+            })
+            .filter(|id| *id <= Glyph::Tremelo5.into())
+            .collect();
+        assert_eq!(tremolos, [u16::from(Glyph::Tremelo3)]);
+    }
+
+    #[test]
+    fn barlines() {
+        let (meta, _glyphs) = font();
+        // Full height rectangles (x, width)
+        let lines = |barline| {
+            let mut scof = score(&["1/1C4"]);
+            scof.movement[0].bar[0].barline = barline;
+            let bar = engrave(&scof, Default::default());
+            let height = bar.offset_y(bar.stave.steps_stave_bottom())
+                - bar.offset_y(bar.stave.steps_middle_c);
+            let lines: Vec<(i32, i32)> = bar
+                .elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Rect(r) if r.height == height => {
+                        Some((r.x, r.width))
+                    }
+                    _ => None,
+                })
+                .collect();
+            lines
+        };
+
+        // The last measure defaults to a final barline.
+        assert_eq!(lines(None), lines(Some(Barline::Final)));
+        let lines = lines(Some(Barline::Final));
+        assert_eq!(lines.len(), 2);
+        let (thin, thick) = (lines[0], lines[1]);
+        assert_eq!(thin.1, meta.barline_thickness);
+        assert_eq!(thick.1, meta.thick_barline_thickness);
+        assert_eq!(thick.0 - (thin.0 + thin.1), meta.barlines_space);
+    }
+
+    #[test]
+    fn single_barline() {
+        let mut scof = score(&["1/1C4"]);
+        scof.new_measure();
+        let bar = engrave(&scof, Default::default());
+        assert_eq!(bar.barline, Barline::Single);
+    }
+
+    #[test]
+    fn flag_and_beam_stems_agree() {
+        // Stems are on the right side of the notehead when up.  Use the
+        // second stave, which is offset from the middle of the first.
+        let stem_xs = |notes: &str| {
+            let bar = engrave(&score(&["", notes]), Default::default());
+            let xs: Vec<i32> = bar
+                .elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Rect(r) if r.rx.is_some() => Some(r.x),
+                    _ => None,
+                })
+                .collect();
+            xs
+        };
+        for pitch in &["A3", "C4", "D4"] {
+            let flag = stem_xs(&format!("1/8{} 7/8R", pitch));
+            let beam = stem_xs(&format!("1/8{} 1/8{} 3/4R", pitch, pitch));
+            assert_eq!(flag[0], beam[0], "{}", pitch);
+        }
+    }
+
+    #[test]
+    fn spacer() {
+        let uses = |notes| {
+            let bar = engrave(&score(&[notes]), Default::default());
+            let uses: Vec<(i32, u16)> = bar
+                .elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some((u.x, u.id)),
+                    _ => None,
+                })
+                .collect();
+            uses
+        };
+        let spacer = uses("1/2s 1/2C4");
+        let rest = uses("1/2R 1/2C4");
+        // Only the notehead is drawn, where it would be after a half rest.
+        assert_eq!(spacer.len(), 1);
+        assert_eq!(spacer[0], rest[1]);
+    }
+
+    #[test]
+    fn time_signature_centered() {
+        let (mut meta, _) = font();
+        meta.advance = vec![0; Glyph::Len as usize];
+        meta.advance[Glyph::TimeSig1 as usize] = 300;
+        meta.advance[Glyph::TimeSig2 as usize] = 400;
+        meta.advance[Glyph::TimeSig8 as usize] = 450;
+        let high = "C4".parse::<Pitch>().unwrap().visual_distance();
+        let mut bar =
+            BarElem::new(Stave::new(5, Steps(4), Steps(0)), high, high);
+        let scof = Scof::new(1, Fraction::new(12, 8), 0);
+        bar.add_times(&meta, &scof, &Cursor::default());
+
+        let uses: Vec<(i32, u16)> = bar
+            .elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Use(u) => Some((u.x, u.id)),
+                _ => None,
+            })
+            .collect();
+        // "12" is 700 wide, centered at 50 + 350 along with "8".
+        assert_eq!(
+            uses,
+            vec![
+                (50, Glyph::TimeSig1 as u16),
+                (350, Glyph::TimeSig2 as u16),
+                (400 - 225, Glyph::TimeSig8 as u16),
+            ]
+        );
+        assert_eq!(bar.width, 50 + 700 + 120);
+    }
+
+    #[test]
+    fn time_symbol() {
+        let (meta, _) = font();
+        let glyphs = |time: Fraction, time_symbol| -> Vec<u16> {
+            let mut scof = Scof::new(1, time, 0);
+            scof.style.sig = vec![scof::SigStyle {
+                tempo: None,
+                time_symbol,
+                swing_text: None,
+            }];
+            let high = "C4".parse::<Pitch>().unwrap().visual_distance();
+            let mut bar =
+                BarElem::new(Stave::new(5, Steps(4), Steps(0)), high, high);
+            bar.add_times(&meta, &scof, &Cursor::default());
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some(u.id),
+                    _ => None,
+                })
+                .collect()
+        };
+        let common = Glyph::TimeSigCommon as u16;
+        assert_eq!(glyphs(Fraction::new(4, 4), true), [common]);
+        assert_eq!(
+            glyphs(Fraction::new(2, 2), true),
+            [Glyph::TimeSigCut as u16]
+        );
+        // Digits without the style, or without a symbol for the signature.
+        let four = Glyph::TimeSig4 as u16;
+        assert_eq!(glyphs(Fraction::new(4, 4), false), [four, four]);
+        assert_eq!(
+            glyphs(Fraction::new(3, 4), true),
+            [Glyph::TimeSig3 as u16, four]
+        );
+    }
+
+    #[test]
+    fn octave_shift() {
+        let elements =
+            |notes| engrave(&score(&[notes]), Default::default()).elements;
+        let shifted = elements("8va 1/4C6 1/4C6 loco 1/2C4");
+        let written = elements("1/4C5 1/4C5 1/2C4");
+        let uses = |elements: &[Element]| -> Vec<(i32, i32, u16)> {
+            elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some((u.x, u.y, u.id)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let paths = |elements: &[Element]| {
+            elements
+                .iter()
+                .filter(|elem| matches!(elem, Element::Path(_)))
+                .count()
+        };
+        // Drawn an octave lower, with "8" and a dashed line.
+        assert_eq!(uses(&shifted), uses(&written));
+        assert_eq!(paths(&shifted), paths(&written) + 1);
+        let text: Vec<&str> = shifted
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Text(t) => Some(t.text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, ["8"]);
+    }
+
+    #[test]
+    fn niente() {
+        // X positions of the subpaths of the hairpin: centers of circles
+        // (with arcs) and the lines (which start at the narrow end).
+        let hairpin = |notes| {
+            let bar = engrave(&score(&[notes]), Default::default());
+            let d = bar
+                .elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Path(path) if path.d.contains('L') => {
+                        Some(path.d.clone())
+                    }
+                    _ => None,
+                })
+                .next()
+                .unwrap();
+            let x = |sub: &str| -> i32 {
+                sub[..sub.find(' ').unwrap()].parse().unwrap()
+            };
+            let (circles, lines): (Vec<&str>, Vec<&str>) =
+                d.split('M').skip(1).partition(|sub| sub.contains('a'));
+            // Circles start on their left side, a radius from the center.
+            let center = |sub: &str| -> i32 {
+                let r = &sub[sub.find('a').unwrap() + 1..];
+                x(sub) + r[..r.find(' ').unwrap()].parse::<i32>().unwrap()
+            };
+            let circles: Vec<i32> = circles.into_iter().map(center).collect();
+            let lines: Vec<i32> = lines.into_iter().map(x).collect();
+            (bar, circles, lines)
+        };
+
+        // Dal niente: the circle is at the start, before the lines.
+        let (bar, circles, lines) = hairpin("n < 1/4C4 1/4C4 1/4C4 1/4C4 f");
+        assert_eq!(circles.len(), 2);
+        assert_eq!(lines.len(), 2);
+        assert!(circles.iter().all(|c| lines.iter().all(|l| c < l)));
+        let uses: Vec<u16> = bar
+            .elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Use(u) => Some(u.id),
+                _ => None,
+            })
+            .collect();
+        assert!(uses.contains(&Glyph::N.into()));
+        assert!(uses.contains(&Glyph::F.into()));
+
+        // Al niente: the lines narrow to the circle at the end.
+        let (_bar, circles, lines) = hairpin("f > 1/2C4 1/2C4 n");
+        assert_eq!(circles.len(), 2);
+        assert!(circles.iter().all(|c| lines.iter().all(|l| c > l)));
+
+        // Hairpins between other dynamics don't have circles.
+        let (_bar, circles, lines) = hairpin("p < 1/2C4 1/2C4 f");
+        assert!(circles.is_empty());
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn rest_lines() {
+        let rest_y = |notes, glyph: Glyph| {
+            let bar = engrave(&score(&[notes]), Default::default());
+            let top = bar.offset_y(bar.stave.line_steps(0));
+            bar.elements
+                .iter()
+                .find_map(|elem| match elem {
+                    Element::Use(u) if u.id == glyph as u16 => Some(u.y - top),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        // Whole rest hangs from line 2, half rest sits on line 3.
+        assert_eq!(rest_y("1/1R", Glyph::Rest1), Stave::SPACE);
+        assert_eq!(rest_y("", Glyph::Rest1), Stave::SPACE);
+        assert_eq!(rest_y("1/2R 1/2C4", Glyph::Rest2), 2 * Stave::SPACE);
+    }
+
+    #[test]
+    fn breve_rest() {
+        let mut scof = Scof::new(1, Fraction::new(2, 1), 0);
+        scof.movement[0].bar[0].chan[0] = "2/1R".parse().unwrap();
+        // A final barline's thick line is as wide as the block.
+        scof.movement[0].bar[0].barline = Some(Barline::Single);
+        let bar = engrave(&scof, Default::default());
+        let top = bar.offset_y(bar.stave.line_steps(0));
+        let blocks: Vec<(i32, i32)> = bar
+            .elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Rect(r) if r.width == LONG_REST_WIDTH => {
+                    Some((r.y - top, r.height))
+                }
+                _ => None,
+            })
+            .collect();
+        // A block between lines 2 and 3, rather than whole rests.
+        assert_eq!(blocks, [(Stave::SPACE, Stave::SPACE)]);
+        assert!(!bar.elements.iter().any(|elem| {
+            matches!(elem, Element::Use(u) if u.id == Glyph::Rest1 as u16)
+        }));
+    }
+
+    #[test]
+    fn key_signature_clefs() {
+        let (meta, _) = font();
+        // Y of each accidental, from the top line.
+        let accidentals = |clef| -> Vec<(u16, i32)> {
+            let mut scof = score(&["1/1R"]);
+            scof.set_key(&Cursor::default(), 14);
+            scof.set_clef(&Cursor::default(), clef);
+            let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+            let mut bar =
+                BarElem::new(Stave::new(5, Steps(4), Steps(0)), c4, c4);
+            bar.add_key(&meta, &scof, &Cursor::default());
+            let top = bar.offset_y(bar.stave.line_steps(0));
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some((u.id, u.y - top)),
+                    _ => None,
+                })
+                .collect()
+        };
+        // G major: F# on the top line, and the fourth line in bass clef.
+        let sharp = Glyph::Sharp as u16;
+        assert_eq!(accidentals(Clef::Treble), [(sharp, 0)]);
+        assert_eq!(accidentals(Clef::Bass), [(sharp, Stave::SPACE)]);
+    }
+
+    #[test]
+    fn key_signature_staves() {
+        let (meta, _) = font();
+        // Y of each accidental, drawing only some of the staves.
+        let accidentals = |staves| -> Vec<i32> {
+            let mut scof = score(&["1/1R", "1/1R"]);
+            scof.set_key(&Cursor::default(), 14);
+            let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+            let mut bar =
+                BarElem::new(Stave::new(5, Steps(4), Steps(0)), c4, c4);
+            bar.options.staves = staves;
+            bar.add_key(&meta, &scof, &Cursor::default());
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some(u.y),
+                    _ => None,
+                })
+                .collect()
+        };
+        let both = accidentals(None);
+        assert_eq!(both.len(), 2);
+        // The second channel is drawn on the first stave.
+        assert_eq!(accidentals(Some(1..2)), [both[0]]);
+    }
+
+    #[test]
+    fn clef_glyphs() {
+        // Glyph and Y of the clef, from the top line.
+        let clef = |clef| -> Vec<(u16, i32)> {
+            let mut scof = score(&["1/1R"]);
+            scof.set_clef(&Cursor::default(), clef);
+            let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+            let mut bar =
+                BarElem::new(Stave::new(5, Steps(4), Steps(0)), c4, c4);
+            bar.add_clefs(&scof, &Cursor::default());
+            let top = bar.offset_y(bar.stave.line_steps(0));
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some((u.id, u.y - top)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let space = Stave::SPACE;
+        let (g, f, c) = (Glyph::ClefG, Glyph::ClefF, Glyph::ClefC);
+        assert_eq!(clef(Clef::Treble), [(g as u16, space * 3)]);
+        assert_eq!(clef(Clef::Bass), [(f as u16, space)]);
+        assert_eq!(clef(Clef::Alto), [(c as u16, space * 2)]);
+        assert_eq!(clef(Clef::Tenor), [(c as u16, space)]);
+    }
+
+    #[test]
+    fn accidentals() {
+        let uses = |notes| -> Vec<u16> {
+            engrave(&score(&[notes]), Default::default())
+                .elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some(u.id),
+                    _ => None,
+                })
+                .collect()
+        };
+        let natural = uses("1/4C4 3/4R");
+        let sharp = uses("1/4C#4 3/4R");
+        assert_eq!(sharp.len(), natural.len() + 1);
+        let sharps = sharp.iter().filter(|id| **id == Glyph::Sharp as u16);
+        assert_eq!(sharps.count(), 1);
+        // Quarter tones
+        let sharp = uses("1/4Ct#4 3/4R");
+        assert!(sharp.contains(&(Glyph::ThreeQuarterSharp as u16)));
+    }
+
+    #[test]
+    fn triplets() {
+        let scof = score(&["1/12C4 1/12D4 1/12E4 3/4R"]);
+        // Three triplet eighths take up a quarter of the measure's time.
+        let curs = Cursor::default();
+        let notations: Vec<_> =
+            Notator::new(&scof, curs.clone(), curs).take(3).collect();
+        for notation in &notations {
+            assert_eq!(notation.note.duration, Fraction::new(1, 8));
+            assert_eq!(notation.tuplet, Some(3));
+        }
+        let ends: Vec<bool> = notations.iter().map(|n| n.tuplet_end).collect();
+        assert_eq!(ends, [false, false, true]);
+        assert_eq!(notations.iter().map(|n| n.dur).sum::<u16>(), 32);
+
+        let bar = engrave(&scof, Default::default());
+        let count = |glyph: Glyph| {
+            bar.elements
+                .iter()
+                .filter(|elem| {
+                    matches!(elem, Element::Use(u) if u.id == glyph as u16)
+                })
+                .count()
+        };
+        // Beamed eighth noteheads, numbered once.
+        assert_eq!(count(Glyph::NoteheadFill), 3);
+        assert_eq!(count(Glyph::FlagUp8) + count(Glyph::FlagDown8), 0);
+        assert_eq!(count(Glyph::Tuplet3), 1);
+    }
+
+    #[test]
+    fn tuplet_brackets() {
+        let brackets = |notes: &str| {
+            let bar = engrave(&score(&[notes]), Default::default());
+            bar.elements
+                .iter()
+                .filter(|elem| {
+                    matches!(elem, Element::Path(p) if p.d.contains('H'))
+                })
+                .count()
+        };
+        // Beamed triplet eighths only get a number.
+        assert_eq!(brackets("1/12C4 1/12D4 1/12E4 3/4R"), 0);
+        // Triplet quarters, or groups with rests, aren't beamed.
+        assert_eq!(brackets("1/6C4 1/6D4 1/6E4 1/2R"), 1);
+        assert_eq!(brackets("1/12C4 1/12R 1/12E4 3/4R"), 1);
+    }
+
+    /// Get the text of each label in a bar.
+    fn labels(bar: &BarElem) -> Vec<&str> {
+        bar.elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn note_labels() {
+        let scof = score(&["1/4C4 3/4R"]);
+        let solfege = RenderOptions {
+            label: Some(NoteLabel::Solfege),
+            ..Default::default()
+        };
+        let name = RenderOptions {
+            label: Some(NoteLabel::Name),
+            ..Default::default()
+        };
+        assert_eq!(labels(&engrave(&scof, solfege)), ["do"]);
+        assert_eq!(labels(&engrave(&scof, name)), ["C"]);
+        assert!(labels(&engrave(&scof, Default::default())).is_empty());
+
+        // Chords label each head.
+        let scof = score(&["1/1C4E4G4"]);
+        let solfege = RenderOptions {
+            label: Some(NoteLabel::Solfege),
+            ..Default::default()
+        };
+        assert_eq!(labels(&engrave(&scof, solfege)), ["do", "mi", "sol"]);
+    }
+
+    #[test]
+    fn tie_direction() {
+        // Each tie's endpoint and control point Y positions.
+        let ties = |notes| -> Vec<(i32, i32)> {
+            let bar = engrave(&score(&[notes]), Default::default());
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Path(path) if path.d.contains('Q') => {
+                        let nums: Vec<i32> = path.d[1..]
+                            .split(&[' ', 'Q'][..])
+                            .take(4)
+                            .map(|num| num.parse().unwrap())
+                            .collect();
+                        Some((nums[1], nums[3]))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        // Stems up (below the middle line): curves down, away from them.
+        let under = ties("1/2C4~ 1/2C4");
+        assert_eq!(under.len(), 1);
+        assert!(under[0].1 > under[0].0);
+        // Stems down: curves up.
+        let over = ties("1/2C5~ 1/2C5");
+        assert_eq!(over.len(), 1);
+        assert!(over[0].1 < over[0].0);
+        // Not tied to a rest.
+        assert!(ties("1/2C4~ 1/2R").is_empty());
+    }
+}
@@ -16,9 +16,49 @@
 //     You should have received a copy of the GNU General Public License
 //     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::VecDeque;
 use std::convert::TryInto;
 
-use scof::{Cursor, Marking, Pitch, Scof};
+use scof::{
+    Clef, Cursor, Dynamic, Fraction, Lyric, Marking, Note, NoteheadStyle,
+    Pitch, Scof,
+};
+
+/// A note or rest output by the `Notator`.
+pub(super) struct Notation {
+    /// The part of the note notated here, as written (no pitches for a rest).
+    pub(super) note: Note,
+    /// Cursor of the note's marking.
+    pub(super) marking: Cursor,
+    /// Number of parts of the note notated before this one.
+    pub(super) part: u16,
+    /// Duration in 128th notes (0 for a note too short to notate).
+    pub(super) dur: u16,
+    /// Is User's Cursor
+    pub(super) is_cursor: bool,
+    /// Number of tremolo slashes.
+    pub(super) tremolo: u8,
+    /// Is an invisible spacer rather than a rest.
+    pub(super) spacer: bool,
+    /// Octaves the pitches are drawn lower than they sound (8va = 1).
+    pub(super) octave_shift: i8,
+    /// Force a new beam group to start here.
+    pub(super) beam_break: bool,
+    /// Draw a beat repeat slash here.
+    pub(super) beat_repeat: bool,
+    /// Lyric sung starting here.
+    pub(super) lyric: Option<Lyric>,
+    /// Dynamic starting here.
+    pub(super) dynamic: Option<Dynamic>,
+    /// Hairpin starting here (`true` for crescendo, `false` for diminuendo).
+    pub(super) hairpin: Option<bool>,
+    /// Breath mark or caesura right before this.
+    pub(super) pause: Option<Marking>,
+    /// Tuplet number (3 for triplets) if part of a tuplet.
+    pub(super) tuplet: Option<u8>,
+    /// Is the last note of a tuplet group.
+    pub(super) tuplet_end: bool,
+}
 
 /// An iterator over durations of notes in a measure.  Should only output
 /// correct notation.  (Turns 3/8 into dotted 1/4 or 1/4 tied to 1/8 depending
@@ -32,8 +72,37 @@ pub(super) struct Notator<'a> {
     check: u16,
     //
     scof: &'a Scof,
-    //
-    pitch: Vec<Pitch>,
+    // Current note, as written
+    note: Note,
+    // Cursor of current note
+    marking: Cursor,
+    // Parts of current note output so far
+    part: u16,
+    // Tremolo slashes of current note
+    tremolo: u8,
+    // Current note is a spacer
+    spacer: bool,
+    // Octave shift in effect
+    octave_shift: i8,
+    // Clef in effect
+    clef: Clef,
+    // Current note starts a new beam group (until its first part is output)
+    beam_break: bool,
+    // Current marking is a beat repeat (until its first part is output)
+    beat_repeat: bool,
+    // Lyrics for the rest of the notes in the bar
+    lyrics: VecDeque<Lyric>,
+    // Lyric of current note (until its first part is output)
+    lyric: Option<Lyric>,
+    // Dynamic before the current note (until its first part is output)
+    dynamic: Option<Dynamic>,
+    // Hairpin before the current note (until its first part is output)
+    hairpin: Option<bool>,
+    // Breath mark or caesura before the current note (until its first part
+    // is output)
+    pause: Option<Marking>,
+    // Start of the next marking in the measure (as a whole note fraction)
+    offset: Fraction,
     // User's cursor
     cursor: Cursor,
     // Is User's Cursor
@@ -44,11 +113,25 @@ impl<'a> Notator<'a> {
     /// Create a new `Notator`
     pub(super) fn new(scof: &'a Scof, cursor: Cursor, curs: Cursor) -> Self {
         Notator {
+            octave_shift: scof.octave_shift(&curs),
+            clef: scof.clef(&curs),
+            lyrics: scof.lyrics(&curs).into(),
+            marking: curs.clone(),
             curs,
             dur: 0,
             check: 128,
             scof,
-            pitch: vec![],
+            note: rest(Fraction::new(0, 1)),
+            part: 0,
+            tremolo: 0,
+            spacer: false,
+            beam_break: false,
+            beat_repeat: false,
+            lyric: None,
+            dynamic: None,
+            hairpin: None,
+            pause: None,
+            offset: Fraction::new(0, 1),
             cursor,
             ic: false,
         }
@@ -57,35 +140,240 @@ impl<'a> Notator<'a> {
     pub(super) fn is_cursor(&self) -> bool {
         self.curs == self.cursor
     }
+
+    /// Get the clef the measure is drawn with.
+    pub(super) fn clef(&self) -> Clef {
+        self.clef
+    }
+
+    /// Get the measure repeat count if the measure is a measure repeat.
+    pub(super) fn measure_repeat(&self) -> Option<u8> {
+        self.scof.measure_repeat(&self.curs)
+    }
+
+    /// Get the last dynamic after the notes output so far, which is drawn at
+    /// the end of the bar if there are no more notes.
+    pub(super) fn end_dynamic(&self) -> Option<Dynamic> {
+        let mut curs = self.curs.clone();
+        let mut dynamic = self.dynamic.clone();
+        while let Some(marking) = self.scof.marking(&curs) {
+            if let Marking::Dynamic(end) = marking {
+                dynamic = Some(end.clone());
+            }
+            curs.right_unchecked();
+        }
+        dynamic
+    }
+
+    /// Get the breath mark or caesura after the notes output so far, which
+    /// is drawn at the end of the bar if there are no more notes.
+    pub(super) fn end_pause(&self) -> Option<Marking> {
+        let mut curs = self.curs.clone();
+        let mut pause = self.pause.clone();
+        while let Some(marking) = self.scof.marking(&curs) {
+            if is_pause(marking) {
+                pause = Some(marking.clone());
+            }
+            curs.right_unchecked();
+        }
+        pause
+    }
+}
+
+impl Notator<'_> {
+    /// Output the next part of the current note.
+    fn notation(&mut self, note: Note, dur: u16) -> Notation {
+        self.part += 1;
+        Notation {
+            note,
+            marking: self.marking.clone(),
+            part: self.part - 1,
+            dur,
+            is_cursor: self.ic,
+            tremolo: self.tremolo,
+            spacer: self.spacer,
+            octave_shift: self.octave_shift,
+            beam_break: std::mem::take(&mut self.beam_break),
+            beat_repeat: std::mem::take(&mut self.beat_repeat),
+            lyric: self.lyric.take(),
+            dynamic: self.dynamic.take(),
+            hairpin: self.hairpin.take(),
+            pause: self.pause.take(),
+            tuplet: None,
+            tuplet_end: false,
+        }
+    }
 }
 
 impl<'a> Iterator for Notator<'a> {
-    type Item = (Vec<Pitch>, u16, bool);
+    type Item = Notation;
 
     fn next(&mut self) -> Option<Self::Item> {
         // If duration is not 0, find next note to add.
         while self.dur != 0 {
             if self.dur >= self.check {
                 self.dur -= self.check;
-                return Some((self.pitch.clone(), self.check, self.ic));
+                let note = Note {
+                    duration: Fraction::new(self.check, 128).simplify(),
+                    // Parts of a note split across beats are tied together.
+                    tie: self.note.tie || self.dur != 0,
+                    ..self.note.clone()
+                };
+                return Some(self.notation(note, self.check));
             }
             self.check /= 2;
         }
         // Get next note/rest, return None if done.
-        match self.scof.marking(&self.curs)? {
-            Marking::Note(note) => {
+        let marking = self.scof.marking(&self.curs)?;
+        match marking {
+            Marking::Note(_) | Marking::Spacer(_) | Marking::BeatRepeat(_) => {
+                let duration = marking.duration().unwrap();
                 self.ic = self.curs == self.cursor;
+                self.marking = self.curs.clone();
+                self.part = 0;
                 self.check = 128;
-                // FIXME: Tuplets (test for not divisible by 128)
-                self.dur = ((note.duration.num as u32 * 128)
-                    / note.duration.den as u32)
+                let start = self.offset;
+                self.offset =
+                    self.offset.checked_add(duration).unwrap_or(self.offset);
+                self.dur = ((duration.num as u32 * 128) / duration.den as u32)
                     .try_into()
                     .unwrap();
-                self.pitch = note.pitch.clone();
+                if let Marking::Note(note) = marking {
+                    self.note = Note {
+                        pitch: note
+                            .pitch
+                            .iter()
+                            .map(|pitch| written(*pitch, self.octave_shift))
+                            .collect(),
+                        ..note.clone()
+                    };
+                    self.tremolo = note.tremolo;
+                    self.spacer = false;
+                    self.beam_break = note.beam_break;
+                    self.beat_repeat = false;
+                    // Each note (not rest) is sung to the next lyric.
+                    self.lyric = if note.pitch.is_empty() {
+                        None
+                    } else {
+                        self.lyrics.pop_front()
+                    };
+                } else {
+                    // Beat repeats are spacers, with a slash at the start.
+                    self.note = rest(duration);
+                    self.tremolo = 0;
+                    self.spacer = true;
+                    self.beam_break = false;
+                    self.beat_repeat =
+                        matches!(marking, Marking::BeatRepeat(_));
+                    self.lyric = None;
+                }
+                // Breve and longa rests aren't split into whole rests.
+                if self.note.pitch.is_empty() {
+                    self.check = 512;
+                }
+                if let Some((tuplet, written)) = tuplet(duration) {
+                    // Takes up its share of the measure's 128th notes, but
+                    // is written as the note it's a tuplet of.
+                    self.curs.right_unchecked();
+                    self.dur = 0;
+                    let note = Note {
+                        duration: written,
+                        ..self.note.clone()
+                    };
+                    let dur = grid(self.offset) - grid(start);
+                    let mut notation = self.notation(note, dur);
+                    notation.tuplet = Some(tuplet);
+                    notation.tuplet_end = on_grid(self.offset);
+                    return Some(notation);
+                }
+                if self.dur == 0 {
+                    // Shorter than a 128th note, so output as is.
+                    self.curs.right_unchecked();
+                    let note = self.note.clone();
+                    return Some(self.notation(note, 0));
+                }
             }
+            Marking::OctaveShift(octaves) => self.octave_shift = *octaves,
+            Marking::Dynamic(dynamic) => self.dynamic = Some(dynamic.clone()),
+            Marking::Cresc => self.hairpin = Some(true),
+            Marking::Dim => self.hairpin = Some(false),
+            marking if is_pause(marking) => self.pause = Some(marking.clone()),
+            // Measure repeats are drawn instead of notes.
+            Marking::MeasureRepeat(_) => return None,
+            // FIXME: Draw the rest of the attached markings with their note.
+            marking if marking.is_attached() => {}
             _ => unreachable!(),
         };
         self.curs.right_unchecked();
         <Self as Iterator>::next(self)
     }
 }
+
+/// Check if a marking is a breath mark or caesura.
+fn is_pause(marking: &Marking) -> bool {
+    matches!(
+        marking,
+        Marking::Breath | Marking::CaesuraShort | Marking::CaesuraLong
+    )
+}
+
+/// Get the tuplet number (3 for triplets) and written duration of a note
+/// that isn't a whole number of 128th notes, if it can be written.
+fn tuplet(duration: Fraction) -> Option<(u8, Fraction)> {
+    let duration = duration.simplify();
+    // Odd factor of the denominator is the number of notes in the tuplet.
+    let odd = duration
+        .den
+        .checked_shr(duration.den.trailing_zeros())
+        .unwrap_or(1);
+    if odd == 1 {
+        return None;
+    }
+    // Fit in the time of the next smaller power of two.
+    let normal = 1 << (15 - odd.leading_zeros());
+    let written = duration * Fraction::new(odd, normal);
+    if !on_grid(written) {
+        return None;
+    }
+    Some((odd.try_into().ok()?, written))
+}
+
+/// Get the number of whole 128th notes in a fraction of a whole note.
+fn grid(offset: Fraction) -> u16 {
+    (u32::from(offset.num) * 128 / u32::from(offset.den.max(1)))
+        .try_into()
+        .unwrap_or(u16::MAX)
+}
+
+/// Check if a fraction of a whole note is a whole number of 128th notes.
+fn on_grid(offset: Fraction) -> bool {
+    (u32::from(offset.num) * 128) % u32::from(offset.den.max(1)) == 0
+}
+
+/// Get a rest with a specific duration.
+fn rest(duration: Fraction) -> Note {
+    Note {
+        pitch: vec![],
+        duration,
+        articulation: vec![],
+        tremolo: 0,
+        tie: false,
+        beam_break: false,
+        feather: None,
+        head: NoteheadStyle::Normal,
+    }
+}
+
+/// Get the pitch a note is written at under an octave shift.
+fn written(pitch: Pitch, octave_shift: i8) -> Pitch {
+    let Pitch(class, mut octave) = pitch;
+    for _ in 0..octave_shift.abs() {
+        let shifted = if octave_shift > 0 {
+            octave.lower()
+        } else {
+            octave.raise()
+        };
+        octave = shifted.unwrap_or(octave);
+    }
+    Pitch(class, octave)
+}
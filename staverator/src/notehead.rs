@@ -16,15 +16,18 @@
 //     You should have received a copy of the GNU General Public License
 //     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use sfff::{Glyph, SfFontMetadata};
-
-/// Different styles of noteheads
-pub enum Notehead {
-    Normal,
-    X,
-    Diamond,
-    Triangle,
-    Slash,
+use scof::NoteheadStyle;
+use sfff::{Notehead, SfFontMetadata};
+
+/// Get the notehead for a note's notehead style.
+pub(super) fn from_style(style: NoteheadStyle) -> Notehead {
+    match style {
+        NoteheadStyle::Normal => Notehead::Normal,
+        NoteheadStyle::X => Notehead::X,
+        NoteheadStyle::Diamond => Notehead::Diamond,
+        NoteheadStyle::Triangle => Notehead::Triangle,
+        NoteheadStyle::Slash => Notehead::Slash,
+    }
 }
 
 /// Get width of the notehead.
@@ -43,102 +46,5 @@ pub(super) fn stems(
     meta: &SfFontMetadata,
     duration: u16,
 ) -> [[i32; 2]; 2] {
-    use Notehead::*;
-
-    let double;
-    let whole;
-    let half;
-    let fill;
-    match notehead {
-        Normal => {
-            double = meta.notehead_double;
-            whole = meta.notehead_whole;
-            half = meta.notehead_half;
-            fill = meta.notehead;
-        }
-        X => {
-            double = meta.notehead_double_x;
-            whole = meta.notehead_whole_x;
-            half = meta.notehead_half_x;
-            fill = meta.notehead_x;
-        }
-        Diamond => {
-            double = meta.notehead_double_diamond;
-            whole = meta.notehead_whole_diamond;
-            half = meta.notehead_half_diamond;
-            fill = meta.notehead_diamond;
-        }
-        Triangle => {
-            double = meta.notehead_double_triangle;
-            whole = meta.notehead_whole_triangle;
-            half = meta.notehead_half_triangle;
-            fill = meta.notehead_triangle;
-        }
-        Slash => {
-            double = meta.notehead_double_slash;
-            whole = meta.notehead_whole_slash;
-            half = meta.notehead_half_slash;
-            fill = meta.notehead_slash;
-        }
-    }
-
-    variants(double, whole, half, fill, duration)
-}
-
-/// Get the notehead glyph for a note with a specific duration
-pub(super) fn duration(duration: u16) -> Glyph {
-    use Glyph::*;
-    variants(
-        NoteheadDouble,
-        NoteheadWhole,
-        NoteheadHalf,
-        NoteheadFill,
-        duration,
-    )
-}
-
-/// Get the notehead glyph for a note with a specific duration
-pub(super) fn x_duration(duration: u16) -> Glyph {
-    use Glyph::*;
-    variants(
-        NoteheadDoubleX,
-        NoteheadWholeX,
-        NoteheadHalfX,
-        NoteheadFillX,
-        duration,
-    )
-}
-
-/// Get the square notehead glyph for a note with a specific duration
-pub(super) fn slashed_duration(duration: u16) -> Glyph {
-    use Glyph::*;
-    variants(
-        NoteheadDoubleSlashed,
-        NoteheadWholeSlashed,
-        NoteheadHalfSlashed,
-        NoteheadFillSlashed,
-        duration,
-    )
-}
-
-/// Get the large square notehead glyph for a note with a specific duration
-pub(super) fn slash_duration(duration: u16) -> Glyph {
-    use Glyph::*;
-    variants(
-        NoteheadDoubleSlash,
-        NoteheadWholeSlash,
-        NoteheadHalfSlash,
-        NoteheadFillSlash,
-        duration,
-    )
-}
-
-/// Given a duration and set of notehead glyphs, choose appropriate glyph
-fn variants<T>(double: T, whole: T, half: T, fill: T, duration: u16) -> T {
-    match duration {
-        1..=63 => fill,
-        64..=127 => half,
-        128..=255 => whole,
-        _ => double,
-    }
+    meta.stem_anchors(notehead, duration)
 }
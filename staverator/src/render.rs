@@ -0,0 +1,478 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/* ************************************************************************** */
+
+//! Headless rendering of a whole score into a standalone SVG document.
+
+use crate::{
+    BarElem, Element, GlyphResolver, Group, NoteLabel, Path, Rect,
+    RenderWarnings, Stave, STAVE_SPACE,
+};
+use scof::{Cursor, Pitch, Scof, Steps};
+use sfff::{Glyph, SfFontMetadata};
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Options for rendering a score.
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// Inline each glyph's path instead of referencing it with `use`.  Some
+    /// consumers (PDF converters, email clients) don't resolve `use`, so this
+    /// produces a self-contained SVG without a `defs` section.
+    pub inline_glyphs: bool,
+    /// Label each notehead with its note name or solfège syllable.
+    pub label: Option<NoteLabel>,
+    /// Draw noteheads without stems, flags or beams (lead sheet style).
+    pub stemless: bool,
+    /// Give each notehead the `id` of its marking,
+    /// `n{movement}-{measure}-{chan}-{marking}`, so front-ends can find the
+    /// marking an element was drawn for.  Further noteheads of the marking
+    /// (in a chord, or tied) add `.{n}`, numbered from 1.
+    pub note_ids: bool,
+    /// Draw the lines of all staves in a system as one path, rather than a
+    /// path for each stave of each bar.  Large scores have many fewer
+    /// elements this way.  Only whole scores are drawn with stave lines then
+    /// (a bar engraved on its own leaves them out).
+    pub combine_staves: bool,
+    /// Choose notehead, rest and flag glyphs with a custom resolver instead
+    /// of the `DefaultGlyphResolver`.
+    pub glyph_resolver: Option<Rc<dyn GlyphResolver>>,
+    /// Only draw the staves of channels in this range (for a fragment of a
+    /// large score, like one instrument group), moved to the top.  Spacing
+    /// only depends on the staves drawn.
+    pub staves: Option<Range<u16>>,
+    /// Draw a translucent box over each glyph's advance width, for debugging
+    /// fonts and layout.
+    pub debug_boxes: bool,
+    /// Widen the space after notes so it's at least the advance width of
+    /// the widest notehead or rest at their offset, plus this gap (in font
+    /// units).  Dense bars are spaced proportionally with `None`, which may
+    /// overlap glyphs.
+    pub min_spacing: Option<i32>,
+}
+
+/// Render the first movement of a score as an SVG document, with the
+/// warnings from engraving each measure.
+///
+/// - `meta`: The font metadata.
+/// - `glyphs`: The font's `\0`-separated glyph paths.
+pub fn render_score_svg(
+    scof: &Scof,
+    meta: &SfFontMetadata,
+    glyphs: &str,
+    options: &RenderOptions,
+) -> (String, RenderWarnings) {
+    let paths: Vec<&str> = glyphs.split('\0').collect();
+    let cursor = Cursor::default();
+    let mut warnings = RenderWarnings::default();
+
+    let mut page = Group::new(0, 0, None);
+    let mut groups = vec![];
+    let mut offset_x = STAVE_SPACE; // Stave Margin
+    let mut height = 0;
+    // Top line and number of staves of the system.
+    let stave = Stave::new(5, Steps(4), Steps(0));
+    let (mut top, mut staves) = (0, 0);
+    let bars = scof.movement.first().map(|m| m.bar.len()).unwrap_or(0);
+    for measure in 0..bars as u16 {
+        let high = "C4".parse::<Pitch>().unwrap().visual_distance();
+        let low = "C4".parse::<Pitch>().unwrap().visual_distance();
+        let mut curs = Cursor::new(0, measure, 0, 0);
+        let mut bar = BarElem::new(stave.clone(), high, low);
+        bar.options = options.clone();
+        bar.system_start = measure == 0;
+        bar.add_markings(meta, scof, &cursor, &mut curs);
+        warnings.extend(bar.warnings.iter().cloned());
+
+        let mut group = Group::new(offset_x, 0, None);
+        let mut elements: Vec<Element> = bar.elements.drain(..).collect();
+        if options.debug_boxes {
+            elements = debug_boxes(elements, meta);
+        }
+        for elem in elements {
+            if options.inline_glyphs {
+                group.push(inline(elem, &paths));
+            } else {
+                group.push(elem);
+            }
+        }
+        groups.push(Element::Group(group));
+        offset_x += bar.width;
+        top = bar.offset_y(bar.stave.steps_middle_c);
+        staves = staves.max(bar.staves(scof, &curs).len() as i32);
+        // Fit the view box to the staves drawn.
+        let ymargin = (bar.stave.height_steps() + Steps(12)).0 * Stave::STEP;
+        height = height.max(bar.height() + ymargin * (staves.max(1) - 1));
+    }
+    // Stave lines across the whole system, under the bars.
+    if options.combine_staves && staves > 0 {
+        let ymargin = stave.height_steps() + Steps(12);
+        let width = offset_x - STAVE_SPACE;
+        let mut d = String::new();
+        for i in 0..staves {
+            let path = stave.path(meta, STAVE_SPACE, top, width, ymargin * i);
+            d.push_str(&path.d);
+        }
+        page.push(Element::Path(Path::new(None, d)));
+    }
+    for group in groups {
+        page.push(group);
+    }
+
+    let mut svg = format!(
+        "<svg xmlns='http://www.w3.org/2000/svg' \
+         xmlns:xlink='http://www.w3.org/1999/xlink' viewBox='0 0 {} {}'>",
+        offset_x + STAVE_SPACE,
+        height
+    );
+    if !options.inline_glyphs {
+        svg.push_str(&sfff::generate_defs(glyphs));
+    }
+    svg.push_str(&page.to_string());
+    svg.push_str("</svg>");
+    (svg, warnings)
+}
+
+/// Render the first movement of a score as a PNG image, `scale` pixels per
+/// font unit, with the warnings from engraving it.  The image is an empty
+/// `Vec` if it would be empty.
+///
+/// - `meta`: The font metadata.
+/// - `glyphs`: The font's `\0`-separated glyph paths.
+#[cfg(all(feature = "png", not(target_arch = "wasm32")))]
+pub fn render_png(
+    scof: &Scof,
+    meta: &SfFontMetadata,
+    glyphs: &str,
+    scale: f32,
+) -> (Vec<u8>, RenderWarnings) {
+    use resvg::{tiny_skia, usvg};
+
+    // Inline the glyphs, so the rasterizer doesn't need to resolve `use`.
+    let options = RenderOptions {
+        inline_glyphs: true,
+        ..Default::default()
+    };
+    let (svg, warnings) = render_score_svg(scof, meta, glyphs, &options);
+    let tree = match usvg::Tree::from_str(&svg, &usvg::Options::default()) {
+        Ok(tree) => tree,
+        Err(_) => return (vec![], warnings),
+    };
+    let size = match tree.size().to_int_size().scale_by(scale) {
+        Some(size) => size,
+        None => return (vec![], warnings),
+    };
+    let mut pixmap = match tiny_skia::Pixmap::new(size.width(), size.height()) {
+        Some(pixmap) => pixmap,
+        None => return (vec![], warnings),
+    };
+    let transform = tiny_skia::Transform::from_scale(scale, scale);
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    (pixmap.encode_png().unwrap_or_default(), warnings)
+}
+
+/// Add a translucent box after each `use` element covering its glyph's
+/// advance width.  Fonts don't have bounding boxes yet, so boxes are a stave
+/// space tall, centered on the glyph's origin.
+fn debug_boxes(elements: Vec<Element>, meta: &SfFontMetadata) -> Vec<Element> {
+    let mut boxed = vec![];
+    for elem in elements {
+        match elem {
+            Element::Use(u) => {
+                let width = Glyph::ALL
+                    .get(u.id as usize)
+                    .map_or(0, |glyph| meta.advance(*glyph));
+                let mut rect = Rect::new(
+                    u.x,
+                    u.y - STAVE_SPACE / 2,
+                    width,
+                    STAVE_SPACE,
+                    None,
+                    None,
+                    None,
+                );
+                rect.fill = Some("#f004".to_string());
+                boxed.push(Element::Use(u));
+                boxed.push(Element::Rect(rect));
+            }
+            Element::Group(g) => {
+                let mut group = Group::new(g.x, g.y, g.render_order);
+                for elem in debug_boxes(g.elements, meta) {
+                    group.push(elem);
+                }
+                boxed.push(Element::Group(group));
+            }
+            elem => boxed.push(elem),
+        }
+    }
+    boxed
+}
+
+/// Replace `use` elements with a translated copy of the glyph's path.
+fn inline(elem: Element, paths: &[&str]) -> Element {
+    match elem {
+        Element::Use(u) => {
+            let d = paths.get(u.id as usize).copied().unwrap_or("");
+            let mut group = Group::new(u.x, u.y, None);
+            group.push(Element::Path(Path::new(None, d)));
+            Element::Group(group)
+        }
+        Element::Group(g) => {
+            let mut group = Group::new(g.x, g.y, g.render_order);
+            for elem in g.elements {
+                group.push(inline(elem, paths));
+            }
+            Element::Group(group)
+        }
+        elem => elem,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RenderWarning;
+
+    #[test]
+    fn inline_glyphs() {
+        let (meta, glyphs) = crate::tests::font();
+        let scof = Scof::default();
+
+        let (svg, _) =
+            render_score_svg(&scof, &meta, &glyphs, &Default::default());
+        let options = RenderOptions {
+            inline_glyphs: true,
+            ..Default::default()
+        };
+        let (inlined, _) = render_score_svg(&scof, &meta, &glyphs, &options);
+
+        let body = &svg[svg.find("</defs>").unwrap()..];
+        let uses = body.matches("<use").count();
+        assert!(uses > 0);
+        assert!(!inlined.contains("<use"));
+        assert!(!inlined.contains("<defs>"));
+        assert_eq!(
+            inlined.matches("<path").count(),
+            uses + body.matches("<path").count()
+        );
+    }
+
+    #[test]
+    fn debug_box_per_glyph() {
+        let (meta, glyphs) = crate::tests::font();
+        let scof = crate::tests::score(&["1/4C4 1/4D4 1/2E4"]);
+        let (svg, _) =
+            render_score_svg(&scof, &meta, &glyphs, &Default::default());
+        let options = RenderOptions {
+            debug_boxes: true,
+            ..Default::default()
+        };
+        let (boxed, _) = render_score_svg(&scof, &meta, &glyphs, &options);
+
+        let uses = svg.matches("<use").count();
+        assert!(uses >= 3);
+        assert_eq!(boxed.matches("<use").count(), uses);
+        assert_eq!(
+            boxed.matches("<rect").count(),
+            svg.matches("<rect").count() + uses
+        );
+        assert_eq!(boxed.matches("fill='#f004'").count(), uses);
+    }
+
+    #[test]
+    fn stave_space() {
+        let (meta, glyphs) = crate::tests::font();
+        let (svg, _) = render_score_svg(
+            &Scof::default(),
+            &meta,
+            &glyphs,
+            &Default::default(),
+        );
+        let body = &svg[svg.find("</defs>").unwrap()..];
+        // The stave is the path drawn with horizontal lines.
+        let stave = body
+            .split("<path d='")
+            .skip(1)
+            .map(|path| &path[..path.find('\'').unwrap()])
+            .find(|d| d.contains('h'))
+            .unwrap();
+        let lines: Vec<i32> = stave
+            .split('M')
+            .skip(1)
+            .map(|line| line.split([' ', 'h']).nth(1).unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(lines.len(), 5);
+        // SVG units are font units, so lines are one font stave space apart.
+        assert!(lines
+            .windows(2)
+            .all(|pair| pair[1] - pair[0] == STAVE_SPACE));
+        assert_eq!(Stave::SPACE, STAVE_SPACE);
+    }
+
+    #[test]
+    fn combine_staves() {
+        let (meta, glyphs) = crate::tests::font();
+        let mut scof = crate::tests::score(&["1/1C4"; 5]);
+        let second = crate::tests::score(&["1/1D4"; 5]).movement.remove(0);
+        scof.movement[0].bar.extend(second.bar);
+        // Subpaths and line length of each path of stave lines (drawn
+        // right, down, left and up), and the view box width.
+        let staves = |combine_staves| -> (Vec<(usize, i32)>, i32) {
+            let options = RenderOptions {
+                combine_staves,
+                ..Default::default()
+            };
+            let (svg, _) = render_score_svg(&scof, &meta, &glyphs, &options);
+            let staves = svg
+                .split("<path d='")
+                .skip(1)
+                .map(|path| &path[..path.find('\'').unwrap()])
+                .filter(|d| d.contains("h-"))
+                .map(|d| {
+                    let length = d.split(['h', 'v']).nth(1).unwrap();
+                    (d.matches('M').count(), length.parse().unwrap())
+                })
+                .collect();
+            let view_box = svg.split("viewBox='").nth(1).unwrap();
+            let width = view_box.split(' ').nth(2).unwrap().parse().unwrap();
+            (staves, width)
+        };
+        // A path for each stave of each bar, or one for the whole system.
+        let (separate, width) = staves(false);
+        assert_eq!(separate.len(), 10);
+        assert!(separate.iter().all(|stave| stave.0 == 5));
+        let (combined, combined_width) = staves(true);
+        assert_eq!(combined_width, width);
+        // Lines span the bars, between the margins.
+        assert_eq!(combined, [(25, width - 2 * STAVE_SPACE)]);
+    }
+
+    #[test]
+    fn note_ids() {
+        let (meta, glyphs) = crate::tests::font();
+        // A chord, and a note split into a quarter tied to an eighth.
+        let scof = crate::tests::score(&["1/4C4 1/4D4F4 3/8E4 1/8R"]);
+        let options = RenderOptions {
+            note_ids: true,
+            ..Default::default()
+        };
+        let (svg, _) = render_score_svg(&scof, &meta, &glyphs, &options);
+        let ids: Vec<&str> = svg
+            .split("<use id='")
+            .skip(1)
+            .map(|elem| &elem[..elem.find('\'').unwrap()])
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "n0-0-0-0",
+                "n0-0-0-1",
+                "n0-0-0-1.1",
+                "n0-0-0-2",
+                "n0-0-0-2.1"
+            ]
+        );
+        // The ID is on the notehead.
+        let first = svg.split("<use id='n0-0-0-0'").nth(1).unwrap();
+        let href = format!("xlink:href='#{:x}'/>", Glyph::NoteheadFill as u16);
+        assert!(first[..first.find('>').unwrap() + 1].ends_with(&href));
+
+        let (svg, _) =
+            render_score_svg(&scof, &meta, &glyphs, &Default::default());
+        assert!(!svg.contains("<use id="));
+    }
+
+    #[test]
+    fn clip_staves() {
+        let (meta, glyphs) = crate::tests::font();
+        let scof = crate::tests::score(&["1/1C4", "1/1D4", "1/1E4", "1/1F4"]);
+        let render = |staves| {
+            let options = RenderOptions {
+                note_ids: true,
+                staves,
+                ..Default::default()
+            };
+            render_score_svg(&scof, &meta, &glyphs, &options).0
+        };
+        // Channels of the noteheads, the stave lines, and the view box
+        // height.
+        let parts = |svg: &str| {
+            let chans: Vec<String> = svg
+                .split("<use id='n0-0-")
+                .skip(1)
+                .map(|elem| elem[..1].to_string())
+                .collect();
+            let lines = svg
+                .split("<path d='")
+                .skip(1)
+                .filter(|path| path[..path.find('\'').unwrap()].contains('h'))
+                .map(|path| path.matches('M').count())
+                .sum::<usize>();
+            let view_box = svg.split("viewBox='").nth(1).unwrap();
+            let view_box = &view_box[..view_box.find('\'').unwrap()];
+            let height: i32 =
+                view_box.split(' ').nth(3).unwrap().parse().unwrap();
+            (chans, lines, height)
+        };
+        let (chans, lines, height) = parts(&render(None));
+        assert_eq!(chans, ["0", "1", "2", "3"]);
+        assert_eq!(lines, 4 * 5);
+        let (chans, lines, clipped) = parts(&render(Some(1..3)));
+        assert_eq!(chans, ["1", "2"]);
+        assert_eq!(lines, 2 * 5);
+        assert!(clipped < height);
+    }
+
+    #[test]
+    fn warnings() {
+        let (meta, glyphs) = crate::tests::font();
+        let scof = crate::tests::score(&["1/4C4 1/256D4 3/4E4"]);
+        let (_, warnings) =
+            render_score_svg(&scof, &meta, &glyphs, &Default::default());
+        let warnings: Vec<&RenderWarning> = warnings.iter().collect();
+        assert_eq!(
+            warnings,
+            [&RenderWarning::TooShort(
+                Cursor::new(0, 0, 0, 1),
+                scof::Fraction::new(1, 256)
+            )]
+        );
+        let (_, warnings) = render_score_svg(
+            &Scof::default(),
+            &meta,
+            &glyphs,
+            &Default::default(),
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[cfg(all(feature = "png", not(target_arch = "wasm32")))]
+    #[test]
+    fn png() {
+        let (meta, glyphs) = crate::tests::font();
+        let (png, _) = render_png(&Scof::default(), &meta, &glyphs, 0.1);
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        // Width and height from the IHDR chunk.
+        let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+        let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+        assert!(height > 0);
+        assert!(width > height);
+    }
+}
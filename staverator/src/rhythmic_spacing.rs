@@ -21,12 +21,23 @@
 //! Render a bar for all parts.  This not only handles space between notes, but
 //! also calculates the required width of the bar.
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::convert::TryInto;
 
-use crate::{BarElem, Beams, Element, Notator, Stave, BAR_WIDTH};
-use scof::Steps;
-use sfff::SfFontMetadata;
+use crate::notator::Notation;
+use crate::stem::tie_over;
+use crate::{
+    accidental_width, BarElem, Beams, DurationClass, Element, GlyphRole, Head,
+    Notator, RenderWarning, Stave, ACCIDENTAL_GAP, BAR_WIDTH, HAIRPIN_GAP,
+    LONG_REST_WIDTH,
+};
+use scof::{Cursor, Dynamic, Lyric, Marking, Pitch, Scof, Steps};
+use sfff::{Glyph, SfFontMetadata};
+
+/// Spacing of a longa, the longest duration with its own spacing.
+const LONGA_SPACING: f32 = 10.0;
+/// Notehead drawn for a note or rest too short to notate.
+const PLACEHOLDER: Glyph = Glyph::NoteheadFillX;
 
 /// Engraver for a single bar of music (multiple staves)
 pub struct BarEngraver<'a, 'b, 'c> {
@@ -38,41 +49,140 @@ pub struct BarEngraver<'a, 'b, 'c> {
     bar: &'b mut BarElem,
     // Bar physical width
     width: f32,
+    // Count of time slices of each duration (in 128ths) so far.  The width is
+    // derived from these rather than accumulated, so it doesn't drift.
+    slices: BTreeMap<u16, u16>,
+    // Width added to time slices narrower than the minimum note spacing
+    widened: f32,
+    // Widest notehead or rest at the current offset (in font units)
+    column: i32,
+    // Right edge of the glyphs at the last offset
+    right: f32,
+    // Nothing's drawn at the current offset yet, so it can be moved right
+    room: bool,
     // Remaining 128th notes for all staves
     all: u16,
+    // Length of the measure in 128th notes
+    measure: u16,
     //
     cursor: Option<(f32, usize)>,
     // Keep track of which notes to beam, and which to flag.
     beams: Vec<Beams>,
+    // Key signature (quarter steps above C)
+    key: u8,
+    // Octave shift bracket of each stave (octaves, start, last note)
+    octave_brackets: Vec<Option<(i8, f32, f32)>>,
+    // Last lyric syllable of each stave
+    syllables: Vec<Option<Syllable>>,
+    // Hairpin of each stave
+    hairpins: Vec<Option<Hairpin>>,
+    // Whether the last dynamic of each stave is niente
+    niente: Vec<bool>,
+    // Ties from the last note of each stave
+    ties: Vec<Option<Tie>>,
+    // Tuplet group of each stave (tuplet number, start, beamed so far)
+    tuplets: Vec<Option<(u8, f32, bool)>>,
+    // Pitches tied into the first note of each stave that repeat their
+    // accidentals in parentheses (only at the start of a system)
+    courtesy: Vec<Vec<Pitch>>,
+}
+
+/// Ties from a note to the next one (or the end of the bar).
+struct Tie {
+    // Offset of the tied note
+    start: f32,
+    // Tied pitches, and the Y position of their noteheads
+    pitches: Vec<(Pitch, i32)>,
+    // Stem direction of the tied note
+    stem_up: bool,
+}
+
+/// A hairpin that ends at the next dynamic, or the end of the bar.
+struct Hairpin {
+    // Crescendo (rather than diminuendo)
+    cresc: bool,
+    // Offset of the note it starts on
+    start: f32,
+    // Space after the start (in font units), for a dynamic on the same note
+    shift: i32,
+    // Grows from niente
+    niente: bool,
+}
+
+/// A lyric syllable that may be followed by a hyphen or melisma line.
+struct Syllable {
+    // Text of the syllable
+    text: String,
+    // Offset of the note it's sung on
+    start: f32,
+    // Offset of the last note it's held over
+    end: f32,
+    // Followed by a hyphen
+    hyphen: bool,
 }
 
 impl<'a, 'b, 'c> BarEngraver<'a, 'b, 'c> {
-    /// Create a new bar engraver from .
+    /// Create a new bar engraver for the measure at a cursor.
     pub(super) fn new(
         bar: &'b mut BarElem,
         notators: &'a mut [Notator<'c>],
+        scof: &Scof,
+        curs: &Cursor,
     ) -> Self {
+        let key = scof.sig(curs).map_or(0, |sig| sig.key);
+        let time = scof.bar_length(curs);
+        let measure = scof.measure_128ths(curs);
         // Add each stave
         let mut beams = vec![];
         let mut pq = VecDeque::new();
         for i in 0..notators.len() {
-            // 128 128ths remaining.
-            pq.push_back((128, i));
-            beams.push(Beams::new());
+            // Whole measure remaining.
+            pq.push_back((measure, i));
+            beams.push(Beams::new(bar.stave.middle_steps(), time, measure));
         }
         // Beginning of bar margin
         let width = Stave::SPACE as f32 / BAR_WIDTH as f32;
-        let all = 128;
+        let all = measure;
         let cursor = None;
+        let octave_brackets = vec![None; notators.len()];
+        let syllables = notators.iter().map(|_| None).collect();
+        let hairpins = notators.iter().map(|_| None).collect();
+        let niente = vec![false; notators.len()];
+        let ties = notators.iter().map(|_| None).collect();
+        let tuplets = vec![None; notators.len()];
+        let courtesy = bar
+            .staves(scof, curs)
+            .map(|chan| {
+                if bar.system_start {
+                    tied_in(scof, &curs.chan(chan))
+                } else {
+                    vec![]
+                }
+            })
+            .collect();
 
         Self {
             pq,
             notators,
             bar,
             width,
+            slices: BTreeMap::new(),
+            widened: 0.0,
+            column: 0,
+            right: 0.0,
+            room: true,
             all,
+            measure,
             cursor,
+            octave_brackets,
+            syllables,
+            hairpins,
+            niente,
+            ties,
+            tuplets,
+            courtesy,
             beams,
+            key,
         }
     }
 
@@ -87,22 +197,20 @@ impl<'a, 'b, 'c> BarEngraver<'a, 'b, 'c> {
         self.cursor = None;
         // Empty the priority queue.
         while let Some((mut time, stave_i)) = self.pq.pop_front() {
+            let mut notation = if let Some(a) = self.notators[stave_i].next() {
+                a
+            } else {
+                rests.push((stave_i, self.notators[stave_i].is_cursor()));
+                continue;
+            };
+            let courtesy = std::mem::take(&mut self.courtesy[stave_i]);
+            self.space(meta, time, &notation, &courtesy);
             let (pitches, dur, ic) =
-                if let Some(a) = self.notators[stave_i].next() {
-                    a
-                } else {
-                    rests.push((stave_i, self.notators[stave_i].is_cursor()));
-                    continue;
-                };
-            // Increment width
-            if time < self.all {
-                self.width += get_spacing(self.all - time) / 7.0;
-                self.all = time;
-            }
+                (&notation.note.pitch, notation.dur, notation.is_cursor);
             // Render cursor
             if ic {
                 if self.cursor.is_none() {
-                    if time == 128 {
+                    if time == self.measure {
                         // If first thing, cursor takes up margin.
                         self.cursor = Some((0.0, stave_i));
                     } else {
@@ -124,75 +232,213 @@ impl<'a, 'b, 'c> BarEngraver<'a, 'b, 'c> {
                     ));
                 }
             }
-            // Render pitch or rest.
-            if pitches.is_empty() {
-                // Add rest
-                self.bar.add_rest(
-                    crate::glyph::rest_duration(dur),
+            // Extend, end or start the octave shift bracket.
+            match self.octave_brackets[stave_i] {
+                Some((octaves, _, ref mut end))
+                    if octaves == notation.octave_shift =>
+                {
+                    *end = self.width
+                }
+                _ => {
+                    self.end_octave_bracket(meta, stave_i, ymargin);
+                    if notation.octave_shift != 0 {
+                        self.octave_brackets[stave_i] = Some((
+                            notation.octave_shift,
+                            self.width,
+                            self.width,
+                        ));
+                    }
+                }
+            }
+            if let Some(pause) = notation.pause.take() {
+                self.bar.add_pause(
+                    &pause,
                     self.width,
                     ymargin * stave_i as i32,
                 );
+            }
+            self.add_dynamic(
+                meta,
+                stave_i,
+                notation.dynamic.take(),
+                notation.hairpin.take(),
+                ymargin,
+            );
+            if dur == 0 {
+                self.add_placeholder(&notation, stave_i, ymargin);
+                requeue(&mut self.pq, time, stave_i);
+                continue;
+            }
+            // Render pitch or rest.  Ties only continue to a note.
+            if pitches.is_empty() {
+                self.ties[stave_i] = None;
+            }
+            if notation.beat_repeat {
+                self.bar
+                    .add_beat_repeat(self.width, ymargin * stave_i as i32);
+                self.beams[stave_i].advance(dur, self.width, None, true);
+            } else if notation.spacer {
+                // Takes up space, but isn't drawn (or beamed over).
+                self.beams[stave_i].advance(dur, self.width, None, true);
+            } else if pitches.is_empty() {
+                // Add rest
+                let ofs = ymargin * stave_i as i32;
+                if DurationClass::new(dur) == DurationClass::DoubleWhole {
+                    self.bar.add_long_rest(dur, self.width, ofs);
+                } else {
+                    let glyph = self.bar.glyph(&notation.note, GlyphRole::Rest);
+                    self.bar.add_rest(glyph, self.width, ofs);
+                }
                 // Advance beaming
-                self.beams[stave_i].advance(dur, self.width, None);
+                self.beams[stave_i].advance(dur, self.width, None, false);
             } else {
                 // Offset Y, so that the note appears on the correct stave.
                 let y_offset = ymargin * stave_i as i32;
+                let clef = self.notators[stave_i].clef();
                 // Add chord
-                for pitch in &pitches {
-                    let y = self
-                        .bar
-                        .y_from_steps(pitch.visual_distance(), y_offset);
-
-                    self.bar.add_pitch(
+                for (i, pitch) in pitches.iter().enumerate() {
+                    let steps = self.bar.stave.pitch_steps(*pitch, clef);
+                    let y = self.bar.y_from_steps(steps, y_offset);
+                    let head = Head {
+                        pitch: *pitch,
+                        clef,
+                        y,
+                        id: self.note_id(&notation, i),
+                        courtesy: is_courtesy(&courtesy, *pitch),
+                    };
+                    self.bar.add_pitch(meta, &notation.note, self.width, head);
+                    self.bar
+                        .add_label(meta, *pitch, self.key, dur, self.width, y);
+                }
+                let steps = self.bar.stave.pitch_steps(pitches[0], clef);
+                let stem_up = self.bar.stem_up(steps);
+                self.end_ties(meta, stave_i, Some((pitches, stem_up)));
+                if notation.note.tie {
+                    let pitches = pitches
+                        .iter()
+                        .map(|pitch| {
+                            let steps =
+                                self.bar.stave.pitch_steps(*pitch, clef);
+                            (*pitch, self.bar.y_from_steps(steps, y_offset))
+                        })
+                        .collect();
+                    self.ties[stave_i] = Some(Tie {
+                        start: self.width,
+                        pitches,
+                        stem_up,
+                    });
+                }
+                if notation.tremolo > 0 {
+                    let y = self.bar.y_from_steps(steps, y_offset);
+                    self.bar.add_tremolo(
                         meta,
                         dur,
                         self.width,
-                        pitch.visual_distance(),
+                        steps,
                         y,
+                        notation.tremolo,
                     );
                 }
+                if let Some(lyric) = notation.lyric {
+                    self.add_lyric(meta, stave_i, lyric, ymargin);
+                }
                 // Advance beaming (using closest note to the beam)
                 self.beams[stave_i].advance(
                     dur,
                     self.width,
-                    Some((pitches.clone(), y_offset)),
+                    Some((notation.note.clone(), y_offset, clef)),
+                    notation.beam_break,
                 );
             }
+            // Number tuplets over the middle of the group, bracketing the
+            // group unless it's all under one beam.
+            if let Some(tuplet) = notation.tuplet {
+                let group = self.tuplets[stave_i];
+                let start = group.map_or(self.width, |t| t.1);
+                let beamed = group.is_none_or(|t| t.2)
+                    && !pitches.is_empty()
+                    && !notation.beat_repeat
+                    && !notation.spacer
+                    && !self.bar.options.stemless
+                    && notation.note.duration < scof::Fraction::new(1, 4)
+                    && !(group.is_some() && notation.beam_break);
+                self.tuplets[stave_i] = if notation.tuplet_end {
+                    let ofs = ymargin * stave_i as i32;
+                    self.bar.add_tuplet(
+                        meta, tuplet, start, self.width, !beamed, ofs,
+                    );
+                    None
+                } else {
+                    Some((tuplet, start, beamed))
+                };
+            }
             // Add back to queue if time is remaining.
             time -= dur;
-            if time != 0 {
-                // Insert at correct priority level.
-                let mut index = self.pq.len();
-                'p: loop {
-                    if index == 0 {
-                        self.pq.push_front((time, stave_i));
-                        break 'p;
-                    }
-                    index -= 1;
-                    if self.pq[index].0 > time {
-                        self.pq.push_back((time, stave_i));
-                        break 'p;
-                    }
-                }
-            }
+            requeue(&mut self.pq, time, stave_i);
+        }
+        // Close octave shift brackets at the end of the bar.
+        for stave_i in 0..self.octave_brackets.len() {
+            self.end_octave_bracket(meta, stave_i, ymargin);
+        }
+        // Finish lyrics at the end of the bar.
+        // FIXME: Hyphens between syllables on either side of a barline.
+        for stave_i in 0..self.syllables.len() {
+            self.end_syllable(meta, stave_i, None, ymargin);
         }
         // Beam eighth notes and shorter.
         while let Some(beam) = self.beams.pop() {
             self.bar.add_flags_and_beams(meta, beam);
         }
         // Add the rest of the width.
-        self.width += get_spacing(self.all) / 7.0;
-        // End of bar margin
-        self.width += Stave::SPACE as f32 / BAR_WIDTH as f32;
-        // Draw measure rests
+        self.add_slice(self.all);
+        // Ties to the next bar end at the barline.
+        for stave_i in 0..self.ties.len() {
+            self.end_ties(meta, stave_i, None);
+        }
+        // Draw dynamics after the last note, and end hairpins with the bar.
+        for stave_i in 0..self.notators.len() {
+            let ofs = ymargin * stave_i as i32;
+            if let Some(pause) = self.notators[stave_i].end_pause() {
+                self.bar.add_pause(&pause, self.width, ofs);
+            }
+            match self.notators[stave_i].end_dynamic() {
+                Some(dynamic) => {
+                    let width = BarElem::dynamic_width(meta, &dynamic);
+                    let end = (self.width, -width - HAIRPIN_GAP);
+                    self.end_hairpin(
+                        meta,
+                        stave_i,
+                        end,
+                        dynamic == Dynamic::N,
+                        ymargin,
+                    );
+                    self.bar
+                        .add_dynamic(meta, &dynamic, self.width, -width, ofs);
+                }
+                None => self.end_hairpin(
+                    meta,
+                    stave_i,
+                    (self.width, 0),
+                    false,
+                    ymargin,
+                ),
+            }
+        }
+        let bar_width = self.bar_width();
+        // Draw measure rests, centered in the stretched bar.
         for (rest_stave, rest_ic) in rests {
-            self.bar
-                .add_measure_rest(self.width, ymargin * rest_stave as i32);
+            let ofs = ymargin * rest_stave as i32;
+            match self.notators[rest_stave].measure_repeat() {
+                Some(count) => {
+                    self.bar.add_measure_repeat(bar_width, ofs, count)
+                }
+                None => self.bar.add_measure_rest(meta, bar_width, ofs),
+            }
             if rest_ic {
                 cursor_rect = Some((
-                    meta.barline_thickness,                 // X
-                    0i32,                                   // Y
-                    (BAR_WIDTH as f32 * self.width) as i32, // W
+                    meta.barline_thickness, // X
+                    0i32,                   // Y
+                    bar_width,              // W
                     self.bar.height(),
                 ));
             }
@@ -211,18 +457,410 @@ impl<'a, 'b, 'c> BarEngraver<'a, 'b, 'c> {
                 self.bar.height(),
             ));
         }
-        // Calculate physical bar width.
-        let bar_width = ((BAR_WIDTH as f32 * self.width) as i32).max(BAR_WIDTH);
-        // Draw barlines
+        // Draw barlines, and stave lines unless they're combined for the
+        // whole system.
         for i in 0..self.notators.len().try_into().unwrap() {
-            let y = self.bar.offset_y(self.bar.stave.steps_middle_c);
-            let path = self.bar.stave.path(meta, y, bar_width, ymargin * i);
-            self.bar.elements.push(Element::Path(path));
+            if !self.bar.options.combine_staves {
+                let y = self.bar.offset_y(self.bar.stave.steps_middle_c);
+                let ofs = ymargin * i;
+                let path = self.bar.stave.path(meta, 0, y, bar_width, ofs);
+                self.bar.elements.push(Element::Path(path));
+            }
             self.bar.add_barline(meta, bar_width, ymargin * i);
         }
         // Return calculated physical bar width.
         (bar_width, cursor_rect)
     }
+
+    /// Draw and remove a stave's octave shift bracket, if there is one.
+    fn end_octave_bracket(
+        &mut self,
+        meta: &SfFontMetadata,
+        stave_i: usize,
+        ymargin: Steps,
+    ) {
+        if let Some((octaves, start, end)) =
+            self.octave_brackets[stave_i].take()
+        {
+            let ofs = ymargin * stave_i as i32;
+            self.bar.add_octave_bracket(meta, octaves, start, end, ofs);
+        }
+    }
+
+    /// Draw and remove a stave's ties, if there are any, to the `next` note
+    /// (its pitches and stem direction) at the current offset, or to the end
+    /// of the bar.  Pitches the next note doesn't have aren't tied.
+    ///
+    /// FIXME: Stem directions of beamed groups, and chords with ties both
+    /// over and under.
+    fn end_ties(
+        &mut self,
+        meta: &SfFontMetadata,
+        stave_i: usize,
+        next: Option<(&[Pitch], bool)>,
+    ) {
+        let tie = match self.ties[stave_i].take() {
+            Some(tie) => tie,
+            None => return,
+        };
+        let over = tie_over(tie.stem_up, next.map_or(tie.stem_up, |n| n.1));
+        for (pitch, y) in tie.pitches {
+            match next {
+                Some((pitches, _)) if !pitches.contains(&pitch) => {}
+                _ => self.bar.add_tie(meta, tie.start, self.width, y, over),
+            }
+        }
+    }
+
+    /// Get the ID of a notehead (`i` is the index of its pitch), if enabled.
+    fn note_id(&self, notation: &Notation, i: usize) -> Option<String> {
+        if !self.bar.options.note_ids {
+            return None;
+        }
+        let marking = &notation.marking;
+        let id = format!(
+            "n{}-{}-{}-{}",
+            marking.movement(),
+            marking.measure(),
+            marking.channel(),
+            marking.marking()
+        );
+        let head = usize::from(notation.part) * notation.note.pitch.len() + i;
+        Some(if head == 0 {
+            id
+        } else {
+            format!("{}.{}", id, head)
+        })
+    }
+
+    /// Add the dynamic on the current note of a stave, ending the hairpin
+    /// before it, and start the next hairpin (after the dynamic).
+    fn add_dynamic(
+        &mut self,
+        meta: &SfFontMetadata,
+        stave_i: usize,
+        dynamic: Option<Dynamic>,
+        hairpin: Option<bool>,
+        ymargin: Steps,
+    ) {
+        let mut shift = 0;
+        if let Some(dynamic) = dynamic {
+            let niente = dynamic == Dynamic::N;
+            let end = (self.width, -HAIRPIN_GAP);
+            self.end_hairpin(meta, stave_i, end, niente, ymargin);
+            let ofs = ymargin * stave_i as i32;
+            self.bar.add_dynamic(meta, &dynamic, self.width, 0, ofs);
+            shift = BarElem::dynamic_width(meta, &dynamic) + HAIRPIN_GAP;
+            self.niente[stave_i] = niente;
+        }
+        if let Some(cresc) = hairpin {
+            let end = (self.width, 0);
+            self.end_hairpin(meta, stave_i, end, false, ymargin);
+            self.hairpins[stave_i] = Some(Hairpin {
+                cresc,
+                start: self.width,
+                shift,
+                niente: self.niente[stave_i],
+            });
+        }
+    }
+
+    /// Draw a placeholder notehead for a note or rest too short to notate,
+    /// and warn about it.
+    fn add_placeholder(
+        &mut self,
+        notation: &Notation,
+        stave_i: usize,
+        ymargin: Steps,
+    ) {
+        let y_offset = ymargin * stave_i as i32;
+        let x = self.bar.width + ((self.width * BAR_WIDTH as f32) as i32);
+        let clef = self.notators[stave_i].clef();
+        let mut ys: Vec<i32> = notation
+            .note
+            .pitch
+            .iter()
+            .map(|pitch| {
+                let steps = self.bar.stave.pitch_steps(*pitch, clef);
+                self.bar.y_from_steps(steps, y_offset)
+            })
+            .collect();
+        if ys.is_empty() {
+            ys.push(self.bar.middle() + (y_offset * Stave::STEP).0);
+        }
+        for y in ys {
+            self.bar.add_use(PLACEHOLDER, x, y);
+        }
+        self.bar.warnings.push(RenderWarning::TooShort(
+            notation.marking.clone(),
+            notation.note.duration,
+        ));
+    }
+
+    /// Draw and remove a stave's hairpin, if there is one, ending at `end`
+    /// (an offset and a shift in font units).
+    ///
+    /// - `niente`: The hairpin ends at niente.
+    fn end_hairpin(
+        &mut self,
+        meta: &SfFontMetadata,
+        stave_i: usize,
+        end: (f32, i32),
+        niente: bool,
+        ymargin: Steps,
+    ) {
+        if let Some(hairpin) = self.hairpins[stave_i].take() {
+            let ofs = ymargin * stave_i as i32;
+            let niente = if hairpin.cresc {
+                hairpin.niente
+            } else {
+                niente
+            };
+            let start = (hairpin.start, hairpin.shift);
+            self.bar
+                .add_hairpin(meta, hairpin.cresc, start, end, niente, ofs);
+        }
+    }
+
+    /// Add the lyric sung on the current note of a stave.
+    fn add_lyric(
+        &mut self,
+        meta: &SfFontMetadata,
+        stave_i: usize,
+        lyric: Lyric,
+        ymargin: Steps,
+    ) {
+        match lyric {
+            Lyric::Syllable(text, hyphen) => {
+                self.end_syllable(meta, stave_i, Some(&text), ymargin);
+                let ofs = ymargin * stave_i as i32;
+                self.bar.add_lyric(meta, &text, self.width, ofs);
+                self.syllables[stave_i] = Some(Syllable {
+                    text,
+                    start: self.width,
+                    end: self.width,
+                    hyphen,
+                });
+            }
+            Lyric::Extend => {
+                if let Some(syllable) = &mut self.syllables[stave_i] {
+                    syllable.end = self.width;
+                }
+            }
+        }
+    }
+
+    /// Draw the hyphen or melisma line after a stave's last syllable, before
+    /// the `next` syllable (at the current offset) if there is one.
+    fn end_syllable(
+        &mut self,
+        meta: &SfFontMetadata,
+        stave_i: usize,
+        next: Option<&str>,
+        ymargin: Steps,
+    ) {
+        let syllable = match self.syllables[stave_i].take() {
+            Some(syllable) => syllable,
+            None => return,
+        };
+        let ofs = ymargin * stave_i as i32;
+        let first = (syllable.text.as_str(), syllable.start);
+        if syllable.end > syllable.start {
+            self.bar.add_melisma(meta, first, syllable.end, ofs);
+        }
+        if let (true, Some(next)) = (syllable.hyphen, next) {
+            self.bar
+                .add_lyric_hyphen(meta, first, (next, self.width), ofs);
+        }
+    }
+
+    /// Space the bar up to a notation that starts `time` 128ths before its
+    /// end: finish the time slice before it, and make room for its glyphs.
+    fn space(
+        &mut self,
+        meta: &SfFontMetadata,
+        time: u16,
+        notation: &Notation,
+        courtesy: &[Pitch],
+    ) {
+        // Increment width
+        if time < self.all {
+            self.add_slice(self.all - time);
+            self.all = time;
+        }
+        let pitches = &notation.note.pitch;
+        // Make room for accidentals.  Only the first notation at an offset
+        // can, since the rest are drawn where it is.
+        if self.room {
+            self.make_room(meta, pitches, courtesy);
+            self.room = false;
+        }
+        if notation.dur == 0 || notation.beat_repeat || notation.spacer {
+            return;
+        }
+        // Widest notehead or rest at the offset.
+        let column = if pitches.is_empty() {
+            match DurationClass::new(notation.dur) {
+                DurationClass::DoubleWhole => LONG_REST_WIDTH,
+                _ => {
+                    let glyph = self.bar.glyph(&notation.note, GlyphRole::Rest);
+                    meta.advance(glyph)
+                }
+            }
+        } else {
+            pitches
+                .iter()
+                .map(|pitch| {
+                    let head = GlyphRole::Notehead(*pitch);
+                    meta.advance(self.bar.glyph(&notation.note, head))
+                })
+                .max()
+                .unwrap_or(0)
+        };
+        self.column = self.column.max(column);
+    }
+
+    /// Add the end of bar margin, and get the physical bar width.
+    fn bar_width(&mut self) -> i32 {
+        self.width += Stave::SPACE as f32 / BAR_WIDTH as f32;
+        ((BAR_WIDTH as f32 * self.width) as i32)
+            .max(BAR_WIDTH)
+            .max(self.bar.min_width)
+    }
+
+    /// Move the current offset right if the accidentals of a note would
+    /// overlap the glyphs at the last offset.
+    fn make_room(
+        &mut self,
+        meta: &SfFontMetadata,
+        pitches: &[Pitch],
+        courtesy: &[Pitch],
+    ) {
+        let lead = pitches
+            .iter()
+            .filter_map(|pitch| {
+                let courtesy = is_courtesy(courtesy, *pitch);
+                accidental_width(meta, *pitch, courtesy)
+            })
+            .max();
+        if let Some(lead) = lead {
+            let min =
+                self.right + (lead + ACCIDENTAL_GAP) as f32 / BAR_WIDTH as f32;
+            if min > self.width {
+                self.widened += min - self.width;
+                self.width = min;
+            }
+        }
+    }
+
+    /// Add a time slice (in 128ths), recalculating the width.
+    fn add_slice(&mut self, duration: u16) {
+        let spacing = get_spacing(duration).unwrap_or_else(|| {
+            self.bar.warnings.push(RenderWarning::TooLong(duration));
+            LONGA_SPACING
+        });
+        // Widen the slice to fit the widest glyph at its start.
+        if let Some(gap) = self.bar.options.min_spacing {
+            let min = (self.column + gap) as f32 / BAR_WIDTH as f32;
+            self.widened += (min - spacing / 7.0).max(0.0);
+        }
+        self.right = self.width + self.column as f32 / BAR_WIDTH as f32;
+        self.room = true;
+        self.column = 0;
+        *self.slices.entry(duration).or_default() += 1;
+        self.width = slices_width(&self.slices) + self.widened;
+    }
+}
+
+impl BarEngraver<'_, '_, '_> {
+    /// Compute the width of a bar as `engrave` would, without making any
+    /// elements.  Useful for laying out (justifying) a system before drawing
+    /// it.
+    ///
+    /// - `bar`: The bar to engrave into, for its stave, options and minimum
+    ///   width.
+    /// - `curs`: Cursor of measure.
+    pub fn measure_width(
+        bar: &BarElem,
+        scof: &Scof,
+        curs: &Cursor,
+        meta: &SfFontMetadata,
+    ) -> i32 {
+        let mut bar = bar.blank();
+        let mut notators: Vec<Notator> = bar
+            .staves(scof, curs)
+            .map(|chan| Notator::new(scof, Cursor::default(), curs.chan(chan)))
+            .collect();
+        let mut engraver =
+            BarEngraver::new(&mut bar, &mut notators, scof, curs);
+        while let Some((time, stave_i)) = engraver.pq.pop_front() {
+            let notation = match engraver.notators[stave_i].next() {
+                Some(notation) => notation,
+                None => continue,
+            };
+            let courtesy = std::mem::take(&mut engraver.courtesy[stave_i]);
+            engraver.space(meta, time, &notation, &courtesy);
+            requeue(&mut engraver.pq, time - notation.dur, stave_i);
+        }
+        engraver.add_slice(engraver.all);
+        engraver.bar_width()
+    }
+}
+
+/// Get the pitches of the last note before the measure at a cursor, if it's
+/// tied into the measure.
+fn tied_in(scof: &Scof, curs: &Cursor) -> Vec<Pitch> {
+    if curs.is_first_bar() {
+        return vec![];
+    }
+    let prev = curs.bar(curs.measure() - 1);
+    let last = (0..scof.marking_len(&prev)).rev().find_map(|marking| {
+        let curs = Cursor::new(
+            prev.movement(),
+            prev.measure(),
+            prev.channel(),
+            marking,
+        );
+        scof.marking(&curs).filter(|m| m.duration().is_some())
+    });
+    match last {
+        Some(Marking::Note(note)) if note.tie => note.pitch.clone(),
+        _ => vec![],
+    }
+}
+
+/// Check if a pitch is tied in with a courtesy accidental.  Pitch classes are
+/// compared, since notes are written octaves from where they sound under an
+/// octave shift.
+fn is_courtesy(courtesy: &[Pitch], pitch: Pitch) -> bool {
+    courtesy.iter().any(|tied| tied.0 == pitch.0)
+}
+
+/// Add a stave back to the priority queue at the correct priority level, if
+/// it has time remaining.  Staves are engraved in onset order, so notes that
+/// start together on different staves share an offset.
+fn requeue(pq: &mut VecDeque<(u16, usize)>, time: u16, stave_i: usize) {
+    if time == 0 {
+        return;
+    }
+    // After the staves with more time remaining (earlier onsets).
+    let index = pq.iter().rposition(|queued| queued.0 > time);
+    pq.insert(index.map_or(0, |i| i + 1), (time, stave_i));
+}
+
+/// Get the width of a bar (a fraction of `BAR_WIDTH`) from counts of time
+/// slices of each duration, including the beginning of bar margin.
+fn slices_width(slices: &BTreeMap<u16, u16>) -> f32 {
+    let spacing: f64 = slices
+        .iter()
+        .map(|(dur, count)| {
+            let spacing = get_spacing(*dur).unwrap_or(LONGA_SPACING);
+            f64::from(spacing) * f64::from(*count) / 7.0
+        })
+        .sum();
+    // Beginning of bar margin
+    let margin = f64::from(Stave::SPACE) / f64::from(BAR_WIDTH);
+    (margin + spacing) as f32
 }
 
 /// Linear interpolation
@@ -236,10 +874,10 @@ fn clamp(a: f32, min: f32, max: f32) -> f32 {
 }
 
 /// Get the fraction of the spacing of a whole note that this note needs based
-/// on duration (in 128th notes).
-fn get_spacing(duration: u16) -> f32 {
+/// on duration (in 128th notes), or `None` if it's longer than a longa.
+fn get_spacing(duration: u16) -> Option<f32> {
     let dur = duration as f32;
-    match duration {
+    Some(match duration {
         1..=7 => lerp(1.8, 2.0, clamp(dur, 1.0, 8.0)), // 128th-16th
         8..=15 => lerp(2.0, 2.5, clamp(dur, 8.0, 16.0)), // Sixteenth
         16..=23 => lerp(2.5, 3.0, clamp(dur, 16.0, 24.0)), // Eighth
@@ -251,7 +889,254 @@ fn get_spacing(duration: u16) -> f32 {
         128..=255 => lerp(7.0, 8.0, clamp(dur, 128.0, 256.0)), // Whole
         256..=383 => lerp(8.0, 9.0, clamp(dur, 256.0, 384.0)), // Dot'd Whole
         384..=511 => lerp(9.0, 10.0, clamp(dur, 384.0, 512.0)), // Breve
-        512 => 10.0,                                   // Longa
-        _ => panic!("Bug in Notator, no glyph for ({})", duration),
+        512 => LONGA_SPACING,                          // Longa
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{engrave, font, score};
+    use scof::Pitch;
+    use sfff::Glyph;
+
+    #[test]
+    fn aligned_onsets() {
+        let scof =
+            score(&["1/4C4 3/4D4", "1/8C4 1/8D4 1/4E4 1/2F4", "1/2C4 1/2G4"]);
+        let options = crate::RenderOptions {
+            note_ids: true,
+            ..Default::default()
+        };
+        let bar = engrave(&scof, options);
+        let x = |id: &str| {
+            bar.elements
+                .iter()
+                .find_map(|elem| match elem {
+                    Element::Use(u) if u.element_id.as_deref() == Some(id) => {
+                        Some(u.x)
+                    }
+                    _ => None,
+                })
+                .unwrap()
+        };
+        // Onsets shared across staves, from the score.
+        let beats: Vec<Vec<u16>> = scof
+            .simultaneities(0, 0)
+            .into_iter()
+            .map(|(_, notes)| notes.iter().map(|note| note.0).collect())
+            .collect();
+        assert_eq!(beats, [vec![0, 1, 2], vec![1], vec![0, 1], vec![1, 2]]);
+        // Second beat, and the third beat.
+        assert_eq!(x("n0-0-0-1"), x("n0-0-1-2"));
+        assert_eq!(x("n0-0-1-3"), x("n0-0-2-1"));
+        assert!(x("n0-0-1-1") < x("n0-0-0-1"));
+    }
+
+    #[test]
+    fn no_drift() {
+        let notes = vec!["1/32C4"; 32].join(" ");
+        let bar = engrave(&score(&[&notes]), Default::default());
+        let spacing = f64::from(get_spacing(4).unwrap()) * 32.0 / 7.0;
+        let expected =
+            2.0 * f64::from(Stave::SPACE) + f64::from(BAR_WIDTH) * spacing;
+        assert!((f64::from(bar.width) - expected).abs() <= 1.0);
+    }
+
+    #[test]
+    fn min_spacing() {
+        let (meta, _glyphs) = font();
+        let notes = vec!["1/32C4"; 16].join(" ") + " 1/2R";
+        let scof = score(&[&notes]);
+        let options = crate::RenderOptions {
+            min_spacing: Some(Stave::SPACE / 4),
+            ..Default::default()
+        };
+        let bar = engrave(&scof, options);
+        let head = meta.advance(Glyph::NoteheadFill);
+        assert!(bar.width >= 16 * head);
+        let mut heads: Vec<i32> = bar
+            .elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Use(u) if u.id == u16::from(Glyph::NoteheadFill) => {
+                    Some(u.x)
+                }
+                _ => None,
+            })
+            .collect();
+        heads.sort_unstable();
+        assert_eq!(heads.len(), 16);
+        for pair in heads.windows(2) {
+            assert!(pair[1] - pair[0] >= head);
+        }
+        // Proportional spacing alone is much narrower.
+        assert!(engrave(&scof, Default::default()).width < 16 * head);
+    }
+
+    #[test]
+    fn accidental_room() {
+        let (meta, _glyphs) = font();
+        let x = |notes| -> Vec<i32> {
+            let bar = engrave(&score(&[notes]), Default::default());
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some(u.x),
+                    _ => None,
+                })
+                .collect()
+        };
+        // Notehead, then notehead and sharp.  The sharp (at most a unit of
+        // rounding off) clears the first notehead.
+        let sharp = x("1/16C4 1/16D#4 7/8R");
+        let head = meta.advance(Glyph::NoteheadFill);
+        assert!(sharp[2] + 1 >= sharp[0] + head);
+        // Sixteenths are closer together without the sharp.
+        let natural = x("1/16C4 1/16D4 7/8R");
+        assert!(natural[1] < sharp[1]);
+    }
+
+    #[test]
+    fn combine_staves() {
+        let scof = score(&["1/1C4"; 5]);
+        // Stave lines are rectangles drawn right, down, left and up.
+        let staves = |options| -> Vec<usize> {
+            let bar = engrave(&scof, options);
+            bar.elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Path(path) if path.d.contains("h-") => {
+                        Some(path.d.matches('M').count())
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(staves(Default::default()), [5; 5]);
+        // Combined stave lines are drawn for the system, not the bar.
+        let options = crate::RenderOptions {
+            combine_staves: true,
+            ..Default::default()
+        };
+        assert_eq!(staves(options), []);
+    }
+
+    #[test]
+    fn measure_rest_justified() {
+        let (meta, _glyphs) = font();
+        let scof = score(&[""]);
+        let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+        let mut bar = BarElem::new(Stave::new(5, Steps(4), Steps(0)), c4, c4);
+        bar.min_width = BAR_WIDTH * 3;
+        let mut curs = Cursor::default();
+        bar.add_markings(&meta, &scof, &Cursor::default(), &mut curs);
+        assert_eq!(bar.width, BAR_WIDTH * 3);
+        let rest = bar
+            .elements
+            .iter()
+            .find_map(|elem| match elem {
+                Element::Use(u) if u.id == u16::from(Glyph::Rest1) => Some(u.x),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(rest + meta.advance(Glyph::Rest1) / 2, bar.width / 2);
+    }
+
+    #[test]
+    fn attached_dynamic() {
+        let uses = |notes| -> Vec<(i32, u16)> {
+            engrave(&score(&[notes]), Default::default())
+                .elements
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Use(u) => Some((u.x, u.id)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let plain = uses("1/4C4 1/4D4 1/2E4");
+        let (dynamic, notes): (Vec<_>, Vec<_>) = uses("1/4C4 p 1/4D4 1/2E4")
+            .into_iter()
+            .partition(|(_, id)| *id == u16::from(Glyph::P));
+        // Takes up no time, drawn at the note after it.
+        assert_eq!(notes, plain);
+        let d4 = notes
+            .iter()
+            .filter(|(_, id)| *id == u16::from(Glyph::NoteheadFill))
+            .nth(1)
+            .unwrap();
+        assert_eq!(dynamic, [(d4.0, u16::from(Glyph::P))]);
+    }
+
+    #[test]
+    fn too_short() {
+        let scof = score(&["1/4C4 1/256D4 3/4E4"]);
+        let bar = engrave(&scof, Default::default());
+        let placeholders: Vec<i32> = bar
+            .elements
+            .iter()
+            .filter_map(|elem| match elem {
+                Element::Use(u) if u.id == u16::from(PLACEHOLDER) => Some(u.x),
+                _ => None,
+            })
+            .collect();
+        // Drawn at the next note, taking no time.
+        assert_eq!(placeholders.len(), 1);
+        let warnings: Vec<&RenderWarning> = bar.warnings.iter().collect();
+        assert_eq!(
+            warnings,
+            [&RenderWarning::TooShort(
+                Cursor::new(0, 0, 0, 1),
+                scof::Fraction::new(1, 256)
+            )]
+        );
+        // Longer than a longa is spaced like one.
+        assert_eq!(get_spacing(513), None);
+        assert_eq!(get_spacing(512), Some(LONGA_SPACING));
+    }
+
+    #[test]
+    fn measure_width() {
+        let (meta, _glyphs) = font();
+        let c4 = "C4".parse::<Pitch>().unwrap().visual_distance();
+        let mut bar = BarElem::new(Stave::new(5, Steps(4), Steps(0)), c4, c4);
+        let min_spacing = crate::RenderOptions {
+            min_spacing: Some(Stave::SPACE / 4),
+            ..Default::default()
+        };
+        for options in &[Default::default(), min_spacing] {
+            bar.options = options.clone();
+            for chans in &[
+                &["1/1C4"][..],
+                &[""],
+                &["1/8C4 1/8D4 1/4E4 1/2F4"],
+                &["1/32C4 1/32C4 1/16C4 3/4R 1/8C4", "1/2C4 1/4R 1/4C4"],
+                &["1/1C4", "1/3C4 1/3C4 1/3C4", ""],
+                &["1/16C4 1/16D#4 1/16Eb4 1/16Fx4 3/4R"],
+                &["1/32C4 1/32D4 1/32E4 1/32F4 1/32G4 1/32A4 1/32B4 1/32C5 \
+                   3/4R"],
+            ] {
+                let scof = score(chans);
+                let width = BarEngraver::measure_width(
+                    &bar,
+                    &scof,
+                    &scof::Cursor::default(),
+                    &meta,
+                );
+                assert_eq!(width, engrave(&scof, options.clone()).width);
+            }
+        }
+        // Stretched to the bar's minimum width.
+        bar.min_width = BAR_WIDTH * 3;
+        let scof = score(&["1/1C4"]);
+        let width = BarEngraver::measure_width(
+            &bar,
+            &scof,
+            &scof::Cursor::default(),
+            &meta,
+        );
+        assert_eq!(width, BAR_WIDTH * 3);
     }
 }
@@ -0,0 +1,291 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/* ************************************************************************** */
+
+//! Engraving snapshot tests: fixture scores are engraved headlessly, and the
+//! structure of the resulting elements is checked.
+
+use crate::tests::{engrave, score};
+use crate::{BarElem, Element, RenderOptions, Stave};
+use sfff::Glyph;
+
+/// The structure of an engraved bar.
+#[derive(Debug, Default)]
+struct Snapshot {
+    /// Noteheads (x, y, glyph).
+    heads: Vec<(i32, i32, Glyph)>,
+    /// Rests (x, y, glyph).
+    rests: Vec<(i32, i32, Glyph)>,
+    /// Flags (x, y, glyph).
+    flags: Vec<(i32, i32, Glyph)>,
+    /// Stems (x).
+    stems: Vec<i32>,
+    /// Beam groups.
+    beams: usize,
+    /// Ties (curves).
+    ties: usize,
+}
+
+impl Snapshot {
+    /// Engrave the first bar of a score, one channel per string of notes.
+    fn new(chans: &[&str]) -> Self {
+        Self::of(&engrave(&score(chans), Default::default()))
+    }
+
+    /// Classify the elements of an engraved bar.
+    fn of(bar: &BarElem) -> Self {
+        let mut snapshot = Snapshot::default();
+        for elem in &bar.elements {
+            match elem {
+                Element::Use(u) => {
+                    let glyph = *Glyph::ALL
+                        .iter()
+                        .find(|glyph| u16::from(**glyph) == u.id)
+                        .unwrap();
+                    let name = glyph.name();
+                    let list = if name.starts_with("notehead") {
+                        &mut snapshot.heads
+                    } else if name.starts_with("rest") {
+                        &mut snapshot.rests
+                    } else if name.starts_with("flag") {
+                        &mut snapshot.flags
+                    } else {
+                        continue;
+                    };
+                    list.push((u.x, u.y, glyph));
+                }
+                // Stems are rounded rectangles.
+                Element::Rect(rect) if rect.rx.is_some() => {
+                    snapshot.stems.push(rect.x)
+                }
+                Element::Path(path) if path.d.contains('Q') => {
+                    snapshot.ties += 1
+                }
+                Element::Path(path) if is_beam(&path.d) => snapshot.beams += 1,
+                _ => {}
+            }
+        }
+        snapshot
+    }
+
+    /// Glyphs of the noteheads.
+    fn head_glyphs(&self) -> Vec<Glyph> {
+        self.heads.iter().map(|head| head.2).collect()
+    }
+
+    /// Are the noteheads in left to right order?
+    fn heads_ordered(&self) -> bool {
+        self.heads.windows(2).all(|pair| pair[0].0 < pair[1].0)
+    }
+}
+
+/// Get the corners of each subpath of path data, or `None` if it isn't made
+/// only of straight lines.
+fn corners(d: &str) -> Option<Vec<Vec<(i32, i32)>>> {
+    let mut subpaths = vec![];
+    let mut points: Vec<(i32, i32)> = vec![];
+    let mut pen = (0, 0);
+    let mut chars = d.chars().peekable();
+    while let Some(command) = chars.next() {
+        let mut args = vec![];
+        loop {
+            while chars.peek() == Some(&' ') {
+                chars.next();
+            }
+            let mut num = String::new();
+            while let Some(c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_digit() || **c == '-' && num.is_empty())
+            {
+                num.push(*c);
+                chars.next();
+            }
+            if num.is_empty() {
+                break;
+            }
+            args.push(num.parse::<i32>().ok()?);
+        }
+        pen = match (command, args.as_slice()) {
+            ('M', [x, y]) => {
+                if !points.is_empty() {
+                    subpaths.push(std::mem::take(&mut points));
+                }
+                (*x, *y)
+            }
+            ('L', [x, y]) => (*x, *y),
+            ('l', [x, y]) => (pen.0 + x, pen.1 + y),
+            ('H', [x]) => (*x, pen.1),
+            ('h', [x]) => (pen.0 + x, pen.1),
+            ('V', [y]) => (pen.0, *y),
+            ('v', [y]) => (pen.0, pen.1 + y),
+            ('z', []) => continue,
+            _ => return None,
+        };
+        points.push(pen);
+    }
+    subpaths.push(points);
+    Some(subpaths)
+}
+
+/// Is path data a beam?  Each subpath is a band one step thick with vertical
+/// ends, from one stem to another (or a beamlet).
+fn is_beam(d: &str) -> bool {
+    let beam = |corners: &Vec<(i32, i32)>| match corners.as_slice() {
+        [a, b, c, d] => {
+            a.0 != b.0
+                && b.0 == c.0
+                && a.0 == d.0
+                && (c.1 - b.1).abs() == Stave::STEP
+                && d.1 - a.1 == c.1 - b.1
+        }
+        _ => false,
+    };
+    corners(d).is_some_and(|subpaths| subpaths.iter().all(beam))
+}
+
+#[test]
+fn whole_notes() {
+    let snapshot = Snapshot::new(&["1/1C5"]);
+    assert_eq!(snapshot.head_glyphs(), [Glyph::NoteheadWhole]);
+    assert!(snapshot.stems.is_empty());
+    assert!(snapshot.flags.is_empty());
+    assert!(snapshot.rests.is_empty());
+    assert_eq!(snapshot.beams, 0);
+}
+
+#[test]
+fn half_note_run() {
+    let snapshot = Snapshot::new(&["1/2C5 1/2D5"]);
+    assert_eq!(snapshot.head_glyphs(), [Glyph::NoteheadHalf; 2]);
+    assert_eq!(snapshot.stems.len(), 2);
+    assert!(snapshot.heads_ordered());
+    assert!(snapshot.flags.is_empty());
+    assert_eq!(snapshot.beams, 0);
+}
+
+#[test]
+fn quarter_note_run() {
+    let snapshot = Snapshot::new(&["1/4C5 1/4D5 1/4E5 1/4F5"]);
+    assert_eq!(snapshot.head_glyphs(), [Glyph::NoteheadFill; 4]);
+    assert_eq!(snapshot.stems.len(), 4);
+    assert!(snapshot.heads_ordered());
+    assert!(snapshot.stems.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(snapshot.flags.is_empty());
+    assert_eq!(snapshot.beams, 0);
+    // Evenly spaced.
+    let gaps: Vec<i32> = snapshot
+        .heads
+        .windows(2)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .collect();
+    assert!(gaps.iter().all(|gap| (gap - gaps[0]).abs() <= 1));
+}
+
+#[test]
+fn eighth_note_run() {
+    let snapshot = Snapshot::new(&["1/8C5 1/8D5 1/8E5 1/8F5 1/8C5 1/8D5 \
+         1/8E5 1/8F5"]);
+    assert_eq!(snapshot.head_glyphs(), [Glyph::NoteheadFill; 8]);
+    assert_eq!(snapshot.stems.len(), 8);
+    assert!(snapshot.heads_ordered());
+    // Beamed in two groups of four (one per half of the bar), not flagged.
+    assert!(snapshot.flags.is_empty());
+    assert_eq!(snapshot.beams, 2);
+}
+
+#[test]
+fn beams_without_stave_lines() {
+    let options = RenderOptions {
+        combine_staves: true,
+        ..Default::default()
+    };
+    let bar = engrave(&score(&["1/8C5 1/8D5 1/8E5 1/8F5 1/2R"]), options);
+    assert_eq!(Snapshot::of(&bar).beams, 1);
+}
+
+#[test]
+fn tuplet_bracket_isnt_a_beam() {
+    let snapshot = Snapshot::new(&["1/6C5 1/6D5 1/6E5 1/2R"]);
+    assert_eq!(snapshot.head_glyphs(), [Glyph::NoteheadFill; 3]);
+    assert_eq!(snapshot.beams, 0);
+}
+
+#[test]
+fn single_eighth_note() {
+    let snapshot = Snapshot::new(&["1/8C5 7/8R"]);
+    assert_eq!(snapshot.head_glyphs(), [Glyph::NoteheadFill]);
+    assert_eq!(snapshot.stems.len(), 1);
+    assert_eq!(snapshot.flags.len(), 1);
+    assert_eq!(snapshot.beams, 0);
+    assert!(!snapshot.rests.is_empty());
+    assert!(snapshot
+        .rests
+        .iter()
+        .all(|rest| rest.0 > snapshot.heads[0].0));
+}
+
+#[test]
+fn chord() {
+    let snapshot = Snapshot::new(&["1/2E4G4B4 1/2R"]);
+    assert_eq!(snapshot.head_glyphs(), [Glyph::NoteheadHalf; 3]);
+    // FIXME: Each head of a chord gets its own stem, rather than one stem
+    // spanning the chord.
+    assert_eq!(snapshot.stems.len(), 3);
+    // All heads lined up.
+    assert!(snapshot
+        .heads
+        .iter()
+        .all(|head| head.0 == snapshot.heads[0].0));
+    // Stacked from bottom to top.
+    assert!(snapshot.heads.windows(2).all(|pair| pair[0].1 > pair[1].1));
+    assert_eq!(snapshot.rests.len(), 1);
+    assert_eq!(snapshot.rests[0].2, Glyph::Rest2);
+}
+
+#[test]
+fn dotted_note() {
+    // Without augmentation dots, a dotted half is notated as a half and a
+    // quarter.
+    let snapshot = Snapshot::new(&["3/4C5 1/4C5"]);
+    assert_eq!(
+        snapshot.head_glyphs(),
+        [
+            Glyph::NoteheadHalf,
+            Glyph::NoteheadFill,
+            Glyph::NoteheadFill
+        ]
+    );
+    assert_eq!(snapshot.stems.len(), 3);
+    assert!(snapshot.heads_ordered());
+    assert_eq!(snapshot.beams, 0);
+    assert_eq!(snapshot.ties, 1);
+}
+
+#[test]
+fn rests() {
+    let snapshot = Snapshot::new(&["1/4R 1/8R 1/8R 1/2R"]);
+    assert!(snapshot.heads.is_empty());
+    assert!(snapshot.stems.is_empty());
+    let glyphs: Vec<Glyph> = snapshot.rests.iter().map(|r| r.2).collect();
+    assert_eq!(
+        glyphs,
+        [Glyph::Rest4, Glyph::Rest8, Glyph::Rest8, Glyph::Rest2]
+    );
+    assert!(snapshot.rests.windows(2).all(|pair| pair[0].0 < pair[1].0));
+}
@@ -0,0 +1,89 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/* ************************************************************************** */
+
+//! Stem direction for single notes and beamed groups.
+
+use scof::Steps;
+
+/// How the stem direction is chosen.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum StemContext<'a> {
+    /// A single note or chord.
+    Single,
+    /// Forced direction (true is up).
+    #[allow(dead_code)] // FIXME: Use for multiple voices on one stave.
+    Forced(bool),
+    /// A beamed group, with the steps above the middle line of each note.
+    Group(&'a [Steps]),
+}
+
+/// Choose whether a stem points up.
+///
+/// - `steps`: Number of steps the note is above the middle stave line.
+pub(crate) fn stem_up(steps: Steps, ctx: StemContext) -> bool {
+    match ctx {
+        // Notes on or above the middle line have stems down.
+        StemContext::Single => steps.0 < 0,
+        StemContext::Forced(up) => up,
+        // Average the group, so it agrees with a single note at the average.
+        StemContext::Group(group) if !group.is_empty() => {
+            let sum: i32 = group.iter().map(|steps| steps.0).sum();
+            sum < 0
+        }
+        StemContext::Group(_) => stem_up(steps, StemContext::Single),
+    }
+}
+
+/// Choose whether a tie curves over its notes, from the stem directions of
+/// the two tied notes.  Ties curve away from the stems, so they only curve
+/// under when both stems are up.
+pub(crate) fn tie_over(first_up: bool, second_up: bool) -> bool {
+    !(first_up && second_up)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_and_group() {
+        for steps in -8..=8 {
+            let steps = Steps(steps);
+            assert_eq!(
+                stem_up(steps, StemContext::Single),
+                stem_up(steps, StemContext::Group(&[steps, steps])),
+            );
+        }
+        assert!(stem_up(Steps(-1), StemContext::Single));
+        assert!(!stem_up(Steps(0), StemContext::Single));
+        assert!(stem_up(Steps(4), StemContext::Forced(true)));
+        let group = [Steps(-5), Steps(6)];
+        assert!(!stem_up(group[0], StemContext::Group(&group)));
+    }
+
+    #[test]
+    fn tie_direction() {
+        assert!(!tie_over(true, true));
+        assert!(tie_over(false, false));
+        // Stems in different directions: over the notes.
+        assert!(tie_over(true, false));
+        assert!(tie_over(false, true));
+    }
+}
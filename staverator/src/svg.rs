@@ -91,13 +91,19 @@ pub struct Use {
     pub y: i32,
     /// Element ID
     pub id: u16,
+    /// ID of this `use` element
+    pub element_id: Option<String>,
 }
 
 impl fmt::Display for Use {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<use")?;
+        if let Some(ref element_id) = self.element_id {
+            write!(f, " id='{}'", element_id)?;
+        }
         write!(
             f,
-            "<use x='{}' y='{}' xlink:href='#{:x}'/>",
+            " x='{}' y='{}' xlink:href='#{:x}'/>",
             self.x, self.y, self.id
         )
     }
@@ -106,7 +112,12 @@ impl fmt::Display for Use {
 impl Use {
     /// Create a new SVG `use` element
     pub fn new(x: i32, y: i32, id: u16) -> Self {
-        Use { x, y, id }
+        Use {
+            x,
+            y,
+            id,
+            element_id: None,
+        }
     }
 }
 
@@ -128,7 +139,7 @@ impl fmt::Display for Group {
         if let Some(ro) = self.render_order {
             write!(f, " render-order=\"{}\"", ro)?;
         }
-        if self.x != 0 && self.y != 0 {
+        if self.x != 0 || self.y != 0 {
             write!(f, " transform='translate({} {})'>", self.x, self.y)?;
         } else {
             write!(f, ">")?;
@@ -187,6 +198,65 @@ impl Path {
     }
 }
 
+/// SVG `text` element, centered on its position
+pub struct Text {
+    /// X position
+    pub x: i32,
+    /// Y position
+    pub y: i32,
+    /// Font size in user units
+    pub size: i32,
+    /// Fill color
+    pub fill: Option<String>,
+    /// Text content
+    pub text: String,
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "<text x='{}' y='{}' font-size='{}' text-anchor='middle' \
+             dominant-baseline='central'",
+            self.x, self.y, self.size
+        )?;
+        if let Some(ref fill) = self.fill {
+            write!(f, " fill='{}'", fill)?;
+        }
+        write!(f, ">")?;
+        for c in self.text.chars() {
+            match c {
+                '&' => write!(f, "&amp;")?,
+                '<' => write!(f, "&lt;")?,
+                '>' => write!(f, "&gt;")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "</text>")
+    }
+}
+
+impl Text {
+    /// Create a new SVG `text` element
+    pub fn new<T: Into<String>>(
+        x: i32,
+        y: i32,
+        size: i32,
+        fill: Option<u32>,
+        text: T,
+    ) -> Self {
+        let fill = fill.map(|f| format!("#{:x}", f));
+        let text = text.into();
+        Text {
+            x,
+            y,
+            size,
+            fill,
+            text,
+        }
+    }
+}
+
 /// SVG element
 pub enum Element {
     /// Group `g`
@@ -197,6 +267,8 @@ pub enum Element {
     Use(Use),
     /// `path`
     Path(Path),
+    /// `text`
+    Text(Text),
 }
 
 impl fmt::Display for Element {
@@ -206,6 +278,7 @@ impl fmt::Display for Element {
             Element::Rect(r) => r.fmt(f),
             Element::Use(u) => u.fmt(f),
             Element::Path(p) => p.fmt(f),
+            Element::Text(t) => t.fmt(f),
         }
     }
 }
@@ -240,4 +313,23 @@ mod tests {
             "<g><use x='2' y='3' xlink:href='#3'/></g>"
         );
     }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            Text::new(5, 8, 75, Some(0xFFFFFF), "C&D").to_string(),
+            "<text x='5' y='8' font-size='75' text-anchor='middle' \
+             dominant-baseline='central' fill='#ffffff'>C&amp;D</text>"
+        );
+    }
+
+    #[test]
+    fn group_translate() {
+        let mut group = Group::new(40, 0, None);
+        group.push(Element::Path(Path::new(None, "M0 0h1")));
+        assert_eq!(
+            group.to_string(),
+            "<g transform='translate(40 0)'><path d='M0 0h1'/></g>"
+        );
+    }
 }
@@ -0,0 +1,61 @@
+// ScoreFall Ink - Music Composition Software
+//
+// Copyright (C) 2019-2020 Jeron Aldaron Lau <jeronlau@plopgrizzly.com>
+// Copyright (C) 2019-2020 Doug P. Lau
+//
+//     This program is free software: you can redistribute it and/or modify
+//     it under the terms of the GNU General Public License as published by
+//     the Free Software Foundation, either version 3 of the License, or
+//     (at your option) any later version.
+//
+//     This program is distributed in the hope that it will be useful,
+//     but WITHOUT ANY WARRANTY; without even the implied warranty of
+//     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//     GNU General Public License for more details.
+//
+//     You should have received a copy of the GNU General Public License
+//     along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/* ************************************************************************** */
+
+//! Warnings about music the engraver can't draw properly.
+
+use scof::{Cursor, Fraction};
+
+/// Something drawn in a simplified way, rather than failing to render.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderWarning {
+    /// The note or rest at a cursor is shorter than a 128th note, and is
+    /// drawn as a placeholder notehead that takes no space.
+    TooShort(Cursor, Fraction),
+    /// A span of time (in 128th notes) in a bar is longer than a longa, and
+    /// is spaced like a longa.
+    TooLong(u16),
+}
+
+/// Warnings collected while rendering.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderWarnings(Vec<RenderWarning>);
+
+impl RenderWarnings {
+    /// Add a warning.
+    pub fn push(&mut self, warning: RenderWarning) {
+        self.0.push(warning);
+    }
+
+    /// Check if there are no warnings.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterate over the warnings, in the order they were found.
+    pub fn iter(&self) -> impl Iterator<Item = &RenderWarning> {
+        self.0.iter()
+    }
+}
+
+impl Extend<RenderWarning> for RenderWarnings {
+    fn extend<I: IntoIterator<Item = RenderWarning>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
@@ -32,7 +32,8 @@ use cala::task::{exec, wait};
 
 use std::panic;
 
-use scof::{Cursor, Fraction, Pitch, Steps};
+use scof::{Cursor, Pitch, Steps};
+use scorefall_ink::keymap::{self, Action, Modifiers};
 use scorefall_ink::Program;
 use staverator::{BarElem, Element, SfFontMetadata, Stave, STAVE_SPACE};
 
@@ -65,7 +66,10 @@ fn create_elem(screen: &Screen, elem: Element) -> Option<web_sys::Element> {
         },
         Element::Use(u) => {
             let id = format!("#{:x}", u.id);
-            let stamp = screen.new_use(u.x as f32, u.y as f32, &id);
+            let mut stamp = screen.new_use(u.x as f32, u.y as f32, &id);
+            if let Some(element_id) = u.element_id {
+                stamp.set_id(&element_id);
+            }
             stamp.0
         }
         Element::Path(p) => {
@@ -87,6 +91,8 @@ struct State {
     screen: Screen,
     // The front-end agnostic back-end
     program: Program,
+    // Key bindings
+    keymap: Vec<(Modifiers, keymap::Key, Action)>,
     meta: SfFontMetadata,
     // Window width in Stave Spaces.
     width: f32,
@@ -106,6 +112,7 @@ impl State {
         State {
             screen,
             program: Program::new(),
+            keymap: keymap::default_keymap(),
             meta,
             width: 0.0,
         }
@@ -121,106 +128,53 @@ impl State {
     
     /// Input handler.
     fn event_input(&mut self, input: Input) {
-        match input {
-            Input::Key(mods, key, true) if mods.ctrl() && matches!(key, Key::H | Key::Left) => {
-                // TODO: Halve duration
-            }
-            Input::Key(mods, key, true) if mods.ctrl() && matches!(key, Key::J | Key::Down) => {
-                self.program.down_half_step();
-                self.render_measures();
-            }
-            Input::Key(mods, key, true) if mods.ctrl() && matches!(key, Key::K | Key::Up) => {
-                self.program.up_half_step();
-                self.render_measures();
-            }
-            Input::Key(mods, key, true) if mods.ctrl() && matches!(key, Key::L | Key::Right) => {
-                // TODO: Double duration
-            }
-
-            Input::Key(mods, key, true) if mods.alt() && matches!(key, Key::H | Key::Left) => {
-                // TODO: Move selection to the left
-            }
-            Input::Key(mods, key, true) if mods.alt() && matches!(key, Key::J | Key::Down) => {
-                self.program.down_quarter_step();
-                self.render_measures();
-            }
-            Input::Key(mods, key, true) if mods.alt() && matches!(key, Key::K | Key::Up) => {
-                self.program.up_quarter_step();
-                self.render_measures();
-            }
-            Input::Key(mods, key, true) if mods.alt() && matches!(key, Key::L | Key::Right) => {
-                // TODO: Move selection to the right
-            }
-            Input::Key(mods, key, true) if mods.shift() && matches!(key, Key::H | Key::Left) => {
-                // TODO: Select left
-            }
-            Input::Key(mods, key, true) if mods.shift() && matches!(key, Key::J | Key::Down) => {
-                // TODO: Select down
-            }
-            Input::Key(mods, key, true) if mods.shift() && matches!(key, Key::K | Key::Up) => {
-                // TODO: Select up
-            }
-            Input::Key(mods, key, true) if mods.shift() && matches!(key, Key::L | Key::Right) => {
-                // TODO: Select right
-            }
-
-            Input::Key(mods, key, true) if mods.none() && matches!(key, Key::H | Key::Left) => {
-                self.program.left();
-                self.render_measures();
-            }
-            Input::Key(mods, key, true) if mods.none() && matches!(key, Key::J | Key::Down) => {
-                self.program.down_step();
-                self.render_measures();
-            }
-            Input::Key(mods, key, true) if mods.none() && matches!(key, Key::K | Key::Up) => {
-                self.program.up_step();
-                self.render_measures();
-            }
-            Input::Key(mods, key, true) if mods.none() && matches!(key, Key::L | Key::Right) => {
-                self.program.right();
-                self.render_measures();
-            }
-            Input::Key(mods, Key::One, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(1, 64));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Two, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(1, 32));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Three, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(1, 16));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Four, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(1, 8));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Five, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(1, 4));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Six, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(1, 2));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Seven, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(1, 1));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Eight, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(2, 1));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Nine, true) if mods.none() => {
-                self.program.set_dur(Fraction::new(4, 1));
-                self.render_measures();
-            }
-            Input::Key(mods, Key::Period, true) if mods.none() => {
-                self.program.dotted();
+        let (mods, key) = match input {
+            Input::Key(mods, key, true) => (mods, key),
+            _ => return, /* ignore all other input */
+        };
+        let mods = if mods.ctrl() {
+            Modifiers::Ctrl
+        } else if mods.alt() {
+            Modifiers::Alt
+        } else if mods.shift() {
+            Modifiers::Shift
+        } else if mods.none() {
+            Modifiers::None
+        } else {
+            return;
+        };
+        let key = match key {
+            Key::H => keymap::Key::H,
+            Key::J => keymap::Key::J,
+            Key::K => keymap::Key::K,
+            Key::L => keymap::Key::L,
+            Key::Left => keymap::Key::Left,
+            Key::Down => keymap::Key::Down,
+            Key::Up => keymap::Key::Up,
+            Key::Right => keymap::Key::Right,
+            Key::One => keymap::Key::One,
+            Key::Two => keymap::Key::Two,
+            Key::Three => keymap::Key::Three,
+            Key::Four => keymap::Key::Four,
+            Key::Five => keymap::Key::Five,
+            Key::Six => keymap::Key::Six,
+            Key::Seven => keymap::Key::Seven,
+            Key::Eight => keymap::Key::Eight,
+            Key::Nine => keymap::Key::Nine,
+            Key::Period => keymap::Key::Period,
+            Key::A => keymap::Key::A,
+            Key::M => keymap::Key::M,
+            Key::N => keymap::Key::N,
+            Key::S => keymap::Key::S,
+            Key::T => keymap::Key::T,
+            _ => return,
+        };
+        if let Some(action) = keymap::lookup(&self.keymap, mods, key) {
+            if self.program.act(action) {
                 self.render_measures();
+            } else {
+                log!(GUI, "Can't {:?}", action);
             }
-            _ => { /* ignore all other input */ },
         }
     }
 
@@ -300,6 +254,8 @@ impl State {
         // Alto clef has 0 steps offset
         let mut bar =
             BarElem::new(Stave::new(5, Steps(4), Steps(0)), high, low);
+        // Let noteheads be found from the markings they're drawn for.
+        bar.options.note_ids = true;
         if let Some((cx, cy, cwidth, cheight)) = bar.add_markings(
             &self.meta,
             &self.program.scof,
@@ -181,6 +181,12 @@ impl Group {
     }
 }
 
+impl Use {
+    pub fn set_id(&mut self, id: &str) {
+        self.0.set_attribute_ns(None, "id", &id.to_string()).unwrap();
+    }
+}
+
 impl Rect {
     pub fn set_id(&mut self, id: &str) {
         self.0.set_attribute_ns(None, "id", &id.to_string()).unwrap();